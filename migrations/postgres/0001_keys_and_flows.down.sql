@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS public.flows;
+DROP TABLE IF EXISTS public.keys;
@@ -0,0 +1,24 @@
+CREATE TABLE IF NOT EXISTS public.keys (
+    key_id SERIAL PRIMARY KEY,
+    host VARCHAR(255) NOT NULL,
+    key TEXT NOT NULL,
+    updated TIMESTAMP WITH TIME ZONE NOT NULL,
+    deprecated BOOLEAN NOT NULL DEFAULT FALSE,
+    CONSTRAINT unique_host_key UNIQUE (host, key)
+);
+
+-- Databases created before keys could be deprecated
+ALTER TABLE public.keys ADD COLUMN IF NOT EXISTS deprecated BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE IF NOT EXISTS public.flows (
+    flow_id SERIAL PRIMARY KEY,
+    name VARCHAR(255) NOT NULL,
+    key_id INTEGER NOT NULL,
+    CONSTRAINT fk_key
+        FOREIGN KEY(key_id)
+        REFERENCES public.keys(key_id)
+        ON DELETE CASCADE,
+    CONSTRAINT unique_flow_key UNIQUE (name, key_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_flows_name ON public.flows(name);
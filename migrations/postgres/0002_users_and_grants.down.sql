@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS public.grants;
+DROP TABLE IF EXISTS public.users;
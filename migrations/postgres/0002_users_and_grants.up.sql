@@ -0,0 +1,19 @@
+CREATE TABLE IF NOT EXISTS public.users (
+    user_id SERIAL PRIMARY KEY,
+    username VARCHAR(255) NOT NULL,
+    created TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    CONSTRAINT unique_username UNIQUE (username)
+);
+
+CREATE TABLE IF NOT EXISTS public.grants (
+    grant_id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL,
+    flow VARCHAR(255) NOT NULL,
+    role VARCHAR(16) NOT NULL,
+    CONSTRAINT fk_user
+        FOREIGN KEY(user_id)
+        REFERENCES public.users(user_id)
+        ON DELETE CASCADE,
+    CONSTRAINT valid_role CHECK (role IN ('reader', 'writer', 'admin')),
+    CONSTRAINT unique_user_flow UNIQUE (user_id, flow)
+);
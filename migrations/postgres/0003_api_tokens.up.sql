@@ -0,0 +1,15 @@
+-- Bearer tokens for machine clients, only the SHA-256 hash of each token is stored
+CREATE TABLE IF NOT EXISTS public.api_tokens (
+    token_id SERIAL PRIMARY KEY,
+    name VARCHAR(255) NOT NULL,
+    token_hash CHAR(64) NOT NULL,
+    flows TEXT[] NOT NULL,
+    role VARCHAR(16) NOT NULL,
+    created_by VARCHAR(255),
+    created TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    expires TIMESTAMP WITH TIME ZONE,
+    last_used TIMESTAMP WITH TIME ZONE,
+    last_hostname VARCHAR(255),
+    CONSTRAINT valid_token_role CHECK (role IN ('reader', 'writer')),
+    CONSTRAINT unique_token_hash UNIQUE (token_hash)
+);
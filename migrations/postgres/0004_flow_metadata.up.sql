@@ -0,0 +1,12 @@
+-- Flow metadata; `flows` itself only maps flow names to keys
+CREATE TABLE IF NOT EXISTS public.flow_metadata (
+    name VARCHAR(255) PRIMARY KEY,
+    description TEXT,
+    archived BOOLEAN NOT NULL DEFAULT FALSE,
+    created TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+-- Register flows that already hold keys, e.g. after upgrading from a --flows only setup
+INSERT INTO public.flow_metadata (name)
+SELECT DISTINCT name FROM public.flows
+ON CONFLICT (name) DO NOTHING;
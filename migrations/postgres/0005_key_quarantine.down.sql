@@ -0,0 +1,2 @@
+ALTER TABLE public.flows DROP COLUMN IF EXISTS pending;
+ALTER TABLE public.flow_metadata DROP COLUMN IF EXISTS key_policy;
@@ -0,0 +1,5 @@
+-- Per-flow key policy and pending flow associations
+ALTER TABLE public.flow_metadata
+    ADD COLUMN IF NOT EXISTS key_policy VARCHAR(16) NOT NULL DEFAULT 'tofu';
+ALTER TABLE public.flows
+    ADD COLUMN IF NOT EXISTS pending BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1 @@
+ALTER TABLE public.keys DROP COLUMN IF EXISTS revoked;
@@ -0,0 +1,3 @@
+-- Revoked keys are also deprecated; clients write them as @revoked lines
+ALTER TABLE public.keys
+    ADD COLUMN IF NOT EXISTS revoked BOOLEAN NOT NULL DEFAULT FALSE;
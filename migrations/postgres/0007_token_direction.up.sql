@@ -0,0 +1,3 @@
+-- Push-only and pull-only API tokens
+ALTER TABLE public.api_tokens
+    ADD COLUMN IF NOT EXISTS direction VARCHAR(8) NOT NULL DEFAULT 'both';
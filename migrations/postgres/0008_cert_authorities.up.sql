@@ -0,0 +1,10 @@
+-- Host certificate authorities trusted per flow, served as @cert-authority lines
+CREATE TABLE IF NOT EXISTS public.cert_authorities (
+    ca_id SERIAL PRIMARY KEY,
+    flow VARCHAR(255) NOT NULL,
+    hosts TEXT[] NOT NULL,
+    public_key TEXT NOT NULL,
+    comment TEXT,
+    created TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    UNIQUE (flow, public_key)
+);
@@ -0,0 +1,26 @@
+-- Append-only history of every change; rows are written by the same statement as the change
+CREATE TABLE IF NOT EXISTS public.audit_log (
+    id BIGSERIAL PRIMARY KEY,
+    at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    action VARCHAR(32) NOT NULL,
+    flow VARCHAR(255),
+    server VARCHAR(255),
+    public_key TEXT,
+    actor VARCHAR(255),
+    client_hostname VARCHAR(255),
+    details TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_audit_log_flow_server ON public.audit_log(flow, server, at);
+CREATE INDEX IF NOT EXISTS idx_audit_log_flow_id ON public.audit_log(flow, id);
+
+CREATE OR REPLACE FUNCTION public.audit_log_append_only() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'audit_log is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS audit_log_append_only ON public.audit_log;
+CREATE TRIGGER audit_log_append_only
+    BEFORE UPDATE OR DELETE ON public.audit_log
+    FOR EACH ROW EXECUTE FUNCTION public.audit_log_append_only();
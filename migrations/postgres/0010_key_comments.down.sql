@@ -0,0 +1,2 @@
+UPDATE public.keys SET key = key || ' ' || comment WHERE comment IS NOT NULL;
+ALTER TABLE public.keys DROP COLUMN IF EXISTS comment;
@@ -0,0 +1,42 @@
+-- Keys are stored as `type base64` with the comment in its own column, so that the
+-- same key sent with another comment or none is not a second key
+ALTER TABLE public.keys ADD COLUMN IF NOT EXISTS comment TEXT;
+
+CREATE TEMPORARY TABLE split_keys AS
+SELECT key_id, host, deprecated,
+       split_part(key, ' ', 1) || ' ' || split_part(key, ' ', 2) AS key,
+       NULLIF(btrim(substr(key, length(split_part(key, ' ', 1)) + length(split_part(key, ' ', 2)) + 3)), '') AS comment
+FROM public.keys;
+
+-- Keys that were stored more than once merge into one, an active one where there is
+-- one, which takes over their flows
+CREATE TEMPORARY TABLE duplicate_keys AS
+SELECT s.key_id, (
+    SELECT kept.key_id FROM split_keys kept
+    WHERE kept.host = s.host AND kept.key = s.key
+    ORDER BY kept.deprecated, kept.key_id
+    LIMIT 1
+) AS kept_id
+FROM split_keys s;
+DELETE FROM duplicate_keys WHERE key_id = kept_id;
+
+INSERT INTO public.flows (name, key_id, pending)
+SELECT f.name, d.kept_id, f.pending
+FROM public.flows f INNER JOIN duplicate_keys d ON d.key_id = f.key_id
+ON CONFLICT (name, key_id) DO NOTHING;
+
+DELETE FROM public.keys WHERE key_id IN (SELECT key_id FROM duplicate_keys);
+
+UPDATE public.keys k
+SET key = s.key,
+    comment = (
+        SELECT c.comment FROM split_keys c
+        WHERE c.host = s.host AND c.key = s.key AND c.comment IS NOT NULL
+        ORDER BY c.key_id <> s.key_id, c.key_id
+        LIMIT 1
+    )
+FROM split_keys s
+WHERE s.key_id = k.key_id;
+
+DROP TABLE duplicate_keys;
+DROP TABLE split_keys;
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS flows;
+DROP TABLE IF EXISTS keys;
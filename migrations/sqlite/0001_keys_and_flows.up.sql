@@ -0,0 +1,23 @@
+-- The same tables as the PostgreSQL schema. Timestamps are stored as text in UTC
+-- ("YYYY-MM-DD HH:MM:SS.SSS+00:00") and arrays as JSON text.
+CREATE TABLE keys (
+    key_id INTEGER PRIMARY KEY AUTOINCREMENT,
+    host VARCHAR(255) NOT NULL,
+    key TEXT NOT NULL,
+    updated TEXT NOT NULL,
+    deprecated BOOLEAN NOT NULL DEFAULT FALSE,
+    CONSTRAINT unique_host_key UNIQUE (host, key)
+);
+
+CREATE TABLE flows (
+    flow_id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name VARCHAR(255) NOT NULL,
+    key_id INTEGER NOT NULL,
+    CONSTRAINT fk_key
+        FOREIGN KEY(key_id)
+        REFERENCES keys(key_id)
+        ON DELETE CASCADE,
+    CONSTRAINT unique_flow_key UNIQUE (name, key_id)
+);
+
+CREATE INDEX idx_flows_name ON flows(name);
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS grants;
+DROP TABLE IF EXISTS users;
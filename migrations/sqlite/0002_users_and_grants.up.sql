@@ -0,0 +1,19 @@
+CREATE TABLE users (
+    user_id INTEGER PRIMARY KEY AUTOINCREMENT,
+    username VARCHAR(255) NOT NULL,
+    created TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f+00:00', 'now')),
+    CONSTRAINT unique_username UNIQUE (username)
+);
+
+CREATE TABLE grants (
+    grant_id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL,
+    flow VARCHAR(255) NOT NULL,
+    role VARCHAR(16) NOT NULL,
+    CONSTRAINT fk_user
+        FOREIGN KEY(user_id)
+        REFERENCES users(user_id)
+        ON DELETE CASCADE,
+    CONSTRAINT valid_role CHECK (role IN ('reader', 'writer', 'admin')),
+    CONSTRAINT unique_user_flow UNIQUE (user_id, flow)
+);
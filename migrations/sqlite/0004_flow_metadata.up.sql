@@ -0,0 +1,10 @@
+-- Flow metadata; `flows` itself only maps flow names to keys
+CREATE TABLE flow_metadata (
+    name VARCHAR(255) PRIMARY KEY,
+    description TEXT,
+    archived BOOLEAN NOT NULL DEFAULT FALSE,
+    created TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f+00:00', 'now'))
+);
+
+INSERT OR IGNORE INTO flow_metadata (name)
+SELECT DISTINCT name FROM flows;
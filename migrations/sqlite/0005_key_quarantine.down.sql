@@ -0,0 +1,2 @@
+ALTER TABLE flows DROP COLUMN pending;
+ALTER TABLE flow_metadata DROP COLUMN key_policy;
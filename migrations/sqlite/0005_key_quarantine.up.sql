@@ -0,0 +1,3 @@
+-- Per-flow key policy and pending flow associations
+ALTER TABLE flow_metadata ADD COLUMN key_policy VARCHAR(16) NOT NULL DEFAULT 'tofu';
+ALTER TABLE flows ADD COLUMN pending BOOLEAN NOT NULL DEFAULT FALSE;
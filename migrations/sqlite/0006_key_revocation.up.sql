@@ -0,0 +1,2 @@
+-- Revoked keys are also deprecated; clients write them as @revoked lines
+ALTER TABLE keys ADD COLUMN revoked BOOLEAN NOT NULL DEFAULT FALSE;
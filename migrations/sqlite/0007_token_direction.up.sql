@@ -0,0 +1,2 @@
+-- Push-only and pull-only API tokens
+ALTER TABLE api_tokens ADD COLUMN direction VARCHAR(8) NOT NULL DEFAULT 'both';
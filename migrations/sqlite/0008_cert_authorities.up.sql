@@ -0,0 +1,10 @@
+-- Host certificate authorities trusted per flow, served as @cert-authority lines
+CREATE TABLE cert_authorities (
+    ca_id INTEGER PRIMARY KEY AUTOINCREMENT,
+    flow VARCHAR(255) NOT NULL,
+    hosts TEXT NOT NULL,
+    public_key TEXT NOT NULL,
+    comment TEXT,
+    created TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f+00:00', 'now')),
+    UNIQUE (flow, public_key)
+);
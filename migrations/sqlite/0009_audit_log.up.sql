@@ -0,0 +1,26 @@
+-- Append-only history of every change. AUTOINCREMENT keeps ids from being reused,
+-- since the newest id of a flow is its revision.
+CREATE TABLE audit_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f+00:00', 'now')),
+    action VARCHAR(32) NOT NULL,
+    flow VARCHAR(255),
+    server VARCHAR(255),
+    public_key TEXT,
+    actor VARCHAR(255),
+    client_hostname VARCHAR(255),
+    details TEXT
+);
+
+CREATE INDEX idx_audit_log_flow_server ON audit_log(flow, server, at);
+CREATE INDEX idx_audit_log_flow_id ON audit_log(flow, id);
+
+CREATE TRIGGER audit_log_no_update BEFORE UPDATE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'audit_log is append-only');
+END;
+
+CREATE TRIGGER audit_log_no_delete BEFORE DELETE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'audit_log is append-only');
+END;
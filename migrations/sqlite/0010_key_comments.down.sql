@@ -0,0 +1,2 @@
+UPDATE keys SET key = key || ' ' || comment WHERE comment IS NOT NULL;
+ALTER TABLE keys DROP COLUMN comment;
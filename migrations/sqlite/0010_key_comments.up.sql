@@ -0,0 +1,44 @@
+-- Keys are stored as `type base64` with the comment in its own column, so that the
+-- same key sent with another comment or none is not a second key
+ALTER TABLE keys ADD COLUMN comment TEXT;
+
+CREATE TEMP TABLE split_keys AS
+SELECT key_id, host, deprecated,
+       CASE WHEN second_space > 0 THEN substr(key, 1, first_space + second_space - 1) ELSE key END AS key,
+       CASE WHEN second_space > 0 THEN NULLIF(trim(substr(key, first_space + second_space + 1)), '') END AS comment
+FROM (
+    SELECT key_id, host, key, deprecated, instr(key, ' ') AS first_space,
+           instr(substr(key, instr(key, ' ') + 1), ' ') AS second_space
+    FROM keys
+);
+
+-- Keys that were stored more than once merge into one, an active one where there is
+-- one, which takes over their flows
+CREATE TEMP TABLE duplicate_keys AS
+SELECT s.key_id, (
+    SELECT kept.key_id FROM split_keys kept
+    WHERE kept.host = s.host AND kept.key = s.key
+    ORDER BY kept.deprecated, kept.key_id
+    LIMIT 1
+) AS kept_id
+FROM split_keys s;
+DELETE FROM duplicate_keys WHERE key_id = kept_id;
+
+INSERT OR IGNORE INTO flows (name, key_id, pending)
+SELECT f.name, d.kept_id, f.pending
+FROM flows f INNER JOIN duplicate_keys d ON d.key_id = f.key_id;
+
+DELETE FROM keys WHERE key_id IN (SELECT key_id FROM duplicate_keys);
+
+UPDATE keys
+SET key = (SELECT s.key FROM split_keys s WHERE s.key_id = keys.key_id),
+    comment = (
+        SELECT c.comment FROM split_keys s
+        INNER JOIN split_keys c ON c.host = s.host AND c.key = s.key
+        WHERE s.key_id = keys.key_id AND c.comment IS NOT NULL
+        ORDER BY c.key_id <> s.key_id, c.key_id
+        LIMIT 1
+    );
+
+DROP TABLE duplicate_keys;
+DROP TABLE split_keys;
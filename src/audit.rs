@@ -0,0 +1,73 @@
+use actix_web::HttpRequest;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::auth;
+
+// Who performed a mutation, recorded next to every audit entry
+#[derive(Clone, Debug, Default)]
+pub struct Actor {
+    pub user: Option<String>,
+    pub client_hostname: Option<String>,
+}
+
+impl Actor {
+    pub fn from_request(req: &HttpRequest) -> Self {
+        Self {
+            user: auth::current_user(req),
+            client_hostname: req
+                .headers()
+                .get("X-Client-Hostname")
+                .and_then(|hostname| hostname.to_str().ok())
+                .map(|hostname| hostname.to_string()),
+        }
+    }
+
+    // Changes made by the server itself, e.g. flows seeded from --flows at startup
+    pub fn system() -> Self {
+        Self {
+            user: Some("system".to_string()),
+            client_hostname: None,
+        }
+    }
+}
+
+// One row of the append-only audit log
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub at: DateTime<Utc>,
+    pub action: String,
+    pub flow: Option<String>,
+    pub server: Option<String>,
+    pub public_key: Option<String>,
+    pub actor: Option<String>,
+    pub client_hostname: Option<String>,
+    pub details: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct AuditQuery {
+    pub flow: Option<String>,
+    pub server: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub limit: Option<i64>,
+}
+
+pub const DEFAULT_AUDIT_LIMIT: i64 = 500;
+pub const MAX_AUDIT_LIMIT: i64 = 5000;
+
+// Actions that change what a flow serves to clients. The newest of them is the
+// flow revision; the key actions name the exact key, the others need a full resync.
+pub const KEY_ACTIONS: &[&str] = &[
+    "key_added",
+    "key_quarantined",
+    "key_approved",
+    "key_rejected",
+    "key_deprecated",
+    "key_restored",
+    "key_revoked",
+    "key_deleted",
+];
+pub const FLOW_CONTENT_ACTIONS: &[&str] =
+    &["ca_added", "ca_removed", "flow_created", "flow_renamed"];
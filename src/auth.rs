@@ -0,0 +1,495 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{AUTHORIZATION, WWW_AUTHENTICATE};
+use actix_web::middleware::Next;
+use actix_web::{web, Error, HttpMessage, HttpRequest, HttpResponse};
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use base64::{engine::general_purpose, Engine as _};
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+use crate::client::SyncDirection;
+use crate::storage::Storage;
+use crate::tls::ClientCertificateUser;
+
+const REALM: &str = "khm";
+
+// Reachable without credentials
+const PUBLIC_PATHS: &[&str] = &["/healthz", "/readyz"];
+
+// Flow name used in grants that apply to every flow
+pub const ALL_FLOWS: &str = "*";
+
+// Username -> password hash, loaded from an htpasswd-style file
+pub struct Credentials {
+    users: HashMap<String, String>,
+}
+
+// Identity attached to the request once the credentials have been verified
+#[derive(Clone, Debug)]
+pub struct AuthenticatedUser(pub String);
+
+// Flows, role and sync direction an API token was issued for, attached to
+// token-authenticated requests
+#[derive(Clone, Debug)]
+pub struct TokenScope {
+    pub flows: Vec<String>,
+    pub role: Role,
+    pub direction: SyncDirection,
+}
+
+impl TokenScope {
+    // Pull-only tokens can only read; push-only tokens can only upload keys,
+    // the one action that needs exactly the writer role
+    fn allows(&self, required: Role) -> bool {
+        let direction_allows = match self.direction {
+            SyncDirection::Both => true,
+            SyncDirection::Pull => required == Role::Reader,
+            SyncDirection::Push => required == Role::Writer,
+        };
+        direction_allows && self.role >= required
+    }
+}
+
+impl Credentials {
+    /// Load credentials from an htpasswd-style file (`user:hash` per line).
+    /// Only bcrypt (`$2a$`, `$2b$`, `$2y$`) and argon2 (`$argon2id$`, ...) hashes are accepted.
+    pub fn load(path: &str) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        let mut users = HashMap::new();
+
+        for (line_no, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let Some((username, hash)) = line.split_once(':') else {
+                warn!(
+                    "Skipping malformed line {} in credentials file {}",
+                    line_no + 1,
+                    path
+                );
+                continue;
+            };
+
+            if !is_supported_hash(hash) {
+                warn!(
+                    "Skipping user '{}' in credentials file {}: only bcrypt and argon2 hashes are supported",
+                    username, path
+                );
+                continue;
+            }
+
+            users.insert(username.to_string(), hash.to_string());
+        }
+
+        if users.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("No usable credentials found in {}", path),
+            ));
+        }
+
+        info!(
+            "Loaded {} user(s) from credentials file {}",
+            users.len(),
+            path
+        );
+        Ok(Self { users })
+    }
+
+    pub fn verify(&self, username: &str, password: &str) -> bool {
+        let Some(hash) = self.users.get(username) else {
+            return false;
+        };
+
+        if hash.starts_with("$argon2") {
+            match PasswordHash::new(hash) {
+                Ok(parsed) => Argon2::default()
+                    .verify_password(password.as_bytes(), &parsed)
+                    .is_ok(),
+                Err(e) => {
+                    error!("Invalid argon2 hash for user '{}': {}", username, e);
+                    false
+                }
+            }
+        } else {
+            match bcrypt::verify(password, hash) {
+                Ok(valid) => valid,
+                Err(e) => {
+                    error!("Invalid bcrypt hash for user '{}': {}", username, e);
+                    false
+                }
+            }
+        }
+    }
+}
+
+fn is_supported_hash(hash: &str) -> bool {
+    hash.starts_with("$2a$")
+        || hash.starts_with("$2b$")
+        || hash.starts_with("$2y$")
+        || hash.starts_with("$argon2")
+}
+
+// Decode `Authorization: Basic <base64(user:pass)>` into its parts
+fn parse_basic_auth(req: &ServiceRequest) -> Option<(String, String)> {
+    let header = req.headers().get(AUTHORIZATION)?.to_str().ok()?;
+    let encoded = header.strip_prefix("Basic ")?;
+    let decoded = general_purpose::STANDARD.decode(encoded.trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+    Some((username.to_string(), password.to_string()))
+}
+
+// Extract the token from `Authorization: Bearer <token>`
+fn parse_bearer_token(req: &ServiceRequest) -> Option<String> {
+    let header = req.headers().get(AUTHORIZATION)?.to_str().ok()?;
+    let token = header.strip_prefix("Bearer ")?.trim();
+    (!token.is_empty()).then(|| token.to_string())
+}
+
+fn unauthorized() -> HttpResponse {
+    HttpResponse::Unauthorized()
+        .insert_header((WWW_AUTHENTICATE, format!("Basic realm=\"{}\"", REALM)))
+        .insert_header((WWW_AUTHENTICATE, format!("Bearer realm=\"{}\"", REALM)))
+        .body("Authentication required")
+}
+
+/// Authentication middleware for every server route.
+/// Requests pass through untouched when the server runs without a credentials file,
+/// which is why API tokens can only be created with one. Without an Authorization
+/// header, a client certificate verified against --tls-client-ca names the user.
+pub async fn authenticate(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    // Health probes from load balancers and orchestrators carry no credentials
+    if PUBLIC_PATHS.contains(&req.path()) {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_left_body);
+    }
+
+    let Some(credentials) = req.app_data::<web::Data<Credentials>>().cloned() else {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_left_body);
+    };
+
+    if let Some(token) = parse_bearer_token(&req) {
+        return match authenticate_token(&req, &token) {
+            Some(api_token) => {
+                req.extensions_mut()
+                    .insert(AuthenticatedUser(format!("token:{}", api_token.name)));
+                req.extensions_mut().insert(TokenScope {
+                    flows: api_token.flows,
+                    role: api_token.role,
+                    direction: api_token.direction,
+                });
+                next.call(req)
+                    .await
+                    .map(ServiceResponse::map_into_left_body)
+            }
+            None => Ok(req.into_response(unauthorized()).map_into_right_body()),
+        };
+    }
+
+    let Some((username, password)) = parse_basic_auth(&req) else {
+        if let Some(ClientCertificateUser(username)) = req.conn_data().cloned() {
+            req.extensions_mut().insert(AuthenticatedUser(username));
+            return next
+                .call(req)
+                .await
+                .map(ServiceResponse::map_into_left_body);
+        }
+        info!("Rejecting unauthenticated request to {}", req.path());
+        return Ok(req.into_response(unauthorized()).map_into_right_body());
+    };
+
+    // Password hashing is deliberately slow, keep it off the async workers
+    let verified_user = username.clone();
+    let valid = web::block(move || credentials.verify(&verified_user, &password))
+        .await
+        .unwrap_or(false);
+
+    if !valid {
+        warn!(
+            "Authentication failed for user '{}' on {}",
+            username,
+            req.path()
+        );
+        return Ok(req.into_response(unauthorized()).map_into_right_body());
+    }
+
+    req.extensions_mut().insert(AuthenticatedUser(username));
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_left_body)
+}
+
+// Look up a bearer token and record its use; None when unknown or expired
+fn authenticate_token(req: &ServiceRequest, token: &str) -> Option<ApiToken> {
+    let Some(tokens) = req.app_data::<web::Data<TokenStore>>() else {
+        warn!("Bearer token presented but API tokens are not available");
+        return None;
+    };
+
+    let token_hash = hash_token(token);
+    let Some(api_token) = tokens.find(&token_hash) else {
+        warn!("Unknown API token used on {}", req.path());
+        return None;
+    };
+
+    if api_token
+        .expires
+        .is_some_and(|expires| expires <= Utc::now())
+    {
+        warn!(
+            "Expired API token '{}' used on {}",
+            api_token.name,
+            req.path()
+        );
+        return None;
+    }
+
+    let hostname = req
+        .headers()
+        .get("X-Client-Hostname")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("unknown-client")
+        .to_string();
+    tokens.touch(&token_hash, &hostname);
+
+    // Persist the usage in the background, the request does not depend on it
+    if let Some(db_client) = req.app_data::<web::Data<Arc<dyn Storage>>>() {
+        let db_client = db_client.get_ref().clone();
+        let token_id = api_token.id;
+        actix_web::rt::spawn(async move {
+            if let Err(e) = db_client.touch_api_token(token_id, hostname).await {
+                error!("Failed to record API token usage: {}", e);
+            }
+        });
+    }
+
+    Some(api_token)
+}
+
+// Name of the authenticated user for logging, if authentication is enabled
+pub fn current_user(req: &HttpRequest) -> Option<String> {
+    req.extensions()
+        .get::<AuthenticatedUser>()
+        .map(|user| user.0.clone())
+}
+
+// Per-flow permission level; each role includes everything granted by the lower ones
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Reader,
+    Writer,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Reader => "reader",
+            Role::Writer => "writer",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reader" => Ok(Role::Reader),
+            "writer" => Ok(Role::Writer),
+            "admin" => Ok(Role::Admin),
+            other => Err(format!("Unknown role: {}", other)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Grant {
+    pub username: String,
+    pub flow: String,
+    pub role: Role,
+}
+
+// In-memory copy of the grants table, refreshed whenever grants change
+#[derive(Clone, Default)]
+pub struct GrantStore {
+    grants: Arc<RwLock<HashMap<String, HashMap<String, Role>>>>,
+}
+
+impl GrantStore {
+    pub fn replace(&self, grants: Vec<Grant>) {
+        let mut by_user: HashMap<String, HashMap<String, Role>> = HashMap::new();
+        for grant in grants {
+            by_user
+                .entry(grant.username)
+                .or_default()
+                .insert(grant.flow, grant.role);
+        }
+        *self.grants.write().unwrap() = by_user;
+    }
+
+    pub fn all(&self) -> Vec<Grant> {
+        let grants = self.grants.read().unwrap();
+        let mut all = Vec::new();
+        for (username, flows) in grants.iter() {
+            for (flow, role) in flows {
+                all.push(Grant {
+                    username: username.clone(),
+                    flow: flow.clone(),
+                    role: *role,
+                });
+            }
+        }
+        all.sort_by(|a, b| (&a.username, &a.flow).cmp(&(&b.username, &b.flow)));
+        all
+    }
+
+    /// Effective role of a user on a flow: the higher of the flow grant and the `*` grant.
+    pub fn role_for(&self, username: &str, flow: &str) -> Option<Role> {
+        let grants = self.grants.read().unwrap();
+        let user_grants = grants.get(username)?;
+        let flow_role = user_grants.get(flow).copied();
+        let global_role = user_grants.get(ALL_FLOWS).copied();
+        flow_role.max(global_role)
+    }
+}
+
+/// Whether the caller holds at least `required` on `flow`.
+/// Always true when the server runs without a credentials file.
+pub fn has_role(req: &HttpRequest, flow: &str, required: Role) -> bool {
+    if req.app_data::<web::Data<Credentials>>().is_none() {
+        return true;
+    }
+
+    // Token-authenticated requests are limited to the token's own scope
+    if let Some(scope) = req.extensions().get::<TokenScope>() {
+        return scope.allows(required)
+            && scope
+                .flows
+                .iter()
+                .any(|scoped| scoped == flow || scoped == ALL_FLOWS);
+    }
+
+    let Some(username) = current_user(req) else {
+        return false;
+    };
+
+    req.app_data::<web::Data<GrantStore>>()
+        .and_then(|grants| grants.role_for(&username, flow))
+        .is_some_and(|role| role >= required)
+}
+
+/// Check the caller's role on a flow, producing a 403 response when it is insufficient.
+pub fn authorize(req: &HttpRequest, flow: &str, required: Role) -> Result<(), HttpResponse> {
+    if has_role(req, flow, required) {
+        return Ok(());
+    }
+
+    let username = current_user(req).unwrap_or_else(|| "anonymous".to_string());
+    warn!(
+        "User '{}' lacks '{}' role on flow '{}' for {}",
+        username,
+        required,
+        flow,
+        req.path()
+    );
+    Err(HttpResponse::Forbidden().json(json!({
+        "error": format!("'{}' role required on flow '{}'", required, flow)
+    })))
+}
+
+// API token metadata; the token itself is only known to its holder, we keep a SHA-256 hash
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ApiToken {
+    pub id: i32,
+    pub name: String,
+    #[serde(skip)]
+    pub token_hash: String,
+    pub flows: Vec<String>,
+    pub role: Role,
+    pub direction: SyncDirection,
+    pub created_by: Option<String>,
+    pub created: DateTime<Utc>,
+    pub expires: Option<DateTime<Utc>>,
+    pub last_used: Option<DateTime<Utc>>,
+    pub last_hostname: Option<String>,
+}
+
+// In-memory copy of the api_tokens table, keyed by token hash
+#[derive(Clone, Default)]
+pub struct TokenStore {
+    tokens: Arc<RwLock<HashMap<String, ApiToken>>>,
+}
+
+impl TokenStore {
+    pub fn replace(&self, tokens: Vec<ApiToken>) {
+        let by_hash = tokens
+            .into_iter()
+            .map(|token| (token.token_hash.clone(), token))
+            .collect();
+        *self.tokens.write().unwrap() = by_hash;
+    }
+
+    pub fn find(&self, token_hash: &str) -> Option<ApiToken> {
+        self.tokens.read().unwrap().get(token_hash).cloned()
+    }
+
+    pub fn all(&self) -> Vec<ApiToken> {
+        let mut all: Vec<ApiToken> = self.tokens.read().unwrap().values().cloned().collect();
+        all.sort_by_key(|token| token.id);
+        all
+    }
+
+    fn touch(&self, token_hash: &str, hostname: &str) {
+        if let Some(token) = self.tokens.write().unwrap().get_mut(token_hash) {
+            token.last_used = Some(Utc::now());
+            token.last_hostname = Some(hostname.to_string());
+        }
+    }
+}
+
+/// Generate a new random API token, returned to the caller exactly once.
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::rngs::OsRng.fill_bytes(&mut bytes);
+    let encoded: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("khm_{}", encoded)
+}
+
+pub fn hash_token(token: &str) -> String {
+    use sha2::{Digest, Sha256};
+
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
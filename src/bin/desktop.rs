@@ -45,14 +45,37 @@ impl From<DesktopArgs> for Args {
             flows: vec!["default".to_string()],
             ip: "127.0.0.1".to_string(),
             port: 8080,
+            db_url: None,
             db_host: "127.0.0.1".to_string(),
             db_name: "khm".to_string(),
             db_user: None,
             db_password: None,
+            db_pool_size: 16,
+            auth_file: None,
+            admin_users: Vec::new(),
+            tls_cert: None,
+            tls_key: None,
+            tls_client_ca: None,
             host: None,
-            flow: None,
+            flow: Vec::new(),
+            flow_rule: Vec::new(),
             known_hosts: "~/.ssh/known_hosts".to_string(),
             basic_auth: String::new(),
+            token: String::new(),
+            ca_cert: None,
+            client_cert: None,
+            client_key: None,
+            pin_sha256: Vec::new(),
+            upload_hashed: false,
+            hash_known_hosts: false,
+            merge_mode: Default::default(),
+            backups: khm::client::DEFAULT_BACKUP_COUNT,
+            dry_run: false,
+            output: Default::default(),
+            push_only: false,
+            pull_only: false,
+            watch: false,
+            poll_interval: khm::client::DEFAULT_POLL_INTERVAL_SECS,
         }
     }
 }
@@ -1,11 +1,19 @@
 use base64::{engine::general_purpose, Engine as _};
-use log::{error, info};
-use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION};
-use reqwest::Client;
+use log::{error, info, warn};
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, ETAG, IF_NONE_MATCH};
+use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
-use std::fs::File;
-use std::io::{self, BufRead, Write};
-use std::path::Path;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+use crate::events::{SseParser, KEEPALIVE_INTERVAL};
+use crate::known_hosts::{self, key_material, Entry, HostPattern, Line, Marker};
+use crate::tls::{self, ClientTlsOptions};
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct SshKey {
@@ -13,56 +21,884 @@ pub struct SshKey {
     public_key: String,
     #[serde(default)]
     deprecated: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    marker: Option<Marker>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
+}
+
+impl SshKey {
+    // One key per host name so the server can index and search them. Negated
+    // patterns only make sense next to the patterns they exclude, so such an
+    // entry keeps its whole host list.
+    fn from_entry(entry: &Entry) -> Vec<SshKey> {
+        let servers = if entry.hosts.iter().any(|pattern| pattern.negated) {
+            vec![entry.host_list()]
+        } else {
+            entry
+                .hosts
+                .iter()
+                .map(|pattern| pattern.to_string())
+                .collect()
+        };
+
+        servers
+            .into_iter()
+            .map(|server| SshKey {
+                server,
+                public_key: entry.public_key(),
+                deprecated: false, // Keys from known_hosts are not deprecated
+                marker: entry.marker,
+                comment: entry.comment.clone(),
+            })
+            .collect()
+    }
+
+    fn to_entry(&self) -> Result<Entry, String> {
+        let mut line = format!("{} {}", self.server, self.public_key);
+        if let Some(marker) = self.marker {
+            line = format!("{} {}", marker, line);
+        }
+        let mut entry: Entry = line.parse()?;
+        if self.comment.is_some() {
+            entry.comment = self.comment.clone();
+        }
+        Ok(entry)
+    }
+}
+
+// How --in-place combines the keys from the server with the existing file
+#[derive(clap::ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum MergeMode {
+    // Only the server's keys
+    #[default]
+    Replace,
+    // Also keep local marker lines and entries for hosts the server has no keys for
+    Union,
+    // Only rewrite the lines between `# BEGIN KHM <flow>` and `# END KHM <flow>`
+    ManagedBlock,
+}
+
+impl MergeMode {
+    pub const ALL: [MergeMode; 3] = [
+        MergeMode::Replace,
+        MergeMode::Union,
+        MergeMode::ManagedBlock,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MergeMode::Replace => "replace",
+            MergeMode::Union => "union",
+            MergeMode::ManagedBlock => "managed-block",
+        }
+    }
+}
+
+// Which way a client syncs. Also limits what an API token may do on the server.
+#[derive(clap::ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncDirection {
+    // Upload local keys, then write the server's keys to the file
+    #[default]
+    Both,
+    // Only upload local keys, the file is never written
+    Push,
+    // Only write the server's keys to the file, nothing is uploaded
+    Pull,
+}
+
+impl SyncDirection {
+    pub const ALL: [SyncDirection; 3] = [
+        SyncDirection::Both,
+        SyncDirection::Pull,
+        SyncDirection::Push,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SyncDirection::Both => "both",
+            SyncDirection::Push => "push",
+            SyncDirection::Pull => "pull",
+        }
+    }
+
+    pub fn pushes(&self) -> bool {
+        *self != SyncDirection::Pull
+    }
+
+    pub fn pulls(&self) -> bool {
+        *self != SyncDirection::Push
+    }
+}
+
+impl std::fmt::Display for SyncDirection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for SyncDirection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "both" => Ok(SyncDirection::Both),
+            "push" => Ok(SyncDirection::Push),
+            "pull" => Ok(SyncDirection::Pull),
+            other => Err(format!("Unknown sync direction: {}", other)),
+        }
+    }
+}
+
+// Provenance comments written above each flow's keys when syncing several flows
+const FLOW_COMMENT_PREFIX: &str = "# KHM flow: ";
+const LOCAL_ENTRIES_COMMENT: &str = "# KHM local entries";
+
+// `pattern=flow`: local hosts matching the pattern are uploaded to that flow
+#[derive(Clone, Debug)]
+pub struct FlowRule {
+    pattern: HostPattern,
+    flow: String,
+}
+
+impl FlowRule {
+    fn matches(&self, server: &str) -> bool {
+        server
+            .split(',')
+            .filter_map(|pattern| pattern.parse::<HostPattern>().ok())
+            .filter(|pattern| !pattern.negated && !pattern.is_hashed() && !pattern.has_wildcard())
+            .any(|host| self.pattern.matches(&host.host, host.port))
+    }
+}
+
+impl FromStr for FlowRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (pattern, flow) = s
+            .rsplit_once('=')
+            .ok_or_else(|| format!("Invalid flow rule '{}', expected pattern=flow", s))?;
+        let pattern: HostPattern = pattern.trim().parse()?;
+        if pattern.negated || pattern.is_hashed() {
+            return Err(format!(
+                "Invalid flow rule '{}': negated and hashed patterns cannot be used",
+                s
+            ));
+        }
+        let flow = flow.trim();
+        if flow.is_empty() {
+            return Err(format!("Invalid flow rule '{}': missing flow", s));
+        }
+        Ok(FlowRule {
+            pattern,
+            flow: flow.to_string(),
+        })
+    }
 }
 
+// Decide which flow each local key is uploaded to. A single flow gets every
+// key. With several flows, keys that some flow already serves are not sent
+// again, so keys pulled from one flow do not leak into another; the rest go
+// to the first matching rule, else to a flow that already knows the host,
+// else to the first flow.
+fn route_keys(
+    keys: Vec<SshKey>,
+    flows: &[String],
+    rules: &[FlowRule],
+    known: &[(String, Vec<SshKey>)],
+) -> Vec<(String, Vec<SshKey>)> {
+    let mut routed: Vec<(String, Vec<SshKey>)> = flows
+        .iter()
+        .map(|flow| (flow.clone(), Vec::new()))
+        .collect();
+    if flows.len() == 1 {
+        routed[0].1 = keys;
+        return routed;
+    }
+
+    for key in keys {
+        let served = known.iter().flat_map(|(_, keys)| keys).any(|known_key| {
+            known_key.server == key.server
+                && key_material(&known_key.public_key) == key_material(&key.public_key)
+        });
+        if served {
+            continue;
+        }
+
+        let flow = rules
+            .iter()
+            .find(|rule| rule.matches(&key.server))
+            .map(|rule| rule.flow.as_str())
+            .or_else(|| {
+                known
+                    .iter()
+                    .find(|(_, keys)| keys.iter().any(|known_key| known_key.server == key.server))
+                    .map(|(flow, _)| flow.as_str())
+            })
+            .unwrap_or(&flows[0])
+            .to_string();
+        if let Some((_, flow_keys)) = routed.iter_mut().find(|(name, _)| *name == flow) {
+            flow_keys.push(key);
+        }
+    }
+    routed
+}
+
+// How --dry-run reports the pending changes
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+// Exit status of --dry-run when the known_hosts file would change
+pub const DRY_RUN_CHANGES_EXIT_CODE: i32 = 2;
+
 pub fn read_known_hosts(file_path: &str) -> io::Result<Vec<SshKey>> {
-    let path = Path::new(file_path);
-    let file = File::open(&path)?;
-    let reader = io::BufReader::new(file);
+    let content = fs::read_to_string(file_path)?;
+    Ok(parse_known_hosts(&content))
+}
 
+fn parse_known_hosts(content: &str) -> Vec<SshKey> {
     let mut keys = Vec::new();
-    for line in reader.lines() {
+    for (number, line) in known_hosts::parse(content).iter().enumerate() {
         match line {
-            Ok(line) => {
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                if parts.len() >= 2 {
-                    let server = parts[0].to_string();
-                    let public_key = parts[1..].join(" ");
-                    keys.push(SshKey {
-                        server,
-                        public_key,
-                        deprecated: false, // Keys from known_hosts are not deprecated
-                    });
-                }
-            }
-            Err(e) => {
-                error!("Error reading line from known_hosts file: {}", e);
+            Line::Entry(entry) => keys.extend(SshKey::from_entry(entry)),
+            Line::Invalid { error, .. } => {
+                error!(
+                    "Skipping invalid line {} of known_hosts file: {}",
+                    number + 1,
+                    error
+                );
             }
+            Line::Comment(_) | Line::Blank => {}
         }
     }
     info!("Read {} keys from known_hosts file", keys.len());
-    Ok(keys)
+    keys
 }
 
-fn write_known_hosts(file_path: &str, keys: &[SshKey]) -> io::Result<()> {
-    let path = Path::new(file_path);
-    let mut file = File::create(&path)?;
+// The known_hosts file as it was before syncing
+struct LocalKnownHosts {
+    content: String,
+    keys: Vec<SshKey>,
+}
 
-    // Filter out deprecated keys - they should not be written to known_hosts
-    let active_keys: Vec<&SshKey> = keys.iter().filter(|key| !key.deprecated).collect();
-    let active_count = active_keys.len();
+// Lines for the keys returned by the server. Deprecated keys are left out;
+// revoked keys are deprecated too but are written as @revoked lines.
+fn server_lines(keys: &[SshKey], local_keys: &[SshKey], hash_hosts: bool) -> Vec<Line> {
+    let active_keys = keys
+        .iter()
+        .filter(|key| !key.deprecated || key.marker == Some(Marker::Revoked));
 
+    let mut lines = Vec::new();
     for key in active_keys {
-        writeln!(file, "{} {}", key.server, key.public_key)?;
+        let mut entry = match key.to_entry() {
+            Ok(entry) => entry,
+            Err(e) => {
+                error!("Skipping invalid key for '{}': {}", key.server, e);
+                continue;
+            }
+        };
+
+        // Keys the server has no comment for keep the local one
+        if entry.comment.is_none() {
+            entry.comment = local_keys
+                .iter()
+                .find(|local| local.server == key.server && local.public_key == key.public_key)
+                .and_then(|local| local.comment.clone());
+        }
+
+        let hashable = entry
+            .hosts
+            .iter()
+            .all(|pattern| !pattern.negated && !pattern.has_wildcard());
+        if hash_hosts && hashable {
+            // Like ssh-keygen -H, every name of a comma-separated host list gets its own line
+            for pattern in &entry.hosts {
+                let host = if pattern.is_hashed() {
+                    pattern.host.clone()
+                } else {
+                    hashed_host_name(&pattern.to_string(), &entry.public_key(), local_keys)
+                };
+                lines.push(Line::Entry(Entry {
+                    hosts: vec![HostPattern {
+                        negated: false,
+                        host,
+                        port: None,
+                    }],
+                    ..entry.clone()
+                }));
+            }
+        } else {
+            lines.push(Line::Entry(entry));
+        }
+    }
+    lines
+}
+
+// New content of the known_hosts file after syncing with the server. Lines
+// served by several flows are written once, for the first flow listed.
+fn merge_known_hosts(
+    mode: MergeMode,
+    server_keys: &[(String, Vec<SshKey>)],
+    local: &LocalKnownHosts,
+    hash_hosts: bool,
+) -> io::Result<String> {
+    let mut seen = HashSet::new();
+    let sections: Vec<(&str, Vec<Line>)> = server_keys
+        .iter()
+        .map(|(flow, keys)| {
+            let mut lines = server_lines(keys, &local.keys, hash_hosts);
+            lines.retain(|line| seen.insert(line.to_string()));
+            (flow.as_str(), lines)
+        })
+        .collect();
+
+    if mode == MergeMode::ManagedBlock {
+        let mut content = local.content.clone();
+        for (flow, lines) in &sections {
+            content = replace_managed_block(&content, flow, lines)?;
+        }
+        return Ok(content);
+    }
+
+    // With several flows every section starts with a comment naming its flow
+    let multi_flow = sections.len() > 1;
+    let mut lines = Vec::new();
+    for (flow, section) in sections {
+        if multi_flow && !section.is_empty() {
+            lines.push(Line::Comment(format!("{}{}", FLOW_COMMENT_PREFIX, flow)));
+        }
+        lines.extend(section);
+    }
+
+    let keys: Vec<&SshKey> = server_keys.iter().flat_map(|(_, keys)| keys).collect();
+    let revoked: Vec<&str> = keys
+        .iter()
+        .filter(|key| key.marker == Some(Marker::Revoked))
+        .map(|key| key_material(&key.public_key))
+        .collect();
+
+    // Union keeps marker lines and every entry for a host the server does not know
+    let local_entries: Vec<&SshKey> = match mode {
+        MergeMode::Replace | MergeMode::ManagedBlock => Vec::new(),
+        MergeMode::Union => local
+            .keys
+            .iter()
+            .filter(|local_key| {
+                local_key.marker.is_some()
+                    || !keys
+                        .iter()
+                        .any(|key| same_host(&local_key.server, &key.server))
+            })
+            .collect(),
+    };
+
+    if multi_flow && !local_entries.is_empty() {
+        lines.push(Line::Comment(LOCAL_ENTRIES_COMMENT.to_string()));
     }
+
+    // Local entries stay in the file unchanged, unless the server revoked their key
+    for key in local_entries {
+        if key.marker.is_none() && revoked.contains(&key_material(&key.public_key)) {
+            warn!(
+                "Dropping local entry for '{}': its key was revoked on the server",
+                key.server
+            );
+            continue;
+        }
+        if let Ok(entry) = key.to_entry() {
+            let line = Line::Entry(entry);
+            if !lines.contains(&line) {
+                lines.push(line);
+            }
+        }
+    }
+
+    Ok(known_hosts::serialize(&lines))
+}
+
+// A hashed local entry matches a server host when the host name hashes to it
+fn same_host(local_server: &str, server: &str) -> bool {
+    if known_hosts::is_hashed_host(local_server) && !known_hosts::is_hashed_host(server) {
+        known_hosts::hashed_host_matches(local_server, server)
+    } else {
+        local_server == server
+    }
+}
+
+// Swap the lines between the flow's BEGIN/END markers, or append the block,
+// leaving the rest of the file as it is
+fn replace_managed_block(content: &str, flow: &str, lines: &[Line]) -> io::Result<String> {
+    let begin = format!("# BEGIN KHM {}", flow);
+    let end = format!("# END KHM {}", flow);
+
+    let existing: Vec<&str> = content.lines().collect();
+    let begin_index = existing.iter().position(|line| line.trim() == begin);
+    let end_index = existing.iter().position(|line| line.trim() == end);
+
+    let mut block = vec![begin.clone()];
+    block.extend(lines.iter().map(|line| line.to_string()));
+    block.push(end.clone());
+
+    let merged: Vec<String> = match (begin_index, end_index) {
+        (Some(begin_index), Some(end_index)) if begin_index < end_index => existing[..begin_index]
+            .iter()
+            .map(|line| line.to_string())
+            .chain(block)
+            .chain(
+                existing[end_index + 1..]
+                    .iter()
+                    .map(|line| line.to_string()),
+            )
+            .collect(),
+        (None, None) => {
+            let mut merged: Vec<String> = existing.iter().map(|line| line.to_string()).collect();
+            if merged.last().is_some_and(|line| !line.trim().is_empty()) {
+                merged.push(String::new());
+            }
+            merged.extend(block);
+            merged
+        }
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "known_hosts has an unbalanced '{}' / '{}' block, fix it by hand",
+                    begin, end
+                ),
+            ));
+        }
+    };
+
+    let mut merged_content = merged.join("\n");
+    merged_content.push('\n');
+    Ok(merged_content)
+}
+
+// Backups are written next to the file as `<name>.khm-bak.<timestamp>`
+const BACKUP_INFIX: &str = ".khm-bak.";
+const BACKUP_TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%S%3fZ";
+pub const DEFAULT_BACKUP_COUNT: usize = 5;
+
+// Replace the file through a synced temp file and a rename, so a crash or a
+// full disk leaves either the old or the new file but never a truncated one.
+// The previous version is kept as a backup first.
+fn write_known_hosts(file_path: &str, content: &str, backups: usize) -> io::Result<()> {
+    // Write through symlinks instead of replacing them
+    let path = match fs::canonicalize(file_path) {
+        Ok(path) => path,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => PathBuf::from(file_path),
+        Err(e) => return Err(e),
+    };
+    let metadata = match fs::metadata(&path) {
+        Ok(metadata) => Some(metadata),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e),
+    };
+
+    if metadata.is_some() {
+        if fs::read_to_string(&path).is_ok_and(|existing| existing == content) {
+            info!("known_hosts file is up to date, nothing to write");
+            return Ok(());
+        }
+        if backups > 0 {
+            let backup = backup_known_hosts(&path)?;
+            info!("Saved previous known_hosts as {}", backup.display());
+            rotate_backups(&path, backups)?;
+        }
+    }
+
+    atomic_write(&path, content.as_bytes(), metadata.as_ref())?;
     info!(
-        "Wrote {} active keys to known_hosts file (filtered out deprecated keys)",
-        active_count
+        "Wrote {} lines to {}",
+        content.lines().count(),
+        path.display()
     );
 
     Ok(())
 }
 
+fn atomic_write(path: &Path, content: &[u8], metadata: Option<&fs::Metadata>) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid known_hosts path"))?
+        .to_string_lossy();
+    let temp_path = dir.join(format!(".{}.khm-tmp.{}", file_name, std::process::id()));
+
+    let result = (|| {
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)?;
+        if let Some(metadata) = metadata {
+            preserve_owner_and_mode(&file, metadata)?;
+        }
+        file.write_all(content)?;
+        file.sync_all()?;
+        fs::rename(&temp_path, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result?;
+
+    // Make the rename itself durable
+    #[cfg(unix)]
+    fs::File::open(dir)?.sync_all()?;
+
+    Ok(())
+}
+
+fn preserve_owner_and_mode(file: &fs::File, metadata: &fs::Metadata) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{fchown, MetadataExt};
+        // Only root can hand the file to another user; anyone else already owns it
+        if let Err(e) = fchown(file, Some(metadata.uid()), Some(metadata.gid())) {
+            warn!("Could not keep the owner of the known_hosts file: {}", e);
+        }
+    }
+    file.set_permissions(metadata.permissions())
+}
+
+fn backup_prefix(path: &Path) -> String {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    format!("{}{}", file_name, BACKUP_INFIX)
+}
+
+fn backup_known_hosts(path: &Path) -> io::Result<PathBuf> {
+    let timestamp = chrono::Utc::now().format(BACKUP_TIMESTAMP_FORMAT);
+    let backup = path.with_file_name(format!("{}{}", backup_prefix(path), timestamp));
+    // fs::copy keeps the permission bits, the backup holds the same data
+    fs::copy(path, &backup)?;
+    Ok(backup)
+}
+
+// Backups of the given known_hosts file, oldest first
+pub fn list_backups(file_path: &str) -> io::Result<Vec<PathBuf>> {
+    let path = fs::canonicalize(file_path).unwrap_or_else(|_| PathBuf::from(file_path));
+    let prefix = backup_prefix(&path);
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+
+    let mut backups = Vec::new();
+    for dir_entry in fs::read_dir(&dir)? {
+        let dir_entry = dir_entry?;
+        let name = dir_entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with(&prefix) && dir_entry.file_type()?.is_file() {
+            backups.push(dir_entry.path());
+        }
+    }
+    // Timestamps sort chronologically
+    backups.sort();
+    Ok(backups)
+}
+
+fn rotate_backups(path: &Path, keep: usize) -> io::Result<()> {
+    let backups = list_backups(&path.to_string_lossy())?;
+    let excess = backups.len().saturating_sub(keep);
+    for backup in &backups[..excess] {
+        fs::remove_file(backup)?;
+        info!("Removed old known_hosts backup {}", backup.display());
+    }
+    Ok(())
+}
+
+// Put a backup back in place. The newest one is used when none is named; a
+// name may be the backup's path, file name or timestamp. The current file is
+// backed up first, so a restore can itself be undone.
+pub fn restore_backup(file_path: &str, backup: Option<&str>, backups: usize) -> io::Result<()> {
+    let available = list_backups(file_path)?;
+    let selected = match backup {
+        None => available.last(),
+        Some(name) => available.iter().find(|path| {
+            path.as_os_str() == name
+                || path.file_name().is_some_and(|file_name| file_name == name)
+                || path
+                    .to_string_lossy()
+                    .ends_with(&format!("{}{}", BACKUP_INFIX, name))
+        }),
+    };
+    let selected = selected.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            match backup {
+                Some(name) => format!("No backup '{}' found for {}", name, file_path),
+                None => format!("No backups found for {}", file_path),
+            },
+        )
+    })?;
+
+    let content = fs::read_to_string(selected)?;
+    write_known_hosts(file_path, &content, backups)?;
+    info!("Restored {} from {}", file_path, selected.display());
+    Ok(())
+}
+
+// Keys of one host before and after a sync
+#[derive(Serialize, Debug)]
+pub struct HostKeyChange {
+    host: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    marker: Option<Marker>,
+    old_keys: Vec<String>,
+    new_keys: Vec<String>,
+}
+
+// What a sync would change in the known_hosts file
+#[derive(Serialize, Debug)]
+pub struct KnownHostsDiff {
+    known_hosts: String,
+    changes_pending: bool,
+    added: Vec<HostKeyChange>,
+    removed: Vec<HostKeyChange>,
+    changed: Vec<HostKeyChange>,
+    // Line-level diff of the file in unified format
+    diff: String,
+}
+
+impl KnownHostsDiff {
+    fn new(file_path: &str, old_content: &str, new_content: &str) -> Self {
+        let mut hosts: BTreeMap<(String, Option<Marker>), HostKeyChange> = BTreeMap::new();
+        let old_keys = parse_known_hosts(old_content);
+        let new_keys = parse_known_hosts(new_content);
+        for (keys, is_new) in [(&old_keys, false), (&new_keys, true)] {
+            for key in keys {
+                let change = hosts
+                    .entry((key.server.clone(), key.marker))
+                    .or_insert_with(|| HostKeyChange {
+                        host: key.server.clone(),
+                        marker: key.marker,
+                        old_keys: Vec::new(),
+                        new_keys: Vec::new(),
+                    });
+                let list = if is_new {
+                    &mut change.new_keys
+                } else {
+                    &mut change.old_keys
+                };
+                if !list.contains(&key.public_key) {
+                    list.push(key.public_key.clone());
+                }
+            }
+        }
+
+        let (mut added, mut removed, mut changed) = (Vec::new(), Vec::new(), Vec::new());
+        for mut change in hosts.into_values() {
+            change.old_keys.sort();
+            change.new_keys.sort();
+            if change.old_keys == change.new_keys {
+                continue;
+            }
+            if change.old_keys.is_empty() {
+                added.push(change);
+            } else if change.new_keys.is_empty() {
+                removed.push(change);
+            } else {
+                changed.push(change);
+            }
+        }
+
+        KnownHostsDiff {
+            known_hosts: file_path.to_string(),
+            changes_pending: old_content != new_content,
+            added,
+            removed,
+            changed,
+            diff: unified_diff(file_path, old_content, new_content),
+        }
+    }
+
+    pub fn changes_pending(&self) -> bool {
+        self.changes_pending
+    }
+
+    fn summary(&self) -> String {
+        let mut summary = format!(
+            "{}: {} host(s) added, {} removed, {} changed\n",
+            self.known_hosts,
+            self.added.len(),
+            self.removed.len(),
+            self.changed.len()
+        );
+        for (sign, changes) in [
+            ("+", &self.added),
+            ("-", &self.removed),
+            ("~", &self.changed),
+        ] {
+            for change in changes {
+                let host = match change.marker {
+                    Some(marker) => format!("{} {}", marker, change.host),
+                    None => change.host.clone(),
+                };
+                let keys = if change.new_keys.is_empty() {
+                    &change.old_keys
+                } else {
+                    &change.new_keys
+                };
+                summary.push_str(&format!("{} {} {}\n", sign, host, keys.join(", ")));
+            }
+        }
+        summary
+    }
+}
+
+// Editing steps between two files: the old and new line ranges of every
+// changed run, found through the longest common subsequence
+fn changed_runs(old: &[&str], new: &[&str]) -> Vec<(usize, usize, usize, usize)> {
+    // Common lines at both ends do not need the quadratic table
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+
+    // Very large rewrites are shown as one block instead of an exact diff
+    let mut pairs = Vec::new();
+    if old_mid.len().saturating_mul(new_mid.len()) <= 4_000_000 {
+        let mut lcs = vec![vec![0u32; new_mid.len() + 1]; old_mid.len() + 1];
+        for i in (0..old_mid.len()).rev() {
+            for j in (0..new_mid.len()).rev() {
+                lcs[i][j] = if old_mid[i] == new_mid[j] {
+                    lcs[i + 1][j + 1] + 1
+                } else {
+                    lcs[i + 1][j].max(lcs[i][j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < old_mid.len() && j < new_mid.len() {
+            if old_mid[i] == new_mid[j] {
+                pairs.push((prefix + i, prefix + j));
+                i += 1;
+                j += 1;
+            } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+    }
+    pairs.push((old.len() - suffix, new.len() - suffix));
+
+    let mut runs = Vec::new();
+    let (mut i, mut j) = (prefix, prefix);
+    for (next_i, next_j) in pairs {
+        if next_i > i || next_j > j {
+            runs.push((i, next_i, j, next_j));
+        }
+        i = next_i + 1;
+        j = next_j + 1;
+    }
+    runs
+}
+
+// Unified diff with three lines of context, as `diff -u` prints it
+fn unified_diff(file_path: &str, old_content: &str, new_content: &str) -> String {
+    const CONTEXT: usize = 3;
+    let old: Vec<&str> = old_content.lines().collect();
+    let new: Vec<&str> = new_content.lines().collect();
+    let runs = changed_runs(&old, &new);
+    if runs.is_empty() {
+        return String::new();
+    }
+
+    let mut diff = format!("--- {}\n+++ {} (after sync)\n", file_path, file_path);
+    let mut index = 0;
+    while index < runs.len() {
+        // Runs closer than twice the context share a hunk
+        let mut last = index;
+        while last + 1 < runs.len() && runs[last + 1].0 - runs[last].1 <= 2 * CONTEXT {
+            last += 1;
+        }
+        let (old_start, _, new_start, _) = runs[index];
+        let (_, old_end, _, new_end) = runs[last];
+        let before = old_start.min(CONTEXT);
+        let after = (old.len() - old_end).min(CONTEXT);
+        let (hunk_old, hunk_new) = (old_start - before, new_start - before);
+        let old_len = old_end + after - hunk_old;
+        let new_len = new_end + after - hunk_new;
+
+        // Empty ranges are numbered by the line before them
+        let old_line = if old_len == 0 { hunk_old } else { hunk_old + 1 };
+        let new_line = if new_len == 0 { hunk_new } else { hunk_new + 1 };
+        diff.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            old_line, old_len, new_line, new_len
+        ));
+
+        let mut position = hunk_old;
+        for &(old_from, old_to, new_from, new_to) in &runs[index..=last] {
+            for line in &old[position..old_from] {
+                diff.push_str(&format!(" {}\n", line));
+            }
+            for line in &old[old_from..old_to] {
+                diff.push_str(&format!("-{}\n", line));
+            }
+            for line in &new[new_from..new_to] {
+                diff.push_str(&format!("+{}\n", line));
+            }
+            position = old_to;
+        }
+        for line in &old[position..old_end + after] {
+            diff.push_str(&format!(" {}\n", line));
+        }
+        index = last + 1;
+    }
+    diff
+}
+
+fn print_diff(diff: &KnownHostsDiff, output: OutputFormat) -> io::Result<()> {
+    match output {
+        OutputFormat::Json => {
+            let json = serde_json::to_string_pretty(diff).map_err(io::Error::other)?;
+            println!("{}", json);
+        }
+        OutputFormat::Text => {
+            if !diff.changes_pending {
+                println!("{}: no changes", diff.known_hosts);
+                return Ok(());
+            }
+            print!("{}", diff.summary());
+            if !diff.diff.is_empty() {
+                println!();
+                print!("{}", diff.diff);
+            }
+        }
+    }
+    Ok(())
+}
+
+// Reuse the salt of an existing hashed entry for the same host and key so
+// the file does not change on every sync
+fn hashed_host_name(host: &str, public_key: &str, local_keys: &[SshKey]) -> String {
+    local_keys
+        .iter()
+        .find(|key| {
+            key.public_key == public_key && known_hosts::hashed_host_matches(&key.server, host)
+        })
+        .map(|key| key.server.clone())
+        .unwrap_or_else(|| known_hosts::hash_host(host))
+}
+
 // Get local hostname for request headers
 fn get_hostname() -> String {
     match hostname::get() {
@@ -71,15 +907,8 @@ fn get_hostname() -> String {
     }
 }
 
-async fn send_keys_to_server(
-    host: &str,
-    keys: Vec<SshKey>,
-    auth_string: &str,
-) -> Result<(), reqwest::Error> {
-    let client = Client::new();
-    let url = format!("{}/keys", host);
-    info!("URL: {} ", url);
-
+// Build request headers: client hostname plus bearer token or basic auth credentials
+fn build_headers(auth_string: &str, token: &str) -> HeaderMap {
     let mut headers = HeaderMap::new();
 
     // Add hostname header
@@ -93,7 +922,14 @@ async fn send_keys_to_server(
     );
     info!("Adding hostname header: {}", hostname);
 
-    if !auth_string.is_empty() {
+    if !token.is_empty() {
+        match HeaderValue::from_str(&format!("Bearer {}", token)) {
+            Ok(value) => {
+                headers.insert(AUTHORIZATION, value);
+            }
+            Err(_) => error!("Invalid API token format"),
+        }
+    } else if !auth_string.is_empty() {
         let parts: Vec<&str> = auth_string.splitn(2, ':').collect();
         if parts.len() == 2 {
             let username = parts[0];
@@ -109,6 +945,21 @@ async fn send_keys_to_server(
         }
     }
 
+    headers
+}
+
+async fn send_keys_to_server(
+    client: &Client,
+    host: &str,
+    keys: Vec<SshKey>,
+    auth_string: &str,
+    token: &str,
+) -> Result<(), reqwest::Error> {
+    let url = format!("{}/keys", host);
+    info!("URL: {} ", url);
+
+    let headers = build_headers(auth_string, token);
+
     let response = client
         .post(&url)
         .headers(headers)
@@ -118,6 +969,19 @@ async fn send_keys_to_server(
 
     if response.status().is_success() {
         info!("Keys successfully sent to server.");
+
+        let pending = response
+            .headers()
+            .get("X-Keys-Pending")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or(0);
+        if pending > 0 {
+            warn!(
+                "{} key(s) were quarantined by the server and await admin approval",
+                pending
+            );
+        }
     } else {
         error!(
             "Failed to send keys to server. Status: {}",
@@ -128,104 +992,524 @@ async fn send_keys_to_server(
     Ok(())
 }
 
-async fn get_keys_from_server(
-    host: &str,
-    auth_string: &str,
-) -> Result<Vec<SshKey>, reqwest::Error> {
-    let client = Client::new();
-    let url = format!("{}/keys", host);
+// Changes of a flow since a revision, as served by /{flow}/keys/changes
+#[derive(Deserialize, Debug)]
+struct KeyChanges {
+    revision: i64,
+    #[serde(default)]
+    full: bool,
+    #[serde(default)]
+    added: Vec<SshKey>,
+    #[serde(default)]
+    deprecated: Vec<SshKey>,
+    #[serde(default)]
+    deleted: Vec<SshKey>,
+}
 
-    let mut headers = HeaderMap::new();
+// Keys of a flow as of a server revision, kept between runs so that the next
+// run only asks for what changed
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct FlowCache {
+    revision: i64,
+    keys: Vec<SshKey>,
+}
 
-    // Add hostname header
-    let hostname = get_hostname();
-    headers.insert(
-        "X-Client-Hostname",
-        HeaderValue::from_str(&hostname).unwrap_or_else(|_| {
-            error!("Failed to create hostname header value");
-            HeaderValue::from_static("unknown-host")
-        }),
-    );
-    info!("Adding hostname header: {}", hostname);
+impl FlowCache {
+    fn apply(&mut self, changes: KeyChanges) {
+        self.revision = changes.revision;
+        if changes.full {
+            self.keys = changes.added;
+            return;
+        }
 
-    if !auth_string.is_empty() {
-        let parts: Vec<&str> = auth_string.splitn(2, ':').collect();
-        if parts.len() == 2 {
-            let username = parts[0];
-            let password = parts[1];
+        let same_key =
+            |a: &SshKey, b: &SshKey| a.server == b.server && a.public_key == b.public_key;
+        self.keys.retain(|key| {
+            !changes
+                .deprecated
+                .iter()
+                .chain(&changes.deleted)
+                .any(|gone| same_key(gone, key))
+        });
+        for key in changes.added {
+            match self.keys.iter_mut().find(|cached| same_key(cached, &key)) {
+                Some(cached) => *cached = key,
+                None => self.keys.push(key),
+            }
+        }
+        sort_as_served(&mut self.keys);
+    }
+}
 
-            let auth_header_value = format!("{}:{}", username, password);
-            let encoded_auth = general_purpose::STANDARD.encode(auth_header_value);
-            let auth_header = format!("Basic {}", encoded_auth);
+// The order of a full GET /{flow}/keys: keys by server and public key, then the
+// certificate authorities as the server listed them. The sort is stable and only
+// keys are ever added locally, so the CAs keep their order.
+fn sort_as_served(keys: &mut [SshKey]) {
+    let is_cert_authority = |key: &SshKey| key.marker == Some(Marker::CertAuthority);
+    keys.sort_by(|a, b| match (is_cert_authority(a), is_cert_authority(b)) {
+        (false, false) => (&a.server, &a.public_key).cmp(&(&b.server, &b.public_key)),
+        (a_is_ca, b_is_ca) => a_is_ca.cmp(&b_is_ca),
+    });
+}
 
-            headers.insert(AUTHORIZATION, HeaderValue::from_str(&auth_header).unwrap());
-        } else {
-            error!("Invalid auth string format. Expected 'username:password'");
+// For a dry run: the keys of each flow as they would be after the upload that was
+// skipped, with the keys the flows do not serve yet added. The server may still hold
+// some of them back for approval.
+fn add_uploads(server_keys: &mut [(String, Vec<SshKey>)], uploads: Vec<(String, Vec<SshKey>)>) {
+    for (flow, keys) in uploads {
+        let Some((_, served)) = server_keys.iter_mut().find(|(name, _)| *name == flow) else {
+            continue;
+        };
+        for key in keys {
+            let known = served.iter().any(|served_key| {
+                served_key.server == key.server
+                    && key_material(&served_key.public_key) == key_material(&key.public_key)
+            });
+            if !known {
+                served.push(key);
+            }
         }
+        sort_as_served(served);
     }
+}
+
+// ~/.khm/cache/<server and flow>.json
+fn flow_cache_path(host: &str, flow: &str) -> Option<PathBuf> {
+    let name: String = format!("{}_{}", host, flow)
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let mut path = dirs::home_dir()?;
+    path.push(".khm");
+    path.push("cache");
+    path.push(format!("{}.json", name));
+    Some(path)
+}
 
-    let response = client.get(&url).headers(headers).send().await?;
+fn load_flow_cache(path: &Path) -> Option<FlowCache> {
+    let content = fs::read_to_string(path).ok()?;
+    match serde_json::from_str(&content) {
+        Ok(cache) => Some(cache),
+        Err(e) => {
+            warn!("Ignoring unreadable cache {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+fn save_flow_cache(path: &Path, cache: &FlowCache) {
+    let result = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| serde_json::to_vec(cache).map_err(io::Error::other))
+        .and_then(|json| atomic_write(path, &json, None));
+    if let Err(e) = result {
+        warn!("Failed to write cache {}: {}", path.display(), e);
+    }
+}
+
+// The server tags responses with the flow revision
+fn etag_revision(response: &reqwest::Response) -> Option<i64> {
+    response
+        .headers()
+        .get(ETAG)?
+        .to_str()
+        .ok()?
+        .trim_start_matches("W/")
+        .trim_matches('"')
+        .parse()
+        .ok()
+}
+
+// Keys of a flow, fetched as a delta against the cached revision when there is one
+async fn get_keys_from_server(
+    client: &Client,
+    host: &str,
+    auth_string: &str,
+    token: &str,
+    cache: Option<FlowCache>,
+) -> Result<(Vec<SshKey>, Option<i64>), reqwest::Error> {
+    let headers = build_headers(auth_string, token);
+
+    if let Some(mut cache) = cache.clone() {
+        let url = format!("{}/keys/changes?since={}", host, cache.revision);
+        let response = client.get(&url).headers(headers.clone()).send().await?;
+        match response.status() {
+            StatusCode::NOT_MODIFIED => {
+                info!("Keys unchanged since revision {}", cache.revision);
+                return Ok((cache.keys, Some(cache.revision)));
+            }
+            status if status.is_success() => {
+                let changes: KeyChanges = response.json().await?;
+                info!(
+                    "Received changes since revision {}: {} added, {} deprecated, {} deleted{}",
+                    cache.revision,
+                    changes.added.len(),
+                    changes.deprecated.len(),
+                    changes.deleted.len(),
+                    if changes.full { " (full list)" } else { "" }
+                );
+                cache.apply(changes);
+                return Ok((cache.keys, Some(cache.revision)));
+            }
+            status => info!(
+                "Incremental sync not available ({}), fetching all keys",
+                status
+            ),
+        }
+    }
+
+    let url = format!("{}/keys", host);
+    let mut request = client.get(&url).headers(headers);
+    if let Some(cache) = &cache {
+        request = request.header(IF_NONE_MATCH, format!("\"{}\"", cache.revision));
+    }
+    let response = request.send().await?;
+    if response.status() == StatusCode::NOT_MODIFIED {
+        if let Some(cache) = cache {
+            info!("Keys unchanged since revision {}", cache.revision);
+            return Ok((cache.keys, Some(cache.revision)));
+        }
+    }
 
     let response = response.error_for_status()?;
+    let revision = etag_revision(&response);
 
     let keys: Vec<SshKey> = response.json().await?;
     info!("Received {} keys from server", keys.len());
-    Ok(keys)
+    Ok((keys, revision))
+}
+
+// Keys of every flow, in the order the flows were given
+async fn fetch_flows(
+    client: &Client,
+    host: &str,
+    flows: &[String],
+    auth_string: &str,
+    token: &str,
+) -> io::Result<Vec<(String, Vec<SshKey>)>> {
+    let mut server_keys = Vec::with_capacity(flows.len());
+    for flow in flows {
+        let url = format!("{}/{}", host, flow);
+        let cache_path = flow_cache_path(host, flow);
+        let cache = cache_path.as_deref().and_then(load_flow_cache);
+        match get_keys_from_server(client, &url, auth_string, token, cache).await {
+            Ok((keys, revision)) => {
+                if let (Some(path), Some(revision)) = (&cache_path, revision) {
+                    let cache = FlowCache {
+                        revision,
+                        keys: keys.clone(),
+                    };
+                    save_flow_cache(path, &cache);
+                }
+                server_keys.push((flow.clone(), keys));
+            }
+            Err(e) => {
+                error!("Failed to get keys of flow '{}' from server: {}", flow, e);
+                return Err(io::Error::other(format!("Network error: {}", e)));
+            }
+        }
+    }
+    Ok(server_keys)
 }
 
-pub async fn run_client(args: crate::Args) -> std::io::Result<()> {
+// Returns whether the known_hosts file changed, or with --dry-run whether it would change
+pub async fn run_client(args: crate::Args) -> std::io::Result<bool> {
     info!("Client mode: Reading known_hosts file");
 
-    let keys = match read_known_hosts(&args.known_hosts) {
-        Ok(keys) => keys,
+    let content = match fs::read_to_string(&args.known_hosts) {
+        Ok(content) => content,
         Err(e) => {
             if e.kind() == io::ErrorKind::NotFound {
                 info!(
                     "known_hosts file not found: {}. Starting with empty key list.",
                     args.known_hosts
                 );
-                Vec::new()
+                String::new()
             } else {
                 error!("Failed to read known_hosts file: {}", e);
                 return Err(e);
             }
         }
     };
+    let local_keys = parse_known_hosts(&content);
 
-    let host = args.host.expect("host is required in client mode");
-    let flow = args.flow.expect("flow is required in client mode");
-    let url = format!("{}/{}", host, flow);
+    // Hashed host names can only be matched by whoever knows the host name, so
+    // they are not uploaded unless explicitly requested, and marker lines never are
+    let (kept_local, keys): (Vec<SshKey>, Vec<SshKey>) =
+        local_keys.iter().cloned().partition(|key| {
+            key.marker.is_some()
+                || (!args.upload_hashed && known_hosts::is_hashed_host(&key.server))
+        });
+    let hashed_count = kept_local.iter().filter(|key| key.marker.is_none()).count();
+    if hashed_count > 0 {
+        info!(
+            "Skipping {} entries with hashed host names (use --upload-hashed to send them)",
+            hashed_count
+        );
+    }
+    let marker_count = kept_local.len() - hashed_count;
+    if marker_count > 0 {
+        info!(
+            "Not uploading {} @cert-authority/@revoked entries",
+            marker_count
+        );
+    }
+    let local = LocalKnownHosts {
+        content,
+        keys: local_keys,
+    };
 
-    info!("Client mode: Sending keys to server at {}", url);
+    let tls_options = ClientTlsOptions {
+        ca_cert: args.ca_cert.clone(),
+        client_cert: args.client_cert.clone(),
+        client_key: args.client_key.clone(),
+        pin_sha256: args.pin_sha256.clone(),
+    };
+    let client = match tls::http_client_builder(&tls_options)?.build() {
+        Ok(client) => client,
+        Err(e) => {
+            error!("Failed to create HTTP client: {}", e);
+            return Err(io::Error::other(format!("HTTP client error: {}", e)));
+        }
+    };
 
-    if let Err(e) = send_keys_to_server(&url, keys, &args.basic_auth).await {
-        error!("Failed to send keys to server: {}", e);
+    let host = args.host.expect("host is required in client mode");
+    let flows = args.flow;
+    if flows.is_empty() {
         return Err(io::Error::new(
-            io::ErrorKind::Other,
-            format!("Network error: {}", e),
+            io::ErrorKind::InvalidInput,
+            "At least one flow is required in client mode",
         ));
     }
+    let mut rules = Vec::new();
+    for rule in &args.flow_rule {
+        let rule: FlowRule = rule
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        if !flows.contains(&rule.flow) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Flow rule for '{}' targets flow '{}', which is not in --flow",
+                    rule.pattern, rule.flow
+                ),
+            ));
+        }
+        rules.push(rule);
+    }
 
-    if args.in_place {
-        info!("Client mode: In-place update is enabled. Fetching keys from server.");
-        let server_keys = match get_keys_from_server(&url, &args.basic_auth).await {
-            Ok(keys) => keys,
-            Err(e) => {
-                error!("Failed to get keys from server: {}", e);
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    format!("Network error: {}", e),
-                ));
-            }
+    let direction = if args.push_only {
+        SyncDirection::Push
+    } else if args.pull_only {
+        SyncDirection::Pull
+    } else {
+        SyncDirection::Both
+    };
+
+    // Routing keys between several flows needs to know what each flow already has.
+    // A push-only client never pulled keys, so none of them can leak between flows.
+    let known = if flows.len() > 1 && direction == SyncDirection::Both {
+        fetch_flows(&client, &host, &flows, &args.basic_auth, &args.token).await?
+    } else {
+        Vec::new()
+    };
+
+    let mut skipped_uploads = Vec::new();
+    for (flow, keys) in route_keys(keys, &flows, &rules, &known) {
+        let url = format!("{}/{}", host, flow);
+        if !direction.pushes() {
+            info!(
+                "Client mode: Pull-only, not sending {} keys to flow '{}'",
+                keys.len(),
+                flow
+            );
+            continue;
+        }
+        if args.dry_run {
+            info!(
+                "Client mode: Dry run, not sending {} keys to flow '{}'",
+                keys.len(),
+                flow
+            );
+            skipped_uploads.push((flow, keys));
+            continue;
+        }
+        if flows.len() > 1 && keys.is_empty() {
+            continue;
+        }
+
+        info!("Client mode: Sending keys to server at {}", url);
+
+        if let Err(e) =
+            send_keys_to_server(&client, &url, keys, &args.basic_auth, &args.token).await
+        {
+            error!("Failed to send keys to server: {}", e);
+            return Err(io::Error::other(format!("Network error: {}", e)));
+        }
+    }
+
+    let mut changed = false;
+    if direction.pulls() && (args.in_place || args.pull_only || args.dry_run) {
+        info!("Client mode: Fetching keys from server.");
+        // Nothing was uploaded in a dry run, so keys fetched for routing are still current
+        let mut server_keys = if args.dry_run && !known.is_empty() {
+            known
+        } else {
+            fetch_flows(&client, &host, &flows, &args.basic_auth, &args.token).await?
         };
+        // A dry run merges what the server would serve after the upload it skipped
+        add_uploads(&mut server_keys, skipped_uploads);
+
+        let merged =
+            match merge_known_hosts(args.merge_mode, &server_keys, &local, args.hash_known_hosts) {
+                Ok(merged) => merged,
+                Err(e) => {
+                    error!("Failed to merge known_hosts file: {}", e);
+                    return Err(e);
+                }
+            };
+        changed = merged != local.content;
 
-        info!("Client mode: Writing updated known_hosts file");
-        if let Err(e) = write_known_hosts(&args.known_hosts, &server_keys) {
-            error!("Failed to write known_hosts file: {}", e);
-            return Err(e);
+        if args.dry_run {
+            let diff = KnownHostsDiff::new(&args.known_hosts, &local.content, &merged);
+            print_diff(&diff, args.output)?;
+        } else {
+            info!(
+                "Client mode: Writing updated known_hosts file (merge mode: {})",
+                args.merge_mode.as_str()
+            );
+            if let Err(e) = write_known_hosts(&args.known_hosts, &merged, args.backups) {
+                error!("Failed to write known_hosts file: {}", e);
+                return Err(e);
+            }
         }
     }
 
     info!("Client mode: Finished operations");
+    Ok(changed)
+}
+
+pub const DEFAULT_POLL_INTERVAL_SECS: u64 = 300;
+
+// The server sends a keepalive well within this, so a silent stream is a dead one
+const EVENT_STREAM_TIMEOUT: Duration = Duration::from_secs(KEEPALIVE_INTERVAL.as_secs() * 3);
+
+// Follow the change events of every flow. The receiver gets a message whenever
+// a sync is due: when a flow's revision changes, and every poll interval while
+// its event stream is unavailable. Must be called from within a Tokio runtime.
+pub fn watch_flows(args: &crate::Args) -> io::Result<mpsc::Receiver<()>> {
+    let tls_options = ClientTlsOptions {
+        ca_cert: args.ca_cert.clone(),
+        client_cert: args.client_cert.clone(),
+        client_key: args.client_key.clone(),
+        pin_sha256: args.pin_sha256.clone(),
+    };
+    let client = tls::http_client_builder(&tls_options)?
+        .build()
+        .map_err(|e| io::Error::other(format!("HTTP client error: {}", e)))?;
+    let host = args.host.clone().expect("host is required in client mode");
+    let poll_interval = Duration::from_secs(args.poll_interval.max(1));
+
+    let (sender, receiver) = mpsc::channel(1);
+    for flow in &args.flow {
+        let url = format!("{}/{}/events", host, flow);
+        let headers = build_headers(&args.basic_auth, &args.token);
+        tokio::spawn(watch_flow(
+            client.clone(),
+            url,
+            headers,
+            poll_interval,
+            sender.clone(),
+        ));
+    }
+    Ok(receiver)
+}
+
+async fn watch_flow(
+    client: Client,
+    url: String,
+    headers: HeaderMap,
+    poll_interval: Duration,
+    sync: mpsc::Sender<()>,
+) {
+    let mut last_revision = None;
+    loop {
+        match follow_events(&client, &url, headers.clone(), &mut last_revision, &sync).await {
+            Ok(()) => warn!("Event stream {} closed by the server", url),
+            Err(e) => warn!("Event stream {} unavailable: {}", url, e),
+        }
+        if sync.is_closed() {
+            return;
+        }
+
+        // Changes made while disconnected are only picked up by a sync
+        info!(
+            "Polling instead, reconnecting to {} in {} seconds",
+            url,
+            poll_interval.as_secs()
+        );
+        let _ = sync.try_send(());
+        tokio::time::sleep(poll_interval).await;
+    }
+}
+
+async fn follow_events(
+    client: &Client,
+    url: &str,
+    headers: HeaderMap,
+    last_revision: &mut Option<i64>,
+    sync: &mpsc::Sender<()>,
+) -> io::Result<()> {
+    let mut response = client
+        .get(url)
+        .headers(headers)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(io::Error::other)?;
+    info!("Watching {} for changes", url);
+
+    let mut parser = SseParser::default();
+    loop {
+        let chunk = tokio::time::timeout(EVENT_STREAM_TIMEOUT, response.chunk())
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "no data from server"))?
+            .map_err(io::Error::other)?;
+        let Some(chunk) = chunk else {
+            return Ok(());
+        };
+
+        for event in parser.push(&chunk) {
+            if *last_revision != Some(event.revision) {
+                info!(
+                    "Flow '{}' is now at revision {}, syncing",
+                    event.flow, event.revision
+                );
+                *last_revision = Some(event.revision);
+                // A full channel already holds a pending sync
+                let _ = sync.try_send(());
+            }
+        }
+    }
+}
+
+// Sync with the server whenever one of the flows changes, until the process is stopped
+pub async fn watch_client(mut args: crate::Args) -> io::Result<()> {
+    args.in_place = true;
+    let mut changes = watch_flows(&args)?;
+
+    while changes.recv().await.is_some() {
+        while changes.try_recv().is_ok() {}
+        if let Err(e) = run_client(args.clone()).await {
+            error!("Sync failed: {}", e);
+        }
+    }
     Ok(())
 }
@@ -0,0 +1,221 @@
+use log::{error, info, warn, Level, Record};
+use notify::RecursiveMode;
+use notify_debouncer_mini::{new_debouncer, DebounceEventResult};
+use rand::Rng;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+use crate::client;
+
+// Retries after a failed sync start here and double up to the sync interval
+const MIN_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+// Every wait is stretched or shortened by up to this fraction, so that hosts
+// started together do not all hit the server at the same moment
+const JITTER: f64 = 0.1;
+
+// Let ssh finish writing before the file is read
+const FILE_DEBOUNCE: Duration = Duration::from_millis(500);
+
+// systemd connects stdout and stderr to the journal and says so in JOURNAL_STREAM
+pub fn logging_to_journal() -> bool {
+    std::env::var_os("JOURNAL_STREAM").is_some()
+}
+
+// The journal adds its own timestamps; `<N>` sets the priority (sd-daemon(3))
+pub fn format_for_journal(buf: &mut impl Write, record: &Record) -> io::Result<()> {
+    let priority = match record.level() {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    };
+    writeln!(buf, "<{}>{}: {}", priority, record.target(), record.args())
+}
+
+// Report state changes to systemd for Type=notify services; a no-op elsewhere
+#[cfg(unix)]
+fn sd_notify(state: &str) {
+    use std::os::unix::net::UnixDatagram;
+
+    let Some(socket_path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    let result = UnixDatagram::unbound().and_then(|socket| {
+        let socket_path = socket_path.to_string_lossy();
+        match socket_path.strip_prefix('@') {
+            #[cfg(target_os = "linux")]
+            Some(name) => {
+                use std::os::linux::net::SocketAddrExt;
+                let address = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+                socket.send_to_addr(state.as_bytes(), &address)
+            }
+            _ => socket.send_to(state.as_bytes(), socket_path.as_ref()),
+        }
+    });
+    if let Err(e) = result {
+        warn!("Failed to notify systemd: {}", e);
+    }
+}
+
+#[cfg(not(unix))]
+fn sd_notify(_state: &str) {}
+
+fn with_jitter(delay: Duration) -> Duration {
+    delay.mul_f64(rand::thread_rng().gen_range(1.0 - JITTER..=1.0 + JITTER))
+}
+
+// Wait before the next sync: the interval, or after failures an exponentially
+// growing delay capped at the interval
+fn next_delay(interval: Duration, failures: u32) -> Duration {
+    if failures == 0 {
+        return with_jitter(interval);
+    }
+    let backoff = MIN_RETRY_DELAY.saturating_mul(2u32.saturating_pow(failures - 1));
+    with_jitter(backoff.min(interval.max(MIN_RETRY_DELAY)))
+}
+
+// Watch the directory, since the file itself is replaced on every write
+fn watch_known_hosts(
+    path: &Path,
+) -> notify::Result<(
+    notify_debouncer_mini::Debouncer<notify::RecommendedWatcher>,
+    mpsc::Receiver<()>,
+)> {
+    let file_name = path.file_name().map(|name| name.to_os_string());
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+
+    let (sender, receiver) = mpsc::channel(1);
+    let mut debouncer = new_debouncer(FILE_DEBOUNCE, move |result: DebounceEventResult| {
+        if let Ok(events) = result {
+            if events
+                .iter()
+                .any(|event| event.path.file_name() == file_name.as_deref())
+            {
+                // A full channel already holds a pending push
+                let _ = sender.try_send(());
+            }
+        }
+    })?;
+    debouncer
+        .watcher()
+        .watch(&dir, RecursiveMode::NonRecursive)?;
+    Ok((debouncer, receiver))
+}
+
+// Sync on an interval until SIGTERM or SIGINT. New local entries are pushed as
+// soon as they are written, and with --watch the server's change events trigger
+// a sync as well.
+pub async fn run_daemon(args: crate::Args) -> io::Result<()> {
+    let interval = Duration::from_secs(args.poll_interval.max(1));
+    let known_hosts = PathBuf::from(&args.known_hosts);
+    let pushes = !args.pull_only;
+
+    let (_debouncer, mut local_changes) = if pushes {
+        match watch_known_hosts(&known_hosts) {
+            Ok((debouncer, receiver)) => {
+                info!("Watching {} for new entries", known_hosts.display());
+                (Some(debouncer), Some(receiver))
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to watch {}, new entries wait for the next sync: {}",
+                    known_hosts.display(),
+                    e
+                );
+                (None, None)
+            }
+        }
+    } else {
+        (None, None)
+    };
+
+    let mut server_changes = if args.watch {
+        Some(client::watch_flows(&args)?)
+    } else {
+        None
+    };
+
+    let shutdown = crate::server::shutdown_signal();
+    tokio::pin!(shutdown);
+
+    info!(
+        "Daemon started, syncing every {} seconds",
+        interval.as_secs()
+    );
+    let mut failures = 0;
+    let mut last_content = None;
+    let mut ready = false;
+    loop {
+        match client::run_client(args.clone()).await {
+            Ok(_) => {
+                failures = 0;
+                sd_notify("STATUS=Last sync succeeded");
+            }
+            Err(e) => {
+                failures += 1;
+                error!("Sync failed ({} in a row): {}", failures, e);
+                sd_notify(&format!("STATUS=Sync failed: {}", e));
+            }
+        }
+        // Our own writes should not count as new local entries
+        last_content = fs::read_to_string(&known_hosts).ok().or(last_content);
+        if !ready {
+            sd_notify("READY=1");
+            ready = true;
+        }
+
+        let delay = next_delay(interval, failures);
+        if failures > 0 {
+            info!("Retrying in {} seconds", delay.as_secs());
+        }
+        let sleep = tokio::time::sleep(delay);
+        tokio::pin!(sleep);
+        loop {
+            tokio::select! {
+                _ = &mut shutdown => {
+                    info!("Shutting down");
+                    sd_notify("STOPPING=1");
+                    return Ok(());
+                }
+                _ = &mut sleep => break,
+                Some(()) = recv(&mut server_changes) => {
+                    info!("Server reported a change, syncing");
+                    break;
+                }
+                Some(()) = recv(&mut local_changes) => {
+                    let content = fs::read_to_string(&known_hosts).ok();
+                    if content.is_some() && content != last_content {
+                        info!("{} changed, pushing new entries", known_hosts.display());
+                        push_local_entries(&args).await;
+                        last_content = content;
+                    }
+                }
+            }
+        }
+    }
+}
+
+async fn recv(receiver: &mut Option<mpsc::Receiver<()>>) -> Option<()> {
+    match receiver {
+        Some(receiver) => receiver.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+// Upload without touching the file. Keeping the sync direction keeps the routing
+// of keys between several flows the same as in a full sync.
+async fn push_local_entries(args: &crate::Args) {
+    let mut args = args.clone();
+    args.in_place = false;
+    args.watch = false;
+    if let Err(e) = client::run_client(args).await {
+        error!("Failed to push new entries: {}", e);
+    }
+}
@@ -1,10 +1,21 @@
-use crate::server::SshKey;
-use log::{error, info};
+use crate::audit::{Actor, AuditEntry, AuditQuery, FLOW_CONTENT_ACTIONS, KEY_ACTIONS};
+use crate::auth::{ApiToken, Grant, Role, TokenScope};
+use crate::client::SyncDirection;
+use crate::known_hosts::Marker;
+use crate::migrations::{self, AppliedMigration, Downgrade, Migration};
+use crate::server::{CertAuthority, Flow, FlowInfo, KeyPolicy, SshKey};
+use crate::storage::Storage;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use deadpool_postgres::{Manager, ManagerConfig, Object, Pool, RecyclingMethod, Runtime};
+use log::{error, info, warn};
 use std::collections::HashMap;
 use std::collections::HashSet;
-use tokio_postgres::tls::NoTlsStream;
-use tokio_postgres::Socket;
-use tokio_postgres::{Client, Connection, NoTls};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+use tokio_postgres::error::SqlState;
+use tokio_postgres::NoTls;
 
 // Structure for storing key processing statistics
 pub struct KeyInsertStats {
@@ -14,40 +25,155 @@ pub struct KeyInsertStats {
     pub key_id_map: Vec<(SshKey, i32)>, // Mapping of keys to their IDs in the database
 }
 
-// Simple database client that exits on connection errors
-pub struct DbClient {
-    client: Client,
+// Result of associating keys with a flow
+#[derive(Default)]
+pub struct FlowKeyStats {
+    pub added: usize,   // Number of new associations
+    pub pending: usize, // Number of those quarantined until an admin approves them
 }
 
-impl DbClient {
-    pub async fn connect(
-        connection_string: &str,
-    ) -> Result<(Self, Connection<Socket, NoTlsStream>), tokio_postgres::Error> {
-        info!("Connecting to database...");
-        let (client, connection) = tokio_postgres::connect(connection_string, NoTls).await?;
-        info!("Successfully connected to database");
+// Current rows behind part of the in-memory flows: the keys of some hosts in every
+// flow, and the certificate authorities and revisions of some flows
+pub struct FlowUpdate {
+    pub hosts: Vec<String>,
+    pub keys: Vec<(String, SshKey)>, // (flow, key)
+    pub flows: Vec<String>,
+    pub cert_authorities: Vec<CertAuthority>,
+    pub revisions: HashMap<String, i64>,
+}
+
+// Changes to a flow's metadata that are applied together, all or none
+#[derive(Clone, Debug, Default)]
+pub struct FlowChanges {
+    pub name: Option<String>,
+    pub description: Option<Option<String>>,
+    pub archived: Option<bool>,
+    pub key_policy: Option<KeyPolicy>,
+}
+
+// Flows from their revisions, keys and CAs. Registered flows are listed even while
+// empty, so their revision is known.
+pub(crate) fn group_flows(
+    revisions: HashMap<String, i64>,
+    keys: Vec<(String, SshKey)>,
+    cert_authorities: Vec<CertAuthority>,
+) -> Vec<Flow> {
+    let mut flows_map: HashMap<String, Flow> = revisions
+        .into_iter()
+        .map(|(name, revision)| {
+            let mut flow = Flow::empty(name.clone());
+            flow.revision = revision;
+            (name, flow)
+        })
+        .collect();
+
+    for (name, ssh_key) in keys {
+        flows_map
+            .entry(name.clone())
+            .or_insert_with(|| Flow::empty(name))
+            .servers
+            .push(ssh_key);
+    }
+    for cert_authority in cert_authorities {
+        flows_map
+            .entry(cert_authority.flow.clone())
+            .or_insert_with(|| Flow::empty(cert_authority.flow.clone()))
+            .cert_authorities
+            .push(cert_authority);
+    }
+    flows_map.into_values().collect()
+}
+
+// Giving up on a connection attempt or a free pooled connection after these
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const POOL_WAIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Retries of operations that never reached the database: 100 ms, 200 ms, 400 ms
+const MAX_ATTEMPTS: u32 = 4;
+const MIN_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+// Advisory lock taken while migrating, an arbitrary number ("khm")
+const MIGRATION_LOCK_ID: i64 = 0x6b686d;
+
+#[derive(Debug)]
+pub enum DbError {
+    // No connection could be made, so nothing was sent to the database
+    Unavailable(String),
+    Query(tokio_postgres::Error),
+    #[cfg(feature = "sqlite")]
+    Sqlite(rusqlite::Error),
+}
+
+impl DbError {
+    // The database could not be reached, as opposed to rejecting a statement
+    pub fn is_unavailable(&self) -> bool {
+        match self {
+            DbError::Unavailable(_) => true,
+            DbError::Query(e) => DbClient::is_connection_error(e),
+            #[cfg(feature = "sqlite")]
+            DbError::Sqlite(_) => false,
+        }
+    }
+
+    // Safe to run again: the statement never got to the database, or the database
+    // rolled it back because of a conflict with a concurrent one
+    fn is_retryable(&self) -> bool {
+        match self {
+            DbError::Unavailable(_) => true,
+            DbError::Query(e) => matches!(
+                e.code(),
+                Some(code) if *code == SqlState::T_R_SERIALIZATION_FAILURE
+                    || *code == SqlState::T_R_DEADLOCK_DETECTED
+            ),
+            #[cfg(feature = "sqlite")]
+            DbError::Sqlite(_) => false,
+        }
+    }
+}
+
+impl fmt::Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DbError::Unavailable(e) => write!(f, "database unavailable: {}", e),
+            DbError::Query(e) => e.fmt(f),
+            #[cfg(feature = "sqlite")]
+            DbError::Sqlite(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for DbError {}
+
+impl From<tokio_postgres::Error> for DbError {
+    fn from(e: tokio_postgres::Error) -> Self {
+        DbError::Query(e)
+    }
+}
 
-        Ok((DbClient { client }, connection))
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for DbError {
+    fn from(e: rusqlite::Error) -> Self {
+        DbError::Sqlite(e)
     }
+}
+
+// One pooled connection, checked out for a single operation
+pub struct DbClient {
+    client: Object,
+}
 
-    // Helper function to handle database errors - exits the application on connection errors
+impl DbClient {
+    // Log lost connections; the pool drops the broken one and connects again later
     fn handle_db_error<T>(
         result: Result<T, tokio_postgres::Error>,
         operation: &str,
     ) -> Result<T, tokio_postgres::Error> {
-        match result {
-            Ok(value) => Ok(value),
-            Err(e) => {
-                if Self::is_connection_error(&e) {
-                    error!("Database connection lost during {}: {}", operation, e);
-                    error!("Exiting application due to database connection failure");
-                    std::process::exit(1);
-                } else {
-                    // For non-connection errors, just return the error
-                    Err(e)
-                }
+        result.map_err(|e| {
+            if Self::is_connection_error(&e) {
+                error!("Database connection lost during {}: {}", operation, e);
             }
-        }
+            e
+        })
     }
 
     fn is_connection_error(error: &tokio_postgres::Error) -> bool {
@@ -61,117 +187,138 @@ impl DbClient {
             || error.as_db_error().is_none() // Non-database errors are often connection issues
     }
 
-    pub async fn initialize_schema(&self) -> Result<(), tokio_postgres::Error> {
-        info!("Checking and initializing database schema if needed");
-
-        // Check if tables exist by querying information_schema
-        let result = self
-            .client
-            .query(
-                "SELECT EXISTS (
-                    SELECT FROM information_schema.tables
-                    WHERE table_schema = 'public'
-                    AND table_name = 'keys'
-                ) AND EXISTS (
-                    SELECT FROM information_schema.tables
-                    WHERE table_schema = 'public'
-                    AND table_name = 'flows'
-                )",
-                &[],
-            )
-            .await;
-
-        let tables_exist = Self::handle_db_error(result, "checking table existence")?
-            .get(0)
-            .map(|row| row.get::<_, bool>(0))
-            .unwrap_or(false);
-
-        if !tables_exist {
-            info!("Database schema doesn't exist. Creating tables...");
+    // Run `operation` in a transaction, committed when it succeeds and rolled back
+    // when it fails
+    async fn in_transaction<T>(
+        &self,
+        context: &str,
+        operation: impl AsyncFn(&Self) -> Result<T, tokio_postgres::Error>,
+    ) -> Result<T, tokio_postgres::Error> {
+        let result = self.client.batch_execute("BEGIN").await;
+        Self::handle_db_error(result, &format!("starting transaction for {}", context))?;
+
+        match operation(self).await {
+            Ok(value) => {
+                let result = self.client.batch_execute("COMMIT").await;
+                Self::handle_db_error(result, &format!("committing {}", context))?;
+                Ok(value)
+            }
+            Err(e) => {
+                // A broken connection is dropped by the pool, the rollback is for the rest
+                let _ = self.client.batch_execute("ROLLBACK").await;
+                Self::handle_db_error(Err(e), context)
+            }
+        }
+    }
 
-            // Create the keys table
-            let result = self
+    // Run `operation` in a transaction holding the migration lock, so that servers
+    // started together apply each migration once. DDL is transactional in
+    // PostgreSQL: a failed migration leaves the schema as it was.
+    async fn in_migration_transaction<T>(
+        &self,
+        operation: impl AsyncFn(&Self) -> Result<T, tokio_postgres::Error>,
+    ) -> Result<T, tokio_postgres::Error> {
+        self.in_transaction("running migrations", async |client| {
+            client
                 .client
-                .execute(
-                    "CREATE TABLE IF NOT EXISTS public.keys (
-                        key_id SERIAL PRIMARY KEY,
-                        host VARCHAR(255) NOT NULL,
-                        key TEXT NOT NULL,
-                        updated TIMESTAMP WITH TIME ZONE NOT NULL,
-                        deprecated BOOLEAN NOT NULL DEFAULT FALSE,
-                        CONSTRAINT unique_host_key UNIQUE (host, key)
-                    )",
-                    &[],
-                )
-                .await;
-            Self::handle_db_error(result, "creating keys table")?;
-
-            // Create the flows table
-            let result = self
+                .execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK_ID])
+                .await?;
+            client
                 .client
-                .execute(
-                    "CREATE TABLE IF NOT EXISTS public.flows (
-                        flow_id SERIAL PRIMARY KEY,
+                .batch_execute(
+                    "CREATE TABLE IF NOT EXISTS public.schema_migrations (
+                        version BIGINT PRIMARY KEY,
                         name VARCHAR(255) NOT NULL,
-                        key_id INTEGER NOT NULL,
-                        CONSTRAINT fk_key
-                            FOREIGN KEY(key_id)
-                            REFERENCES public.keys(key_id)
-                            ON DELETE CASCADE,
-                        CONSTRAINT unique_flow_key UNIQUE (name, key_id)
+                        applied TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
                     )",
-                    &[],
                 )
-                .await;
-            Self::handle_db_error(result, "creating flows table")?;
-
-            // Create an index for faster lookups
-            let result = self
-                .client
-                .execute(
-                    "CREATE INDEX IF NOT EXISTS idx_flows_name ON public.flows(name)",
-                    &[],
-                )
-                .await;
-            Self::handle_db_error(result, "creating index")?;
-
-            info!("Database schema created successfully");
-        } else {
-            info!("Database schema already exists");
+                .await?;
+            operation(client).await
+        })
+        .await
+    }
 
-            // Check if deprecated column exists, add it if missing (migration)
-            let result = self
-                .client
-                .query(
-                    "SELECT EXISTS (
-                        SELECT FROM information_schema.columns
-                        WHERE table_schema = 'public'
-                        AND table_name = 'keys'
-                        AND column_name = 'deprecated'
-                    )",
-                    &[],
-                )
-                .await;
+    async fn query_applied_migrations(
+        &self,
+    ) -> Result<Vec<AppliedMigration>, tokio_postgres::Error> {
+        let rows = self
+            .client
+            .query(
+                "SELECT version, name, applied FROM public.schema_migrations ORDER BY version",
+                &[],
+            )
+            .await?;
+        Ok(rows
+            .iter()
+            .map(|row| AppliedMigration {
+                version: row.get(0),
+                name: row.get(1),
+                applied: row.get(2),
+            })
+            .collect())
+    }
 
-            let column_exists = Self::handle_db_error(result, "checking deprecated column")?
-                .get(0)
-                .map(|row| row.get::<_, bool>(0))
-                .unwrap_or(false);
+    // Migrations recorded in schema_migrations, none before the first run
+    pub async fn get_applied_migrations(
+        &self,
+    ) -> Result<Vec<AppliedMigration>, tokio_postgres::Error> {
+        let result = self
+            .client
+            .query(
+                "SELECT to_regclass('public.schema_migrations') IS NOT NULL",
+                &[],
+            )
+            .await;
+        let exists: bool = Self::handle_db_error(result, "checking schema_migrations")?[0].get(0);
+        if !exists {
+            return Ok(Vec::new());
+        }
+        let result = self.query_applied_migrations().await;
+        Self::handle_db_error(result, "getting applied migrations")
+    }
 
-            if !column_exists {
-                info!("Adding deprecated column to existing keys table...");
-                let result = self.client
+    // Apply every pending migration and return them
+    pub async fn migrate_up(&self) -> Result<Vec<&'static Migration>, tokio_postgres::Error> {
+        self.in_migration_transaction(async |client| {
+            let applied = client.query_applied_migrations().await?;
+            let pending = migrations::pending(migrations::POSTGRES, &applied);
+            for migration in &pending {
+                info!("Applying migration {}", migration.name);
+                client.client.batch_execute(migration.up).await?;
+                client
+                    .client
                     .execute(
-                        "ALTER TABLE public.keys ADD COLUMN deprecated BOOLEAN NOT NULL DEFAULT FALSE",
-                        &[],
+                        "INSERT INTO public.schema_migrations (version, name) VALUES ($1, $2)",
+                        &[&migration.version, &migration.name],
                     )
-                    .await;
-                Self::handle_db_error(result, "adding deprecated column")?;
-                info!("Migration completed: deprecated column added");
+                    .await?;
             }
-        }
+            Ok(pending)
+        })
+        .await
+    }
 
-        Ok(())
+    // Revert the newest applied migration
+    pub async fn migrate_down(&self) -> Result<Downgrade, tokio_postgres::Error> {
+        self.in_migration_transaction(async |client| {
+            let Some(newest) = client.query_applied_migrations().await?.pop() else {
+                return Ok(Downgrade::Empty);
+            };
+            let Some(migration) = migrations::find(migrations::POSTGRES, newest.version) else {
+                return Ok(Downgrade::Unknown(newest));
+            };
+            info!("Reverting migration {}", migration.name);
+            client.client.batch_execute(migration.down).await?;
+            client
+                .client
+                .execute(
+                    "DELETE FROM public.schema_migrations WHERE version = $1",
+                    &[&migration.version],
+                )
+                .await?;
+            Ok(Downgrade::Reverted(migration))
+        })
+        .await
     }
 
     pub async fn batch_insert_keys(
@@ -252,7 +399,7 @@ impl DbClient {
         // If there are keys to insert, perform the insertion
         if !keys_to_insert.is_empty() {
             let mut insert_sql =
-                String::from("INSERT INTO public.keys (host, key, updated) VALUES ");
+                String::from("INSERT INTO public.keys (host, key, comment, updated) VALUES ");
 
             let mut insert_params: Vec<&(dyn tokio_postgres::types::ToSql + Sync)> = Vec::new();
             let mut param_count = 1;
@@ -261,10 +408,16 @@ impl DbClient {
                 if i > 0 {
                     insert_sql.push_str(", ");
                 }
-                insert_sql.push_str(&format!("(${}, ${}, NOW())", param_count, param_count + 1));
+                insert_sql.push_str(&format!(
+                    "(${}, ${}, ${}, NOW())",
+                    param_count,
+                    param_count + 1,
+                    param_count + 2
+                ));
                 insert_params.push(&key.server);
                 insert_params.push(&key.public_key);
-                param_count += 2;
+                insert_params.push(&key.comment);
+                param_count += 3;
             }
 
             insert_sql.push_str(" RETURNING key_id, host, key");
@@ -314,10 +467,11 @@ impl DbClient {
         &self,
         flow_name: &str,
         key_ids: &[i32],
-    ) -> Result<usize, tokio_postgres::Error> {
+        actor: &Actor,
+    ) -> Result<FlowKeyStats, tokio_postgres::Error> {
         if key_ids.is_empty() {
             info!("No keys to associate with flow '{}'", flow_name);
-            return Ok(0);
+            return Ok(FlowKeyStats::default());
         }
 
         // First, check which associations already exist
@@ -360,104 +514,257 @@ impl DbClient {
                 key_ids.len(),
                 flow_name
             );
-            return Ok(0);
+            return Ok(FlowKeyStats::default());
         }
 
-        // Build SQL query with multiple values only for new associations
-        let mut sql = String::from("INSERT INTO public.flows (name, key_id) VALUES ");
+        // A key is quarantined when the flow policy asks for it: always under 'strict',
+        // and under 'tofu' when the host already has a different active key of the same type
+        let mut sql = String::from(
+            "WITH added AS (
+                 INSERT INTO public.flows (name, key_id, pending)
+                 SELECT $1::VARCHAR, k.key_id,
+                        CASE COALESCE(
+                            (SELECT key_policy FROM public.flow_metadata WHERE name = $1::VARCHAR),
+                            'tofu'
+                        )
+                            WHEN 'accept' THEN FALSE
+                            WHEN 'strict' THEN TRUE
+                            ELSE EXISTS (
+                                SELECT 1 FROM public.flows known
+                                INNER JOIN public.keys known_key ON known_key.key_id = known.key_id
+                                WHERE known.name = $1::VARCHAR
+                                AND NOT known.pending
+                                AND NOT known_key.deprecated
+                                AND known_key.host = k.host
+                                AND split_part(known_key.key, ' ', 1) = split_part(k.key, ' ', 1)
+                                AND known_key.key <> k.key
+                            )
+                        END
+                 FROM public.keys k
+                 WHERE k.key_id IN (",
+        );
 
         for i in 0..new_key_ids.len() {
             if i > 0 {
                 sql.push_str(", ");
             }
-            sql.push_str(&format!("($1, ${})", i + 2));
+            sql.push_str(&format!("${}", i + 2));
         }
 
-        sql.push_str(" ON CONFLICT (name, key_id) DO NOTHING");
+        // Every association that actually got created is recorded in the audit log
+        let actor_param = new_key_ids.len() + 2;
+        sql.push_str(&format!(
+            ")
+                 ON CONFLICT (name, key_id) DO NOTHING
+                 RETURNING key_id, pending
+             ), logged AS (
+                 INSERT INTO public.audit_log (action, flow, server, public_key, actor, client_hostname)
+                 SELECT CASE WHEN added.pending THEN 'key_quarantined' ELSE 'key_added' END,
+                        $1::VARCHAR, k.host, k.key, ${}, ${}
+                 FROM added INNER JOIN public.keys k ON k.key_id = added.key_id
+             )
+             SELECT COUNT(*), COUNT(*) FILTER (WHERE pending) FROM added",
+            actor_param,
+            actor_param + 1
+        ));
 
         // Prepare parameters for the query
         let mut insert_params: Vec<&(dyn tokio_postgres::types::ToSql + Sync)> =
-            Vec::with_capacity(new_key_ids.len() + 1);
+            Vec::with_capacity(new_key_ids.len() + 3);
         insert_params.push(&flow_name);
         for key_id in &new_key_ids {
             insert_params.push(*key_id);
         }
+        insert_params.push(&actor.user);
+        insert_params.push(&actor.client_hostname);
 
         // Execute query
-        let result = self.client.execute(&sql, &insert_params[..]).await;
-        let affected = Self::handle_db_error(result, "inserting flow associations")?;
+        let result = self.client.query_one(&sql, &insert_params[..]).await;
+        let row = Self::handle_db_error(result, "inserting flow associations")?;
 
-        let affected_usize = affected as usize;
+        let stats = FlowKeyStats {
+            added: row.get::<_, i64>(0) as usize,
+            pending: row.get::<_, i64>(1) as usize,
+        };
 
         info!(
-            "Added {} new key-flow associations for flow '{}' (skipped {} existing)",
-            affected_usize,
+            "Added {} new key-flow associations for flow '{}' ({} pending approval, skipped {} existing)",
+            stats.added,
             flow_name,
+            stats.pending,
             existing_associations.len()
         );
 
-        Ok(affected_usize)
+        Ok(stats)
     }
 
-    pub async fn get_keys_from_db(
+    // Revision of each flow (all of them without a filter): the id of the last audit
+    // row that changed what the flow serves
+    async fn get_flow_revisions(
         &self,
-    ) -> Result<Vec<crate::server::Flow>, tokio_postgres::Error> {
+        flow_names: Option<&[String]>,
+    ) -> Result<HashMap<String, i64>, tokio_postgres::Error> {
+        let result = self
+            .client
+            .query(
+                "SELECT m.name, (
+                     SELECT MAX(a.id) FROM public.audit_log a
+                     WHERE a.flow = m.name AND (a.action = ANY($1) OR a.action = ANY($2))
+                 )
+                 FROM public.flow_metadata m
+                 WHERE $3::TEXT[] IS NULL OR m.name = ANY($3)",
+                &[&KEY_ACTIONS, &FLOW_CONTENT_ACTIONS, &flow_names],
+            )
+            .await;
+        Ok(Self::handle_db_error(result, "getting flow revisions")?
+            .iter()
+            .map(|row| (row.get(0), row.get::<_, Option<i64>>(1).unwrap_or(0)))
+            .collect())
+    }
+
+    fn row_to_flow_key(row: &tokio_postgres::Row) -> (String, SshKey) {
+        let revoked: bool = row.get(5);
+        (
+            row.get(3),
+            SshKey {
+                server: row.get(0),
+                public_key: row.get(1),
+                deprecated: row.get(2),
+                pending: row.get(4),
+                marker: revoked.then_some(Marker::Revoked),
+                comment: row.get(6),
+            },
+        )
+    }
+
+    pub async fn get_keys_from_db(&self) -> Result<Vec<Flow>, tokio_postgres::Error> {
+        // Read before the keys, so a change racing with this refresh shows up again
+        // in the next revision instead of being lost
+        let revisions = self.get_flow_revisions(None).await?;
+
         let result = self.client.query(
-            "SELECT k.host, k.key, k.deprecated, f.name FROM public.keys k INNER JOIN public.flows f ON k.key_id = f.key_id",
+            "SELECT k.host, k.key, k.deprecated, f.name, f.pending, k.revoked, k.comment FROM public.keys k INNER JOIN public.flows f ON k.key_id = f.key_id",
             &[]
         ).await;
-        let rows = Self::handle_db_error(result, "getting keys from database")?;
-
-        let mut flows_map: HashMap<String, crate::server::Flow> = HashMap::new();
-
-        for row in rows {
-            let host: String = row.get(0);
-            let key: String = row.get(1);
-            let deprecated: bool = row.get(2);
-            let flow: String = row.get(3);
+        let keys = Self::handle_db_error(result, "getting keys from database")?
+            .iter()
+            .map(Self::row_to_flow_key)
+            .collect();
 
-            let ssh_key = SshKey {
-                server: host,
-                public_key: key,
-                deprecated,
-            };
+        let flows = group_flows(revisions, keys, self.get_cert_authorities().await?);
+        info!("Retrieved {} flows from database", flows.len());
+        Ok(flows)
+    }
 
-            if let Some(flow_entry) = flows_map.get_mut(&flow) {
-                flow_entry.servers.push(ssh_key);
-            } else {
-                flows_map.insert(
-                    flow.clone(),
-                    crate::server::Flow {
-                        name: flow,
-                        servers: vec![ssh_key],
-                    },
-                );
+    // Rows a change may have touched, for updating the in-memory flows without
+    // reading every key again
+    pub async fn get_flow_update(
+        &self,
+        flow_names: &[String],
+        hosts: &[String],
+    ) -> Result<FlowUpdate, tokio_postgres::Error> {
+        // A host's keys are shared by every flow linking them, so those flows moved too
+        let mut flow_names = flow_names.to_vec();
+        if !hosts.is_empty() {
+            let result = self
+                .client
+                .query(
+                    "SELECT DISTINCT f.name
+                     FROM public.flows f INNER JOIN public.keys k ON k.key_id = f.key_id
+                     WHERE k.host = ANY($1)",
+                    &[&hosts],
+                )
+                .await;
+            for row in Self::handle_db_error(result, "getting flows of changed hosts")? {
+                let name: String = row.get(0);
+                if !flow_names.contains(&name) {
+                    flow_names.push(name);
+                }
             }
         }
 
-        info!("Retrieved {} flows from database", flows_map.len());
-        Ok(flows_map.into_values().collect())
+        let revisions = self.get_flow_revisions(Some(&flow_names)).await?;
+
+        let keys = if hosts.is_empty() {
+            Vec::new()
+        } else {
+            let result = self
+                .client
+                .query(
+                    "SELECT k.host, k.key, k.deprecated, f.name, f.pending, k.revoked, k.comment
+                     FROM public.keys k INNER JOIN public.flows f ON k.key_id = f.key_id
+                     WHERE k.host = ANY($1)",
+                    &[&hosts],
+                )
+                .await;
+            Self::handle_db_error(result, "getting changed keys")?
+                .iter()
+                .map(Self::row_to_flow_key)
+                .collect()
+        };
+
+        let cert_authorities = if flow_names.is_empty() {
+            Vec::new()
+        } else {
+            let result = self
+                .client
+                .query(
+                    "SELECT ca_id, flow, hosts, public_key, comment, created
+                     FROM public.cert_authorities WHERE flow = ANY($1) ORDER BY ca_id",
+                    &[&flow_names],
+                )
+                .await;
+            Self::handle_db_error(result, "getting changed certificate authorities")?
+                .iter()
+                .map(Self::row_to_cert_authority)
+                .collect()
+        };
+
+        Ok(FlowUpdate {
+            hosts: hosts.to_vec(),
+            keys,
+            flows: flow_names,
+            cert_authorities,
+            revisions,
+        })
     }
 
     pub async fn deprecate_key_by_server(
         &self,
         server_name: &str,
         flow_name: &str,
+        actor: &Actor,
     ) -> Result<u64, tokio_postgres::Error> {
-        // Update keys to deprecated status for the given server
+        // Update keys to deprecated status for the given server. Keys are shared, so the
+        // change is logged for every flow linking them and moves each of their revisions
         let result = self
             .client
-            .execute(
-                "UPDATE public.keys
-                 SET deprecated = TRUE, updated = NOW()
-                 WHERE host = $1
-                 AND key_id IN (
-                     SELECT key_id FROM public.flows WHERE name = $2
-                 )",
-                &[&server_name, &flow_name],
+            .query_one(
+                "WITH changed AS (
+                     UPDATE public.keys
+                     SET deprecated = TRUE, updated = NOW()
+                     WHERE host = $1
+                     AND key_id IN (
+                         SELECT key_id FROM public.flows WHERE name = $2
+                     )
+                     RETURNING key_id, host, key
+                 ), logged AS (
+                     INSERT INTO public.audit_log
+                         (action, flow, server, public_key, actor, client_hostname, details)
+                     SELECT 'key_deprecated', f.name, c.host, c.key, $3, $4,
+                         CASE WHEN f.name = $2 THEN NULL ELSE 'changed in flow ' || $2 END
+                     FROM changed c INNER JOIN public.flows f ON f.key_id = c.key_id
+                 )
+                 SELECT COUNT(*) FROM changed",
+                &[
+                    &server_name,
+                    &flow_name,
+                    &actor.user,
+                    &actor.client_hostname,
+                ],
             )
             .await;
-        let affected = Self::handle_db_error(result, "deprecating key")?;
+        let affected = Self::handle_db_error(result, "deprecating key")?.get::<_, i64>(0) as u64;
 
         info!(
             "Deprecated {} key(s) for server '{}' in flow '{}'",
@@ -471,6 +778,7 @@ impl DbClient {
         &self,
         server_names: &[String],
         flow_name: &str,
+        actor: &Actor,
     ) -> Result<u64, tokio_postgres::Error> {
         if server_names.is_empty() {
             return Ok(0);
@@ -479,17 +787,33 @@ impl DbClient {
         // Update keys to deprecated status for multiple servers in one query
         let result = self
             .client
-            .execute(
-                "UPDATE public.keys
-                 SET deprecated = TRUE, updated = NOW()
-                 WHERE host = ANY($1)
-                 AND key_id IN (
-                     SELECT key_id FROM public.flows WHERE name = $2
-                 )",
-                &[&server_names, &flow_name],
+            .query_one(
+                "WITH changed AS (
+                     UPDATE public.keys
+                     SET deprecated = TRUE, updated = NOW()
+                     WHERE host = ANY($1)
+                     AND key_id IN (
+                         SELECT key_id FROM public.flows WHERE name = $2
+                     )
+                     RETURNING key_id, host, key
+                 ), logged AS (
+                     INSERT INTO public.audit_log
+                         (action, flow, server, public_key, actor, client_hostname, details)
+                     SELECT 'key_deprecated', f.name, c.host, c.key, $3, $4,
+                         CASE WHEN f.name = $2 THEN NULL ELSE 'changed in flow ' || $2 END
+                     FROM changed c INNER JOIN public.flows f ON f.key_id = c.key_id
+                 )
+                 SELECT COUNT(*) FROM changed",
+                &[
+                    &server_names,
+                    &flow_name,
+                    &actor.user,
+                    &actor.client_hostname,
+                ],
             )
             .await;
-        let affected = Self::handle_db_error(result, "bulk deprecating keys")?;
+        let affected =
+            Self::handle_db_error(result, "bulk deprecating keys")?.get::<_, i64>(0) as u64;
 
         info!(
             "Bulk deprecated {} key(s) for {} servers in flow '{}'",
@@ -505,6 +829,7 @@ impl DbClient {
         &self,
         server_names: &[String],
         flow_name: &str,
+        actor: &Actor,
     ) -> Result<u64, tokio_postgres::Error> {
         if server_names.is_empty() {
             return Ok(0);
@@ -513,18 +838,34 @@ impl DbClient {
         // Update keys to active status for multiple servers in one query
         let result = self
             .client
-            .execute(
-                "UPDATE public.keys
-                 SET deprecated = FALSE, updated = NOW()
-                 WHERE host = ANY($1)
-                 AND deprecated = TRUE
-                 AND key_id IN (
-                     SELECT key_id FROM public.flows WHERE name = $2
-                 )",
-                &[&server_names, &flow_name],
+            .query_one(
+                "WITH changed AS (
+                     UPDATE public.keys
+                     SET deprecated = FALSE, revoked = FALSE, updated = NOW()
+                     WHERE host = ANY($1)
+                     AND deprecated = TRUE
+                     AND key_id IN (
+                         SELECT key_id FROM public.flows WHERE name = $2
+                     )
+                     RETURNING key_id, host, key
+                 ), logged AS (
+                     INSERT INTO public.audit_log
+                         (action, flow, server, public_key, actor, client_hostname, details)
+                     SELECT 'key_restored', f.name, c.host, c.key, $3, $4,
+                         CASE WHEN f.name = $2 THEN NULL ELSE 'changed in flow ' || $2 END
+                     FROM changed c INNER JOIN public.flows f ON f.key_id = c.key_id
+                 )
+                 SELECT COUNT(*) FROM changed",
+                &[
+                    &server_names,
+                    &flow_name,
+                    &actor.user,
+                    &actor.client_hostname,
+                ],
             )
             .await;
-        let affected = Self::handle_db_error(result, "bulk restoring keys")?;
+        let affected =
+            Self::handle_db_error(result, "bulk restoring keys")?.get::<_, i64>(0) as u64;
 
         info!(
             "Bulk restored {} key(s) for {} servers in flow '{}'",
@@ -540,22 +881,38 @@ impl DbClient {
         &self,
         server_name: &str,
         flow_name: &str,
+        actor: &Actor,
     ) -> Result<u64, tokio_postgres::Error> {
         // Update keys to active status for the given server in the flow
         let result = self
             .client
-            .execute(
-                "UPDATE public.keys
-                 SET deprecated = FALSE, updated = NOW()
-                 WHERE host = $1
-                 AND deprecated = TRUE
-                 AND key_id IN (
-                     SELECT key_id FROM public.flows WHERE name = $2
-                 )",
-                &[&server_name, &flow_name],
+            .query_one(
+                "WITH changed AS (
+                     UPDATE public.keys
+                     SET deprecated = FALSE, revoked = FALSE, updated = NOW()
+                     WHERE host = $1
+                     AND deprecated = TRUE
+                     AND key_id IN (
+                         SELECT key_id FROM public.flows WHERE name = $2
+                     )
+                     RETURNING key_id, host, key
+                 ), logged AS (
+                     INSERT INTO public.audit_log
+                         (action, flow, server, public_key, actor, client_hostname, details)
+                     SELECT 'key_restored', f.name, c.host, c.key, $3, $4,
+                         CASE WHEN f.name = $2 THEN NULL ELSE 'changed in flow ' || $2 END
+                     FROM changed c INNER JOIN public.flows f ON f.key_id = c.key_id
+                 )
+                 SELECT COUNT(*) FROM changed",
+                &[
+                    &server_name,
+                    &flow_name,
+                    &actor.user,
+                    &actor.client_hostname,
+                ],
             )
             .await;
-        let affected = Self::handle_db_error(result, "restoring key")?;
+        let affected = Self::handle_db_error(result, "restoring key")?.get::<_, i64>(0) as u64;
 
         info!(
             "Restored {} key(s) for server '{}' in flow '{}'",
@@ -569,66 +926,37 @@ impl DbClient {
         &self,
         server_name: &str,
         flow_name: &str,
+        actor: &Actor,
     ) -> Result<u64, tokio_postgres::Error> {
-        // First, find the key_ids for the given server in the flow
+        // Single statement: drop the flow associations, delete keys no other flow references
+        // and record the deletion, so the audit log can never miss a removed key. Other
+        // flows keep their links and the key, so only this flow is logged.
         let result = self
             .client
-            .query(
-                "SELECT k.key_id FROM public.keys k
-                 INNER JOIN public.flows f ON k.key_id = f.key_id
-                 WHERE k.host = $1 AND f.name = $2",
-                &[&server_name, &flow_name],
+            .query_one(
+                "WITH removed AS (
+                     DELETE FROM public.flows f
+                     USING public.keys k
+                     WHERE f.key_id = k.key_id AND k.host = $1 AND f.name = $2
+                     RETURNING f.key_id, k.host, k.key
+                 ), orphaned AS (
+                     DELETE FROM public.keys k
+                     WHERE k.key_id IN (SELECT key_id FROM removed)
+                     AND NOT EXISTS (
+                         SELECT 1 FROM public.flows f WHERE f.key_id = k.key_id AND f.name <> $2
+                     )
+                     RETURNING k.key_id
+                 ), logged AS (
+                     INSERT INTO public.audit_log (action, flow, server, public_key, actor, client_hostname)
+                     SELECT 'key_deleted', $2, host, key, $3, $4 FROM removed
+                 )
+                 SELECT (SELECT COUNT(*) FROM removed), (SELECT COUNT(*) FROM orphaned)",
+                &[&server_name, &flow_name, &actor.user, &actor.client_hostname],
             )
             .await;
-        let key_rows = Self::handle_db_error(result, "finding keys to delete")?;
-
-        if key_rows.is_empty() {
-            return Ok(0);
-        }
-
-        let key_ids: Vec<i32> = key_rows.iter().map(|row| row.get::<_, i32>(0)).collect();
-
-        // Delete flow associations first
-        let mut flow_delete_count = 0;
-        for key_id in &key_ids {
-            let result = self
-                .client
-                .execute(
-                    "DELETE FROM public.flows WHERE name = $1 AND key_id = $2",
-                    &[&flow_name, key_id],
-                )
-                .await;
-            let deleted = Self::handle_db_error(result, "deleting flow association")?;
-            flow_delete_count += deleted;
-        }
-
-        // Check if any of these keys are used in other flows
-        let mut keys_to_delete = Vec::new();
-        for key_id in &key_ids {
-            let result = self
-                .client
-                .query_one(
-                    "SELECT COUNT(*) FROM public.flows WHERE key_id = $1",
-                    &[key_id],
-                )
-                .await;
-            let count: i64 = Self::handle_db_error(result, "checking key references")?.get(0);
-
-            if count == 0 {
-                keys_to_delete.push(*key_id);
-            }
-        }
-
-        // Permanently delete keys that are no longer referenced by any flow
-        let mut total_deleted = 0;
-        for key_id in keys_to_delete {
-            let result = self
-                .client
-                .execute("DELETE FROM public.keys WHERE key_id = $1", &[&key_id])
-                .await;
-            let deleted = Self::handle_db_error(result, "deleting key")?;
-            total_deleted += deleted;
-        }
+        let row = Self::handle_db_error(result, "deleting keys")?;
+        let flow_delete_count = row.get::<_, i64>(0) as u64;
+        let total_deleted = row.get::<_, i64>(1) as u64;
 
         info!(
             "Permanently deleted {} flow associations and {} orphaned keys for server '{}' in flow '{}'",
@@ -637,139 +965,1235 @@ impl DbClient {
 
         Ok(std::cmp::max(flow_delete_count, total_deleted))
     }
-}
 
-// Compatibility wrapper for transition
-pub struct ReconnectingDbClient {
-    inner: Option<DbClient>,
-}
+    pub async fn get_grants(&self) -> Result<Vec<Grant>, tokio_postgres::Error> {
+        let result = self
+            .client
+            .query(
+                "SELECT u.username, g.flow, g.role FROM public.grants g
+                 INNER JOIN public.users u ON g.user_id = u.user_id
+                 ORDER BY u.username, g.flow",
+                &[],
+            )
+            .await;
+        let rows = Self::handle_db_error(result, "getting grants")?;
 
-impl ReconnectingDbClient {
-    pub fn new(_connection_string: String) -> Self {
-        Self { inner: None }
+        let mut grants = Vec::with_capacity(rows.len());
+        for row in rows {
+            let username: String = row.get(0);
+            let flow: String = row.get(1);
+            let role: String = row.get(2);
+
+            match role.parse::<Role>() {
+                Ok(role) => grants.push(Grant {
+                    username,
+                    flow,
+                    role,
+                }),
+                Err(e) => error!("Ignoring grant for '{}' on '{}': {}", username, flow, e),
+            }
+        }
+
+        info!("Retrieved {} grants from database", grants.len());
+        Ok(grants)
     }
 
-    pub async fn connect(&mut self, connection_string: &str) -> Result<(), tokio_postgres::Error> {
-        let (client, connection) = DbClient::connect(connection_string).await?;
+    pub async fn set_grant(
+        &self,
+        username: &str,
+        flow_name: &str,
+        role: Role,
+        actor: &Actor,
+    ) -> Result<(), tokio_postgres::Error> {
+        let result = self
+            .client
+            .execute(
+                "INSERT INTO public.users (username) VALUES ($1)
+                 ON CONFLICT (username) DO NOTHING",
+                &[&username],
+            )
+            .await;
+        Self::handle_db_error(result, "creating user")?;
 
-        // Spawn connection handler that will exit on error
-        tokio::spawn(async move {
-            if let Err(e) = connection.await {
-                error!("Database connection error: {}", e);
-                error!("Exiting application due to database connection failure");
-                std::process::exit(1);
-            }
-        });
+        // Re-granting the same role (e.g. --admin-users on every start) is not logged again
+        let result = self
+            .client
+            .execute(
+                "WITH granted AS (
+                     INSERT INTO public.grants (user_id, flow, role)
+                     SELECT user_id, $2, $3 FROM public.users WHERE username = $1
+                     ON CONFLICT (user_id, flow) DO UPDATE SET role = EXCLUDED.role
+                     WHERE public.grants.role <> EXCLUDED.role
+                     RETURNING flow
+                 )
+                 INSERT INTO public.audit_log (action, flow, actor, client_hostname, details)
+                 SELECT 'grant_set', flow, $4, $5, $1 || '=' || $3 FROM granted",
+                &[
+                    &username,
+                    &flow_name,
+                    &role.as_str(),
+                    &actor.user,
+                    &actor.client_hostname,
+                ],
+            )
+            .await;
+        Self::handle_db_error(result, "setting grant")?;
 
-        self.inner = Some(client);
+        info!(
+            "Granted '{}' role on flow '{}' to user '{}'",
+            role, flow_name, username
+        );
         Ok(())
     }
 
-    pub async fn initialize_schema(&self) -> Result<(), tokio_postgres::Error> {
-        match &self.inner {
-            Some(client) => client.initialize_schema().await,
-            None => panic!("Database client not initialized"),
-        }
+    pub async fn revoke_grant(
+        &self,
+        username: &str,
+        flow_name: &str,
+        actor: &Actor,
+    ) -> Result<u64, tokio_postgres::Error> {
+        let result = self
+            .client
+            .execute(
+                "WITH revoked AS (
+                     DELETE FROM public.grants
+                     WHERE flow = $2
+                     AND user_id IN (SELECT user_id FROM public.users WHERE username = $1)
+                     RETURNING flow, role
+                 )
+                 INSERT INTO public.audit_log (action, flow, actor, client_hostname, details)
+                 SELECT 'grant_revoked', flow, $3, $4, $1 || '=' || role FROM revoked",
+                &[&username, &flow_name, &actor.user, &actor.client_hostname],
+            )
+            .await;
+        let affected = Self::handle_db_error(result, "revoking grant")?;
+
+        info!(
+            "Revoked {} grant(s) on flow '{}' from user '{}'",
+            affected, flow_name, username
+        );
+        Ok(affected)
     }
 
-    pub async fn batch_insert_keys_reconnecting(
-        &self,
-        keys: Vec<SshKey>,
-    ) -> Result<KeyInsertStats, tokio_postgres::Error> {
-        match &self.inner {
-            Some(client) => client.batch_insert_keys(&keys).await,
-            None => panic!("Database client not initialized"),
+    pub async fn get_api_tokens(&self) -> Result<Vec<ApiToken>, tokio_postgres::Error> {
+        let result = self
+            .client
+            .query(
+                "SELECT token_id, name, token_hash, flows, role, created_by, created, expires, last_used, last_hostname, direction
+                 FROM public.api_tokens ORDER BY token_id",
+                &[],
+            )
+            .await;
+        let rows = Self::handle_db_error(result, "getting API tokens")?;
+
+        let mut tokens = Vec::with_capacity(rows.len());
+        for row in rows {
+            let name: String = row.get(1);
+            let role: String = row.get(4);
+            let direction: String = row.get(10);
+
+            match (role.parse::<Role>(), direction.parse::<SyncDirection>()) {
+                (Ok(role), Ok(direction)) => tokens.push(ApiToken {
+                    id: row.get(0),
+                    name,
+                    token_hash: row.get(2),
+                    flows: row.get(3),
+                    role,
+                    direction,
+                    created_by: row.get(5),
+                    created: row.get(6),
+                    expires: row.get(7),
+                    last_used: row.get(8),
+                    last_hostname: row.get(9),
+                }),
+                (Err(e), _) | (_, Err(e)) => error!("Ignoring API token '{}': {}", name, e),
+            }
         }
+
+        info!("Retrieved {} API tokens from database", tokens.len());
+        Ok(tokens)
     }
 
-    pub async fn batch_insert_flow_keys_reconnecting(
+    pub async fn create_api_token(
         &self,
-        flow_name: String,
-        key_ids: Vec<i32>,
-    ) -> Result<usize, tokio_postgres::Error> {
-        match &self.inner {
-            Some(client) => client.batch_insert_flow_keys(&flow_name, &key_ids).await,
-            None => panic!("Database client not initialized"),
-        }
+        name: &str,
+        token_hash: &str,
+        scope: &TokenScope,
+        expires: Option<DateTime<Utc>>,
+        actor: &Actor,
+    ) -> Result<i32, tokio_postgres::Error> {
+        let result = self
+            .client
+            .query_one(
+                "WITH created AS (
+                     INSERT INTO public.api_tokens (name, token_hash, flows, role, created_by, expires, direction)
+                     VALUES ($1, $2, $3, $4, $5, $6, $8)
+                     RETURNING token_id
+                 ), logged AS (
+                     INSERT INTO public.audit_log (action, flow, actor, client_hostname, details)
+                     SELECT 'token_created', flow, $5, $7,
+                            'token ' || created.token_id || ' (' || $1 || ', ' || $4
+                            || CASE WHEN $8 = 'both' THEN '' ELSE ', ' || $8 || '-only' END || ')'
+                     FROM created, UNNEST($3::TEXT[]) AS flow
+                 )
+                 SELECT token_id FROM created",
+                &[
+                    &name,
+                    &token_hash,
+                    &scope.flows,
+                    &scope.role.as_str(),
+                    &actor.user,
+                    &expires,
+                    &actor.client_hostname,
+                    &scope.direction.as_str(),
+                ],
+            )
+            .await;
+        let token_id: i32 = Self::handle_db_error(result, "creating API token")?.get(0);
+
+        info!(
+            "Created API token '{}' (id {}) with '{}' role ({}) on flows {:?}",
+            name, token_id, scope.role, scope.direction, scope.flows
+        );
+        Ok(token_id)
     }
 
-    pub async fn get_keys_from_db_reconnecting(
+    pub async fn revoke_api_token(
         &self,
-    ) -> Result<Vec<crate::server::Flow>, tokio_postgres::Error> {
-        match &self.inner {
-            Some(client) => client.get_keys_from_db().await,
-            None => panic!("Database client not initialized"),
-        }
+        token_id: i32,
+        actor: &Actor,
+    ) -> Result<u64, tokio_postgres::Error> {
+        let result = self
+            .client
+            .query_one(
+                "WITH revoked AS (
+                     DELETE FROM public.api_tokens WHERE token_id = $1
+                     RETURNING token_id, name, flows, role
+                 ), logged AS (
+                     INSERT INTO public.audit_log (action, flow, actor, client_hostname, details)
+                     SELECT 'token_revoked', flow, $2, $3,
+                            'token ' || revoked.token_id || ' (' || name || ', ' || role || ')'
+                     FROM revoked, UNNEST(revoked.flows) AS flow
+                 )
+                 SELECT COUNT(*) FROM revoked",
+                &[&token_id, &actor.user, &actor.client_hostname],
+            )
+            .await
+            .map(|row| row.get::<_, i64>(0) as u64);
+        let affected = Self::handle_db_error(result, "revoking API token")?;
+
+        info!("Revoked {} API token(s) with id {}", affected, token_id);
+        Ok(affected)
     }
 
-    pub async fn deprecate_key_by_server_reconnecting(
+    pub async fn touch_api_token(
         &self,
-        server_name: String,
-        flow_name: String,
-    ) -> Result<u64, tokio_postgres::Error> {
-        match &self.inner {
-            Some(client) => {
+        token_id: i32,
+        hostname: &str,
+    ) -> Result<(), tokio_postgres::Error> {
+        let result = self
+            .client
+            .execute(
+                "UPDATE public.api_tokens SET last_used = NOW(), last_hostname = $2
+                 WHERE token_id = $1",
+                &[&token_id, &hostname],
+            )
+            .await;
+        Self::handle_db_error(result, "recording API token usage")?;
+        Ok(())
+    }
+
+    pub async fn get_flow_metadata(&self) -> Result<Vec<FlowInfo>, tokio_postgres::Error> {
+        let result = self
+            .client
+            .query(
+                "SELECT name, description, archived, key_policy, created
+                 FROM public.flow_metadata ORDER BY name",
+                &[],
+            )
+            .await;
+        let rows = Self::handle_db_error(result, "getting flow metadata")?;
+
+        let flows: Vec<FlowInfo> = rows
+            .iter()
+            .map(|row| {
+                let name: String = row.get(0);
+                let key_policy: String = row.get(3);
+                FlowInfo {
+                    key_policy: key_policy.parse().unwrap_or_else(|e| {
+                        error!("Flow '{}': {}, using the default policy", name, e);
+                        KeyPolicy::default()
+                    }),
+                    name,
+                    description: row.get(1),
+                    archived: row.get(2),
+                    created: row.get(4),
+                }
+            })
+            .collect();
+
+        info!("Retrieved metadata for {} flows from database", flows.len());
+        Ok(flows)
+    }
+
+    pub async fn create_flow(
+        &self,
+        flow_name: &str,
+        description: Option<&str>,
+        actor: &Actor,
+    ) -> Result<bool, tokio_postgres::Error> {
+        let result = self
+            .client
+            .execute(
+                "WITH created AS (
+                     INSERT INTO public.flow_metadata (name, description) VALUES ($1, $2)
+                     ON CONFLICT (name) DO NOTHING
+                     RETURNING name, description
+                 )
+                 INSERT INTO public.audit_log (action, flow, actor, client_hostname, details)
+                 SELECT 'flow_created', name, $3, $4, description FROM created",
+                &[
+                    &flow_name,
+                    &description,
+                    &actor.user,
+                    &actor.client_hostname,
+                ],
+            )
+            .await;
+        let affected = Self::handle_db_error(result, "creating flow")?;
+
+        if affected > 0 {
+            info!("Created flow '{}'", flow_name);
+        }
+        Ok(affected > 0)
+    }
+
+    // Apply all changes in one transaction, so a failing one leaves the flow as it was.
+    // False, with nothing changed, when the new name is already taken.
+    pub async fn update_flow(
+        &self,
+        flow_name: &str,
+        changes: &FlowChanges,
+        actor: &Actor,
+    ) -> Result<bool, tokio_postgres::Error> {
+        self.in_transaction("updating flow", async |client| {
+            if let Some(new_name) = &changes.name {
+                let taken: bool = client
+                    .client
+                    .query_one(
+                        "SELECT EXISTS (SELECT 1 FROM public.flow_metadata WHERE name = $1)",
+                        &[new_name],
+                    )
+                    .await?
+                    .get(0);
+                if taken {
+                    return Ok(false);
+                }
+            }
+            if let Some(description) = &changes.description {
+                client
+                    .set_flow_description(flow_name, description.as_deref(), actor)
+                    .await?;
+            }
+            if let Some(archived) = changes.archived {
+                client.set_flow_archived(flow_name, archived, actor).await?;
+            }
+            if let Some(key_policy) = changes.key_policy {
                 client
-                    .deprecate_key_by_server(&server_name, &flow_name)
-                    .await
+                    .set_flow_key_policy(flow_name, key_policy, actor)
+                    .await?;
+            }
+            if let Some(new_name) = &changes.name {
+                client.rename_flow(flow_name, new_name, actor).await?;
             }
-            None => panic!("Database client not initialized"),
+            Ok(true)
+        })
+        .await
+    }
+
+    pub async fn rename_flow(
+        &self,
+        flow_name: &str,
+        new_name: &str,
+        actor: &Actor,
+    ) -> Result<u64, tokio_postgres::Error> {
+        // Single statement so keys, grants, CAs and token scopes move together with the flow
+        let result = self
+            .client
+            .query_one(
+                "WITH renamed AS (
+                     UPDATE public.flow_metadata SET name = $2 WHERE name = $1 RETURNING name
+                 ), flow_keys AS (
+                     UPDATE public.flows SET name = $2 WHERE name = $1
+                 ), flow_grants AS (
+                     UPDATE public.grants SET flow = $2 WHERE flow = $1
+                 ), flow_cert_authorities AS (
+                     UPDATE public.cert_authorities SET flow = $2 WHERE flow = $1
+                 ), token_scopes AS (
+                     UPDATE public.api_tokens SET flows = array_replace(flows, $1::TEXT, $2::TEXT)
+                     WHERE $1 = ANY(flows)
+                 ), logged AS (
+                     INSERT INTO public.audit_log (action, flow, actor, client_hostname, details)
+                     SELECT 'flow_renamed', name, $3, $4, 'renamed from ' || $1 FROM renamed
+                 )
+                 SELECT COUNT(*) FROM renamed",
+                &[&flow_name, &new_name, &actor.user, &actor.client_hostname],
+            )
+            .await;
+        let renamed: i64 = Self::handle_db_error(result, "renaming flow")?.get(0);
+
+        info!("Renamed flow '{}' to '{}'", flow_name, new_name);
+        Ok(renamed as u64)
+    }
+
+    pub async fn set_flow_description(
+        &self,
+        flow_name: &str,
+        description: Option<&str>,
+        actor: &Actor,
+    ) -> Result<u64, tokio_postgres::Error> {
+        let result = self
+            .client
+            .execute(
+                "WITH updated AS (
+                     UPDATE public.flow_metadata SET description = $2 WHERE name = $1
+                     RETURNING name, description
+                 )
+                 INSERT INTO public.audit_log (action, flow, actor, client_hostname, details)
+                 SELECT 'flow_described', name, $3, $4, description FROM updated",
+                &[
+                    &flow_name,
+                    &description,
+                    &actor.user,
+                    &actor.client_hostname,
+                ],
+            )
+            .await;
+        Self::handle_db_error(result, "updating flow description")
+    }
+
+    pub async fn set_flow_archived(
+        &self,
+        flow_name: &str,
+        archived: bool,
+        actor: &Actor,
+    ) -> Result<u64, tokio_postgres::Error> {
+        let result = self
+            .client
+            .execute(
+                "WITH updated AS (
+                     UPDATE public.flow_metadata SET archived = $2 WHERE name = $1
+                     RETURNING name, archived
+                 )
+                 INSERT INTO public.audit_log (action, flow, actor, client_hostname)
+                 SELECT CASE WHEN archived THEN 'flow_archived' ELSE 'flow_unarchived' END,
+                        name, $3, $4
+                 FROM updated",
+                &[&flow_name, &archived, &actor.user, &actor.client_hostname],
+            )
+            .await;
+        let affected = Self::handle_db_error(result, "archiving flow")?;
+
+        info!(
+            "{} flow '{}'",
+            if archived { "Archived" } else { "Unarchived" },
+            flow_name
+        );
+        Ok(affected)
+    }
+
+    pub async fn get_audit_log(
+        &self,
+        query: &AuditQuery,
+        limit: i64,
+    ) -> Result<Vec<AuditEntry>, tokio_postgres::Error> {
+        let result = self
+            .client
+            .query(
+                "SELECT id, at, action, flow, server, public_key, actor, client_hostname, details
+                 FROM public.audit_log
+                 WHERE ($1::VARCHAR IS NULL OR flow = $1)
+                 AND ($2::VARCHAR IS NULL OR server = $2)
+                 AND ($3::TIMESTAMPTZ IS NULL OR at >= $3)
+                 ORDER BY id DESC
+                 LIMIT $4",
+                &[&query.flow, &query.server, &query.since, &limit],
+            )
+            .await;
+        let rows = Self::handle_db_error(result, "getting audit log")?;
+
+        Ok(rows
+            .iter()
+            .map(|row| AuditEntry {
+                id: row.get(0),
+                at: row.get(1),
+                action: row.get(2),
+                flow: row.get(3),
+                server: row.get(4),
+                public_key: row.get(5),
+                actor: row.get(6),
+                client_hostname: row.get(7),
+                details: row.get(8),
+            })
+            .collect())
+    }
+
+    // Audit rows that changed what a flow serves, after revision `since` and up to `until`
+    pub async fn get_flow_changes(
+        &self,
+        flow_name: &str,
+        since: i64,
+        until: i64,
+        limit: i64,
+    ) -> Result<Vec<AuditEntry>, tokio_postgres::Error> {
+        let result = self
+            .client
+            .query(
+                "SELECT id, at, action, flow, server, public_key, actor, client_hostname, details
+                 FROM public.audit_log
+                 WHERE flow = $1 AND id > $2 AND id <= $3
+                 AND (action = ANY($4) OR action = ANY($5))
+                 ORDER BY id
+                 LIMIT $6",
+                &[
+                    &flow_name,
+                    &since,
+                    &until,
+                    &KEY_ACTIONS,
+                    &FLOW_CONTENT_ACTIONS,
+                    &limit,
+                ],
+            )
+            .await;
+        let rows = Self::handle_db_error(result, "getting flow changes")?;
+
+        Ok(rows
+            .iter()
+            .map(|row| AuditEntry {
+                id: row.get(0),
+                at: row.get(1),
+                action: row.get(2),
+                flow: row.get(3),
+                server: row.get(4),
+                public_key: row.get(5),
+                actor: row.get(6),
+                client_hostname: row.get(7),
+                details: row.get(8),
+            })
+            .collect())
+    }
+
+    pub async fn set_flow_key_policy(
+        &self,
+        flow_name: &str,
+        key_policy: KeyPolicy,
+        actor: &Actor,
+    ) -> Result<u64, tokio_postgres::Error> {
+        let result = self
+            .client
+            .execute(
+                "WITH updated AS (
+                     UPDATE public.flow_metadata SET key_policy = $2
+                     WHERE name = $1 AND key_policy <> $2
+                     RETURNING name, key_policy
+                 )
+                 INSERT INTO public.audit_log (action, flow, actor, client_hostname, details)
+                 SELECT 'flow_policy', name, $3, $4, key_policy FROM updated",
+                &[
+                    &flow_name,
+                    &key_policy.as_str(),
+                    &actor.user,
+                    &actor.client_hostname,
+                ],
+            )
+            .await;
+        let affected = Self::handle_db_error(result, "updating flow key policy")?;
+
+        info!("Set key policy of flow '{}' to '{}'", flow_name, key_policy);
+        Ok(affected)
+    }
+
+    pub async fn approve_pending_keys(
+        &self,
+        server_name: &str,
+        flow_name: &str,
+        public_key: Option<&str>,
+        actor: &Actor,
+    ) -> Result<u64, tokio_postgres::Error> {
+        // Approving a changed key replaces the host's previous active key of the same type.
+        // Approval only touches this flow's link, the replaced key is deprecated in every
+        // flow linking it and logged for each of them.
+        let result = self
+            .client
+            .query_one(
+                "WITH approved AS (
+                     UPDATE public.flows f SET pending = FALSE
+                     FROM public.keys k
+                     WHERE f.key_id = k.key_id
+                     AND f.name = $2
+                     AND f.pending
+                     AND k.host = $1
+                     AND ($3::TEXT IS NULL OR k.key = $3)
+                     RETURNING k.key_id, k.host, k.key
+                 ), replaced AS (
+                     UPDATE public.keys k SET deprecated = TRUE, updated = NOW()
+                     FROM public.flows f
+                     WHERE f.key_id = k.key_id
+                     AND f.name = $2
+                     AND NOT f.pending
+                     AND NOT k.deprecated
+                     AND k.host = $1
+                     AND k.key_id NOT IN (SELECT key_id FROM approved)
+                     AND split_part(k.key, ' ', 1) IN (SELECT split_part(key, ' ', 1) FROM approved)
+                     RETURNING k.key_id, k.host, k.key
+                 ), logged AS (
+                     INSERT INTO public.audit_log
+                         (action, flow, server, public_key, actor, client_hostname, details)
+                     SELECT 'key_approved', $2, host, key, $4, $5, NULL FROM approved
+                     UNION ALL
+                     SELECT 'key_deprecated', f.name, r.host, r.key, $4, $5,
+                         CASE WHEN f.name = $2 THEN 'replaced by an approved key'
+                         ELSE 'replaced by an approved key in flow ' || $2 END
+                     FROM replaced r INNER JOIN public.flows f ON f.key_id = r.key_id
+                 )
+                 SELECT COUNT(*) FROM approved",
+                &[
+                    &server_name,
+                    &flow_name,
+                    &public_key,
+                    &actor.user,
+                    &actor.client_hostname,
+                ],
+            )
+            .await;
+        let approved =
+            Self::handle_db_error(result, "approving pending keys")?.get::<_, i64>(0) as u64;
+
+        info!(
+            "Approved {} pending key(s) for server '{}' in flow '{}'",
+            approved, server_name, flow_name
+        );
+        Ok(approved)
+    }
+
+    pub async fn reject_pending_keys(
+        &self,
+        server_name: &str,
+        flow_name: &str,
+        public_key: Option<&str>,
+        actor: &Actor,
+    ) -> Result<u64, tokio_postgres::Error> {
+        // Rejected keys stay in the flow as deprecated so clients re-sending them are ignored.
+        // The key is deprecated in the other flows linking it too, which is logged for each.
+        let result = self
+            .client
+            .query_one(
+                "WITH rejected AS (
+                     UPDATE public.flows f SET pending = FALSE
+                     FROM public.keys k
+                     WHERE f.key_id = k.key_id
+                     AND f.name = $2
+                     AND f.pending
+                     AND k.host = $1
+                     AND ($3::TEXT IS NULL OR k.key = $3)
+                     RETURNING k.key_id, k.host, k.key
+                 ), deprecated AS (
+                     UPDATE public.keys SET deprecated = TRUE, updated = NOW()
+                     WHERE key_id IN (SELECT key_id FROM rejected)
+                 ), logged AS (
+                     INSERT INTO public.audit_log
+                         (action, flow, server, public_key, actor, client_hostname, details)
+                     SELECT 'key_rejected', $2, host, key, $4, $5, NULL FROM rejected
+                     UNION ALL
+                     SELECT 'key_deprecated', f.name, r.host, r.key, $4, $5,
+                         'rejected in flow ' || $2
+                     FROM rejected r INNER JOIN public.flows f ON f.key_id = r.key_id
+                     WHERE f.name <> $2
+                 )
+                 SELECT COUNT(*) FROM rejected",
+                &[
+                    &server_name,
+                    &flow_name,
+                    &public_key,
+                    &actor.user,
+                    &actor.client_hostname,
+                ],
+            )
+            .await;
+        let rejected =
+            Self::handle_db_error(result, "rejecting pending keys")?.get::<_, i64>(0) as u64;
+
+        info!(
+            "Rejected {} pending key(s) for server '{}' in flow '{}'",
+            rejected, server_name, flow_name
+        );
+        Ok(rejected)
+    }
+
+    pub async fn get_cert_authorities(&self) -> Result<Vec<CertAuthority>, tokio_postgres::Error> {
+        let result = self
+            .client
+            .query(
+                "SELECT ca_id, flow, hosts, public_key, comment, created
+                 FROM public.cert_authorities ORDER BY ca_id",
+                &[],
+            )
+            .await;
+        let rows = Self::handle_db_error(result, "getting certificate authorities")?;
+
+        Ok(rows.iter().map(Self::row_to_cert_authority).collect())
+    }
+
+    fn row_to_cert_authority(row: &tokio_postgres::Row) -> CertAuthority {
+        CertAuthority {
+            id: row.get(0),
+            flow: row.get(1),
+            hosts: row.get(2),
+            public_key: row.get(3),
+            comment: row.get(4),
+            created: row.get(5),
         }
     }
 
-    pub async fn bulk_deprecate_keys_by_servers_reconnecting(
+    // Returns None when the flow already trusts this CA key
+    pub async fn add_cert_authority(
         &self,
-        server_names: Vec<String>,
-        flow_name: String,
+        flow_name: &str,
+        hosts: &[String],
+        public_key: &str,
+        comment: Option<&str>,
+        actor: &Actor,
+    ) -> Result<Option<CertAuthority>, tokio_postgres::Error> {
+        let result = self
+            .client
+            .query_opt(
+                "WITH added AS (
+                     INSERT INTO public.cert_authorities (flow, hosts, public_key, comment)
+                     VALUES ($1, $2, $3, $4)
+                     ON CONFLICT (flow, public_key) DO NOTHING
+                     RETURNING ca_id, flow, hosts, public_key, comment, created
+                 ), logged AS (
+                     INSERT INTO public.audit_log (action, flow, server, public_key, actor, client_hostname, details)
+                     SELECT 'ca_added', flow, array_to_string(hosts, ','), public_key, $5, $6, comment
+                     FROM added
+                 )
+                 SELECT ca_id, flow, hosts, public_key, comment, created FROM added",
+                &[
+                    &flow_name,
+                    &hosts,
+                    &public_key,
+                    &comment,
+                    &actor.user,
+                    &actor.client_hostname,
+                ],
+            )
+            .await;
+        let row = Self::handle_db_error(result, "adding certificate authority")?;
+
+        Ok(row.map(|row| {
+            info!(
+                "Added certificate authority for '{}' to flow '{}'",
+                hosts.join(","),
+                flow_name
+            );
+            CertAuthority {
+                id: row.get(0),
+                flow: row.get(1),
+                hosts: row.get(2),
+                public_key: row.get(3),
+                comment: row.get(4),
+                created: row.get(5),
+            }
+        }))
+    }
+
+    pub async fn remove_cert_authority(
+        &self,
+        flow_name: &str,
+        ca_id: i32,
+        actor: &Actor,
     ) -> Result<u64, tokio_postgres::Error> {
-        match &self.inner {
-            Some(client) => {
-                client
-                    .bulk_deprecate_keys_by_servers(&server_names, &flow_name)
-                    .await
+        let result = self
+            .client
+            .execute(
+                "WITH removed AS (
+                     DELETE FROM public.cert_authorities WHERE flow = $1 AND ca_id = $2
+                     RETURNING flow, hosts, public_key, comment
+                 )
+                 INSERT INTO public.audit_log (action, flow, server, public_key, actor, client_hostname, details)
+                 SELECT 'ca_removed', flow, array_to_string(hosts, ','), public_key, $3, $4, comment
+                 FROM removed",
+                &[&flow_name, &ca_id, &actor.user, &actor.client_hostname],
+            )
+            .await;
+        let affected = Self::handle_db_error(result, "removing certificate authority")?;
+
+        info!(
+            "Removed {} certificate authority record(s) with id {} from flow '{}'",
+            affected, ca_id, flow_name
+        );
+        Ok(affected)
+    }
+
+    pub async fn revoke_keys_by_server(
+        &self,
+        server_name: &str,
+        flow_name: &str,
+        public_key: Option<&str>,
+        actor: &Actor,
+    ) -> Result<u64, tokio_postgres::Error> {
+        // Revoked keys are deprecated as well, so every path that skips inactive keys skips them
+        let result = self
+            .client
+            .query_one(
+                "WITH revoked AS (
+                     UPDATE public.keys
+                     SET deprecated = TRUE, revoked = TRUE, updated = NOW()
+                     WHERE host = $1
+                     AND revoked = FALSE
+                     AND ($3::TEXT IS NULL OR key = $3)
+                     AND key_id IN (
+                         SELECT key_id FROM public.flows WHERE name = $2
+                     )
+                     RETURNING key_id, host, key
+                 ), logged AS (
+                     INSERT INTO public.audit_log
+                         (action, flow, server, public_key, actor, client_hostname, details)
+                     SELECT 'key_revoked', f.name, r.host, r.key, $4, $5,
+                         CASE WHEN f.name = $2 THEN NULL ELSE 'changed in flow ' || $2 END
+                     FROM revoked r INNER JOIN public.flows f ON f.key_id = r.key_id
+                 )
+                 SELECT COUNT(*) FROM revoked",
+                &[
+                    &server_name,
+                    &flow_name,
+                    &public_key,
+                    &actor.user,
+                    &actor.client_hostname,
+                ],
+            )
+            .await;
+        let affected = Self::handle_db_error(result, "revoking keys")?.get::<_, i64>(0) as u64;
+
+        info!(
+            "Revoked {} key(s) for server '{}' in flow '{}'",
+            affected, server_name, flow_name
+        );
+        Ok(affected)
+    }
+}
+
+// Pool of connections shared by all requests. A connection that breaks is replaced
+// on a later checkout, so losing the database fails requests until it is back
+// instead of ending the process.
+pub struct ReconnectingDbClient {
+    pool: Pool,
+    available: AtomicBool,
+    // Times the database became reachable, the first connection included
+    reconnects: AtomicU64,
+}
+
+impl ReconnectingDbClient {
+    // The pool connects lazily; nothing is sent to the database here
+    pub fn new(connection_string: &str, pool_size: usize) -> Result<Self, DbError> {
+        let config: tokio_postgres::Config = connection_string.parse()?;
+        let manager = Manager::from_config(
+            config,
+            NoTls,
+            ManagerConfig {
+                // Check a pooled connection before handing it out, so one left over
+                // from before a database restart is replaced instead of failing a query
+                recycling_method: RecyclingMethod::Verified,
+            },
+        );
+        let pool = Pool::builder(manager)
+            .max_size(pool_size.max(1))
+            .runtime(Runtime::Tokio1)
+            .create_timeout(Some(CONNECT_TIMEOUT))
+            .wait_timeout(Some(POOL_WAIT_TIMEOUT))
+            .recycle_timeout(Some(CONNECT_TIMEOUT))
+            .build()
+            .map_err(|e| DbError::Unavailable(e.to_string()))?;
+
+        Ok(ReconnectingDbClient {
+            pool,
+            available: AtomicBool::new(false),
+            reconnects: AtomicU64::new(0),
+        })
+    }
+
+    fn set_available(&self, available: bool) {
+        if self.available.swap(available, Ordering::Relaxed) != available {
+            if available {
+                self.reconnects.fetch_add(1, Ordering::Relaxed);
+                info!("Database connection established");
+            } else {
+                error!("Database is unreachable");
             }
-            None => panic!("Database client not initialized"),
         }
     }
 
-    pub async fn bulk_restore_keys_by_servers_reconnecting(
+    // Run an operation on a pooled connection. Failures that left the database
+    // untouched are retried with backoff; the rest are returned right away, since
+    // the statement may already have been applied.
+    async fn run<T>(
         &self,
-        server_names: Vec<String>,
-        flow_name: String,
-    ) -> Result<u64, tokio_postgres::Error> {
-        match &self.inner {
-            Some(client) => {
-                client
-                    .bulk_restore_keys_by_servers(&server_names, &flow_name)
-                    .await
+        operation: impl AsyncFn(&DbClient) -> Result<T, tokio_postgres::Error>,
+    ) -> Result<T, DbError> {
+        let mut delay = MIN_RETRY_DELAY;
+        let mut attempt = 1;
+        loop {
+            let result = match self.pool.get().await {
+                Ok(client) => operation(&DbClient { client }).await.map_err(DbError::from),
+                Err(e) => Err(DbError::Unavailable(e.to_string())),
+            };
+            match result {
+                Err(e) if e.is_retryable() && attempt < MAX_ATTEMPTS => {
+                    warn!(
+                        "Database operation failed (attempt {} of {}), retrying in {} ms: {}",
+                        attempt,
+                        MAX_ATTEMPTS,
+                        delay.as_millis(),
+                        e
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                    attempt += 1;
+                }
+                result => {
+                    self.set_available(!matches!(&result, Err(e) if e.is_unavailable()));
+                    return result;
+                }
             }
-            None => panic!("Database client not initialized"),
         }
     }
+}
+
+#[async_trait(?Send)]
+impl Storage for ReconnectingDbClient {
+    fn is_available(&self) -> bool {
+        self.available.load(Ordering::Relaxed)
+    }
+
+    fn reconnects(&self) -> u64 {
+        self.reconnects.load(Ordering::Relaxed)
+    }
+
+    fn migrations(&self) -> &'static [Migration] {
+        migrations::POSTGRES
+    }
+
+    async fn ping(&self) -> Result<(), DbError> {
+        self.run(async |client| client.client.simple_query("").await.map(|_| ()))
+            .await
+    }
+
+    async fn migrate_up(&self) -> Result<Vec<&'static Migration>, DbError> {
+        self.run(async |client| client.migrate_up().await).await
+    }
+
+    async fn migrate_down(&self) -> Result<Downgrade, DbError> {
+        self.run(async |client| client.migrate_down().await).await
+    }
+
+    async fn get_applied_migrations(&self) -> Result<Vec<AppliedMigration>, DbError> {
+        self.run(async |client| client.get_applied_migrations().await)
+            .await
+    }
+
+    async fn batch_insert_keys(&self, keys: Vec<SshKey>) -> Result<KeyInsertStats, DbError> {
+        self.run(async |client| client.batch_insert_keys(&keys).await)
+            .await
+    }
+
+    async fn batch_insert_flow_keys(
+        &self,
+        flow_name: String,
+        key_ids: Vec<i32>,
+        actor: Actor,
+    ) -> Result<FlowKeyStats, DbError> {
+        self.run(async |client| {
+            client
+                .batch_insert_flow_keys(&flow_name, &key_ids, &actor)
+                .await
+        })
+        .await
+    }
 
-    pub async fn restore_key_by_server_reconnecting(
+    async fn get_keys_from_db(&self) -> Result<Vec<Flow>, DbError> {
+        self.run(async |client| client.get_keys_from_db().await)
+            .await
+    }
+
+    async fn get_flow_update(
+        &self,
+        flow_names: Vec<String>,
+        hosts: Vec<String>,
+    ) -> Result<FlowUpdate, DbError> {
+        self.run(async |client| client.get_flow_update(&flow_names, &hosts).await)
+            .await
+    }
+
+    async fn deprecate_key_by_server(
         &self,
         server_name: String,
         flow_name: String,
-    ) -> Result<u64, tokio_postgres::Error> {
-        match &self.inner {
-            Some(client) => client.restore_key_by_server(&server_name, &flow_name).await,
-            None => panic!("Database client not initialized"),
-        }
+        actor: Actor,
+    ) -> Result<u64, DbError> {
+        self.run(async |client| {
+            client
+                .deprecate_key_by_server(&server_name, &flow_name, &actor)
+                .await
+        })
+        .await
     }
 
-    pub async fn permanently_delete_key_by_server_reconnecting(
+    async fn bulk_deprecate_keys_by_servers(
+        &self,
+        server_names: Vec<String>,
+        flow_name: String,
+        actor: Actor,
+    ) -> Result<u64, DbError> {
+        self.run(async |client| {
+            client
+                .bulk_deprecate_keys_by_servers(&server_names, &flow_name, &actor)
+                .await
+        })
+        .await
+    }
+
+    async fn bulk_restore_keys_by_servers(
+        &self,
+        server_names: Vec<String>,
+        flow_name: String,
+        actor: Actor,
+    ) -> Result<u64, DbError> {
+        self.run(async |client| {
+            client
+                .bulk_restore_keys_by_servers(&server_names, &flow_name, &actor)
+                .await
+        })
+        .await
+    }
+
+    async fn restore_key_by_server(
         &self,
         server_name: String,
         flow_name: String,
-    ) -> Result<u64, tokio_postgres::Error> {
-        match &self.inner {
-            Some(client) => {
-                client
-                    .permanently_delete_key_by_server(&server_name, &flow_name)
-                    .await
-            }
-            None => panic!("Database client not initialized"),
-        }
+        actor: Actor,
+    ) -> Result<u64, DbError> {
+        self.run(async |client| {
+            client
+                .restore_key_by_server(&server_name, &flow_name, &actor)
+                .await
+        })
+        .await
+    }
+
+    async fn permanently_delete_key_by_server(
+        &self,
+        server_name: String,
+        flow_name: String,
+        actor: Actor,
+    ) -> Result<u64, DbError> {
+        self.run(async |client| {
+            client
+                .permanently_delete_key_by_server(&server_name, &flow_name, &actor)
+                .await
+        })
+        .await
+    }
+
+    async fn get_grants(&self) -> Result<Vec<Grant>, DbError> {
+        self.run(async |client| client.get_grants().await).await
+    }
+
+    async fn set_grant(
+        &self,
+        username: String,
+        flow_name: String,
+        role: Role,
+        actor: Actor,
+    ) -> Result<(), DbError> {
+        self.run(async |client| client.set_grant(&username, &flow_name, role, &actor).await)
+            .await
+    }
+
+    async fn revoke_grant(
+        &self,
+        username: String,
+        flow_name: String,
+        actor: Actor,
+    ) -> Result<u64, DbError> {
+        self.run(async |client| client.revoke_grant(&username, &flow_name, &actor).await)
+            .await
+    }
+
+    async fn get_api_tokens(&self) -> Result<Vec<ApiToken>, DbError> {
+        self.run(async |client| client.get_api_tokens().await).await
+    }
+
+    async fn create_api_token(
+        &self,
+        name: String,
+        token_hash: String,
+        scope: TokenScope,
+        expires: Option<DateTime<Utc>>,
+        actor: Actor,
+    ) -> Result<i32, DbError> {
+        self.run(async |client| {
+            client
+                .create_api_token(&name, &token_hash, &scope, expires, &actor)
+                .await
+        })
+        .await
+    }
+
+    async fn revoke_api_token(&self, token_id: i32, actor: Actor) -> Result<u64, DbError> {
+        self.run(async |client| client.revoke_api_token(token_id, &actor).await)
+            .await
+    }
+
+    async fn touch_api_token(&self, token_id: i32, hostname: String) -> Result<(), DbError> {
+        self.run(async |client| client.touch_api_token(token_id, &hostname).await)
+            .await
+    }
+
+    async fn get_flow_metadata(&self) -> Result<Vec<FlowInfo>, DbError> {
+        self.run(async |client| client.get_flow_metadata().await)
+            .await
+    }
+
+    async fn create_flow(
+        &self,
+        flow_name: String,
+        description: Option<String>,
+        actor: Actor,
+    ) -> Result<bool, DbError> {
+        self.run(async |client| {
+            client
+                .create_flow(&flow_name, description.as_deref(), &actor)
+                .await
+        })
+        .await
+    }
+
+    async fn update_flow(
+        &self,
+        flow_name: String,
+        changes: FlowChanges,
+        actor: Actor,
+    ) -> Result<bool, DbError> {
+        self.run(async |client| client.update_flow(&flow_name, &changes, &actor).await)
+            .await
+    }
+
+    async fn set_flow_archived(
+        &self,
+        flow_name: String,
+        archived: bool,
+        actor: Actor,
+    ) -> Result<u64, DbError> {
+        self.run(async |client| client.set_flow_archived(&flow_name, archived, &actor).await)
+            .await
+    }
+
+    async fn get_flow_changes(
+        &self,
+        flow_name: String,
+        since: i64,
+        until: i64,
+        limit: i64,
+    ) -> Result<Vec<AuditEntry>, DbError> {
+        self.run(async |client| {
+            client
+                .get_flow_changes(&flow_name, since, until, limit)
+                .await
+        })
+        .await
+    }
+
+    async fn get_audit_log(
+        &self,
+        query: AuditQuery,
+        limit: i64,
+    ) -> Result<Vec<AuditEntry>, DbError> {
+        self.run(async |client| client.get_audit_log(&query, limit).await)
+            .await
+    }
+
+    async fn approve_pending_keys(
+        &self,
+        server_name: String,
+        flow_name: String,
+        public_key: Option<String>,
+        actor: Actor,
+    ) -> Result<u64, DbError> {
+        self.run(async |client| {
+            client
+                .approve_pending_keys(&server_name, &flow_name, public_key.as_deref(), &actor)
+                .await
+        })
+        .await
+    }
+
+    async fn reject_pending_keys(
+        &self,
+        server_name: String,
+        flow_name: String,
+        public_key: Option<String>,
+        actor: Actor,
+    ) -> Result<u64, DbError> {
+        self.run(async |client| {
+            client
+                .reject_pending_keys(&server_name, &flow_name, public_key.as_deref(), &actor)
+                .await
+        })
+        .await
+    }
+
+    async fn add_cert_authority(
+        &self,
+        flow_name: String,
+        hosts: Vec<String>,
+        public_key: String,
+        comment: Option<String>,
+        actor: Actor,
+    ) -> Result<Option<CertAuthority>, DbError> {
+        self.run(async |client| {
+            client
+                .add_cert_authority(&flow_name, &hosts, &public_key, comment.as_deref(), &actor)
+                .await
+        })
+        .await
+    }
+
+    async fn remove_cert_authority(
+        &self,
+        flow_name: String,
+        ca_id: i32,
+        actor: Actor,
+    ) -> Result<u64, DbError> {
+        self.run(async |client| {
+            client
+                .remove_cert_authority(&flow_name, ca_id, &actor)
+                .await
+        })
+        .await
+    }
+
+    async fn revoke_keys_by_server(
+        &self,
+        server_name: String,
+        flow_name: String,
+        public_key: Option<String>,
+        actor: Actor,
+    ) -> Result<u64, DbError> {
+        self.run(async |client| {
+            client
+                .revoke_keys_by_server(&server_name, &flow_name, public_key.as_deref(), &actor)
+                .await
+        })
+        .await
     }
 }
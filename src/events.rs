@@ -0,0 +1,69 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+// Pushed on /{flow}/events whenever what a flow serves changes. The revision
+// is the one `GET /{flow}/keys` returns as its ETag.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FlowEvent {
+    pub flow: String,
+    pub revision: i64,
+}
+
+const EVENT_NAME: &str = "revision";
+
+// Comment frames keep idle streams open through proxies and let both ends notice dead ones
+pub const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+pub const KEEPALIVE_FRAME: &str = ": keepalive\n\n";
+
+impl FlowEvent {
+    // One Server-Sent Events frame
+    pub fn to_sse(&self) -> String {
+        format!(
+            "event: {}\nid: {}\ndata: {}\n\n",
+            EVENT_NAME,
+            self.revision,
+            serde_json::to_string(self).unwrap_or_default()
+        )
+    }
+}
+
+// Splits a text/event-stream body into events as chunks arrive
+#[derive(Default)]
+pub struct SseParser {
+    buffer: Vec<u8>,
+}
+
+impl SseParser {
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<FlowEvent> {
+        self.buffer
+            .extend(chunk.iter().copied().filter(|&byte| byte != b'\r'));
+
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.windows(2).position(|pair| pair == b"\n\n") {
+            let frame: Vec<u8> = self.buffer.drain(..end + 2).collect();
+            if let Some(event) = Self::parse_frame(&String::from_utf8_lossy(&frame)) {
+                events.push(event);
+            }
+        }
+        events
+    }
+
+    fn parse_frame(frame: &str) -> Option<FlowEvent> {
+        let mut name = None;
+        let mut data = Vec::new();
+        for line in frame.lines() {
+            // Lines starting with a colon are comments and have an empty field name
+            let (field, value) = line.split_once(':').unwrap_or((line, ""));
+            let value = value.strip_prefix(' ').unwrap_or(value);
+            match field {
+                "event" => name = Some(value),
+                "data" => data.push(value),
+                _ => {}
+            }
+        }
+        if name != Some(EVENT_NAME) {
+            return None;
+        }
+        serde_json::from_str(&data.join("\n")).ok()
+    }
+}
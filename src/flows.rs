@@ -0,0 +1,266 @@
+use arc_swap::ArcSwap;
+use im::OrdMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::Arc;
+
+use crate::db::{DbError, FlowUpdate};
+use crate::events::FlowEvent;
+use crate::known_hosts::Marker;
+use crate::server::{CertAuthority, Flow, FlowEvents, SshKey};
+use crate::storage::Storage;
+
+// Keys that clients syncing known_hosts get: active approved keys, and revoked ones
+// so that they can be written as @revoked lines
+pub fn is_served(key: &SshKey) -> bool {
+    (!key.deprecated && !key.pending) || key.marker == Some(Marker::Revoked)
+}
+
+// One flow as held in memory. Keys are indexed by server and public key; the
+// persistent map makes a copy for a change cost only the entries it touches.
+#[derive(Clone, Debug, Default)]
+pub struct FlowIndex {
+    pub name: String,
+    keys: OrdMap<(String, String), SshKey>,
+    pub cert_authorities: Vec<CertAuthority>,
+    // Audit log id of the last change to what the flow serves, used as its ETag
+    pub revision: i64,
+}
+
+impl FlowIndex {
+    fn new(name: String) -> Self {
+        FlowIndex {
+            name,
+            ..FlowIndex::default()
+        }
+    }
+
+    fn from_flow(flow: Flow) -> Self {
+        FlowIndex {
+            name: flow.name,
+            keys: flow
+                .servers
+                .into_iter()
+                .map(|key| ((key.server.clone(), key.public_key.clone()), key))
+                .collect(),
+            cert_authorities: flow.cert_authorities,
+            revision: flow.revision,
+        }
+    }
+
+    // Every key of the flow, pending and deprecated ones included, ordered by server
+    pub fn keys(&self) -> impl Iterator<Item = &SshKey> {
+        self.keys.values()
+    }
+
+    pub fn key_count(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn get(&self, server: &str, public_key: &str) -> Option<&SshKey> {
+        self.keys.get(&(server.to_string(), public_key.to_string()))
+    }
+
+    // Keys handed out to clients: active approved keys and revoked ones, then the CAs
+    pub fn served_keys(&self, include_deprecated: bool) -> Vec<SshKey> {
+        let mut servers: Vec<SshKey> = self
+            .keys
+            .values()
+            .filter(|key| include_deprecated || is_served(key))
+            .cloned()
+            .collect();
+        servers.extend(self.cert_authorities.iter().map(CertAuthority::to_ssh_key));
+        servers
+    }
+
+    fn holds_host(&self, host: &str) -> bool {
+        self.host_entries(host).next().is_some()
+    }
+
+    fn host_entries<'a>(&'a self, host: &'a str) -> impl Iterator<Item = (String, String)> + 'a {
+        self.keys
+            .range((host.to_string(), String::new())..)
+            .map(|(id, _)| id)
+            .take_while(move |(server, _)| server == host)
+            .cloned()
+    }
+
+    fn remove_host(&mut self, host: &str) {
+        let ids: Vec<(String, String)> = self.host_entries(host).collect();
+        for id in ids {
+            self.keys.remove(&id);
+        }
+    }
+
+    fn insert(&mut self, key: SshKey) {
+        self.keys
+            .insert((key.server.clone(), key.public_key.clone()), key);
+    }
+}
+
+type Snapshot = HashMap<String, Arc<FlowIndex>>;
+
+// Keys, CAs and revisions of every flow. Handlers read a snapshot without taking
+// a lock; after a change only the rows it touched are read back from the database
+// and swapped in.
+pub struct Flows {
+    current: ArcSwap<Snapshot>,
+    // One writer at a time, so that rows read from the database are never swapped
+    // in after rows read later
+    writer: tokio::sync::Mutex<()>,
+}
+
+impl Flows {
+    pub fn new(flows: Vec<Flow>) -> Self {
+        let snapshot = flows
+            .into_iter()
+            .map(|flow| (flow.name.clone(), Arc::new(FlowIndex::from_flow(flow))))
+            .collect();
+        Flows {
+            current: ArcSwap::from_pointee(snapshot),
+            writer: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<FlowIndex>> {
+        self.current.load().get(name).cloned()
+    }
+
+    pub fn all(&self) -> Vec<Arc<FlowIndex>> {
+        let mut flows: Vec<Arc<FlowIndex>> = self.current.load().values().cloned().collect();
+        flows.sort_by(|a, b| a.name.cmp(&b.name));
+        flows
+    }
+
+    pub fn revision(&self, name: &str) -> i64 {
+        self.current
+            .load()
+            .get(name)
+            .map_or(0, |flow| flow.revision)
+    }
+
+    // Re-read the keys of `hosts` in every flow, and the CAs and revisions of `flows`
+    // and of every flow holding one of the hosts. Keys live in one table shared by all
+    // flows, so deprecating a host's key in one flow changes it in the others as well.
+    pub async fn refresh(
+        &self,
+        db_client: &Arc<dyn Storage>,
+        events: &FlowEvents,
+        mut flows: Vec<String>,
+        hosts: Vec<String>,
+    ) -> Result<(), DbError> {
+        let _writer = self.writer.lock().await;
+        // The storage adds the flows linking the hosts now; these are the ones that
+        // held them before and may have lost them
+        for flow in self.current.load().values() {
+            if !flows.contains(&flow.name) && hosts.iter().any(|host| flow.holds_host(host)) {
+                flows.push(flow.name.clone());
+            }
+        }
+        let update = db_client.get_flow_update(flows, hosts).await?;
+        let mut snapshot = Snapshot::clone(&self.current.load());
+        let changed = apply_update(&mut snapshot, update);
+        self.publish(snapshot, changed, events);
+        Ok(())
+    }
+
+    // Read everything again, e.g. after changes may have been missed while the
+    // database was unreachable
+    pub async fn reload(
+        &self,
+        db_client: &Arc<dyn Storage>,
+        events: &FlowEvents,
+    ) -> Result<(), DbError> {
+        let _writer = self.writer.lock().await;
+        let flows = db_client.get_keys_from_db().await?;
+        let mut changed: BTreeSet<String> = self.current.load().keys().cloned().collect();
+        let snapshot: Snapshot = flows
+            .into_iter()
+            .map(|flow| {
+                changed.insert(flow.name.clone());
+                (flow.name.clone(), Arc::new(FlowIndex::from_flow(flow)))
+            })
+            .collect();
+        self.publish(snapshot, changed, events);
+        Ok(())
+    }
+
+    // Keys and CAs move with a renamed flow; only its revision has to be read again
+    pub async fn rename(
+        &self,
+        db_client: &Arc<dyn Storage>,
+        events: &FlowEvents,
+        old_name: &str,
+        new_name: &str,
+    ) -> Result<(), DbError> {
+        let _writer = self.writer.lock().await;
+        let update = db_client
+            .get_flow_update(vec![new_name.to_string()], Vec::new())
+            .await?;
+        let mut snapshot = Snapshot::clone(&self.current.load());
+        let mut changed = BTreeSet::from([old_name.to_string()]);
+        if let Some(flow) = snapshot.remove(old_name) {
+            let mut flow = Arc::unwrap_or_clone(flow);
+            flow.name = new_name.to_string();
+            snapshot.insert(new_name.to_string(), Arc::new(flow));
+        }
+        changed.extend(apply_update(&mut snapshot, update));
+        self.publish(snapshot, changed, events);
+        Ok(())
+    }
+
+    // Swap in the new snapshot and tell subscribers about every flow it changed
+    fn publish(&self, snapshot: Snapshot, changed: BTreeSet<String>, events: &FlowEvents) {
+        self.current.store(Arc::new(snapshot));
+        for name in changed {
+            events.send(FlowEvent {
+                revision: self.revision(&name),
+                flow: name,
+            });
+        }
+    }
+}
+
+// Replace what the update covers and return the flows it covered. Every flow whose
+// keys are replaced must be among `update.flows`, or it would serve the new keys
+// under its old revision.
+fn apply_update(snapshot: &mut Snapshot, update: FlowUpdate) -> BTreeSet<String> {
+    let mut changed = BTreeSet::new();
+
+    let hosts: HashSet<&str> = update.hosts.iter().map(String::as_str).collect();
+    for flow in snapshot.values_mut() {
+        if hosts.iter().any(|host| flow.holds_host(host)) {
+            let flow = Arc::make_mut(flow);
+            for host in &hosts {
+                flow.remove_host(host);
+            }
+            changed.insert(flow.name.clone());
+        }
+    }
+    for (name, key) in update.keys {
+        changed.insert(name.clone());
+        Arc::make_mut(
+            snapshot
+                .entry(name.clone())
+                .or_insert_with(|| Arc::new(FlowIndex::new(name))),
+        )
+        .insert(key);
+    }
+
+    for name in update.flows {
+        let flow = Arc::make_mut(
+            snapshot
+                .entry(name.clone())
+                .or_insert_with(|| Arc::new(FlowIndex::new(name.clone()))),
+        );
+        flow.cert_authorities = update
+            .cert_authorities
+            .iter()
+            .filter(|cert_authority| cert_authority.flow == name)
+            .cloned()
+            .collect();
+        flow.revision = update.revisions.get(&name).copied().unwrap_or(0);
+        changed.insert(name);
+    }
+
+    changed
+}
@@ -1,4 +1,4 @@
-use crate::gui::api::{fetch_keys, SshKey};
+use crate::gui::api::{fetch_flows, fetch_keys, FlowInfo, SshKey};
 use crate::gui::common::KhmSettings;
 use eframe::egui;
 use log::{error, info};
@@ -13,9 +13,18 @@ pub enum AdminOperation {
     DeletingKey,
     BulkDeprecating,
     BulkRestoring,
+    ManagingFlow,
     None,
 }
 
+/// Flow being edited in the flow management panel
+#[derive(Debug, Clone)]
+pub struct FlowEdit {
+    pub original_name: String,
+    pub name: String,
+    pub description: String,
+}
+
 #[derive(Debug, Clone)]
 pub struct AdminState {
     pub keys: Vec<SshKey>,
@@ -26,6 +35,11 @@ pub struct AdminState {
     pub expanded_servers: HashMap<String, bool>,
     pub current_operation: AdminOperation,
     pub last_load_time: Option<std::time::Instant>,
+    pub flows: Vec<FlowInfo>,
+    pub flows_loaded: bool,
+    pub new_flow_name: String,
+    pub new_flow_description: String,
+    pub editing_flow: Option<FlowEdit>,
 }
 
 impl Default for AdminState {
@@ -39,6 +53,11 @@ impl Default for AdminState {
             expanded_servers: HashMap::new(),
             current_operation: AdminOperation::None,
             last_load_time: None,
+            flows: Vec::new(),
+            flows_loaded: false,
+            new_flow_name: String::new(),
+            new_flow_description: String::new(),
+            editing_flow: None,
         }
     }
 }
@@ -82,11 +101,13 @@ impl AdminState {
         let host = settings.host.clone();
         let flow = settings.flow.clone();
         let basic_auth = settings.basic_auth.clone();
+        let tls_options = settings.tls_options();
         let ctx_clone = ctx.clone();
 
         std::thread::spawn(move || {
             let rt = tokio::runtime::Runtime::new().unwrap();
-            let result = rt.block_on(async { fetch_keys(host, flow, basic_auth).await });
+            let result =
+                rt.block_on(async { fetch_keys(host, flow, basic_auth, tls_options).await });
 
             let _ = tx.send(result);
             ctx_clone.request_repaint();
@@ -112,6 +133,50 @@ impl AdminState {
         }
     }
 
+    /// Load flow metadata from server
+    pub fn load_flows(
+        &mut self,
+        settings: &KhmSettings,
+        ctx: &egui::Context,
+    ) -> Option<mpsc::Receiver<Result<Vec<FlowInfo>, String>>> {
+        if settings.host.is_empty() {
+            return None;
+        }
+
+        // Mark as loaded up front so a failing server isn't polled every frame
+        self.flows_loaded = true;
+
+        let (tx, rx) = mpsc::channel();
+
+        let host = settings.host.clone();
+        let basic_auth = settings.basic_auth.clone();
+        let tls_options = settings.tls_options();
+        let ctx_clone = ctx.clone();
+
+        std::thread::spawn(move || {
+            let rt = tokio::runtime::Runtime::new().unwrap();
+            let result = rt.block_on(async { fetch_flows(host, basic_auth, tls_options).await });
+
+            let _ = tx.send(result);
+            ctx_clone.request_repaint();
+        });
+
+        Some(rx)
+    }
+
+    /// Handle flows load result
+    pub fn handle_flows_loaded(&mut self, result: Result<Vec<FlowInfo>, String>) {
+        match result {
+            Ok(flows) => {
+                info!("Flows loaded successfully: {} flows", flows.len());
+                self.flows = flows;
+            }
+            Err(error) => {
+                error!("Failed to load flows: {}", error);
+            }
+        }
+    }
+
     /// Get selected servers list
     pub fn get_selected_servers(&self) -> Vec<String> {
         self.selected_servers
@@ -1,4 +1,4 @@
-use super::state::{get_key_preview, get_key_type, AdminState};
+use super::state::{get_key_preview, get_key_type, AdminState, FlowEdit};
 use crate::gui::api::SshKey;
 use eframe::egui;
 use std::collections::BTreeMap;
@@ -248,6 +248,138 @@ pub fn render_bulk_actions(ui: &mut egui::Ui, admin_state: &mut AdminState) -> B
     action
 }
 
+/// Render flow management: list, create, rename, describe and archive flows
+pub fn render_flow_management(ui: &mut egui::Ui, admin_state: &mut AdminState) -> FlowAction {
+    let mut action = FlowAction::None;
+
+    ui.group(|ui| {
+        ui.set_min_width(ui.available_width());
+        ui.vertical(|ui| {
+            ui.horizontal(|ui| {
+                ui.label(egui::RichText::new("🗂 Flows").size(16.0).strong());
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    if ui
+                        .small_button("🔁")
+                        .on_hover_text("Reload flows")
+                        .clicked()
+                    {
+                        action = FlowAction::Reload;
+                    }
+                });
+            });
+            ui.add_space(8.0);
+
+            if admin_state.flows.is_empty() {
+                ui.label(
+                    egui::RichText::new("No flows loaded")
+                        .size(11.0)
+                        .color(egui::Color32::GRAY),
+                );
+            }
+
+            let flows = admin_state.flows.clone();
+            for flow in &flows {
+                let is_editing = admin_state
+                    .editing_flow
+                    .as_ref()
+                    .is_some_and(|edit| edit.original_name == flow.name);
+
+                ui.horizontal(|ui| {
+                    if is_editing {
+                        let Some(edit) = admin_state.editing_flow.as_mut() else {
+                            return;
+                        };
+                        ui.add(egui::TextEdit::singleline(&mut edit.name).desired_width(140.0));
+                        ui.add(
+                            egui::TextEdit::singleline(&mut edit.description)
+                                .hint_text("Description")
+                                .desired_width(220.0),
+                        );
+                        if ui.button("💾 Save").clicked() {
+                            action = FlowAction::Save(edit.clone());
+                            admin_state.editing_flow = None;
+                        } else if ui.button("Cancel").clicked() {
+                            admin_state.editing_flow = None;
+                        }
+                        return;
+                    }
+
+                    let name_text = egui::RichText::new(&flow.name).monospace().strong();
+                    if flow.archived {
+                        ui.label(name_text.strikethrough().color(egui::Color32::GRAY));
+                        render_badge(
+                            ui,
+                            "archived",
+                            egui::Color32::from_rgb(231, 76, 60),
+                            egui::Color32::WHITE,
+                        );
+                    } else {
+                        ui.label(name_text);
+                    }
+
+                    if let Some(description) = &flow.description {
+                        ui.label(
+                            egui::RichText::new(description)
+                                .size(11.0)
+                                .color(egui::Color32::GRAY),
+                        );
+                    }
+
+                    ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                        if flow.archived {
+                            if ui.small_button("✅ Unarchive").clicked() {
+                                action = FlowAction::Unarchive(flow.name.clone());
+                            }
+                        } else if ui.small_button("❗ Archive").clicked() {
+                            action = FlowAction::Archive(flow.name.clone());
+                        }
+
+                        if ui.small_button("✏ Edit").clicked() {
+                            admin_state.editing_flow = Some(FlowEdit {
+                                original_name: flow.name.clone(),
+                                name: flow.name.clone(),
+                                description: flow.description.clone().unwrap_or_default(),
+                            });
+                        }
+                    });
+                });
+            }
+
+            ui.add_space(8.0);
+            ui.separator();
+
+            // New flow form
+            ui.horizontal(|ui| {
+                ui.add(
+                    egui::TextEdit::singleline(&mut admin_state.new_flow_name)
+                        .hint_text("New flow name")
+                        .desired_width(140.0),
+                );
+                ui.add(
+                    egui::TextEdit::singleline(&mut admin_state.new_flow_description)
+                        .hint_text("Description (optional)")
+                        .desired_width(220.0),
+                );
+
+                let can_create = !admin_state.new_flow_name.trim().is_empty();
+                if ui
+                    .add_enabled(can_create, egui::Button::new("➕ Create"))
+                    .clicked()
+                {
+                    action = FlowAction::Create {
+                        name: admin_state.new_flow_name.trim().to_string(),
+                        description: admin_state.new_flow_description.trim().to_string(),
+                    };
+                    admin_state.new_flow_name.clear();
+                    admin_state.new_flow_description.clear();
+                }
+            });
+        });
+    });
+
+    action
+}
+
 /// Render keys table grouped by servers
 pub fn render_keys_table(ui: &mut egui::Ui, admin_state: &mut AdminState) -> KeyAction {
     if admin_state.filtered_keys.is_empty() {
@@ -628,3 +760,14 @@ pub enum BulkAction {
     RestoreSelected,
     ClearSelection,
 }
+
+/// Flow management actions
+#[derive(Debug, Clone)]
+pub enum FlowAction {
+    None,
+    Reload,
+    Create { name: String, description: String },
+    Save(FlowEdit),
+    Archive(String),
+    Unarchive(String),
+}
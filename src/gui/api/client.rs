@@ -1,4 +1,5 @@
 use crate::gui::common::{perform_sync, KhmSettings};
+use crate::tls::{self, ClientTlsOptions};
 use log::info;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
@@ -11,12 +12,22 @@ pub struct SshKey {
     pub deprecated: bool,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlowInfo {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub archived: bool,
+}
+
 /// Test connection to KHM server
 #[cfg(feature = "gui")]
 pub async fn test_connection(
     host: String,
     flow: String,
     basic_auth: String,
+    tls_options: ClientTlsOptions,
 ) -> Result<String, String> {
     if host.is_empty() || flow.is_empty() {
         return Err("Host and flow must be specified".to_string());
@@ -25,7 +36,7 @@ pub async fn test_connection(
     let url = format!("{}/{}/keys", host.trim_end_matches('/'), flow);
     info!("Testing connection to: {}", url);
 
-    let client = create_http_client()?;
+    let client = create_http_client(&tls_options)?;
     let mut request = client.get(&url);
 
     request = add_auth_if_needed(request, &basic_auth)?;
@@ -58,6 +69,7 @@ pub async fn fetch_keys(
     host: String,
     flow: String,
     basic_auth: String,
+    tls_options: ClientTlsOptions,
 ) -> Result<Vec<SshKey>, String> {
     if host.is_empty() || flow.is_empty() {
         return Err("Host and flow must be specified".to_string());
@@ -70,7 +82,7 @@ pub async fn fetch_keys(
     );
     info!("Fetching keys from: {}", url);
 
-    let client = create_http_client()?;
+    let client = create_http_client(&tls_options)?;
     let mut request = client.get(&url);
 
     request = add_auth_if_needed(request, &basic_auth)?;
@@ -102,6 +114,7 @@ pub async fn deprecate_key(
     host: String,
     flow: String,
     basic_auth: String,
+    tls_options: ClientTlsOptions,
     server: String,
 ) -> Result<String, String> {
     let url = format!(
@@ -112,7 +125,7 @@ pub async fn deprecate_key(
     );
     info!("Deprecating key for server '{}' at: {}", server, url);
 
-    let client = create_http_client()?;
+    let client = create_http_client(&tls_options)?;
     let mut request = client.delete(&url);
 
     request = add_auth_if_needed(request, &basic_auth)?;
@@ -141,6 +154,7 @@ pub async fn restore_key(
     host: String,
     flow: String,
     basic_auth: String,
+    tls_options: ClientTlsOptions,
     server: String,
 ) -> Result<String, String> {
     let url = format!(
@@ -151,7 +165,7 @@ pub async fn restore_key(
     );
     info!("Restoring key for server '{}' at: {}", server, url);
 
-    let client = create_http_client()?;
+    let client = create_http_client(&tls_options)?;
     let mut request = client.post(&url);
 
     request = add_auth_if_needed(request, &basic_auth)?;
@@ -180,6 +194,7 @@ pub async fn delete_key(
     host: String,
     flow: String,
     basic_auth: String,
+    tls_options: ClientTlsOptions,
     server: String,
 ) -> Result<String, String> {
     let url = format!(
@@ -193,7 +208,7 @@ pub async fn delete_key(
         server, url
     );
 
-    let client = create_http_client()?;
+    let client = create_http_client(&tls_options)?;
     let mut request = client.delete(&url);
 
     request = add_auth_if_needed(request, &basic_auth)?;
@@ -222,12 +237,13 @@ pub async fn bulk_deprecate_servers(
     host: String,
     flow: String,
     basic_auth: String,
+    tls_options: ClientTlsOptions,
     servers: Vec<String>,
 ) -> Result<String, String> {
     let url = format!("{}/{}/bulk-deprecate", host.trim_end_matches('/'), flow);
     info!("Bulk deprecating {} servers at: {}", servers.len(), url);
 
-    let client = create_http_client()?;
+    let client = create_http_client(&tls_options)?;
     let mut request = client.post(&url).json(&serde_json::json!({
         "servers": servers
     }));
@@ -255,12 +271,13 @@ pub async fn bulk_restore_servers(
     host: String,
     flow: String,
     basic_auth: String,
+    tls_options: ClientTlsOptions,
     servers: Vec<String>,
 ) -> Result<String, String> {
     let url = format!("{}/{}/bulk-restore", host.trim_end_matches('/'), flow);
     info!("Bulk restoring {} servers at: {}", servers.len(), url);
 
-    let client = create_http_client()?;
+    let client = create_http_client(&tls_options)?;
     let mut request = client.post(&url).json(&serde_json::json!({
         "servers": servers
     }));
@@ -282,6 +299,133 @@ pub async fn bulk_restore_servers(
     parse_api_response(&body, "Successfully restored servers")
 }
 
+/// Fetch all flows with their metadata, archived ones included
+#[cfg(feature = "gui")]
+pub async fn fetch_flows(
+    host: String,
+    basic_auth: String,
+    tls_options: ClientTlsOptions,
+) -> Result<Vec<FlowInfo>, String> {
+    let url = format!("{}/api/flows?details=true", host.trim_end_matches('/'));
+    info!("Fetching flows from: {}", url);
+
+    let client = create_http_client(&tls_options)?;
+    let mut request = client.get(&url);
+
+    request = add_auth_if_needed(request, &basic_auth)?;
+
+    let response = request
+        .send()
+        .await
+        .map_err(|e| format!("Request failed: {}", e))?;
+
+    check_response_status(&response)?;
+
+    let body = response
+        .text()
+        .await
+        .map_err(|e| format!("Failed to read response: {}", e))?;
+
+    check_html_response(&body)?;
+
+    let flows: Vec<FlowInfo> =
+        serde_json::from_str(&body).map_err(|e| format!("Failed to parse response: {}", e))?;
+
+    info!("Fetched {} flows", flows.len());
+    Ok(flows)
+}
+
+/// Create a new flow
+#[cfg(feature = "gui")]
+pub async fn create_flow(
+    host: String,
+    basic_auth: String,
+    tls_options: ClientTlsOptions,
+    name: String,
+    description: String,
+) -> Result<String, String> {
+    let url = format!("{}/api/flows", host.trim_end_matches('/'));
+    info!("Creating flow '{}' at: {}", name, url);
+
+    let client = create_http_client(&tls_options)?;
+    let mut request = client.post(&url).json(&serde_json::json!({
+        "name": name,
+        "description": description
+    }));
+
+    request = add_auth_if_needed(request, &basic_auth)?;
+
+    let response = request
+        .send()
+        .await
+        .map_err(|e| format!("Request failed: {}", e))?;
+
+    check_response_status(&response)?;
+
+    Ok(format!("Created flow '{}'", name))
+}
+
+/// Rename, describe or unarchive a flow
+#[cfg(feature = "gui")]
+pub async fn update_flow(
+    host: String,
+    basic_auth: String,
+    tls_options: ClientTlsOptions,
+    name: String,
+    changes: serde_json::Value,
+) -> Result<String, String> {
+    let url = format!(
+        "{}/api/flows/{}",
+        host.trim_end_matches('/'),
+        urlencoding::encode(&name)
+    );
+    info!("Updating flow '{}' at: {}", name, url);
+
+    let client = create_http_client(&tls_options)?;
+    let mut request = client.patch(&url).json(&changes);
+
+    request = add_auth_if_needed(request, &basic_auth)?;
+
+    let response = request
+        .send()
+        .await
+        .map_err(|e| format!("Request failed: {}", e))?;
+
+    check_response_status(&response)?;
+
+    Ok(format!("Updated flow '{}'", name))
+}
+
+/// Archive a flow so it no longer accepts keys
+#[cfg(feature = "gui")]
+pub async fn archive_flow(
+    host: String,
+    basic_auth: String,
+    tls_options: ClientTlsOptions,
+    name: String,
+) -> Result<String, String> {
+    let url = format!(
+        "{}/api/flows/{}",
+        host.trim_end_matches('/'),
+        urlencoding::encode(&name)
+    );
+    info!("Archiving flow '{}' at: {}", name, url);
+
+    let client = create_http_client(&tls_options)?;
+    let mut request = client.delete(&url);
+
+    request = add_auth_if_needed(request, &basic_auth)?;
+
+    let response = request
+        .send()
+        .await
+        .map_err(|e| format!("Request failed: {}", e))?;
+
+    check_response_status(&response)?;
+
+    Ok(format!("Archived flow '{}'", name))
+}
+
 /// Perform manual sync operation
 #[cfg(feature = "gui")]
 pub async fn perform_manual_sync(settings: KhmSettings) -> Result<String, String> {
@@ -297,8 +441,9 @@ pub async fn perform_manual_sync(settings: KhmSettings) -> Result<String, String
 // Helper functions
 
 #[cfg(feature = "gui")]
-fn create_http_client() -> Result<Client, String> {
-    Client::builder()
+fn create_http_client(tls_options: &ClientTlsOptions) -> Result<Client, String> {
+    tls::http_client_builder(tls_options)
+        .map_err(|e| format!("Invalid TLS settings: {}", e))?
         .timeout(std::time::Duration::from_secs(30))
         .redirect(reqwest::redirect::Policy::none())
         .build()
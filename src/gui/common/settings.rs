@@ -14,9 +14,33 @@ use std::path::PathBuf;
 pub struct KhmSettings {
     pub host: String,
     pub flow: String,
+    /// Further flows to sync into the same file, comma-separated
+    #[serde(default)]
+    pub additional_flows: String,
+    /// Comma-separated pattern=flow rules for uploading to several flows
+    #[serde(default)]
+    pub flow_rules: String,
     pub known_hosts: String,
     pub basic_auth: String,
-    pub in_place: bool,
+    #[serde(default)]
+    pub token: String,
+    #[serde(default)]
+    pub ca_cert: String,
+    #[serde(default)]
+    pub client_cert: String,
+    #[serde(default)]
+    pub client_key: String,
+    #[serde(default)]
+    pub pin_sha256: String,
+    /// Replaces the older `in_place` flag, see `migrate_settings`
+    #[serde(default)]
+    pub sync_direction: crate::client::SyncDirection,
+    #[serde(default)]
+    pub upload_hashed: bool,
+    #[serde(default)]
+    pub hash_known_hosts: bool,
+    #[serde(default)]
+    pub merge_mode: crate::client::MergeMode,
     pub auto_sync_interval_minutes: u32,
 }
 
@@ -26,14 +50,53 @@ impl Default for KhmSettings {
         Self {
             host: String::new(),
             flow: String::new(),
+            additional_flows: String::new(),
+            flow_rules: String::new(),
             known_hosts: get_default_known_hosts_path(),
             basic_auth: String::new(),
-            in_place: true,
+            token: String::new(),
+            ca_cert: String::new(),
+            client_cert: String::new(),
+            client_key: String::new(),
+            pin_sha256: String::new(),
+            sync_direction: crate::client::SyncDirection::Both,
+            upload_hashed: false,
+            hash_known_hosts: false,
+            merge_mode: crate::client::MergeMode::Replace,
             auto_sync_interval_minutes: 60,
         }
     }
 }
 
+#[cfg(feature = "gui")]
+impl KhmSettings {
+    /// The main flow followed by the additional ones
+    pub fn flows(&self) -> Vec<String> {
+        let mut flows = vec![self.flow.trim().to_string()];
+        for flow in split_list(&self.additional_flows) {
+            if !flows.contains(&flow) {
+                flows.push(flow);
+            }
+        }
+        flows
+    }
+
+    /// TLS options for the HTTP client; empty fields mean "not configured"
+    pub fn tls_options(&self) -> crate::tls::ClientTlsOptions {
+        let optional_path = |path: &str| {
+            let path = path.trim();
+            (!path.is_empty()).then(|| expand_path(path))
+        };
+
+        crate::tls::ClientTlsOptions {
+            ca_cert: optional_path(&self.ca_cert),
+            client_cert: optional_path(&self.client_cert),
+            client_key: optional_path(&self.client_key),
+            pin_sha256: split_list(&self.pin_sha256),
+        }
+    }
+}
+
 /// Get default known_hosts file path based on OS
 #[cfg(feature = "gui")]
 fn get_default_known_hosts_path() -> String {
@@ -62,10 +125,13 @@ pub fn load_settings() -> KhmSettings {
     let path = get_config_path();
     match fs::read_to_string(&path) {
         Ok(contents) => {
-            let mut settings: KhmSettings = serde_json::from_str(&contents).unwrap_or_else(|e| {
-                error!("Failed to parse KHM config: {}", e);
-                KhmSettings::default()
-            });
+            let mut settings: KhmSettings = serde_json::from_str(&contents)
+                .map(migrate_settings)
+                .and_then(serde_json::from_value)
+                .unwrap_or_else(|e| {
+                    error!("Failed to parse KHM config: {}", e);
+                    KhmSettings::default()
+                });
 
             // Fill in default known_hosts path if empty
             if settings.known_hosts.is_empty() {
@@ -81,6 +147,24 @@ pub fn load_settings() -> KhmSettings {
     }
 }
 
+/// Older configs only had `in_place`: without it the client only uploaded keys
+#[cfg(feature = "gui")]
+fn migrate_settings(mut value: serde_json::Value) -> serde_json::Value {
+    if let Some(settings) = value.as_object_mut() {
+        if let Some(in_place) = settings.remove("in_place") {
+            if !settings.contains_key("sync_direction") {
+                let direction = if in_place.as_bool() == Some(false) {
+                    crate::client::SyncDirection::Push
+                } else {
+                    crate::client::SyncDirection::Both
+                };
+                settings.insert("sync_direction".to_string(), direction.as_str().into());
+            }
+        }
+    }
+    value
+}
+
 /// Save settings to configuration file
 #[cfg(feature = "gui")]
 pub fn save_settings(settings: &KhmSettings) -> Result<(), std::io::Error> {
@@ -91,6 +175,15 @@ pub fn save_settings(settings: &KhmSettings) -> Result<(), std::io::Error> {
     Ok(())
 }
 
+/// Split a comma-separated settings field, skipping empty items
+#[cfg(feature = "gui")]
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
 /// Expand path with ~ substitution
 #[cfg(feature = "gui")]
 pub fn expand_path(path: &str) -> String {
@@ -102,34 +195,61 @@ pub fn expand_path(path: &str) -> String {
     path.to_string()
 }
 
-/// Perform sync operation using KHM client logic
+/// Client arguments equivalent to the settings
 #[cfg(feature = "gui")]
-pub async fn perform_sync(settings: &KhmSettings) -> Result<usize, std::io::Error> {
-    use crate::Args;
-
-    info!(
-        "Starting sync with settings: host={}, flow={}, known_hosts={}, in_place={}",
-        settings.host, settings.flow, settings.known_hosts, settings.in_place
-    );
-
-    // Convert KhmSettings to Args for client module
-    let args = Args {
+pub fn client_args(settings: &KhmSettings) -> crate::Args {
+    let tls_options = settings.tls_options();
+    crate::Args {
         server: false,
         daemon: false,
         settings_ui: false,
-        in_place: settings.in_place,
+        in_place: settings.sync_direction.pulls(),
         flows: vec!["default".to_string()], // Not used in client mode
         ip: "127.0.0.1".to_string(),        // Not used in client mode
         port: 8080,                         // Not used in client mode
+        db_url: None,                       // Not used in client mode
         db_host: "127.0.0.1".to_string(),   // Not used in client mode
         db_name: "khm".to_string(),         // Not used in client mode
         db_user: None,                      // Not used in client mode
         db_password: None,                  // Not used in client mode
+        db_pool_size: 16,                   // Not used in client mode
+        auth_file: None,                    // Not used in client mode
+        admin_users: Vec::new(),            // Not used in client mode
+        tls_cert: None,                     // Not used in client mode
+        tls_key: None,                      // Not used in client mode
+        tls_client_ca: None,                // Not used in client mode
         host: Some(settings.host.clone()),
-        flow: Some(settings.flow.clone()),
+        flow: settings.flows(),
+        flow_rule: split_list(&settings.flow_rules),
         known_hosts: expand_path(&settings.known_hosts),
         basic_auth: settings.basic_auth.clone(),
-    };
+        token: settings.token.clone(),
+        ca_cert: tls_options.ca_cert,
+        client_cert: tls_options.client_cert,
+        client_key: tls_options.client_key,
+        pin_sha256: tls_options.pin_sha256,
+        upload_hashed: settings.upload_hashed,
+        hash_known_hosts: settings.hash_known_hosts,
+        merge_mode: settings.merge_mode,
+        backups: crate::client::DEFAULT_BACKUP_COUNT,
+        dry_run: false,
+        output: Default::default(),
+        push_only: settings.sync_direction == crate::client::SyncDirection::Push,
+        pull_only: settings.sync_direction == crate::client::SyncDirection::Pull,
+        watch: false,
+        poll_interval: settings.auto_sync_interval_minutes as u64 * 60,
+    }
+}
+
+/// Perform sync operation using KHM client logic
+#[cfg(feature = "gui")]
+pub async fn perform_sync(settings: &KhmSettings) -> Result<usize, std::io::Error> {
+    info!(
+        "Starting sync with settings: host={}, flow={}, known_hosts={}, sync_direction={}",
+        settings.host, settings.flow, settings.known_hosts, settings.sync_direction
+    );
+
+    let args = client_args(settings);
 
     info!("Expanded known_hosts path: {}", args.known_hosts);
 
@@ -140,7 +260,7 @@ pub async fn perform_sync(settings: &KhmSettings) -> Result<usize, std::io::Erro
 
     crate::client::run_client(args.clone()).await?;
 
-    let keys_after = if args.in_place {
+    let keys_after = if settings.sync_direction.pulls() {
         crate::client::read_known_hosts(&args.known_hosts)
             .unwrap_or_else(|_| Vec::new())
             .len()
@@ -64,11 +64,13 @@ impl ConnectionTab {
         let host = settings.host.clone();
         let flow = settings.flow.clone();
         let basic_auth = settings.basic_auth.clone();
+        let tls_options = settings.tls_options();
         let ctx_clone = ctx.clone();
 
         std::thread::spawn(move || {
             let rt = tokio::runtime::Runtime::new().unwrap();
-            let result = rt.block_on(async { test_connection(host, flow, basic_auth).await });
+            let result =
+                rt.block_on(async { test_connection(host, flow, basic_auth, tls_options).await });
 
             let _ = tx.send(result);
             ctx_clone.request_repaint();
@@ -41,6 +41,9 @@ pub fn render_connection_tab(
 
                         // Local Configuration Card
                         render_local_config_card(ui, settings);
+
+                        // TLS Configuration Card
+                        render_tls_config_card(ui, settings);
                     },
                 );
 
@@ -214,6 +217,35 @@ fn render_connection_config_card(ui: &mut egui::Ui, settings: &mut KhmSettings)
                 );
             });
 
+            // Additional flows (optional)
+            ui.vertical(|ui| {
+                ui.horizontal(|ui| {
+                    ui.label(egui::RichText::new("Additional Flows").size(13.0).strong());
+                    ui.label(
+                        egui::RichText::new("(optional)")
+                            .size(12.0)
+                            .weak()
+                            .italics(),
+                    );
+                });
+                ui.add_space(3.0);
+                ui.add_sized(
+                    [ui.available_width(), 28.0],
+                    egui::TextEdit::singleline(&mut settings.additional_flows)
+                        .hint_text("staging, development")
+                        .font(egui::FontId::new(14.0, egui::FontFamily::Proportional))
+                        .margin(egui::Margin::symmetric(8.0, 6.0)),
+                );
+                ui.add_space(3.0);
+                ui.add_sized(
+                    [ui.available_width(), 28.0],
+                    egui::TextEdit::singleline(&mut settings.flow_rules)
+                        .hint_text("Upload rules: *.staging.example.com=staging")
+                        .font(egui::FontId::new(14.0, egui::FontFamily::Monospace))
+                        .margin(egui::Margin::symmetric(8.0, 6.0)),
+                );
+            });
+
             // Basic Auth (optional)
             ui.vertical(|ui| {
                 ui.horizontal(|ui| {
@@ -239,6 +271,28 @@ fn render_connection_config_card(ui: &mut egui::Ui, settings: &mut KhmSettings)
                         .margin(egui::Margin::symmetric(8.0, 6.0)),
                 );
             });
+
+            // API token (optional, takes precedence over basic auth)
+            ui.vertical(|ui| {
+                ui.horizontal(|ui| {
+                    ui.label(egui::RichText::new("API Token").size(13.0).strong());
+                    ui.label(
+                        egui::RichText::new("(optional, overrides basic auth)")
+                            .size(12.0)
+                            .weak()
+                            .italics(),
+                    );
+                });
+                ui.add_space(3.0);
+                ui.add_sized(
+                    [ui.available_width(), 28.0],
+                    egui::TextEdit::singleline(&mut settings.token)
+                        .hint_text("khm_...")
+                        .password(true)
+                        .font(egui::FontId::new(14.0, egui::FontFamily::Monospace))
+                        .margin(egui::Margin::symmetric(8.0, 6.0)),
+                );
+            });
         });
     });
 
@@ -287,18 +341,81 @@ fn render_local_config_card(ui: &mut egui::Ui, settings: &mut KhmSettings) {
 
             ui.add_space(8.0);
 
-            // In-place update option with better styling
+            // Sync direction, replaces the former in-place checkbox
+            ui.horizontal(|ui| {
+                ui.label(egui::RichText::new("Sync direction").size(13.0).strong());
+                egui::ComboBox::from_id_salt("sync_direction")
+                    .selected_text(sync_direction_label(settings.sync_direction))
+                    .show_ui(ui, |ui| {
+                        for direction in crate::client::SyncDirection::ALL {
+                            ui.selectable_value(
+                                &mut settings.sync_direction,
+                                direction,
+                                sync_direction_label(direction),
+                            );
+                        }
+                    });
+            });
+            ui.label(
+                egui::RichText::new(
+                    "Push uploads local keys, pull updates the known_hosts file in place",
+                )
+                .size(12.0)
+                .weak()
+                .italics(),
+            );
+
+            ui.add_space(8.0);
+
+            ui.horizontal(|ui| {
+                ui.label(egui::RichText::new("Merge mode").size(13.0).strong());
+                egui::ComboBox::from_id_salt("merge_mode")
+                    .selected_text(settings.merge_mode.as_str())
+                    .show_ui(ui, |ui| {
+                        for mode in crate::client::MergeMode::ALL {
+                            ui.selectable_value(&mut settings.merge_mode, mode, mode.as_str());
+                        }
+                    });
+            });
+            ui.label(
+                egui::RichText::new(
+                    "replace: only server keys · union: also keep local hosts · managed-block: only the # BEGIN/END KHM block",
+                )
+                .size(12.0)
+                .weak()
+                .italics(),
+            );
+
+            ui.add_space(8.0);
+
             ui.horizontal(|ui| {
-                ui.checkbox(&mut settings.in_place, "");
+                ui.checkbox(&mut settings.hash_known_hosts, "");
                 ui.vertical(|ui| {
+                    ui.label(egui::RichText::new("Hash host names").size(13.0).strong());
                     ui.label(
-                        egui::RichText::new("Update file in-place after sync")
+                        egui::RichText::new(
+                            "Write host names hashed like ssh-keygen -H (HashKnownHosts yes)",
+                        )
+                        .size(12.0)
+                        .weak()
+                        .italics(),
+                    );
+                });
+            });
+
+            ui.add_space(8.0);
+
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut settings.upload_hashed, "");
+                ui.vertical(|ui| {
+                    ui.label(
+                        egui::RichText::new("Upload hashed entries")
                             .size(13.0)
                             .strong(),
                     );
                     ui.label(
                         egui::RichText::new(
-                            "Automatically modify the known_hosts file when synchronizing",
+                            "Send entries with hashed host names, which other clients cannot read",
                         )
                         .size(12.0)
                         .weak()
@@ -312,6 +429,104 @@ fn render_local_config_card(ui: &mut egui::Ui, settings: &mut KhmSettings) {
     ui.add_space(8.0);
 }
 
+/// TLS configuration card for servers behind a private PKI
+fn render_tls_config_card(ui: &mut egui::Ui, settings: &mut KhmSettings) {
+    let frame = egui::Frame::group(ui.style())
+        .fill(ui.visuals().faint_bg_color)
+        .stroke(egui::Stroke::new(
+            1.0,
+            ui.visuals().widgets.noninteractive.bg_stroke.color,
+        ))
+        .rounding(6.0)
+        .inner_margin(egui::Margin::same(12.0));
+
+    frame.show(ui, |ui| {
+        // Header
+        ui.horizontal(|ui| {
+            ui.label("🔒");
+            ui.label(egui::RichText::new("TLS").size(14.0).strong());
+            ui.label(
+                egui::RichText::new("(optional)")
+                    .size(12.0)
+                    .weak()
+                    .italics(),
+            );
+        });
+
+        ui.add_space(8.0);
+
+        ui.vertical(|ui| {
+            ui.spacing_mut().item_spacing.y = 8.0;
+
+            render_file_picker(
+                ui,
+                "CA Certificate",
+                "PEM bundle trusted instead of the built-in roots",
+                &mut settings.ca_cert,
+            );
+            render_file_picker(
+                ui,
+                "Client Certificate",
+                "PEM certificate presented to the server",
+                &mut settings.client_cert,
+            );
+            render_file_picker(
+                ui,
+                "Client Key",
+                "PEM private key for the client certificate",
+                &mut settings.client_key,
+            );
+
+            // Certificate pins
+            ui.vertical(|ui| {
+                ui.label(
+                    egui::RichText::new("Pinned SHA-256 Fingerprints")
+                        .size(13.0)
+                        .strong(),
+                );
+                ui.add_space(3.0);
+                ui.add_sized(
+                    [ui.available_width(), 28.0],
+                    egui::TextEdit::singleline(&mut settings.pin_sha256)
+                        .hint_text("AB:CD:..., comma-separated")
+                        .font(egui::FontId::new(14.0, egui::FontFamily::Monospace))
+                        .margin(egui::Margin::symmetric(8.0, 6.0)),
+                );
+            });
+        });
+    });
+
+    ui.add_space(8.0);
+}
+
+/// Path input with a "Browse" button opening a native file dialog
+fn render_file_picker(ui: &mut egui::Ui, label: &str, hint: &str, path: &mut String) {
+    ui.vertical(|ui| {
+        ui.label(egui::RichText::new(label).size(13.0).strong());
+        ui.add_space(3.0);
+        ui.horizontal(|ui| {
+            let browse_width = 80.0;
+            ui.add_sized(
+                [ui.available_width() - browse_width - 6.0, 28.0],
+                egui::TextEdit::singleline(path)
+                    .hint_text(hint)
+                    .font(egui::FontId::new(14.0, egui::FontFamily::Monospace))
+                    .margin(egui::Margin::symmetric(8.0, 6.0)),
+            );
+
+            if ui
+                .add_sized([browse_width, 28.0], egui::Button::new("Browse…"))
+                .clicked()
+            {
+                let dialog = rfd::FileDialog::new().add_filter("PEM", &["pem", "crt", "key"]);
+                if let Some(selected) = dialog.pick_file() {
+                    *path = selected.to_string_lossy().to_string();
+                }
+            }
+        });
+    });
+}
+
 /// Auto-sync configuration card
 fn render_auto_sync_card(
     ui: &mut egui::Ui,
@@ -328,8 +543,9 @@ fn render_auto_sync_card(
         .inner_margin(egui::Margin::same(12.0));
 
     frame.show(ui, |ui| {
-        let is_auto_sync_enabled =
-            !settings.host.is_empty() && !settings.flow.is_empty() && settings.in_place;
+        let is_auto_sync_enabled = !settings.host.is_empty()
+            && !settings.flow.is_empty()
+            && settings.sync_direction.pulls();
 
         // Header with status
         ui.horizontal(|ui| {
@@ -384,7 +600,7 @@ fn render_auto_sync_card(
 
             let host_ok = !settings.host.is_empty();
             let flow_ok = !settings.flow.is_empty();
-            let in_place_ok = settings.in_place;
+            let in_place_ok = settings.sync_direction.pulls();
 
             ui.horizontal(|ui| {
                 let (icon, color) = if host_ok {
@@ -413,7 +629,7 @@ fn render_auto_sync_card(
                     ("❌", egui::Color32::RED)
                 };
                 ui.label(egui::RichText::new(icon).color(color));
-                ui.label(egui::RichText::new("In-place update").size(11.0));
+                ui.label(egui::RichText::new("Sync direction pulls").size(11.0));
             });
         });
     });
@@ -636,3 +852,11 @@ pub fn add_log_entry(operation_log: &mut Vec<String>, message: String) {
         operation_log.remove(0);
     }
 }
+
+fn sync_direction_label(direction: crate::client::SyncDirection) -> &'static str {
+    match direction {
+        crate::client::SyncDirection::Both => "Push and pull",
+        crate::client::SyncDirection::Pull => "Pull only",
+        crate::client::SyncDirection::Push => "Push only",
+    }
+}
@@ -1,11 +1,12 @@
 use crate::gui::admin::{
-    render_bulk_actions, render_keys_table, render_search_controls, render_statistics,
-    AdminOperation, AdminState, BulkAction, KeyAction,
+    render_bulk_actions, render_flow_management, render_keys_table, render_search_controls,
+    render_statistics, AdminOperation, AdminState, BulkAction, FlowAction, KeyAction,
 };
 use crate::gui::api::{
-    bulk_deprecate_servers, bulk_restore_servers, delete_key, deprecate_key, restore_key, SshKey,
+    archive_flow, bulk_deprecate_servers, bulk_restore_servers, create_flow, delete_key,
+    deprecate_key, restore_key, update_flow, FlowInfo, SshKey,
 };
-use crate::gui::common::{load_settings, KhmSettings};
+use crate::gui::common::{load_settings, save_settings, KhmSettings};
 use eframe::egui;
 use log::info;
 use std::sync::mpsc;
@@ -20,7 +21,10 @@ pub struct SettingsWindow {
     connection_tab: ConnectionTab,
     admin_state: AdminState,
     admin_receiver: Option<mpsc::Receiver<Result<Vec<SshKey>, String>>>,
+    flows_receiver: Option<mpsc::Receiver<Result<Vec<FlowInfo>, String>>>,
     operation_receiver: Option<mpsc::Receiver<Result<String, String>>>,
+    // Old and new name of a flow rename in progress
+    pending_flow_rename: Option<(String, String)>,
     operation_log: Vec<String>,
 }
 
@@ -36,7 +40,9 @@ impl SettingsWindow {
             connection_tab: ConnectionTab::default(),
             admin_state: AdminState::default(),
             admin_receiver: None,
+            flows_receiver: None,
             operation_receiver: None,
+            pending_flow_rename: None,
             operation_log: Vec::new(),
         };
 
@@ -225,15 +231,38 @@ impl SettingsWindow {
             }
         }
 
+        // Check for flows loading result
+        if let Some(receiver) = &self.flows_receiver {
+            if let Ok(result) = receiver.try_recv() {
+                if let Err(error) = &result {
+                    add_log_entry(
+                        &mut self.operation_log,
+                        format!("❌ Failed to load flows: {}", error),
+                    );
+                }
+                self.admin_state.handle_flows_loaded(result);
+                self.flows_receiver = None;
+                ctx.request_repaint();
+            }
+        }
+
         // Check for operation results
         if let Some(receiver) = &self.operation_receiver {
             if let Ok(result) = receiver.try_recv() {
+                let was_flow_operation = matches!(
+                    self.admin_state.current_operation,
+                    AdminOperation::ManagingFlow
+                );
                 match result {
                     Ok(message) => {
                         info!("Operation completed: {}", message);
                         add_log_entry(&mut self.operation_log, format!("✅ {}", message));
+                        self.follow_flow_rename();
                         // Reload keys after operation
                         self.load_admin_keys(ctx);
+                        if was_flow_operation {
+                            self.load_admin_flows(ctx);
+                        }
                     }
                     Err(error) => {
                         add_log_entry(
@@ -242,6 +271,7 @@ impl SettingsWindow {
                         );
                     }
                 }
+                self.pending_flow_rename = None;
                 self.admin_state.current_operation = AdminOperation::None;
                 self.operation_receiver = None;
                 ctx.request_repaint();
@@ -307,6 +337,14 @@ impl SettingsWindow {
             return;
         }
 
+        // Flow management
+        if !self.admin_state.flows_loaded {
+            self.load_admin_flows(ctx);
+        }
+        let flow_action = render_flow_management(ui, &mut self.admin_state);
+        self.handle_flow_action(flow_action, ctx);
+        ui.add_space(10.0);
+
         // Statistics section
         render_statistics(ui, &self.admin_state);
         ui.add_space(10.0);
@@ -339,6 +377,60 @@ impl SettingsWindow {
         }
     }
 
+    fn load_admin_flows(&mut self, ctx: &egui::Context) {
+        if let Some(receiver) = self.admin_state.load_flows(&self.settings, ctx) {
+            self.flows_receiver = Some(receiver);
+        }
+    }
+
+    /// Keep syncing against a flow that was renamed from this window
+    fn follow_flow_rename(&mut self) {
+        let Some((old_name, new_name)) = self.pending_flow_rename.take() else {
+            return;
+        };
+        if self.settings.flow != old_name {
+            return;
+        }
+
+        self.settings.flow = new_name.clone();
+        match save_settings(&self.settings) {
+            Ok(()) => add_log_entry(
+                &mut self.operation_log,
+                format!("Switched configured flow to '{}'", new_name),
+            ),
+            Err(e) => add_log_entry(
+                &mut self.operation_log,
+                format!("❌ Failed to save settings: {}", e),
+            ),
+        }
+    }
+
+    fn handle_flow_action(&mut self, action: FlowAction, ctx: &egui::Context) {
+        match action {
+            FlowAction::Reload => {
+                self.load_admin_flows(ctx);
+            }
+            FlowAction::Create { name, description } => {
+                self.start_create_flow(name, description, ctx);
+            }
+            FlowAction::Save(edit) => {
+                let mut changes = serde_json::json!({ "description": edit.description });
+                if edit.name != edit.original_name {
+                    changes["name"] = serde_json::json!(edit.name);
+                    self.pending_flow_rename = Some((edit.original_name.clone(), edit.name));
+                }
+                self.start_update_flow(edit.original_name, changes, ctx);
+            }
+            FlowAction::Archive(name) => {
+                self.start_archive_flow(name, ctx);
+            }
+            FlowAction::Unarchive(name) => {
+                self.start_update_flow(name, serde_json::json!({ "archived": false }), ctx);
+            }
+            FlowAction::None => {}
+        }
+    }
+
     fn handle_bulk_action(&mut self, action: BulkAction, ctx: &egui::Context) {
         match action {
             BulkAction::DeprecateSelected => {
@@ -388,12 +480,14 @@ impl SettingsWindow {
         let host = self.settings.host.clone();
         let flow = self.settings.flow.clone();
         let basic_auth = self.settings.basic_auth.clone();
+        let tls_options = self.settings.tls_options();
         let ctx_clone = ctx.clone();
 
         std::thread::spawn(move || {
             let rt = tokio::runtime::Runtime::new().unwrap();
-            let result = rt
-                .block_on(async { bulk_deprecate_servers(host, flow, basic_auth, servers).await });
+            let result = rt.block_on(async {
+                bulk_deprecate_servers(host, flow, basic_auth, tls_options, servers).await
+            });
 
             let _ = tx.send(result);
             ctx_clone.request_repaint();
@@ -413,12 +507,14 @@ impl SettingsWindow {
         let host = self.settings.host.clone();
         let flow = self.settings.flow.clone();
         let basic_auth = self.settings.basic_auth.clone();
+        let tls_options = self.settings.tls_options();
         let ctx_clone = ctx.clone();
 
         std::thread::spawn(move || {
             let rt = tokio::runtime::Runtime::new().unwrap();
-            let result =
-                rt.block_on(async { bulk_restore_servers(host, flow, basic_auth, servers).await });
+            let result = rt.block_on(async {
+                bulk_restore_servers(host, flow, basic_auth, tls_options, servers).await
+            });
 
             let _ = tx.send(result);
             ctx_clone.request_repaint();
@@ -438,13 +534,15 @@ impl SettingsWindow {
         let host = self.settings.host.clone();
         let flow = self.settings.flow.clone();
         let basic_auth = self.settings.basic_auth.clone();
+        let tls_options = self.settings.tls_options();
         let server_name = server.to_string();
         let ctx_clone = ctx.clone();
 
         std::thread::spawn(move || {
             let rt = tokio::runtime::Runtime::new().unwrap();
-            let result =
-                rt.block_on(async { deprecate_key(host, flow, basic_auth, server_name).await });
+            let result = rt.block_on(async {
+                deprecate_key(host, flow, basic_auth, tls_options, server_name).await
+            });
 
             let _ = tx.send(result);
             ctx_clone.request_repaint();
@@ -464,13 +562,15 @@ impl SettingsWindow {
         let host = self.settings.host.clone();
         let flow = self.settings.flow.clone();
         let basic_auth = self.settings.basic_auth.clone();
+        let tls_options = self.settings.tls_options();
         let server_name = server.to_string();
         let ctx_clone = ctx.clone();
 
         std::thread::spawn(move || {
             let rt = tokio::runtime::Runtime::new().unwrap();
-            let result =
-                rt.block_on(async { restore_key(host, flow, basic_auth, server_name).await });
+            let result = rt.block_on(async {
+                restore_key(host, flow, basic_auth, tls_options, server_name).await
+            });
 
             let _ = tx.send(result);
             ctx_clone.request_repaint();
@@ -490,13 +590,92 @@ impl SettingsWindow {
         let host = self.settings.host.clone();
         let flow = self.settings.flow.clone();
         let basic_auth = self.settings.basic_auth.clone();
+        let tls_options = self.settings.tls_options();
         let server_name = server.to_string();
         let ctx_clone = ctx.clone();
 
+        std::thread::spawn(move || {
+            let rt = tokio::runtime::Runtime::new().unwrap();
+            let result = rt.block_on(async {
+                delete_key(host, flow, basic_auth, tls_options, server_name).await
+            });
+
+            let _ = tx.send(result);
+            ctx_clone.request_repaint();
+        });
+    }
+
+    fn start_create_flow(&mut self, name: String, description: String, ctx: &egui::Context) {
+        self.admin_state.current_operation = AdminOperation::ManagingFlow;
+        add_log_entry(
+            &mut self.operation_log,
+            format!("Creating flow '{}'...", name),
+        );
+
+        let (tx, rx) = mpsc::channel();
+        self.operation_receiver = Some(rx);
+
+        let host = self.settings.host.clone();
+        let basic_auth = self.settings.basic_auth.clone();
+        let tls_options = self.settings.tls_options();
+        let ctx_clone = ctx.clone();
+
+        std::thread::spawn(move || {
+            let rt = tokio::runtime::Runtime::new().unwrap();
+            let result = rt.block_on(async {
+                create_flow(host, basic_auth, tls_options, name, description).await
+            });
+
+            let _ = tx.send(result);
+            ctx_clone.request_repaint();
+        });
+    }
+
+    fn start_update_flow(&mut self, name: String, changes: serde_json::Value, ctx: &egui::Context) {
+        self.admin_state.current_operation = AdminOperation::ManagingFlow;
+        add_log_entry(
+            &mut self.operation_log,
+            format!("Updating flow '{}'...", name),
+        );
+
+        let (tx, rx) = mpsc::channel();
+        self.operation_receiver = Some(rx);
+
+        let host = self.settings.host.clone();
+        let basic_auth = self.settings.basic_auth.clone();
+        let tls_options = self.settings.tls_options();
+        let ctx_clone = ctx.clone();
+
+        std::thread::spawn(move || {
+            let rt = tokio::runtime::Runtime::new().unwrap();
+            let result = rt.block_on(async {
+                update_flow(host, basic_auth, tls_options, name, changes).await
+            });
+
+            let _ = tx.send(result);
+            ctx_clone.request_repaint();
+        });
+    }
+
+    fn start_archive_flow(&mut self, name: String, ctx: &egui::Context) {
+        self.admin_state.current_operation = AdminOperation::ManagingFlow;
+        add_log_entry(
+            &mut self.operation_log,
+            format!("Archiving flow '{}'...", name),
+        );
+
+        let (tx, rx) = mpsc::channel();
+        self.operation_receiver = Some(rx);
+
+        let host = self.settings.host.clone();
+        let basic_auth = self.settings.basic_auth.clone();
+        let tls_options = self.settings.tls_options();
+        let ctx_clone = ctx.clone();
+
         std::thread::spawn(move || {
             let rt = tokio::runtime::Runtime::new().unwrap();
             let result =
-                rt.block_on(async { delete_key(host, flow, basic_auth, server_name).await });
+                rt.block_on(async { archive_flow(host, basic_auth, tls_options, name).await });
 
             let _ = tx.send(result);
             ctx_clone.request_repaint();
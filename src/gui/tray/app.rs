@@ -258,7 +258,8 @@ impl TrayApplication {
 
     fn handle_update_menu(&mut self) {
         let settings = self.settings.lock().unwrap();
-        if !settings.host.is_empty() && !settings.flow.is_empty() && settings.in_place {
+        if !settings.host.is_empty() && !settings.flow.is_empty() && settings.sync_direction.pulls()
+        {
             let mut sync_status = self.sync_status.lock().unwrap();
             update_sync_status(&settings, &mut sync_status);
         }
@@ -1,4 +1,5 @@
-use crate::gui::common::{perform_sync, KhmSettings};
+use crate::client::watch_flows;
+use crate::gui::common::{client_args, perform_sync, KhmSettings};
 use log::{error, info};
 use std::sync::{Arc, Mutex};
 use tray_icon::{
@@ -66,7 +67,7 @@ pub fn create_tray_icon(
     menu.append(&MenuItem::new(flow_text, false, None)).unwrap();
 
     let is_auto_sync_enabled =
-        !settings.host.is_empty() && !settings.flow.is_empty() && settings.in_place;
+        !settings.host.is_empty() && !settings.flow.is_empty() && settings.sync_direction.pulls();
     let sync_text = format!(
         "Auto sync: {} ({}min)",
         if is_auto_sync_enabled { "On" } else { "Off" },
@@ -142,7 +143,7 @@ pub fn update_tray_menu(tray_icon: &TrayIcon, settings: &KhmSettings) -> TrayMen
     menu.append(&MenuItem::new(flow_text, false, None)).unwrap();
 
     let is_auto_sync_enabled =
-        !settings.host.is_empty() && !settings.flow.is_empty() && settings.in_place;
+        !settings.host.is_empty() && !settings.flow.is_empty() && settings.sync_direction.pulls();
     let sync_text = format!(
         "Auto sync: {} ({}min)",
         if is_auto_sync_enabled { "On" } else { "Off" },
@@ -220,10 +221,10 @@ pub fn start_auto_sync_task(
 ) -> Option<std::thread::JoinHandle<()>> {
     let initial_settings = settings.lock().unwrap().clone();
 
-    // Only start auto sync if settings are valid and in_place is enabled
+    // Only start auto sync if settings are valid and the file is written
     if initial_settings.host.is_empty()
         || initial_settings.flow.is_empty()
-        || !initial_settings.in_place
+        || !initial_settings.sync_direction.pulls()
     {
         info!("Auto sync disabled or settings invalid");
         return None;
@@ -235,11 +236,12 @@ pub fn start_auto_sync_task(
     );
 
     let handle = std::thread::spawn(move || {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+
         // Initial sync on startup
         info!("Performing initial sync on startup");
         let current_settings = settings.lock().unwrap().clone();
         if !current_settings.host.is_empty() && !current_settings.flow.is_empty() {
-            let rt = tokio::runtime::Runtime::new().unwrap();
             rt.block_on(async {
                 match perform_sync(&current_settings).await {
                     Ok(keys_count) => {
@@ -266,22 +268,46 @@ pub fn start_auto_sync_task(
             let _ = timer_sender.send_event(crate::gui::UserEvent::UpdateMenu);
         });
 
+        // Sync as soon as the server reports a change; the interval stays as a fallback
+        let mut changes = match rt.block_on(async { watch_flows(&client_args(&current_settings)) })
+        {
+            Ok(changes) => Some(changes),
+            Err(e) => {
+                error!("Failed to watch the server for changes: {}", e);
+                None
+            }
+        };
+
         // Periodic sync
         loop {
             let interval_minutes = current_settings.auto_sync_interval_minutes;
-            std::thread::sleep(std::time::Duration::from_secs(interval_minutes as u64 * 60));
+            let interval = std::time::Duration::from_secs(interval_minutes as u64 * 60);
+            let changed = rt.block_on(async {
+                match changes.as_mut() {
+                    Some(changes) => tokio::time::timeout(interval, changes.recv())
+                        .await
+                        .is_ok_and(|change| change.is_some()),
+                    None => {
+                        tokio::time::sleep(interval).await;
+                        false
+                    }
+                }
+            });
 
             let current_settings = settings.lock().unwrap().clone();
             if current_settings.host.is_empty()
                 || current_settings.flow.is_empty()
-                || !current_settings.in_place
+                || !current_settings.sync_direction.pulls()
             {
-                info!("Auto sync stopped due to invalid settings or disabled in_place");
+                info!("Auto sync stopped due to invalid settings or push-only sync");
                 break;
             }
 
-            info!("Performing scheduled auto sync");
-            let rt = tokio::runtime::Runtime::new().unwrap();
+            if changed {
+                info!("Performing auto sync after a change on the server");
+            } else {
+                info!("Performing scheduled auto sync");
+            }
             rt.block_on(async {
                 match perform_sync(&current_settings).await {
                     Ok(keys_count) => {
@@ -304,7 +330,7 @@ pub fn start_auto_sync_task(
 
 /// Update sync status for tooltip
 pub fn update_sync_status(settings: &KhmSettings, sync_status: &mut SyncStatus) {
-    if !settings.host.is_empty() && !settings.flow.is_empty() && settings.in_place {
+    if !settings.host.is_empty() && !settings.flow.is_empty() && settings.sync_direction.pulls() {
         if let Some(last_sync) = sync_status.last_sync_time {
             let elapsed = last_sync.elapsed().as_secs();
             let interval_seconds = settings.auto_sync_interval_minutes as u64 * 60;
@@ -0,0 +1,490 @@
+use base64::{engine::general_purpose, Engine as _};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use std::fmt;
+use std::str::FromStr;
+
+type HmacSha1 = Hmac<Sha1>;
+
+// Prefix of host names hashed by OpenSSH (HashKnownHosts yes / ssh-keygen -H)
+pub const HASHED_HOST_MAGIC: &str = "|1|";
+
+const SALT_LEN: usize = 20;
+
+pub fn is_hashed_host(host: &str) -> bool {
+    host.starts_with(HASHED_HOST_MAGIC)
+}
+
+// Hash a host name the way ssh-keygen -H does: |1|base64(salt)|base64(HMAC-SHA1(salt, host))
+pub fn hash_host(host: &str) -> String {
+    let mut salt = [0u8; SALT_LEN];
+    rand::rngs::OsRng.fill_bytes(&mut salt);
+    hash_host_with_salt(host, &salt)
+}
+
+pub fn hash_host_with_salt(host: &str, salt: &[u8]) -> String {
+    let mut mac = HmacSha1::new_from_slice(salt).expect("HMAC accepts keys of any length");
+    mac.update(host.as_bytes());
+    format!(
+        "{}{}|{}",
+        HASHED_HOST_MAGIC,
+        general_purpose::STANDARD.encode(salt),
+        general_purpose::STANDARD.encode(mac.finalize().into_bytes())
+    )
+}
+
+// Check whether a hashed entry was produced from the given host name
+pub fn hashed_host_matches(hashed: &str, host: &str) -> bool {
+    let Some(rest) = hashed.strip_prefix(HASHED_HOST_MAGIC) else {
+        return false;
+    };
+    let Some((salt, hash)) = rest.split_once('|') else {
+        return false;
+    };
+    let (Ok(salt), Ok(hash)) = (
+        general_purpose::STANDARD.decode(salt),
+        general_purpose::STANDARD.decode(hash),
+    ) else {
+        return false;
+    };
+
+    let mut mac = HmacSha1::new_from_slice(&salt).expect("HMAC accepts keys of any length");
+    mac.update(host.as_bytes());
+    mac.verify_slice(&hash).is_ok()
+}
+
+// Markers OpenSSH accepts in front of the host patterns
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum Marker {
+    CertAuthority,
+    Revoked,
+}
+
+impl Marker {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Marker::CertAuthority => "@cert-authority",
+            Marker::Revoked => "@revoked",
+        }
+    }
+}
+
+impl fmt::Display for Marker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Marker {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "@cert-authority" => Ok(Marker::CertAuthority),
+            "@revoked" => Ok(Marker::Revoked),
+            other => Err(format!("Unknown marker: {}", other)),
+        }
+    }
+}
+
+// One entry of the comma-separated host list: `host`, `*.example.com`,
+// `!bastion.example.com`, `[host]:2222` or a hashed `|1|salt|hash`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HostPattern {
+    pub negated: bool,
+    pub host: String,
+    pub port: Option<u16>,
+}
+
+impl HostPattern {
+    pub fn is_hashed(&self) -> bool {
+        is_hashed_host(&self.host)
+    }
+
+    pub fn has_wildcard(&self) -> bool {
+        self.host.contains(['*', '?'])
+    }
+
+    // Name as ssh looks it up: `host` on port 22, `[host]:port` otherwise
+    fn lookup_name(host: &str, port: Option<u16>) -> String {
+        match port {
+            Some(port) if port != 22 => format!("[{}]:{}", host, port),
+            _ => host.to_string(),
+        }
+    }
+
+    pub fn matches(&self, host: &str, port: Option<u16>) -> bool {
+        if self.is_hashed() {
+            return hashed_host_matches(&self.host, &Self::lookup_name(host, port));
+        }
+        if self.port.unwrap_or(22) != port.unwrap_or(22) {
+            return false;
+        }
+        wildcard_match(&self.host.to_ascii_lowercase(), &host.to_ascii_lowercase())
+    }
+}
+
+impl fmt::Display for HostPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.negated {
+            f.write_str("!")?;
+        }
+        match self.port {
+            Some(port) => write!(f, "[{}]:{}", self.host, port),
+            None => f.write_str(&self.host),
+        }
+    }
+}
+
+impl FromStr for HostPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (negated, pattern) = match s.strip_prefix('!') {
+            Some(pattern) => (true, pattern),
+            None => (false, s),
+        };
+        if pattern.is_empty() {
+            return Err(format!("Empty host pattern in '{}'", s));
+        }
+
+        if let Some(bracketed) = pattern.strip_prefix('[') {
+            let (host, port) = bracketed
+                .split_once("]:")
+                .ok_or_else(|| format!("Invalid [host]:port pattern: {}", s))?;
+            let port = port
+                .parse::<u16>()
+                .map_err(|_| format!("Invalid port in host pattern: {}", s))?;
+            if host.is_empty() {
+                return Err(format!("Empty host in host pattern: {}", s));
+            }
+            return Ok(HostPattern {
+                negated,
+                host: host.to_string(),
+                port: Some(port),
+            });
+        }
+
+        Ok(HostPattern {
+            negated,
+            host: pattern.to_string(),
+            port: None,
+        })
+    }
+}
+
+// `*` matches any run of characters and `?` exactly one, as in ssh_config patterns
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            t = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+// Key type and base64 blob of a public key line, without a comment
+pub fn key_material(public_key: &str) -> &str {
+    match public_key.match_indices(' ').nth(1) {
+        Some((end, _)) => &public_key[..end],
+        None => public_key,
+    }
+}
+
+// A key line: `[marker] patterns key-type base64 [comment]`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub marker: Option<Marker>,
+    pub hosts: Vec<HostPattern>,
+    pub key_type: String,
+    pub key: String,
+    pub comment: Option<String>,
+}
+
+impl Entry {
+    // Key type and base64 blob, without the comment
+    pub fn public_key(&self) -> String {
+        format!("{} {}", self.key_type, self.key)
+    }
+
+    pub fn host_list(&self) -> String {
+        self.hosts
+            .iter()
+            .map(|pattern| pattern.to_string())
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    // Negated patterns win over positive ones, as in OpenSSH
+    pub fn matches_host(&self, host: &str, port: Option<u16>) -> bool {
+        let mut matched = false;
+        for pattern in &self.hosts {
+            if pattern.matches(host, port) {
+                if pattern.negated {
+                    return false;
+                }
+                matched = true;
+            }
+        }
+        matched
+    }
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(marker) = self.marker {
+            write!(f, "{} ", marker)?;
+        }
+        write!(f, "{} {} {}", self.host_list(), self.key_type, self.key)?;
+        if let Some(comment) = &self.comment {
+            write!(f, " {}", comment)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Entry {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let line = s.trim();
+        let (first, rest) = next_field(line).ok_or("Empty line")?;
+
+        let (marker, hosts, rest) = if first.starts_with('@') {
+            let (hosts, rest) = next_field(rest).ok_or("Missing host patterns after marker")?;
+            (Some(first.parse::<Marker>()?), hosts, rest)
+        } else {
+            (None, first, rest)
+        };
+
+        let hosts = hosts
+            .split(',')
+            .map(|pattern| pattern.parse::<HostPattern>())
+            .collect::<Result<Vec<_>, _>>()?;
+        let (key_type, rest) = next_field(rest).ok_or("Missing key type")?;
+        let (key, comment) = next_field(rest).ok_or("Missing key data")?;
+
+        Ok(Entry {
+            marker,
+            hosts,
+            key_type: key_type.to_string(),
+            key: key.to_string(),
+            comment: (!comment.is_empty()).then(|| comment.to_string()),
+        })
+    }
+}
+
+// Split off the first whitespace-separated field; the remainder keeps its inner spacing
+fn next_field(s: &str) -> Option<(&str, &str)> {
+    let s = s.trim_start();
+    if s.is_empty() {
+        return None;
+    }
+    match s.find(char::is_whitespace) {
+        Some(end) => Some((&s[..end], s[end..].trim())),
+        None => Some((s, "")),
+    }
+}
+
+// One line of a known_hosts file. Comments, blank lines and lines that fail to
+// parse are kept verbatim so that a file can be written back unchanged.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Line {
+    Entry(Entry),
+    Comment(String),
+    Blank,
+    Invalid { text: String, error: String },
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Line::Entry(entry) => entry.fmt(f),
+            Line::Comment(text) | Line::Invalid { text, .. } => f.write_str(text),
+            Line::Blank => Ok(()),
+        }
+    }
+}
+
+pub fn parse_line(line: &str) -> Line {
+    let trimmed = line.trim();
+    if trimmed.is_empty() {
+        Line::Blank
+    } else if trimmed.starts_with('#') {
+        Line::Comment(line.to_string())
+    } else {
+        match trimmed.parse::<Entry>() {
+            Ok(entry) => Line::Entry(entry),
+            Err(error) => Line::Invalid {
+                text: line.to_string(),
+                error,
+            },
+        }
+    }
+}
+
+pub fn parse(content: &str) -> Vec<Line> {
+    content.lines().map(parse_line).collect()
+}
+
+pub fn serialize(lines: &[Line]) -> String {
+    let mut content = String::new();
+    for line in lines {
+        content.push_str(&line.to_string());
+        content.push('\n');
+    }
+    content
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // GitHub's published host key, as `ssh-keyscan github.com` prints it
+    const GITHUB_KEY: &str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIOMqqnkVzrm0SdG6UOoqKLsabgH5C9okWi0dh2l9GKJl";
+
+    fn parse_entry(line: &str) -> Entry {
+        match parse_line(line) {
+            Line::Entry(entry) => entry,
+            other => panic!("'{}' is not an entry: {:?}", line, other),
+        }
+    }
+
+    #[test]
+    fn plain_entry_with_comment() {
+        let line = format!("github.com,140.82.121.4 {} github", GITHUB_KEY);
+        let entry = parse_entry(&line);
+        assert_eq!(entry.marker, None);
+        assert_eq!(entry.host_list(), "github.com,140.82.121.4");
+        assert_eq!(entry.public_key(), GITHUB_KEY);
+        assert_eq!(entry.comment.as_deref(), Some("github"));
+        assert!(entry.matches_host("github.com", None));
+        assert!(entry.matches_host("140.82.121.4", Some(22)));
+        assert!(!entry.matches_host("github.com", Some(2222)));
+        assert_eq!(entry.to_string(), line);
+    }
+
+    #[test]
+    fn wildcards() {
+        let entry = parse_entry(&format!("*.example.com,web-?.internal {}", GITHUB_KEY));
+        assert!(entry.hosts.iter().all(HostPattern::has_wildcard));
+        assert!(entry.matches_host("www.example.com", None));
+        assert!(entry.matches_host("a.b.Example.COM", None));
+        assert!(entry.matches_host("web-1.internal", None));
+        assert!(!entry.matches_host("example.com", None));
+        assert!(!entry.matches_host("web-10.internal", None));
+    }
+
+    #[test]
+    fn negated_patterns_win() {
+        let entry = parse_entry(&format!(
+            "*.example.com,!bastion.example.com {}",
+            GITHUB_KEY
+        ));
+        assert!(entry.hosts[1].negated);
+        assert!(entry.matches_host("www.example.com", None));
+        assert!(!entry.matches_host("bastion.example.com", None));
+        // A negation alone matches nothing
+        assert!(
+            !parse_entry(&format!("!bastion.example.com {}", GITHUB_KEY))
+                .matches_host("www.example.com", None)
+        );
+    }
+
+    #[test]
+    fn bracketed_host_and_port() {
+        let line = format!("[git.example.com]:2222,[10.0.0.5]:2222 {}", GITHUB_KEY);
+        let entry = parse_entry(&line);
+        assert_eq!(entry.hosts[0].host, "git.example.com");
+        assert_eq!(entry.hosts[0].port, Some(2222));
+        assert!(entry.matches_host("git.example.com", Some(2222)));
+        assert!(entry.matches_host("10.0.0.5", Some(2222)));
+        assert!(!entry.matches_host("git.example.com", None));
+        assert_eq!(entry.to_string(), line);
+
+        assert!(matches!(
+            parse_line(&format!("[git.example.com]:ssh {}", GITHUB_KEY)),
+            Line::Invalid { .. }
+        ));
+    }
+
+    #[test]
+    fn hashed_hosts() {
+        // Written by `ssh-keygen -H` for github.com and [git.example.com]:2222
+        let github = parse_entry(&format!(
+            "|1|lcVkwjHfN9b/qDuW3bSSybuRZ/c=|hJiHZmUFx/6hZ0R5vofeAU0Xcxg= {}",
+            GITHUB_KEY
+        ));
+        assert!(github.hosts[0].is_hashed());
+        assert!(github.matches_host("github.com", None));
+        assert!(!github.matches_host("gitlab.com", None));
+        assert!(!github.matches_host("github.com", Some(2222)));
+
+        let with_port = parse_entry(&format!(
+            "|1|u+niOTgQsEhZoeVAp+u6LSt5hGg=|1sg12jO492MNdDHxkVD2UFoKGNk= {}",
+            GITHUB_KEY
+        ));
+        assert!(with_port.matches_host("git.example.com", Some(2222)));
+        assert!(!with_port.matches_host("git.example.com", None));
+
+        let hashed = hash_host("github.com");
+        assert!(is_hashed_host(&hashed));
+        assert!(hashed_host_matches(&hashed, "github.com"));
+        assert_ne!(hashed, hash_host("github.com"));
+    }
+
+    #[test]
+    fn markers() {
+        let ca = parse_entry(&format!("@cert-authority *.example.com {} ca", GITHUB_KEY));
+        assert_eq!(ca.marker, Some(Marker::CertAuthority));
+        assert_eq!(ca.host_list(), "*.example.com");
+        assert!(ca.matches_host("db.example.com", None));
+
+        let revoked = parse_entry(&format!("@revoked * {}", GITHUB_KEY));
+        assert_eq!(revoked.marker, Some(Marker::Revoked));
+        assert!(revoked.matches_host("anything.example.org", None));
+        assert_eq!(revoked.to_string(), format!("@revoked * {}", GITHUB_KEY));
+
+        assert!(matches!(
+            parse_line(&format!("@trusted * {}", GITHUB_KEY)),
+            Line::Invalid { .. }
+        ));
+        assert!(matches!(
+            parse_line("@revoked github.com"),
+            Line::Invalid { .. }
+        ));
+    }
+
+    #[test]
+    fn files_are_written_back_unchanged() {
+        let content = format!(
+            "# managed by hand\n\ngithub.com {key}\n@revoked old.example.com {key}\ngitlab.com\n",
+            key = GITHUB_KEY
+        );
+        let lines = parse(&content);
+        assert!(matches!(lines[0], Line::Comment(_)));
+        assert_eq!(lines[1], Line::Blank);
+        assert!(matches!(lines[4], Line::Invalid { .. }));
+        assert_eq!(serialize(&lines), content);
+    }
+}
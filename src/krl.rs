@@ -0,0 +1,61 @@
+use base64::{engine::general_purpose, Engine as _};
+use std::collections::BTreeSet;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// OpenSSH key revocation list (PROTOCOL.krl), usable as sshd/ssh RevokedHostKeys
+const KRL_MAGIC: u64 = 0x5353_484b_524c_0a00;
+const KRL_FORMAT_VERSION: u32 = 1;
+const KRL_SECTION_EXPLICIT_KEY: u8 = 2;
+
+fn put_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_u64(buf: &mut Vec<u8>, value: u64) {
+    buf.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_string(buf: &mut Vec<u8>, value: &[u8]) {
+    put_u32(buf, value.len() as u32);
+    buf.extend_from_slice(value);
+}
+
+// Build a KRL revoking the given `type base64 [comment]` public keys
+pub fn build_krl(public_keys: &[String], comment: &str) -> Result<Vec<u8>, String> {
+    let mut blobs = BTreeSet::new();
+    for public_key in public_keys {
+        let encoded = public_key
+            .split_whitespace()
+            .nth(1)
+            .ok_or_else(|| format!("Missing key data in '{}'", public_key))?;
+        let blob = general_purpose::STANDARD
+            .decode(encoded)
+            .map_err(|e| format!("Invalid key data in '{}': {}", public_key, e))?;
+        blobs.insert(blob);
+    }
+
+    let generated = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+
+    let mut krl = Vec::new();
+    put_u64(&mut krl, KRL_MAGIC);
+    put_u32(&mut krl, KRL_FORMAT_VERSION);
+    put_u64(&mut krl, generated); // krl_version, only needs to grow between lists
+    put_u64(&mut krl, generated);
+    put_u64(&mut krl, 0); // flags
+    put_string(&mut krl, b""); // reserved
+    put_string(&mut krl, comment.as_bytes());
+
+    if !blobs.is_empty() {
+        let mut section = Vec::new();
+        for blob in &blobs {
+            put_string(&mut section, blob);
+        }
+        krl.push(KRL_SECTION_EXPLICIT_KEY);
+        put_string(&mut krl, &section);
+    }
+
+    Ok(krl)
+}
@@ -0,0 +1,1188 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use log::info;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Mutex, MutexGuard};
+
+use crate::audit::{Actor, AuditEntry, AuditQuery, FLOW_CONTENT_ACTIONS, KEY_ACTIONS};
+use crate::auth::{ApiToken, Grant, Role, TokenScope};
+use crate::client::SyncDirection;
+use crate::db::{group_flows, DbError, FlowChanges, FlowKeyStats, FlowUpdate, KeyInsertStats};
+use crate::known_hosts::Marker;
+use crate::migrations::{AppliedMigration, Downgrade, Migration};
+use crate::server::{CertAuthority, Flow, FlowInfo, KeyPolicy, SshKey};
+use crate::storage::Storage;
+
+struct KeyRow {
+    host: String,
+    key: String,
+    comment: Option<String>,
+    deprecated: bool,
+    revoked: bool,
+}
+
+// A row of `flows`: a key in a flow
+struct FlowKeyRow {
+    name: String,
+    key_id: i32,
+    pending: bool,
+}
+
+#[derive(Default)]
+struct Tables {
+    keys: BTreeMap<i32, KeyRow>,
+    flow_keys: Vec<FlowKeyRow>,
+    flow_metadata: BTreeMap<String, FlowInfo>,
+    grants: Vec<Grant>,
+    api_tokens: Vec<ApiToken>,
+    cert_authorities: Vec<CertAuthority>,
+    audit_log: Vec<AuditEntry>,
+    next_key_id: i32,
+    next_token_id: i32,
+    next_ca_id: i32,
+}
+
+// Space-separated part of a public key line: 0 is the type, 1 the key itself
+fn key_part(key: &str, index: usize) -> &str {
+    key.split(' ').nth(index).unwrap_or("")
+}
+
+fn is_revision_action(action: &str) -> bool {
+    KEY_ACTIONS.contains(&action) || FLOW_CONTENT_ACTIONS.contains(&action)
+}
+
+impl Tables {
+    fn log(
+        &mut self,
+        action: &str,
+        flow: &str,
+        server: Option<&str>,
+        public_key: Option<&str>,
+        actor: &Actor,
+        details: Option<&str>,
+    ) {
+        self.audit_log.push(AuditEntry {
+            id: self.audit_log.len() as i64 + 1,
+            at: Utc::now(),
+            action: action.to_string(),
+            flow: Some(flow.to_string()),
+            server: server.map(str::to_string),
+            public_key: public_key.map(str::to_string),
+            actor: actor.user.clone(),
+            client_hostname: actor.client_hostname.clone(),
+            details: details.map(str::to_string),
+        });
+    }
+
+    // Log a change made in `flow` to a shared key for every other flow linking it, whose
+    // revision it moves as well
+    fn log_other_flows(
+        &mut self,
+        action: &str,
+        flow: &str,
+        key_id: i32,
+        actor: &Actor,
+        details: &str,
+    ) {
+        let Some(key) = self.keys.get(&key_id) else {
+            return;
+        };
+        let (host, key) = (key.host.clone(), key.key.clone());
+        let others: Vec<String> = self
+            .flow_keys
+            .iter()
+            .filter(|row| row.key_id == key_id && row.name != flow)
+            .map(|row| row.name.clone())
+            .collect();
+        for other in &others {
+            self.log(action, other, Some(&host), Some(&key), actor, Some(details));
+        }
+    }
+
+    fn key_ids_in_flow(&self, flow: &str) -> HashSet<i32> {
+        self.flow_keys
+            .iter()
+            .filter(|row| row.name == flow)
+            .map(|row| row.key_id)
+            .collect()
+    }
+
+    // Add the flows linking a key of one of `hosts` to `flow_names`
+    fn add_flows_of_hosts(&self, flow_names: &mut Vec<String>, hosts: &[String]) {
+        for row in &self.flow_keys {
+            let linked = self
+                .keys
+                .get(&row.key_id)
+                .is_some_and(|key| hosts.contains(&key.host));
+            if linked && !flow_names.contains(&row.name) {
+                flow_names.push(row.name.clone());
+            }
+        }
+    }
+
+    // Keys, grants, CAs and token scopes move together with the flow
+    fn rename_flow(&mut self, flow_name: &str, new_name: String, actor: &Actor) -> u64 {
+        let Some(mut flow) = self.flow_metadata.remove(flow_name) else {
+            return 0;
+        };
+        flow.name = new_name.clone();
+        self.flow_metadata.insert(new_name.clone(), flow);
+
+        for row in self
+            .flow_keys
+            .iter_mut()
+            .filter(|row| row.name == flow_name)
+        {
+            row.name = new_name.clone();
+        }
+        for grant in self
+            .grants
+            .iter_mut()
+            .filter(|grant| grant.flow == flow_name)
+        {
+            grant.flow = new_name.clone();
+        }
+        for cert_authority in self
+            .cert_authorities
+            .iter_mut()
+            .filter(|cert_authority| cert_authority.flow == flow_name)
+        {
+            cert_authority.flow = new_name.clone();
+        }
+        for flow in self
+            .api_tokens
+            .iter_mut()
+            .flat_map(|token| token.flows.iter_mut())
+            .filter(|flow| **flow == flow_name)
+        {
+            *flow = new_name.clone();
+        }
+
+        let details = format!("renamed from {}", flow_name);
+        self.log("flow_renamed", &new_name, None, None, actor, Some(&details));
+
+        info!("Renamed flow '{}' to '{}'", flow_name, new_name);
+        1
+    }
+
+    fn describe_flow(
+        &mut self,
+        flow_name: &str,
+        description: Option<String>,
+        actor: &Actor,
+    ) -> u64 {
+        let Some(flow) = self.flow_metadata.get_mut(flow_name) else {
+            return 0;
+        };
+        flow.description = description.clone();
+        self.log(
+            "flow_described",
+            flow_name,
+            None,
+            None,
+            actor,
+            description.as_deref(),
+        );
+        1
+    }
+
+    fn archive_flow(&mut self, flow_name: &str, archived: bool, actor: &Actor) -> u64 {
+        let Some(flow) = self.flow_metadata.get_mut(flow_name) else {
+            return 0;
+        };
+        flow.archived = archived;
+        let action = if archived {
+            "flow_archived"
+        } else {
+            "flow_unarchived"
+        };
+        self.log(action, flow_name, None, None, actor, None);
+
+        info!(
+            "{} flow '{}'",
+            if archived { "Archived" } else { "Unarchived" },
+            flow_name
+        );
+        1
+    }
+
+    fn set_key_policy(&mut self, flow_name: &str, key_policy: KeyPolicy, actor: &Actor) -> u64 {
+        let affected = match self.flow_metadata.get_mut(flow_name) {
+            Some(flow) if flow.key_policy != key_policy => {
+                flow.key_policy = key_policy;
+                self.log(
+                    "flow_policy",
+                    flow_name,
+                    None,
+                    None,
+                    actor,
+                    Some(key_policy.as_str()),
+                );
+                1
+            }
+            _ => 0,
+        };
+
+        info!("Set key policy of flow '{}' to '{}'", flow_name, key_policy);
+        affected
+    }
+
+    // Update the keys of `hosts` in a flow that `filter` selects and log each one in
+    // every flow linking it
+    fn update_keys(
+        &mut self,
+        hosts: &[String],
+        flow: &str,
+        filter: impl Fn(&KeyRow) -> bool,
+        update: impl Fn(&mut KeyRow),
+        action: &str,
+        actor: &Actor,
+    ) -> u64 {
+        let key_ids = self.key_ids_in_flow(flow);
+        let mut changed = Vec::new();
+        for (key_id, row) in self.keys.iter_mut() {
+            if key_ids.contains(key_id) && hosts.contains(&row.host) && filter(row) {
+                update(row);
+                changed.push((*key_id, row.host.clone(), row.key.clone()));
+            }
+        }
+        let details = format!("changed in flow {}", flow);
+        for (key_id, host, key) in &changed {
+            self.log(action, flow, Some(host), Some(key), actor, None);
+            self.log_other_flows(action, flow, *key_id, actor, &details);
+        }
+        changed.len() as u64
+    }
+
+    fn deprecate_keys(&mut self, hosts: &[String], flow: &str, actor: &Actor) -> u64 {
+        self.update_keys(
+            hosts,
+            flow,
+            |_| true,
+            |row| row.deprecated = true,
+            "key_deprecated",
+            actor,
+        )
+    }
+
+    fn restore_keys(&mut self, hosts: &[String], flow: &str, actor: &Actor) -> u64 {
+        self.update_keys(
+            hosts,
+            flow,
+            |row| row.deprecated,
+            |row| {
+                row.deprecated = false;
+                row.revoked = false;
+            },
+            "key_restored",
+            actor,
+        )
+    }
+
+    // Under 'tofu' a key is quarantined when the host already has a different active key
+    // of the same type in the flow
+    fn host_key_changed(&self, flow: &str, host: &str, key: &str) -> bool {
+        self.flow_keys
+            .iter()
+            .filter(|row| row.name == flow && !row.pending)
+            .filter_map(|row| self.keys.get(&row.key_id))
+            .any(|known| {
+                !known.deprecated
+                    && known.host == host
+                    && key_part(&known.key, 0) == key_part(key, 0)
+                    && known.key != key
+            })
+    }
+
+    // Indexes into `flow_keys` of the pending keys of a host, all of them without `public_key`
+    fn pending_keys(&self, server: &str, flow: &str, public_key: Option<&str>) -> Vec<usize> {
+        (0..self.flow_keys.len())
+            .filter(|&index| {
+                let row = &self.flow_keys[index];
+                row.name == flow
+                    && row.pending
+                    && self.keys.get(&row.key_id).is_some_and(|key| {
+                        key.host == server
+                            && public_key.is_none_or(|public_key| key.key == public_key)
+                    })
+            })
+            .collect()
+    }
+
+    // Revision of each flow (all of them without a filter): the id of the last audit
+    // row that changed what the flow serves
+    fn flow_revisions(&self, flow_names: Option<&[String]>) -> HashMap<String, i64> {
+        self.flow_metadata
+            .keys()
+            .filter(|name| flow_names.is_none_or(|names| names.contains(name)))
+            .map(|name| {
+                let revision = self
+                    .audit_log
+                    .iter()
+                    .rev()
+                    .find(|entry| {
+                        entry.flow.as_ref() == Some(name) && is_revision_action(&entry.action)
+                    })
+                    .map_or(0, |entry| entry.id);
+                (name.clone(), revision)
+            })
+            .collect()
+    }
+
+    // Keys with their flows, of the given hosts or of every host
+    fn flow_keys(&self, hosts: Option<&[String]>) -> Vec<(String, SshKey)> {
+        self.flow_keys
+            .iter()
+            .filter_map(|row| {
+                let key = self.keys.get(&row.key_id)?;
+                if hosts.is_some_and(|hosts| !hosts.contains(&key.host)) {
+                    return None;
+                }
+                Some((
+                    row.name.clone(),
+                    SshKey {
+                        server: key.host.clone(),
+                        public_key: key.key.clone(),
+                        deprecated: key.deprecated,
+                        pending: row.pending,
+                        marker: key.revoked.then_some(Marker::Revoked),
+                        comment: key.comment.clone(),
+                    },
+                ))
+            })
+            .collect()
+    }
+
+    fn cert_authorities(&self, flow_names: Option<&[String]>) -> Vec<CertAuthority> {
+        self.cert_authorities
+            .iter()
+            .filter(|cert_authority| {
+                flow_names.is_none_or(|names| names.contains(&cert_authority.flow))
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+// Storage that lives only as long as the process, for tests and throwaway servers.
+// Each operation holds one lock for all of its changes, so it is as atomic as a
+// database transaction.
+#[derive(Default)]
+pub struct MemoryStorage {
+    tables: Mutex<Tables>,
+}
+
+impl MemoryStorage {
+    fn tables(&self) -> MutexGuard<'_, Tables> {
+        self.tables.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[async_trait(?Send)]
+impl Storage for MemoryStorage {
+    fn is_available(&self) -> bool {
+        true
+    }
+
+    fn reconnects(&self) -> u64 {
+        0
+    }
+
+    async fn ping(&self) -> Result<(), DbError> {
+        Ok(())
+    }
+
+    // There is no schema to migrate
+    fn migrations(&self) -> &'static [Migration] {
+        &[]
+    }
+
+    async fn migrate_up(&self) -> Result<Vec<&'static Migration>, DbError> {
+        Ok(Vec::new())
+    }
+
+    async fn migrate_down(&self) -> Result<Downgrade, DbError> {
+        Ok(Downgrade::Empty)
+    }
+
+    async fn get_applied_migrations(&self) -> Result<Vec<AppliedMigration>, DbError> {
+        Ok(Vec::new())
+    }
+
+    async fn batch_insert_keys(&self, keys: Vec<SshKey>) -> Result<KeyInsertStats, DbError> {
+        let mut tables = self.tables();
+        let total = keys.len();
+        let mut ignored_deprecated = 0;
+        let mut unchanged_keys = Vec::new();
+        let mut inserted_keys = Vec::new();
+
+        for key in keys {
+            let existing = tables
+                .keys
+                .iter()
+                .find(|(_, row)| row.host == key.server && row.key == key.public_key)
+                .map(|(key_id, row)| (*key_id, row.deprecated));
+            match existing {
+                // Deprecated keys are never brought back by a client sending them again
+                Some((_, true)) => ignored_deprecated += 1,
+                Some((key_id, false)) => unchanged_keys.push((key, key_id)),
+                None => {
+                    tables.next_key_id += 1;
+                    let key_id = tables.next_key_id;
+                    tables.keys.insert(
+                        key_id,
+                        KeyRow {
+                            host: key.server.clone(),
+                            key: key.public_key.clone(),
+                            comment: key.comment.clone(),
+                            deprecated: false,
+                            revoked: false,
+                        },
+                    );
+                    inserted_keys.push((key, key_id));
+                }
+            }
+        }
+
+        let stats = KeyInsertStats {
+            total,
+            inserted: inserted_keys.len(),
+            unchanged: unchanged_keys.len(),
+            key_id_map: unchanged_keys.into_iter().chain(inserted_keys).collect(),
+        };
+
+        info!(
+            "Keys stats: received={}, new={}, unchanged={}, ignored_deprecated={}",
+            stats.total, stats.inserted, stats.unchanged, ignored_deprecated
+        );
+
+        Ok(stats)
+    }
+
+    async fn batch_insert_flow_keys(
+        &self,
+        flow_name: String,
+        key_ids: Vec<i32>,
+        actor: Actor,
+    ) -> Result<FlowKeyStats, DbError> {
+        if key_ids.is_empty() {
+            info!("No keys to associate with flow '{}'", flow_name);
+            return Ok(FlowKeyStats::default());
+        }
+
+        let mut tables = self.tables();
+        let key_policy = tables
+            .flow_metadata
+            .get(&flow_name)
+            .map_or_else(KeyPolicy::default, |flow| flow.key_policy);
+        let existing_key_ids = tables.key_ids_in_flow(&flow_name);
+
+        // Every key is judged against the flow as it was before this batch
+        let mut seen = HashSet::new();
+        let mut existing = 0;
+        let mut additions = Vec::new();
+        for key_id in key_ids {
+            if !seen.insert(key_id) {
+                continue;
+            }
+            if existing_key_ids.contains(&key_id) {
+                existing += 1;
+                continue;
+            }
+            let Some(key) = tables.keys.get(&key_id) else {
+                continue;
+            };
+            let pending = match key_policy {
+                KeyPolicy::Accept => false,
+                KeyPolicy::Strict => true,
+                KeyPolicy::Tofu => tables.host_key_changed(&flow_name, &key.host, &key.key),
+            };
+            additions.push((key_id, key.host.clone(), key.key.clone(), pending));
+        }
+
+        if additions.is_empty() {
+            info!(
+                "All {} keys are already associated with flow '{}'",
+                existing, flow_name
+            );
+            return Ok(FlowKeyStats::default());
+        }
+
+        let mut stats = FlowKeyStats::default();
+        for (key_id, host, key, pending) in additions {
+            tables.flow_keys.push(FlowKeyRow {
+                name: flow_name.clone(),
+                key_id,
+                pending,
+            });
+            let action = if pending {
+                "key_quarantined"
+            } else {
+                "key_added"
+            };
+            tables.log(action, &flow_name, Some(&host), Some(&key), &actor, None);
+            stats.added += 1;
+            if pending {
+                stats.pending += 1;
+            }
+        }
+
+        info!(
+            "Added {} new key-flow associations for flow '{}' ({} pending approval, skipped {} existing)",
+            stats.added, flow_name, stats.pending, existing
+        );
+
+        Ok(stats)
+    }
+
+    async fn get_keys_from_db(&self) -> Result<Vec<Flow>, DbError> {
+        let tables = self.tables();
+        let flows = group_flows(
+            tables.flow_revisions(None),
+            tables.flow_keys(None),
+            tables.cert_authorities(None),
+        );
+        info!("Retrieved {} flows from storage", flows.len());
+        Ok(flows)
+    }
+
+    async fn get_flow_update(
+        &self,
+        mut flow_names: Vec<String>,
+        hosts: Vec<String>,
+    ) -> Result<FlowUpdate, DbError> {
+        let tables = self.tables();
+        tables.add_flows_of_hosts(&mut flow_names, &hosts);
+        Ok(FlowUpdate {
+            keys: tables.flow_keys(Some(&hosts)),
+            cert_authorities: tables.cert_authorities(Some(&flow_names)),
+            revisions: tables.flow_revisions(Some(&flow_names)),
+            hosts,
+            flows: flow_names,
+        })
+    }
+
+    async fn deprecate_key_by_server(
+        &self,
+        server_name: String,
+        flow_name: String,
+        actor: Actor,
+    ) -> Result<u64, DbError> {
+        let affected =
+            self.tables()
+                .deprecate_keys(std::slice::from_ref(&server_name), &flow_name, &actor);
+        info!(
+            "Deprecated {} key(s) for server '{}' in flow '{}'",
+            affected, server_name, flow_name
+        );
+        Ok(affected)
+    }
+
+    async fn bulk_deprecate_keys_by_servers(
+        &self,
+        server_names: Vec<String>,
+        flow_name: String,
+        actor: Actor,
+    ) -> Result<u64, DbError> {
+        let affected = self
+            .tables()
+            .deprecate_keys(&server_names, &flow_name, &actor);
+        info!(
+            "Bulk deprecated {} key(s) for {} servers in flow '{}'",
+            affected,
+            server_names.len(),
+            flow_name
+        );
+        Ok(affected)
+    }
+
+    async fn bulk_restore_keys_by_servers(
+        &self,
+        server_names: Vec<String>,
+        flow_name: String,
+        actor: Actor,
+    ) -> Result<u64, DbError> {
+        let affected = self
+            .tables()
+            .restore_keys(&server_names, &flow_name, &actor);
+        info!(
+            "Bulk restored {} key(s) for {} servers in flow '{}'",
+            affected,
+            server_names.len(),
+            flow_name
+        );
+        Ok(affected)
+    }
+
+    async fn restore_key_by_server(
+        &self,
+        server_name: String,
+        flow_name: String,
+        actor: Actor,
+    ) -> Result<u64, DbError> {
+        let affected =
+            self.tables()
+                .restore_keys(std::slice::from_ref(&server_name), &flow_name, &actor);
+        info!(
+            "Restored {} key(s) for server '{}' in flow '{}'",
+            affected, server_name, flow_name
+        );
+        Ok(affected)
+    }
+
+    async fn permanently_delete_key_by_server(
+        &self,
+        server_name: String,
+        flow_name: String,
+        actor: Actor,
+    ) -> Result<u64, DbError> {
+        let mut tables = self.tables();
+        let tables = &mut *tables;
+
+        let mut removed = Vec::new();
+        tables.flow_keys.retain(|row| {
+            let key = tables.keys.get(&row.key_id);
+            if row.name == flow_name && key.is_some_and(|key| key.host == server_name) {
+                removed.push(row.key_id);
+                false
+            } else {
+                true
+            }
+        });
+
+        // Keys that no other flow references are deleted with the association; other
+        // flows keep the key unchanged, so only this flow is logged
+        let mut orphaned = 0;
+        for key_id in &removed {
+            let referenced = tables.flow_keys.iter().any(|row| row.key_id == *key_id);
+            let key = if referenced {
+                tables.keys.get(key_id).map(|key| key.key.clone())
+            } else {
+                orphaned += 1;
+                tables.keys.remove(key_id).map(|key| key.key)
+            };
+            tables.log(
+                "key_deleted",
+                &flow_name,
+                Some(&server_name),
+                key.as_deref(),
+                &actor,
+                None,
+            );
+        }
+
+        let flow_delete_count = removed.len() as u64;
+        info!(
+            "Permanently deleted {} flow associations and {} orphaned keys for server '{}' in flow '{}'",
+            flow_delete_count, orphaned, server_name, flow_name
+        );
+
+        Ok(std::cmp::max(flow_delete_count, orphaned))
+    }
+
+    async fn get_grants(&self) -> Result<Vec<Grant>, DbError> {
+        let mut grants = self.tables().grants.clone();
+        grants.sort_by(|a, b| (&a.username, &a.flow).cmp(&(&b.username, &b.flow)));
+        info!("Retrieved {} grants from storage", grants.len());
+        Ok(grants)
+    }
+
+    async fn set_grant(
+        &self,
+        username: String,
+        flow_name: String,
+        role: Role,
+        actor: Actor,
+    ) -> Result<(), DbError> {
+        let mut tables = self.tables();
+        let existing = tables
+            .grants
+            .iter_mut()
+            .find(|grant| grant.username == username && grant.flow == flow_name);
+
+        // Re-granting the same role (e.g. --admin-users on every start) is not logged again
+        let changed = match existing {
+            Some(grant) if grant.role == role => false,
+            Some(grant) => {
+                grant.role = role;
+                true
+            }
+            None => {
+                tables.grants.push(Grant {
+                    username: username.clone(),
+                    flow: flow_name.clone(),
+                    role,
+                });
+                true
+            }
+        };
+        if changed {
+            let details = format!("{}={}", username, role.as_str());
+            tables.log("grant_set", &flow_name, None, None, &actor, Some(&details));
+        }
+
+        info!(
+            "Granted '{}' role on flow '{}' to user '{}'",
+            role, flow_name, username
+        );
+        Ok(())
+    }
+
+    async fn revoke_grant(
+        &self,
+        username: String,
+        flow_name: String,
+        actor: Actor,
+    ) -> Result<u64, DbError> {
+        let mut tables = self.tables();
+        let mut revoked = Vec::new();
+        tables.grants.retain(|grant| {
+            if grant.username == username && grant.flow == flow_name {
+                revoked.push(grant.role);
+                false
+            } else {
+                true
+            }
+        });
+        for role in &revoked {
+            let details = format!("{}={}", username, role.as_str());
+            tables.log(
+                "grant_revoked",
+                &flow_name,
+                None,
+                None,
+                &actor,
+                Some(&details),
+            );
+        }
+
+        let affected = revoked.len() as u64;
+        info!(
+            "Revoked {} grant(s) on flow '{}' from user '{}'",
+            affected, flow_name, username
+        );
+        Ok(affected)
+    }
+
+    async fn get_api_tokens(&self) -> Result<Vec<ApiToken>, DbError> {
+        let tokens = self.tables().api_tokens.clone();
+        info!("Retrieved {} API tokens from storage", tokens.len());
+        Ok(tokens)
+    }
+
+    async fn create_api_token(
+        &self,
+        name: String,
+        token_hash: String,
+        scope: TokenScope,
+        expires: Option<DateTime<Utc>>,
+        actor: Actor,
+    ) -> Result<i32, DbError> {
+        let mut tables = self.tables();
+        tables.next_token_id += 1;
+        let token_id = tables.next_token_id;
+        tables.api_tokens.push(ApiToken {
+            id: token_id,
+            name: name.clone(),
+            token_hash,
+            flows: scope.flows.clone(),
+            role: scope.role,
+            direction: scope.direction,
+            created_by: actor.user.clone(),
+            created: Utc::now(),
+            expires,
+            last_used: None,
+            last_hostname: None,
+        });
+
+        let direction = match scope.direction {
+            SyncDirection::Both => String::new(),
+            direction => format!(", {}-only", direction.as_str()),
+        };
+        let details = format!(
+            "token {} ({}, {}{})",
+            token_id,
+            name,
+            scope.role.as_str(),
+            direction
+        );
+        for flow in &scope.flows {
+            tables.log("token_created", flow, None, None, &actor, Some(&details));
+        }
+
+        info!(
+            "Created API token '{}' (id {}) with '{}' role ({}) on flows {:?}",
+            name, token_id, scope.role, scope.direction, scope.flows
+        );
+        Ok(token_id)
+    }
+
+    async fn revoke_api_token(&self, token_id: i32, actor: Actor) -> Result<u64, DbError> {
+        let mut tables = self.tables();
+        let index = tables
+            .api_tokens
+            .iter()
+            .position(|token| token.id == token_id);
+        let affected = match index {
+            Some(index) => {
+                let token = tables.api_tokens.remove(index);
+                let details = format!(
+                    "token {} ({}, {})",
+                    token.id,
+                    token.name,
+                    token.role.as_str()
+                );
+                for flow in &token.flows {
+                    tables.log("token_revoked", flow, None, None, &actor, Some(&details));
+                }
+                1
+            }
+            None => 0,
+        };
+
+        info!("Revoked {} API token(s) with id {}", affected, token_id);
+        Ok(affected)
+    }
+
+    async fn touch_api_token(&self, token_id: i32, hostname: String) -> Result<(), DbError> {
+        if let Some(token) = self
+            .tables()
+            .api_tokens
+            .iter_mut()
+            .find(|token| token.id == token_id)
+        {
+            token.last_used = Some(Utc::now());
+            token.last_hostname = Some(hostname);
+        }
+        Ok(())
+    }
+
+    async fn get_flow_metadata(&self) -> Result<Vec<FlowInfo>, DbError> {
+        let flows: Vec<FlowInfo> = self.tables().flow_metadata.values().cloned().collect();
+        info!("Retrieved metadata for {} flows from storage", flows.len());
+        Ok(flows)
+    }
+
+    async fn create_flow(
+        &self,
+        flow_name: String,
+        description: Option<String>,
+        actor: Actor,
+    ) -> Result<bool, DbError> {
+        let mut tables = self.tables();
+        if tables.flow_metadata.contains_key(&flow_name) {
+            return Ok(false);
+        }
+        tables.flow_metadata.insert(
+            flow_name.clone(),
+            FlowInfo {
+                name: flow_name.clone(),
+                description: description.clone(),
+                archived: false,
+                key_policy: KeyPolicy::default(),
+                created: Utc::now(),
+            },
+        );
+        tables.log(
+            "flow_created",
+            &flow_name,
+            None,
+            None,
+            &actor,
+            description.as_deref(),
+        );
+
+        info!("Created flow '{}'", flow_name);
+        Ok(true)
+    }
+
+    async fn update_flow(
+        &self,
+        flow_name: String,
+        changes: FlowChanges,
+        actor: Actor,
+    ) -> Result<bool, DbError> {
+        let mut tables = self.tables();
+        // Names are unique, as in the flow_metadata table
+        if changes
+            .name
+            .as_ref()
+            .is_some_and(|new_name| tables.flow_metadata.contains_key(new_name))
+        {
+            return Ok(false);
+        }
+        if let Some(description) = changes.description {
+            tables.describe_flow(&flow_name, description, &actor);
+        }
+        if let Some(archived) = changes.archived {
+            tables.archive_flow(&flow_name, archived, &actor);
+        }
+        if let Some(key_policy) = changes.key_policy {
+            tables.set_key_policy(&flow_name, key_policy, &actor);
+        }
+        if let Some(new_name) = changes.name {
+            tables.rename_flow(&flow_name, new_name, &actor);
+        }
+        Ok(true)
+    }
+
+    async fn set_flow_archived(
+        &self,
+        flow_name: String,
+        archived: bool,
+        actor: Actor,
+    ) -> Result<u64, DbError> {
+        Ok(self.tables().archive_flow(&flow_name, archived, &actor))
+    }
+
+    async fn get_flow_changes(
+        &self,
+        flow_name: String,
+        since: i64,
+        until: i64,
+        limit: i64,
+    ) -> Result<Vec<AuditEntry>, DbError> {
+        Ok(self
+            .tables()
+            .audit_log
+            .iter()
+            .filter(|entry| {
+                entry.flow.as_ref() == Some(&flow_name)
+                    && entry.id > since
+                    && entry.id <= until
+                    && is_revision_action(&entry.action)
+            })
+            .take(limit.max(0) as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn get_audit_log(
+        &self,
+        query: AuditQuery,
+        limit: i64,
+    ) -> Result<Vec<AuditEntry>, DbError> {
+        Ok(self
+            .tables()
+            .audit_log
+            .iter()
+            .rev()
+            .filter(|entry| query.flow.is_none() || entry.flow == query.flow)
+            .filter(|entry| query.server.is_none() || entry.server == query.server)
+            .filter(|entry| query.since.is_none_or(|since| entry.at >= since))
+            .take(limit.max(0) as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn approve_pending_keys(
+        &self,
+        server_name: String,
+        flow_name: String,
+        public_key: Option<String>,
+        actor: Actor,
+    ) -> Result<u64, DbError> {
+        let mut tables = self.tables();
+        let approved = tables.pending_keys(&server_name, &flow_name, public_key.as_deref());
+        let approved_ids: HashSet<i32> = approved
+            .iter()
+            .map(|&index| tables.flow_keys[index].key_id)
+            .collect();
+
+        // Approving a changed key replaces the host's previous active key of the same type
+        let approved_types: HashSet<String> = approved_ids
+            .iter()
+            .filter_map(|key_id| tables.keys.get(key_id))
+            .map(|key| key_part(&key.key, 0).to_string())
+            .collect();
+        let replaced: Vec<i32> = tables
+            .flow_keys
+            .iter()
+            .filter(|row| row.name == flow_name && !row.pending)
+            .map(|row| row.key_id)
+            .filter(|key_id| !approved_ids.contains(key_id))
+            .filter(|key_id| {
+                tables.keys.get(key_id).is_some_and(|key| {
+                    !key.deprecated
+                        && key.host == server_name
+                        && approved_types.contains(key_part(&key.key, 0))
+                })
+            })
+            .collect();
+
+        for &index in &approved {
+            tables.flow_keys[index].pending = false;
+            let key = tables.keys[&tables.flow_keys[index].key_id].key.clone();
+            tables.log(
+                "key_approved",
+                &flow_name,
+                Some(&server_name),
+                Some(&key),
+                &actor,
+                None,
+            );
+        }
+        let details = format!("replaced by an approved key in flow {}", flow_name);
+        for key_id in &replaced {
+            let key = tables.keys.get_mut(key_id).expect("replaced keys exist");
+            key.deprecated = true;
+            let key = key.key.clone();
+            tables.log(
+                "key_deprecated",
+                &flow_name,
+                Some(&server_name),
+                Some(&key),
+                &actor,
+                Some("replaced by an approved key"),
+            );
+            tables.log_other_flows("key_deprecated", &flow_name, *key_id, &actor, &details);
+        }
+
+        info!(
+            "Approved {} pending key(s) for server '{}' in flow '{}'",
+            approved.len(),
+            server_name,
+            flow_name
+        );
+        Ok(approved.len() as u64)
+    }
+
+    async fn reject_pending_keys(
+        &self,
+        server_name: String,
+        flow_name: String,
+        public_key: Option<String>,
+        actor: Actor,
+    ) -> Result<u64, DbError> {
+        // Rejected keys stay in the flow as deprecated so clients re-sending them are ignored
+        let mut tables = self.tables();
+        let rejected = tables.pending_keys(&server_name, &flow_name, public_key.as_deref());
+        let details = format!("rejected in flow {}", flow_name);
+        for &index in &rejected {
+            tables.flow_keys[index].pending = false;
+            let key_id = tables.flow_keys[index].key_id;
+            let key = tables.keys.get_mut(&key_id).expect("pending keys exist");
+            key.deprecated = true;
+            let key = key.key.clone();
+            tables.log(
+                "key_rejected",
+                &flow_name,
+                Some(&server_name),
+                Some(&key),
+                &actor,
+                None,
+            );
+            tables.log_other_flows("key_deprecated", &flow_name, key_id, &actor, &details);
+        }
+
+        info!(
+            "Rejected {} pending key(s) for server '{}' in flow '{}'",
+            rejected.len(),
+            server_name,
+            flow_name
+        );
+        Ok(rejected.len() as u64)
+    }
+
+    async fn add_cert_authority(
+        &self,
+        flow_name: String,
+        hosts: Vec<String>,
+        public_key: String,
+        comment: Option<String>,
+        actor: Actor,
+    ) -> Result<Option<CertAuthority>, DbError> {
+        let mut tables = self.tables();
+        if tables.cert_authorities.iter().any(|cert_authority| {
+            cert_authority.flow == flow_name && cert_authority.public_key == public_key
+        }) {
+            return Ok(None);
+        }
+
+        tables.next_ca_id += 1;
+        let cert_authority = CertAuthority {
+            id: tables.next_ca_id,
+            flow: flow_name.clone(),
+            hosts: hosts.clone(),
+            public_key: public_key.clone(),
+            comment: comment.clone(),
+            created: Utc::now(),
+        };
+        tables.cert_authorities.push(cert_authority.clone());
+        tables.log(
+            "ca_added",
+            &flow_name,
+            Some(&hosts.join(",")),
+            Some(&public_key),
+            &actor,
+            comment.as_deref(),
+        );
+
+        info!(
+            "Added certificate authority for '{}' to flow '{}'",
+            hosts.join(","),
+            flow_name
+        );
+        Ok(Some(cert_authority))
+    }
+
+    async fn remove_cert_authority(
+        &self,
+        flow_name: String,
+        ca_id: i32,
+        actor: Actor,
+    ) -> Result<u64, DbError> {
+        let mut tables = self.tables();
+        let index = tables.cert_authorities.iter().position(|cert_authority| {
+            cert_authority.flow == flow_name && cert_authority.id == ca_id
+        });
+        let affected = match index {
+            Some(index) => {
+                let removed = tables.cert_authorities.remove(index);
+                tables.log(
+                    "ca_removed",
+                    &flow_name,
+                    Some(&removed.hosts.join(",")),
+                    Some(&removed.public_key),
+                    &actor,
+                    removed.comment.as_deref(),
+                );
+                1
+            }
+            None => 0,
+        };
+
+        info!(
+            "Removed {} certificate authority record(s) with id {} from flow '{}'",
+            affected, ca_id, flow_name
+        );
+        Ok(affected)
+    }
+
+    async fn revoke_keys_by_server(
+        &self,
+        server_name: String,
+        flow_name: String,
+        public_key: Option<String>,
+        actor: Actor,
+    ) -> Result<u64, DbError> {
+        // Revoked keys are deprecated as well, so every path that skips inactive keys skips them
+        let affected = self.tables().update_keys(
+            std::slice::from_ref(&server_name),
+            &flow_name,
+            |row| !row.revoked && public_key.as_ref().is_none_or(|key| row.key == *key),
+            |row| {
+                row.deprecated = true;
+                row.revoked = true;
+            },
+            "key_revoked",
+            &actor,
+        );
+
+        info!(
+            "Revoked {} key(s) for server '{}' in flow '{}'",
+            affected, server_name, flow_name
+        );
+        Ok(affected)
+    }
+}
@@ -0,0 +1,94 @@
+use chrono::{DateTime, Utc};
+use log::warn;
+
+// One schema change, embedded from migrations/<backend>/<version>_<name>.{up,down}.sql
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub up: &'static str,
+    pub down: &'static str,
+}
+
+macro_rules! migration {
+    ($backend:literal, $version:literal, $file:literal) => {
+        Migration {
+            version: $version,
+            name: $file,
+            up: include_str!(concat!("../migrations/", $backend, "/", $file, ".up.sql")),
+            down: include_str!(concat!("../migrations/", $backend, "/", $file, ".down.sql")),
+        }
+    };
+}
+
+// In the order they are applied. Never edit a migration that has been released,
+// add a new one instead. The ones up to 0009 also bring databases created before
+// migrations were versioned up to date, so they must stay safe to run on those.
+pub const POSTGRES: &[Migration] = &[
+    migration!("postgres", 1, "0001_keys_and_flows"),
+    migration!("postgres", 2, "0002_users_and_grants"),
+    migration!("postgres", 3, "0003_api_tokens"),
+    migration!("postgres", 4, "0004_flow_metadata"),
+    migration!("postgres", 5, "0005_key_quarantine"),
+    migration!("postgres", 6, "0006_key_revocation"),
+    migration!("postgres", 7, "0007_token_direction"),
+    migration!("postgres", 8, "0008_cert_authorities"),
+    migration!("postgres", 9, "0009_audit_log"),
+    migration!("postgres", 10, "0010_key_comments"),
+];
+
+// The same versions as for PostgreSQL, so that both report the same schema version
+pub const SQLITE: &[Migration] = &[
+    migration!("sqlite", 1, "0001_keys_and_flows"),
+    migration!("sqlite", 2, "0002_users_and_grants"),
+    migration!("sqlite", 3, "0003_api_tokens"),
+    migration!("sqlite", 4, "0004_flow_metadata"),
+    migration!("sqlite", 5, "0005_key_quarantine"),
+    migration!("sqlite", 6, "0006_key_revocation"),
+    migration!("sqlite", 7, "0007_token_direction"),
+    migration!("sqlite", 8, "0008_cert_authorities"),
+    migration!("sqlite", 9, "0009_audit_log"),
+    migration!("sqlite", 10, "0010_key_comments"),
+];
+
+pub fn find(migrations: &'static [Migration], version: i64) -> Option<&'static Migration> {
+    migrations
+        .iter()
+        .find(|migration| migration.version == version)
+}
+
+// Migrations not applied yet, in order
+pub fn pending(
+    migrations: &'static [Migration],
+    applied: &[AppliedMigration],
+) -> Vec<&'static Migration> {
+    for unknown in applied
+        .iter()
+        .filter(|migration| find(migrations, migration.version).is_none())
+    {
+        warn!(
+            "Database has migration {} applied, which this version does not know",
+            unknown.name
+        );
+    }
+    migrations
+        .iter()
+        .filter(|migration| !applied.iter().any(|row| row.version == migration.version))
+        .collect()
+}
+
+// A row of schema_migrations
+#[derive(Clone, Debug)]
+pub struct AppliedMigration {
+    pub version: i64,
+    pub name: String,
+    pub applied: DateTime<Utc>,
+}
+
+// Result of reverting the newest migration
+pub enum Downgrade {
+    // No migration is applied
+    Empty,
+    Reverted(&'static Migration),
+    // Applied by a newer version of khm, which holds the script to revert it
+    Unknown(AppliedMigration),
+}
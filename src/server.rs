@@ -1,10 +1,30 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceFactory, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{ETag, EntityTag, Header, IfNoneMatch, CACHE_CONTROL, RETRY_AFTER};
+use actix_web::http::StatusCode;
+use actix_web::middleware::{from_fn, Next};
 use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer, Responder};
-use log::{error, info};
+use log::{error, info, warn};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::sync::{Arc, Mutex};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_util::sync::CancellationToken;
 
-use crate::db::ReconnectingDbClient;
+use crate::audit::{Actor, AuditQuery, DEFAULT_AUDIT_LIMIT, KEY_ACTIONS, MAX_AUDIT_LIMIT};
+use crate::auth::{self, ApiToken, Credentials, Grant, GrantStore, Role, TokenScope, TokenStore};
+use crate::client::SyncDirection;
+use crate::db::{DbError, FlowChanges};
+use crate::events::{FlowEvent, KEEPALIVE_FRAME, KEEPALIVE_INTERVAL};
+use crate::flows::{is_served, Flows};
+use crate::known_hosts::{key_material, HostPattern, Marker};
+use crate::migrations::Migration;
+use crate::storage::{self, Storage};
+use crate::tls::{self, ReloadableCert};
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct SshKey {
@@ -12,15 +32,228 @@ pub struct SshKey {
     pub public_key: String,
     #[serde(default)]
     pub deprecated: bool,
+    // Awaiting admin approval; never handed out to clients syncing known_hosts
+    #[serde(default)]
+    pub pending: bool,
+    // Set on @cert-authority entries served next to the plain keys and on revoked keys,
+    // which stay deprecated so that older clients leave them out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub marker: Option<Marker>,
+    // Kept apart from `public_key`, which holds only the key type and base64 blob
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+impl SshKey {
+    // Keys are stored and compared without their comment. Older clients and API callers
+    // send it as part of `public_key`, so it moves to `comment` unless that is set.
+    pub fn split_comment(mut self) -> SshKey {
+        let key_length = key_material(&self.public_key).len();
+        let inline_comment = normalize_description(Some(self.public_key[key_length..].to_string()));
+        self.public_key.truncate(key_length);
+        self.comment = normalize_description(self.comment).or(inline_comment);
+        self
+    }
+}
+
+// Host certificate authority trusted by a flow for the given host patterns
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CertAuthority {
+    pub id: i32,
+    pub flow: String,
+    pub hosts: Vec<String>,
+    pub public_key: String,
+    pub comment: Option<String>,
+    pub created: chrono::DateTime<chrono::Utc>,
 }
 
+impl CertAuthority {
+    // The @cert-authority line clients write to known_hosts
+    pub fn to_ssh_key(&self) -> SshKey {
+        let public_key = match &self.comment {
+            Some(comment) => format!("{} {}", self.public_key, comment),
+            None => self.public_key.clone(),
+        };
+        SshKey {
+            server: self.hosts.join(","),
+            public_key,
+            deprecated: false,
+            pending: false,
+            marker: Some(Marker::CertAuthority),
+            comment: None,
+        }
+    }
+}
+
+// A flow as loaded from the database at startup
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Flow {
     pub name: String,
     pub servers: Vec<SshKey>,
+    #[serde(default)]
+    pub cert_authorities: Vec<CertAuthority>,
+    // Audit log id of the last change to what the flow serves, used as its ETag
+    #[serde(default)]
+    pub revision: i64,
+}
+
+impl Flow {
+    pub(crate) fn empty(name: String) -> Self {
+        Flow {
+            name,
+            servers: Vec::new(),
+            cert_authorities: Vec::new(),
+            revision: 0,
+        }
+    }
+}
+
+// Revision changes for /{flow}/events subscribers
+#[derive(Clone)]
+pub struct FlowEvents {
+    sender: broadcast::Sender<FlowEvent>,
+    closed: CancellationToken,
+}
+
+const FLOW_EVENTS_CAPACITY: usize = 256;
+
+// How often the database is pinged, which bounds how long readiness lags behind it
+const DATABASE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+// Cap on the wait between connection attempts at startup
+const MAX_STARTUP_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+impl FlowEvents {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(FLOW_EVENTS_CAPACITY);
+        FlowEvents {
+            sender,
+            closed: CancellationToken::new(),
+        }
+    }
+
+    pub(crate) fn send(&self, event: FlowEvent) {
+        // Sending only fails when nobody is subscribed
+        let _ = self.sender.send(event);
+    }
+
+    // Event streams keep their connections open, so shutting down has to end them
+    pub fn close(&self) {
+        self.closed.cancel();
+    }
+}
+
+impl Default for FlowEvents {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Flow names that would collide with fixed routes
+const RESERVED_FLOW_NAMES: &[&str] = &["api", "static", "gui", "wasm"];
+
+// How a flow treats keys it has not seen before
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyPolicy {
+    // Every key is accepted right away
+    Accept,
+    // The first key of each type for a host is accepted, a different one waits for approval
+    #[default]
+    Tofu,
+    // Every new key waits for approval
+    Strict,
+}
+
+impl KeyPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KeyPolicy::Accept => "accept",
+            KeyPolicy::Tofu => "tofu",
+            KeyPolicy::Strict => "strict",
+        }
+    }
+}
+
+impl fmt::Display for KeyPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for KeyPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "accept" => Ok(KeyPolicy::Accept),
+            "tofu" => Ok(KeyPolicy::Tofu),
+            "strict" => Ok(KeyPolicy::Strict),
+            other => Err(format!("Unknown key policy: {}", other)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FlowInfo {
+    pub name: String,
+    pub description: Option<String>,
+    pub archived: bool,
+    pub key_policy: KeyPolicy,
+    pub created: chrono::DateTime<chrono::Utc>,
+}
+
+// In-memory copy of the flow_metadata table, refreshed whenever flows are managed
+#[derive(Clone, Default)]
+pub struct FlowRegistry {
+    flows: Arc<RwLock<BTreeMap<String, FlowInfo>>>,
+}
+
+impl FlowRegistry {
+    pub fn replace(&self, flows: Vec<FlowInfo>) {
+        let by_name = flows
+            .into_iter()
+            .map(|flow| (flow.name.clone(), flow))
+            .collect();
+        *self.flows.write().unwrap() = by_name;
+    }
+
+    pub fn get(&self, name: &str) -> Option<FlowInfo> {
+        self.flows.read().unwrap().get(name).cloned()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.flows.read().unwrap().contains_key(name)
+    }
+
+    pub fn is_archived(&self, name: &str) -> bool {
+        self.get(name).is_some_and(|flow| flow.archived)
+    }
+
+    pub fn all(&self) -> Vec<FlowInfo> {
+        self.flows.read().unwrap().values().cloned().collect()
+    }
+
+    /// Names of the flows that are not archived, sorted.
+    pub fn active_names(&self) -> Vec<String> {
+        self.flows
+            .read()
+            .unwrap()
+            .values()
+            .filter(|flow| !flow.archived)
+            .map(|flow| flow.name.clone())
+            .collect()
+    }
 }
 
-pub type Flows = Arc<Mutex<Vec<Flow>>>;
+pub fn is_valid_flow_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 255
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+        && !RESERVED_FLOW_NAMES.contains(&name)
+}
 
 pub fn is_valid_ssh_key(key: &str) -> bool {
     let rsa_re = Regex::new(r"^ssh-rsa AAAA[0-9A-Za-z+/]+[=]{0,3}( .+)?$").unwrap();
@@ -48,7 +281,7 @@ fn get_client_hostname(req: &HttpRequest) -> String {
 pub async fn get_keys(
     flows: web::Data<Flows>,
     flow_id: web::Path<String>,
-    allowed_flows: web::Data<Vec<String>>,
+    flow_registry: web::Data<FlowRegistry>,
     req: HttpRequest,
     query: web::Query<std::collections::HashMap<String, String>>,
 ) -> impl Responder {
@@ -60,7 +293,7 @@ pub async fn get_keys(
         client_hostname, flow_id_str
     );
 
-    if !allowed_flows.contains(&flow_id_str) {
+    if !flow_registry.contains(&flow_id_str) {
         error!(
             "Flow ID not allowed for client '{}': {}",
             client_hostname, flow_id_str
@@ -68,59 +301,310 @@ pub async fn get_keys(
         return HttpResponse::Forbidden().body("Flow ID not allowed");
     }
 
-    let flows = flows.lock().unwrap();
-    if let Some(flow) = flows.iter().find(|flow| flow.name == flow_id_str) {
-        // Check if we should include deprecated keys (default: false for CLI clients)
-        let include_deprecated = query
-            .get("include_deprecated")
-            .map(|v| v == "true")
-            .unwrap_or(false);
+    if let Err(response) = auth::authorize(&req, &flow_id_str, Role::Reader) {
+        return response;
+    }
 
-        let servers: Vec<&SshKey> = if include_deprecated {
-            // Return all keys (for web interface)
-            flow.servers.iter().collect()
-        } else {
-            // Return only active keys (for CLI clients)
-            flow.servers.iter().filter(|key| !key.deprecated).collect()
-        };
+    // Check if we should include deprecated keys (default: false for CLI clients)
+    let include_deprecated = query
+        .get("include_deprecated")
+        .map(|v| v == "true")
+        .unwrap_or(false);
 
+    let flow = flows.get(&flow_id_str);
+    let etag = revision_etag(
+        flow.as_ref().map_or(0, |flow| flow.revision),
+        include_deprecated,
+    );
+    if is_not_modified(&req, &etag) {
         info!(
-            "Returning {} keys ({} total, deprecated filtered: {}) for flow '{}' to client '{}'",
-            servers.len(),
-            flow.servers.len(),
-            !include_deprecated,
-            flow_id_str,
-            client_hostname
+            "Flow '{}' unchanged for client '{}', returning 304",
+            flow_id_str, client_hostname
         );
-        HttpResponse::Ok().json(servers)
+        return HttpResponse::NotModified()
+            .insert_header(ETag(etag))
+            .finish();
+    }
+
+    let servers = match flow {
+        Some(flow) => {
+            let servers = flow.served_keys(include_deprecated);
+            info!(
+                "Returning {} keys ({} total, {} CAs, deprecated filtered: {}) for flow '{}' to client '{}'",
+                servers.len(),
+                flow.key_count(),
+                flow.cert_authorities.len(),
+                !include_deprecated,
+                flow_id_str,
+                client_hostname
+            );
+            servers
+        }
+        None => {
+            // Registered flows without any keys yet are simply empty
+            info!(
+                "Flow '{}' has no keys yet, returning an empty list to client '{}'",
+                flow_id_str, client_hostname
+            );
+            Vec::new()
+        }
+    };
+
+    HttpResponse::Ok().insert_header(ETag(etag)).json(servers)
+}
+
+// The variant with deprecated keys is a different representation, so it gets its own tag
+fn revision_etag(revision: i64, include_deprecated: bool) -> EntityTag {
+    if include_deprecated {
+        EntityTag::new_strong(format!("{}-all", revision))
     } else {
-        error!(
-            "Flow ID not found for client '{}': {}",
-            client_hostname, flow_id_str
-        );
-        HttpResponse::NotFound().body("Flow ID not found")
+        EntityTag::new_strong(revision.to_string())
+    }
+}
+
+fn is_not_modified(req: &HttpRequest, etag: &EntityTag) -> bool {
+    match IfNoneMatch::parse(req) {
+        Ok(IfNoneMatch::Any) => true,
+        Ok(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(etag)),
+        Err(_) => false,
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct KeyChangesQuery {
+    pub since: i64,
+}
+
+// What changed in a flow since a revision, from a client's point of view. With
+// `full` set the changes could not be narrowed down to single keys and `added`
+// holds everything the flow serves.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct KeyChanges {
+    pub revision: i64,
+    pub full: bool,
+    pub added: Vec<SshKey>,
+    pub deprecated: Vec<SshKey>,
+    pub deleted: Vec<SshKey>,
+}
+
+pub async fn get_key_changes(
+    flows: web::Data<Flows>,
+    flow_id: web::Path<String>,
+    db_client: web::Data<Arc<dyn Storage>>,
+    flow_registry: web::Data<FlowRegistry>,
+    req: HttpRequest,
+    query: web::Query<KeyChangesQuery>,
+) -> impl Responder {
+    let client_hostname = get_client_hostname(&req);
+    let flow_id_str = flow_id.into_inner();
+    let since = query.since;
+
+    if !flow_registry.contains(&flow_id_str) {
+        return HttpResponse::Forbidden().body("Flow ID not allowed");
+    }
+
+    if let Err(response) = auth::authorize(&req, &flow_id_str, Role::Reader) {
+        return response;
+    }
+
+    // One snapshot for the whole request, so the keys match the revision
+    let flow = flows.get(&flow_id_str);
+    let (revision, key_count) = flow
+        .as_ref()
+        .map_or((0, 0), |flow| (flow.revision, flow.key_count()));
+    if since == revision {
+        return HttpResponse::NotModified()
+            .insert_header(ETag(revision_etag(revision, false)))
+            .finish();
+    }
+
+    // Past a flow's size in changed rows a full list is cheaper than the delta
+    let limit = key_count as i64 + 1;
+    let changes = if since < revision {
+        match db_client
+            .get_flow_changes(flow_id_str.clone(), since, revision, limit)
+            .await
+        {
+            Ok(changes) => Some(changes),
+            Err(e) => {
+                error!(
+                    "Failed to get changes of flow '{}' from database: {}",
+                    flow_id_str, e
+                );
+                return HttpResponse::InternalServerError().body("Failed to get changes");
+            }
+        }
+    } else {
+        // A revision from the future means the client saw another database
+        None
+    };
+    let touched: Option<BTreeSet<(String, String)>> = changes.and_then(|changes| {
+        if changes.len() as i64 >= limit {
+            return None;
+        }
+        changes
+            .into_iter()
+            .map(|change| match (change.server, change.public_key) {
+                (Some(server), Some(public_key))
+                    if KEY_ACTIONS.contains(&change.action.as_str()) =>
+                {
+                    // Rows logged before comments had their own column still carry them
+                    Some((server, key_material(&public_key).to_string()))
+                }
+                _ => None,
+            })
+            .collect()
+    });
+
+    let mut key_changes = KeyChanges {
+        revision,
+        full: touched.is_none(),
+        ..KeyChanges::default()
+    };
+    match touched {
+        None => {
+            key_changes.added = flow.map(|flow| flow.served_keys(false)).unwrap_or_default();
+        }
+        Some(touched) => {
+            for (server, public_key) in touched {
+                let current = flow
+                    .as_ref()
+                    .and_then(|flow| flow.get(&server, &public_key));
+                match current {
+                    Some(key) if is_served(key) => key_changes.added.push(key.clone()),
+                    Some(key) if !key.pending => key_changes.deprecated.push(key.clone()),
+                    _ => key_changes.deleted.push(SshKey {
+                        server,
+                        public_key,
+                        deprecated: false,
+                        pending: false,
+                        marker: None,
+                        comment: None,
+                    }),
+                }
+            }
+        }
+    }
+
+    info!(
+        "Returning changes of flow '{}' since revision {} to client '{}': {} added, {} deprecated, {} deleted{}",
+        flow_id_str,
+        since,
+        client_hostname,
+        key_changes.added.len(),
+        key_changes.deprecated.len(),
+        key_changes.deleted.len(),
+        if key_changes.full { " (full)" } else { "" }
+    );
+    HttpResponse::Ok()
+        .insert_header(ETag(revision_etag(revision, false)))
+        .json(key_changes)
+}
+
+// Server-Sent Events stream of the flow's revision: the current one on connect,
+// then one event per change
+pub async fn get_flow_events(
+    flows: web::Data<Flows>,
+    events: web::Data<FlowEvents>,
+    flow_id: web::Path<String>,
+    flow_registry: web::Data<FlowRegistry>,
+    req: HttpRequest,
+) -> impl Responder {
+    let client_hostname = get_client_hostname(&req);
+    let flow_id_str = flow_id.into_inner();
+
+    if !flow_registry.contains(&flow_id_str) {
+        return HttpResponse::Forbidden().body("Flow ID not allowed");
+    }
+
+    if let Err(response) = auth::authorize(&req, &flow_id_str, Role::Reader) {
+        return response;
+    }
+
+    info!(
+        "Client '{}' subscribed to events of flow '{}'",
+        client_hostname, flow_id_str
+    );
+
+    // Subscribe before reading the revision so that no change falls in between
+    let receiver = events.sender.subscribe();
+    let state = EventStream {
+        first: Some(current_flow_event(&flows, &flow_id_str)),
+        receiver,
+        closed: events.closed.clone(),
+        flows,
+        flow: flow_id_str,
+    };
+    let stream = futures::stream::unfold(state, next_event_frame);
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((CACHE_CONTROL, "no-cache"))
+        .streaming(stream)
+}
+
+struct EventStream {
+    first: Option<FlowEvent>,
+    receiver: broadcast::Receiver<FlowEvent>,
+    closed: CancellationToken,
+    flows: web::Data<Flows>,
+    flow: String,
+}
+
+async fn next_event_frame(
+    mut stream: EventStream,
+) -> Option<(Result<web::Bytes, actix_web::Error>, EventStream)> {
+    let frame = match stream.first.take() {
+        Some(event) => event.to_sse(),
+        None => loop {
+            let next = tokio::time::timeout(KEEPALIVE_INTERVAL, stream.receiver.recv());
+            let received = tokio::select! {
+                _ = stream.closed.cancelled() => return None,
+                received = next => received,
+            };
+            match received {
+                Err(_) => break KEEPALIVE_FRAME.to_string(),
+                Ok(Ok(event)) if event.flow == stream.flow => break event.to_sse(),
+                Ok(Ok(_)) => continue,
+                // Missed events only matter for the revision they end at
+                Ok(Err(RecvError::Lagged(_))) => {
+                    break current_flow_event(&stream.flows, &stream.flow).to_sse()
+                }
+                Ok(Err(RecvError::Closed)) => return None,
+            }
+        },
+    };
+    Some((Ok(web::Bytes::from(frame)), stream))
+}
+
+fn current_flow_event(flows: &Flows, flow_name: &str) -> FlowEvent {
+    FlowEvent {
+        flow: flow_name.to_string(),
+        revision: flows.revision(flow_name),
     }
 }
 
 pub async fn add_keys(
     flows: web::Data<Flows>,
+    events: web::Data<FlowEvents>,
     flow_id: web::Path<String>,
     new_keys: web::Json<Vec<SshKey>>,
-    db_client: web::Data<Arc<ReconnectingDbClient>>,
-    allowed_flows: web::Data<Vec<String>>,
+    db_client: web::Data<Arc<dyn Storage>>,
+    flow_registry: web::Data<FlowRegistry>,
     req: HttpRequest,
 ) -> impl Responder {
     let client_hostname = get_client_hostname(&req);
     let flow_id_str = flow_id.into_inner();
 
     info!(
-        "Received {} keys from client '{}' for flow '{}'",
+        "Received {} keys from client '{}' (user: {}) for flow '{}'",
         new_keys.len(),
         client_hostname,
+        auth::current_user(&req).unwrap_or_else(|| "anonymous".to_string()),
         flow_id_str
     );
 
-    if !allowed_flows.contains(&flow_id_str) {
+    if !flow_registry.contains(&flow_id_str) {
         error!(
             "Flow ID not allowed for client '{}': {}",
             client_hostname, flow_id_str
@@ -128,9 +612,27 @@ pub async fn add_keys(
         return HttpResponse::Forbidden().body("Flow ID not allowed");
     }
 
+    if let Err(response) = auth::authorize(&req, &flow_id_str, Role::Writer) {
+        return response;
+    }
+
+    if flow_registry.is_archived(&flow_id_str) {
+        warn!(
+            "Rejecting keys from client '{}' for archived flow '{}'",
+            client_hostname, flow_id_str
+        );
+        return HttpResponse::Conflict().body("Flow is archived");
+    }
+
     // Check SSH key format
-    let mut valid_keys = Vec::new();
+    let mut valid_keys: Vec<SshKey> = Vec::new();
     for new_key in new_keys.iter() {
+        if new_key.marker.is_some() {
+            return HttpResponse::BadRequest().body(format!(
+                "Marker entries cannot be uploaded as keys (server: {}); certificate authorities are managed through /{}/cert-authorities",
+                new_key.server, flow_id_str
+            ));
+        }
         if !is_valid_ssh_key(&new_key.public_key) {
             error!(
                 "Invalid SSH key format from client '{}' for server: {}",
@@ -141,7 +643,15 @@ pub async fn add_keys(
                 new_key.server
             ));
         }
-        valid_keys.push(new_key.clone());
+        // The same key sent with and without a comment is one key
+        let new_key = new_key.clone().split_comment();
+        match valid_keys
+            .iter_mut()
+            .find(|key| key.server == new_key.server && key.public_key == new_key.public_key)
+        {
+            Some(key) => key.comment = key.comment.take().or(new_key.comment),
+            None => valid_keys.push(new_key),
+        }
     }
 
     info!(
@@ -152,10 +662,7 @@ pub async fn add_keys(
     );
 
     // Batch insert keys with statistics
-    let key_stats = match db_client
-        .batch_insert_keys_reconnecting(valid_keys.clone())
-        .await
-    {
+    let key_stats = match db_client.batch_insert_keys(valid_keys.clone()).await {
         Ok(stats) => stats,
         Err(e) => {
             error!(
@@ -168,21 +675,36 @@ pub async fn add_keys(
     };
 
     // Always try to associate all keys with the flow, regardless of whether they're new or existing
+    let mut pending_count = 0;
     if !key_stats.key_id_map.is_empty() {
         // Extract all key IDs from statistics, both new and existing
         let key_ids: Vec<i32> = key_stats.key_id_map.iter().map(|(_, id)| *id).collect();
 
         // Batch insert key-flow associations
-        if let Err(e) = db_client
-            .batch_insert_flow_keys_reconnecting(flow_id_str.clone(), key_ids.clone())
+        match db_client
+            .batch_insert_flow_keys(
+                flow_id_str.clone(),
+                key_ids.clone(),
+                Actor::from_request(&req),
+            )
             .await
         {
-            error!(
-                "Failed to batch insert flow keys from client '{}' into database: {}",
-                client_hostname, e
+            Ok(flow_key_stats) => pending_count = flow_key_stats.pending,
+            Err(e) => {
+                error!(
+                    "Failed to batch insert flow keys from client '{}' into database: {}",
+                    client_hostname, e
+                );
+                return HttpResponse::InternalServerError()
+                    .body("Failed to batch insert flow keys into database");
+            }
+        }
+
+        if pending_count > 0 {
+            warn!(
+                "{} key(s) from client '{}' in flow '{}' are pending admin approval",
+                pending_count, client_hostname, flow_id_str
             );
-            return HttpResponse::InternalServerError()
-                .body("Failed to batch insert flow keys into database");
         }
 
         info!(
@@ -198,25 +720,26 @@ pub async fn add_keys(
         );
     }
 
-    // Get updated data
-    let updated_flows = match db_client.get_keys_from_db_reconnecting().await {
-        Ok(flows) => flows,
-        Err(e) => {
-            error!(
-                "Failed to get updated flows from database after client '{}' request: {}",
-                client_hostname, e
-            );
-            return HttpResponse::InternalServerError()
-                .body("Failed to refresh flows from database");
-        }
-    };
-
-    let mut flows_guard = flows.lock().unwrap();
-    *flows_guard = updated_flows;
+    // Only the uploaded hosts can have changed
+    let hosts: BTreeSet<String> = valid_keys.iter().map(|key| key.server.clone()).collect();
+    if let Err(e) = flows
+        .refresh(
+            &db_client,
+            &events,
+            vec![flow_id_str.clone()],
+            hosts.into_iter().collect(),
+        )
+        .await
+    {
+        error!(
+            "Failed to get updated flows from database after client '{}' request: {}",
+            client_hostname, e
+        );
+        return HttpResponse::InternalServerError().body("Failed to refresh flows from database");
+    }
 
-    let updated_flow = flows_guard.iter().find(|flow| flow.name == flow_id_str);
-    if let Some(flow) = updated_flow {
-        let servers: Vec<&SshKey> = flow.servers.iter().collect();
+    if let Some(flow) = flows.get(&flow_id_str) {
+        let servers: Vec<&SshKey> = flow.keys().filter(|key| !key.pending).collect();
         info!(
             "Keys summary for client '{}', flow '{}': total received={}, new={}, unchanged={}, total in flow={}",
             client_hostname,
@@ -232,6 +755,7 @@ pub async fn add_keys(
         response.append_header(("X-Keys-Total", key_stats.total.to_string()));
         response.append_header(("X-Keys-New", key_stats.inserted.to_string()));
         response.append_header(("X-Keys-Unchanged", key_stats.unchanged.to_string()));
+        response.append_header(("X-Keys-Pending", pending_count.to_string()));
 
         response.json(servers)
     } else {
@@ -243,120 +767,1243 @@ pub async fn add_keys(
     }
 }
 
-pub async fn run_server(args: crate::Args) -> std::io::Result<()> {
-    let db_user = args.db_user.expect("db_user is required in server mode");
-    let db_password = args
-        .db_password
-        .expect("db_password is required in server mode");
-
-    let db_conn_str = format!(
-        "host={} user={} password={} dbname={}",
-        args.db_host, db_user, db_password, args.db_name
+// List grants on the flows the caller administers
+pub async fn get_grants(grants: web::Data<GrantStore>, req: HttpRequest) -> impl Responder {
+    let visible: Vec<Grant> = grants
+        .all()
+        .into_iter()
+        .filter(|grant| auth::has_role(&req, &grant.flow, Role::Admin))
+        .collect();
+
+    HttpResponse::Ok().json(visible)
+}
+
+// Create or update a user's role on a flow
+pub async fn set_grant(
+    grants: web::Data<GrantStore>,
+    grant: web::Json<Grant>,
+    db_client: web::Data<Arc<dyn Storage>>,
+    req: HttpRequest,
+) -> impl Responder {
+    let grant = grant.into_inner();
+
+    if let Err(response) = auth::authorize(&req, &grant.flow, Role::Admin) {
+        return response;
+    }
+
+    info!(
+        "User '{}' grants '{}' role on flow '{}' to '{}'",
+        auth::current_user(&req).unwrap_or_else(|| "anonymous".to_string()),
+        grant.role,
+        grant.flow,
+        grant.username
     );
 
-    info!("Creating database client for {}", args.db_host);
-    let mut db_client_temp = ReconnectingDbClient::new(db_conn_str.clone());
+    if let Err(e) = db_client
+        .set_grant(
+            grant.username.clone(),
+            grant.flow.clone(),
+            grant.role,
+            Actor::from_request(&req),
+        )
+        .await
+    {
+        error!("Failed to store grant: {}", e);
+        return HttpResponse::InternalServerError().body("Failed to store grant");
+    }
 
-    // Initial connection
-    if let Err(e) = db_client_temp.connect(&db_conn_str).await {
-        error!("Failed to connect to the database: {}", e);
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::ConnectionRefused,
-            format!("Database connection error: {}", e),
-        ));
+    if let Err(response) = refresh_grants(&db_client, &grants).await {
+        return response;
     }
 
-    let db_client = Arc::new(db_client_temp);
+    HttpResponse::Ok().json(grant)
+}
 
-    // Initialize database schema if needed
-    if let Err(e) = db_client.initialize_schema().await {
-        error!("Failed to initialize database schema: {}", e);
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            format!("Database schema initialization error: {}", e),
-        ));
+// Remove a user's role on a flow
+pub async fn revoke_grant(
+    grants: web::Data<GrantStore>,
+    path: web::Path<(String, String)>,
+    db_client: web::Data<Arc<dyn Storage>>,
+    req: HttpRequest,
+) -> impl Responder {
+    let (username, flow_name) = path.into_inner();
+
+    if let Err(response) = auth::authorize(&req, &flow_name, Role::Admin) {
+        return response;
     }
 
-    let mut initial_flows = match db_client.get_keys_from_db_reconnecting().await {
-        Ok(flows) => flows,
+    let revoked = match db_client
+        .revoke_grant(
+            username.clone(),
+            flow_name.clone(),
+            Actor::from_request(&req),
+        )
+        .await
+    {
+        Ok(count) => count,
         Err(e) => {
-            error!("Failed to get initial flows from database: {}", e);
-            Vec::new()
+            error!("Failed to revoke grant: {}", e);
+            return HttpResponse::InternalServerError().body("Failed to revoke grant");
         }
     };
 
-    // Ensure all allowed flows are initialized
-    for allowed_flow in &args.flows {
-        if !initial_flows.iter().any(|flow| &flow.name == allowed_flow) {
-            initial_flows.push(Flow {
-                name: allowed_flow.clone(),
-                servers: vec![],
-            });
-        }
-    }
-
-    let flows: Flows = Arc::new(Mutex::new(initial_flows));
-    let allowed_flows = web::Data::new(args.flows);
-
-    info!("Starting HTTP server on {}:{}", args.ip, args.port);
-    HttpServer::new(move || {
-        let mut app = App::new()
-            .app_data(web::Data::new(flows.clone()))
-            .app_data(web::Data::new(db_client.clone()))
-            .app_data(allowed_flows.clone())
-            // Original API routes
-            .route("/{flow_id}/keys", web::get().to(get_keys))
-            .route("/{flow_id}/keys", web::post().to(add_keys));
-        
-        #[cfg(feature = "web")]
-        {
-            app = app.configure(configure_web_routes);
+    if revoked == 0 {
+        return HttpResponse::NotFound().body(format!(
+            "No grant found for user '{}' on flow '{}'",
+            username, flow_name
+        ));
+    }
+
+    if let Err(response) = refresh_grants(&db_client, &grants).await {
+        return response;
+    }
+
+    HttpResponse::NoContent().finish()
+}
+
+async fn refresh_grants(
+    db_client: &Arc<dyn Storage>,
+    grants: &GrantStore,
+) -> Result<(), HttpResponse> {
+    match db_client.get_grants().await {
+        Ok(updated) => {
+            grants.replace(updated);
+            Ok(())
+        }
+        Err(e) => {
+            error!("Failed to refresh grants from database: {}", e);
+            Err(HttpResponse::InternalServerError().body("Failed to refresh grants from database"))
         }
-        
-        app
-    })
-    .bind((args.ip.as_str(), args.port))?
-    .run()
-    .await
+    }
 }
 
-#[cfg(feature = "web")]
-fn configure_web_routes(cfg: &mut web::ServiceConfig) {
-    cfg
-        // API routes
-        .route("/api/version", web::get().to(crate::web::get_version_api))
-        .route("/api/flows", web::get().to(crate::web::get_flows_api))
-        .route(
-            "/{flow_id}/scan-dns",
-            web::post().to(crate::web::scan_dns_resolution),
-        )
-        .route(
-            "/{flow_id}/bulk-deprecate",
-            web::post().to(crate::web::bulk_deprecate_servers),
-        )
-        .route(
-            "/{flow_id}/bulk-restore",
-            web::post().to(crate::web::bulk_restore_servers),
-        )
-        .route(
-            "/{flow_id}/keys/{server}",
-            web::delete().to(crate::web::delete_key_by_server),
-        )
-        .route(
-            "/{flow_id}/keys/{server}/restore",
-            web::post().to(crate::web::restore_key_by_server),
-        )
-        .route(
-            "/{flow_id}/keys/{server}/delete",
-            web::delete().to(crate::web::permanently_delete_key_by_server),
-        )
-        // Web interface routes
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CreateTokenRequest {
+    pub name: String,
+    pub flows: Vec<String>,
+    #[serde(default = "default_token_role")]
+    pub role: Role,
+    #[serde(default)]
+    pub direction: SyncDirection,
+    #[serde(default)]
+    pub expires_in_days: Option<u32>,
+}
+
+fn default_token_role() -> Role {
+    Role::Reader
+}
+
+// List API tokens whose whole scope the caller administers
+pub async fn get_tokens(tokens: web::Data<TokenStore>, req: HttpRequest) -> impl Responder {
+    let visible: Vec<ApiToken> = tokens
+        .all()
+        .into_iter()
+        .filter(|token| {
+            token
+                .flows
+                .iter()
+                .all(|flow| auth::has_role(&req, flow, Role::Admin))
+        })
+        .collect();
+
+    HttpResponse::Ok().json(visible)
+}
+
+// Issue a new API token; the plaintext token is only ever returned here
+pub async fn create_token(
+    tokens: web::Data<TokenStore>,
+    request: web::Json<CreateTokenRequest>,
+    db_client: web::Data<Arc<dyn Storage>>,
+    req: HttpRequest,
+) -> impl Responder {
+    let request = request.into_inner();
+
+    if request.name.trim().is_empty() || request.flows.is_empty() {
+        return HttpResponse::BadRequest().body("Token name and at least one flow are required");
+    }
+
+    if request.role == Role::Admin {
+        return HttpResponse::BadRequest().body("API tokens can only be 'reader' or 'writer'");
+    }
+
+    if request.direction == SyncDirection::Push && request.role != Role::Writer {
+        return HttpResponse::BadRequest().body("Push-only tokens need the 'writer' role");
+    }
+
+    // Without credentials nothing is checked, a token would limit no one
+    if req.app_data::<web::Data<Credentials>>().is_none() {
+        return HttpResponse::Conflict()
+            .body("API tokens need authentication, start the server with --auth-file");
+    }
+
+    for flow in &request.flows {
+        if let Err(response) = auth::authorize(&req, flow, Role::Admin) {
+            return response;
+        }
+    }
+
+    let token = auth::generate_token();
+    let expires = request
+        .expires_in_days
+        .map(|days| chrono::Utc::now() + chrono::Duration::days(days as i64));
+
+    let token_id = match db_client
+        .create_api_token(
+            request.name.clone(),
+            auth::hash_token(&token),
+            TokenScope {
+                flows: request.flows.clone(),
+                role: request.role,
+                direction: request.direction,
+            },
+            expires,
+            Actor::from_request(&req),
+        )
+        .await
+    {
+        Ok(token_id) => token_id,
+        Err(e) => {
+            error!("Failed to create API token '{}': {}", request.name, e);
+            return HttpResponse::InternalServerError().body("Failed to create API token");
+        }
+    };
+
+    if let Err(response) = refresh_tokens(&db_client, &tokens).await {
+        return response;
+    }
+
+    HttpResponse::Created().json(serde_json::json!({
+        "id": token_id,
+        "name": request.name,
+        "token": token,
+        "flows": request.flows,
+        "role": request.role,
+        "direction": request.direction,
+        "expires": expires
+    }))
+}
+
+// Revoke an API token by id
+pub async fn revoke_token(
+    tokens: web::Data<TokenStore>,
+    path: web::Path<i32>,
+    db_client: web::Data<Arc<dyn Storage>>,
+    req: HttpRequest,
+) -> impl Responder {
+    let token_id = path.into_inner();
+
+    let Some(token) = tokens.all().into_iter().find(|token| token.id == token_id) else {
+        return HttpResponse::NotFound().body(format!("API token {} not found", token_id));
+    };
+
+    for flow in &token.flows {
+        if let Err(response) = auth::authorize(&req, flow, Role::Admin) {
+            return response;
+        }
+    }
+
+    if let Err(e) = db_client
+        .revoke_api_token(token_id, Actor::from_request(&req))
+        .await
+    {
+        error!("Failed to revoke API token {}: {}", token_id, e);
+        return HttpResponse::InternalServerError().body("Failed to revoke API token");
+    }
+
+    if let Err(response) = refresh_tokens(&db_client, &tokens).await {
+        return response;
+    }
+
+    info!(
+        "User '{}' revoked API token '{}' (id {})",
+        auth::current_user(&req).unwrap_or_else(|| "anonymous".to_string()),
+        token.name,
+        token_id
+    );
+    HttpResponse::NoContent().finish()
+}
+
+async fn refresh_tokens(
+    db_client: &Arc<dyn Storage>,
+    tokens: &TokenStore,
+) -> Result<(), HttpResponse> {
+    match db_client.get_api_tokens().await {
+        Ok(updated) => {
+            tokens.replace(updated);
+            Ok(())
+        }
+        Err(e) => {
+            error!("Failed to refresh API tokens from database: {}", e);
+            Err(HttpResponse::InternalServerError()
+                .body("Failed to refresh API tokens from database"))
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CreateFlowRequest {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct UpdateFlowRequest {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub archived: Option<bool>,
+    #[serde(default)]
+    pub key_policy: Option<KeyPolicy>,
+}
+
+// Empty descriptions clear the field
+fn normalize_description(description: Option<String>) -> Option<String> {
+    description
+        .map(|description| description.trim().to_string())
+        .filter(|description| !description.is_empty())
+}
+
+// Create a new flow; needs admin on every flow since nobody holds grants on it yet
+pub async fn create_flow(
+    flows: web::Data<Flows>,
+    events: web::Data<FlowEvents>,
+    flow_registry: web::Data<FlowRegistry>,
+    request: web::Json<CreateFlowRequest>,
+    db_client: web::Data<Arc<dyn Storage>>,
+    req: HttpRequest,
+) -> impl Responder {
+    let request = request.into_inner();
+
+    if let Err(response) = auth::authorize(&req, auth::ALL_FLOWS, Role::Admin) {
+        return response;
+    }
+
+    if !is_valid_flow_name(&request.name) {
+        return HttpResponse::BadRequest().body(format!(
+            "Invalid flow name '{}': use letters, digits, '-', '_' or '.'",
+            request.name
+        ));
+    }
+
+    let created = match db_client
+        .create_flow(
+            request.name.clone(),
+            normalize_description(request.description),
+            Actor::from_request(&req),
+        )
+        .await
+    {
+        Ok(created) => created,
+        Err(e) => {
+            error!("Failed to create flow '{}': {}", request.name, e);
+            return HttpResponse::InternalServerError().body("Failed to create flow");
+        }
+    };
+
+    if !created {
+        return HttpResponse::Conflict().body(format!("Flow '{}' already exists", request.name));
+    }
+
+    if let Err(response) =
+        refresh_flows(&db_client, &flow_registry, &flows, &events, &request.name).await
+    {
+        return response;
+    }
+
+    info!(
+        "User '{}' created flow '{}'",
+        auth::current_user(&req).unwrap_or_else(|| "anonymous".to_string()),
+        request.name
+    );
+    HttpResponse::Created().json(flow_registry.get(&request.name))
+}
+
+// Rename, describe, archive or unarchive a flow, or change its key policy
+pub async fn update_flow(
+    flows: web::Data<Flows>,
+    events: web::Data<FlowEvents>,
+    flow_registry: web::Data<FlowRegistry>,
+    path: web::Path<String>,
+    request: web::Json<UpdateFlowRequest>,
+    db_client: web::Data<Arc<dyn Storage>>,
+    req: HttpRequest,
+) -> impl Responder {
+    let flow_name = path.into_inner();
+    let request = request.into_inner();
+
+    if let Err(response) = auth::authorize(&req, &flow_name, Role::Admin) {
+        return response;
+    }
+
+    if !flow_registry.contains(&flow_name) {
+        return HttpResponse::NotFound().body(format!("Flow '{}' not found", flow_name));
+    }
+
+    let new_name = request.name.filter(|name| name != &flow_name);
+    if let Some(new_name) = &new_name {
+        // Grants and tokens move with the flow, so renaming is reserved to global admins
+        if let Err(response) = auth::authorize(&req, auth::ALL_FLOWS, Role::Admin) {
+            return response;
+        }
+        if !is_valid_flow_name(new_name) {
+            return HttpResponse::BadRequest().body(format!(
+                "Invalid flow name '{}': use letters, digits, '-', '_' or '.'",
+                new_name
+            ));
+        }
+        if flow_registry.contains(new_name) {
+            return HttpResponse::Conflict().body(format!("Flow '{}' already exists", new_name));
+        }
+    }
+
+    // Everything is validated above; the changes are applied together or not at all
+    let changes = FlowChanges {
+        name: new_name.clone(),
+        description: request
+            .description
+            .map(|description| normalize_description(Some(description))),
+        archived: request.archived,
+        key_policy: request.key_policy,
+    };
+    match db_client
+        .update_flow(flow_name.clone(), changes, Actor::from_request(&req))
+        .await
+    {
+        Ok(true) => {}
+        Ok(false) => {
+            return HttpResponse::Conflict().body(format!(
+                "Flow '{}' already exists",
+                new_name.unwrap_or_default()
+            ))
+        }
+        Err(e) => {
+            error!("Failed to update flow '{}': {}", flow_name, e);
+            return HttpResponse::InternalServerError().body("Failed to update flow");
+        }
+    }
+
+    if let Some(new_name) = &new_name {
+        if let Err(e) = flows
+            .rename(&db_client, &events, &flow_name, new_name)
+            .await
+        {
+            error!(
+                "Failed to refresh renamed flow '{}' from database: {}",
+                new_name, e
+            );
+            return HttpResponse::InternalServerError()
+                .body("Failed to refresh flows from database");
+        }
+    }
+    let current_name = new_name.clone().unwrap_or(flow_name.clone());
+    if let Err(response) =
+        refresh_flows(&db_client, &flow_registry, &flows, &events, &current_name).await
+    {
+        return response;
+    }
+    // Grants and token scopes were renamed in the database as well
+    if new_name.is_some() {
+        if let Some(grants) = req.app_data::<web::Data<GrantStore>>() {
+            if let Err(response) = refresh_grants(&db_client, grants).await {
+                return response;
+            }
+        }
+        if let Some(tokens) = req.app_data::<web::Data<TokenStore>>() {
+            if let Err(response) = refresh_tokens(&db_client, tokens).await {
+                return response;
+            }
+        }
+    }
+
+    info!(
+        "User '{}' updated flow '{}'",
+        auth::current_user(&req).unwrap_or_else(|| "anonymous".to_string()),
+        current_name
+    );
+    HttpResponse::Ok().json(flow_registry.get(&current_name))
+}
+
+// Archive a flow: it stays readable but no longer accepts keys
+pub async fn archive_flow(
+    flows: web::Data<Flows>,
+    events: web::Data<FlowEvents>,
+    flow_registry: web::Data<FlowRegistry>,
+    path: web::Path<String>,
+    db_client: web::Data<Arc<dyn Storage>>,
+    req: HttpRequest,
+) -> impl Responder {
+    let flow_name = path.into_inner();
+
+    if let Err(response) = auth::authorize(&req, &flow_name, Role::Admin) {
+        return response;
+    }
+
+    let archived = match db_client
+        .set_flow_archived(flow_name.clone(), true, Actor::from_request(&req))
+        .await
+    {
+        Ok(count) => count,
+        Err(e) => {
+            error!("Failed to archive flow '{}': {}", flow_name, e);
+            return HttpResponse::InternalServerError().body("Failed to archive flow");
+        }
+    };
+
+    if archived == 0 {
+        return HttpResponse::NotFound().body(format!("Flow '{}' not found", flow_name));
+    }
+
+    if let Err(response) =
+        refresh_flows(&db_client, &flow_registry, &flows, &events, &flow_name).await
+    {
+        return response;
+    }
+
+    info!(
+        "User '{}' archived flow '{}'",
+        auth::current_user(&req).unwrap_or_else(|| "anonymous".to_string()),
+        flow_name
+    );
+    HttpResponse::NoContent().finish()
+}
+
+// Reload the flow registry, and the CAs and revision of the changed flow
+async fn refresh_flows(
+    db_client: &Arc<dyn Storage>,
+    flow_registry: &FlowRegistry,
+    flows: &Flows,
+    events: &FlowEvents,
+    flow_name: &str,
+) -> Result<(), HttpResponse> {
+    let metadata = db_client.get_flow_metadata().await.map_err(|e| {
+        error!("Failed to refresh flow metadata from database: {}", e);
+        HttpResponse::InternalServerError().body("Failed to refresh flows from database")
+    })?;
+    flow_registry.replace(metadata);
+
+    flows
+        .refresh(db_client, events, vec![flow_name.to_string()], Vec::new())
+        .await
+        .map_err(|e| {
+            error!(
+                "Failed to refresh flow '{}' from database: {}",
+                flow_name, e
+            );
+            HttpResponse::InternalServerError().body("Failed to refresh flows from database")
+        })
+}
+
+// Revoked keys of a flow for RevokedHostKeys: public key lines, or an OpenSSH KRL with ?format=krl
+pub async fn get_revoked_keys(
+    flows: web::Data<Flows>,
+    flow_id: web::Path<String>,
+    flow_registry: web::Data<FlowRegistry>,
+    req: HttpRequest,
+    query: web::Query<std::collections::HashMap<String, String>>,
+) -> impl Responder {
+    let flow_id_str = flow_id.into_inner();
+
+    if !flow_registry.contains(&flow_id_str) {
+        return HttpResponse::Forbidden().body("Flow ID not allowed");
+    }
+
+    if let Err(response) = auth::authorize(&req, &flow_id_str, Role::Reader) {
+        return response;
+    }
+
+    let mut revoked_keys: Vec<String> = flows
+        .get(&flow_id_str)
+        .map(|flow| {
+            flow.keys()
+                .filter(|key| key.marker == Some(Marker::Revoked))
+                .map(|key| key.public_key.clone())
+                .collect()
+        })
+        .unwrap_or_default();
+    revoked_keys.sort();
+    revoked_keys.dedup();
+
+    match query.get("format").map(String::as_str) {
+        Some("krl") => match crate::krl::build_krl(
+            &revoked_keys,
+            &format!("khm revoked keys for flow {}", flow_id_str),
+        ) {
+            Ok(krl) => HttpResponse::Ok()
+                .content_type("application/octet-stream")
+                .body(krl),
+            Err(e) => {
+                error!("Failed to build KRL for flow '{}': {}", flow_id_str, e);
+                HttpResponse::InternalServerError().body("Failed to build KRL")
+            }
+        },
+        None | Some("keys") => {
+            let mut body = String::new();
+            for key in &revoked_keys {
+                body.push_str(key);
+                body.push('\n');
+            }
+            HttpResponse::Ok().content_type("text/plain").body(body)
+        }
+        Some(other) => {
+            HttpResponse::BadRequest().body(format!("Unknown format '{}': use keys or krl", other))
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NewCertAuthority {
+    pub hosts: Vec<String>,
+    pub public_key: String,
+    #[serde(default)]
+    pub comment: Option<String>,
+}
+
+// List the certificate authorities a flow trusts
+pub async fn get_cert_authorities(
+    flows: web::Data<Flows>,
+    flow_id: web::Path<String>,
+    flow_registry: web::Data<FlowRegistry>,
+    req: HttpRequest,
+) -> impl Responder {
+    let flow_id_str = flow_id.into_inner();
+
+    if !flow_registry.contains(&flow_id_str) {
+        return HttpResponse::Forbidden().body("Flow ID not allowed");
+    }
+
+    if let Err(response) = auth::authorize(&req, &flow_id_str, Role::Reader) {
+        return response;
+    }
+
+    let cert_authorities: Vec<CertAuthority> = flows
+        .get(&flow_id_str)
+        .map(|flow| flow.cert_authorities.clone())
+        .unwrap_or_default();
+    HttpResponse::Ok().json(cert_authorities)
+}
+
+// Trust a host CA key for a list of host patterns in the flow
+pub async fn add_cert_authority(
+    flows: web::Data<Flows>,
+    events: web::Data<FlowEvents>,
+    flow_id: web::Path<String>,
+    request: web::Json<NewCertAuthority>,
+    db_client: web::Data<Arc<dyn Storage>>,
+    flow_registry: web::Data<FlowRegistry>,
+    req: HttpRequest,
+) -> impl Responder {
+    let flow_id_str = flow_id.into_inner();
+    let request = request.into_inner();
+
+    if !flow_registry.contains(&flow_id_str) {
+        return HttpResponse::Forbidden().body("Flow ID not allowed");
+    }
+
+    if let Err(response) = auth::authorize(&req, &flow_id_str, Role::Admin) {
+        return response;
+    }
+
+    if flow_registry.is_archived(&flow_id_str) {
+        return HttpResponse::Conflict().body("Flow is archived");
+    }
+
+    let public_key = request.public_key.trim();
+    if !is_valid_ssh_key(public_key) {
+        return HttpResponse::BadRequest().body("Invalid SSH key format for certificate authority");
+    }
+    // A comment pasted together with the key is kept as the CA comment
+    let mut fields = public_key.splitn(3, ' ');
+    let key = format!(
+        "{} {}",
+        fields.next().unwrap_or_default(),
+        fields.next().unwrap_or_default()
+    );
+    let comment = normalize_description(request.comment.or(fields.next().map(str::to_string)));
+
+    let hosts: Vec<String> = request
+        .hosts
+        .iter()
+        .flat_map(|hosts| hosts.split(','))
+        .map(|host| host.trim().to_string())
+        .filter(|host| !host.is_empty())
+        .collect();
+    if hosts.is_empty() {
+        return HttpResponse::BadRequest().body("At least one host pattern is required");
+    }
+    for host in &hosts {
+        match host.parse::<HostPattern>() {
+            Ok(pattern) if pattern.is_hashed() => {
+                return HttpResponse::BadRequest().body(format!(
+                    "Hashed host names cannot be used for a CA: {}",
+                    host
+                ));
+            }
+            Ok(_) => {}
+            Err(e) => return HttpResponse::BadRequest().body(e),
+        }
+    }
+
+    let cert_authority = match db_client
+        .add_cert_authority(
+            flow_id_str.clone(),
+            hosts,
+            key,
+            comment,
+            Actor::from_request(&req),
+        )
+        .await
+    {
+        Ok(Some(cert_authority)) => cert_authority,
+        Ok(None) => {
+            return HttpResponse::Conflict()
+                .body("This certificate authority is already trusted in the flow");
+        }
+        Err(e) => {
+            error!(
+                "Failed to add certificate authority to flow '{}': {}",
+                flow_id_str, e
+            );
+            return HttpResponse::InternalServerError().body("Failed to add certificate authority");
+        }
+    };
+
+    if let Err(response) =
+        refresh_flows(&db_client, &flow_registry, &flows, &events, &flow_id_str).await
+    {
+        return response;
+    }
+
+    HttpResponse::Created().json(cert_authority)
+}
+
+pub async fn remove_cert_authority(
+    flows: web::Data<Flows>,
+    events: web::Data<FlowEvents>,
+    path: web::Path<(String, i32)>,
+    db_client: web::Data<Arc<dyn Storage>>,
+    flow_registry: web::Data<FlowRegistry>,
+    req: HttpRequest,
+) -> impl Responder {
+    let (flow_id_str, ca_id) = path.into_inner();
+
+    if !flow_registry.contains(&flow_id_str) {
+        return HttpResponse::Forbidden().body("Flow ID not allowed");
+    }
+
+    if let Err(response) = auth::authorize(&req, &flow_id_str, Role::Admin) {
+        return response;
+    }
+
+    if flow_registry.is_archived(&flow_id_str) {
+        return HttpResponse::Conflict().body("Flow is archived");
+    }
+
+    match db_client
+        .remove_cert_authority(flow_id_str.clone(), ca_id, Actor::from_request(&req))
+        .await
+    {
+        Ok(0) => {
+            return HttpResponse::NotFound().body(format!(
+                "Certificate authority {} not found in flow '{}'",
+                ca_id, flow_id_str
+            ));
+        }
+        Ok(_) => {}
+        Err(e) => {
+            error!(
+                "Failed to remove certificate authority {} from flow '{}': {}",
+                ca_id, flow_id_str, e
+            );
+            return HttpResponse::InternalServerError()
+                .body("Failed to remove certificate authority");
+        }
+    }
+
+    if let Err(response) =
+        refresh_flows(&db_client, &flow_registry, &flows, &events, &flow_id_str).await
+    {
+        return response;
+    }
+
+    HttpResponse::NoContent().finish()
+}
+
+// History of key, flow, grant and token changes, newest first
+pub async fn get_audit(
+    query: web::Query<AuditQuery>,
+    db_client: web::Data<Arc<dyn Storage>>,
+    req: HttpRequest,
+) -> impl Responder {
+    let query = query.into_inner();
+
+    // Without a flow filter the log spans every flow
+    let scope = query.flow.as_deref().unwrap_or(auth::ALL_FLOWS);
+    if let Err(response) = auth::authorize(&req, scope, Role::Admin) {
+        return response;
+    }
+
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_AUDIT_LIMIT)
+        .clamp(1, MAX_AUDIT_LIMIT);
+
+    match db_client.get_audit_log(query, limit).await {
+        Ok(entries) => HttpResponse::Ok().json(entries),
+        Err(e) => {
+            error!("Failed to read audit log: {}", e);
+            HttpResponse::InternalServerError().body("Failed to read audit log")
+        }
+    }
+}
+
+// Everything the handlers share: the storage and the in-memory copies read from it
+#[derive(Clone)]
+pub struct ServerState {
+    db_client: Arc<dyn Storage>,
+    flows: web::Data<Flows>,
+    events: FlowEvents,
+    flow_registry: web::Data<FlowRegistry>,
+    grants: web::Data<GrantStore>,
+    tokens: web::Data<TokenStore>,
+    credentials: Option<web::Data<Credentials>>,
+}
+
+impl ServerState {
+    // Open the storage the --db-* arguments point to, bring its schema up to date,
+    // seed it from --flows and --admin-users and read everything the handlers serve
+    pub async fn load(args: &crate::Args) -> std::io::Result<Self> {
+        let db_client = storage::open(args)?;
+
+        // Bring the schema up to date; `khm migrate` does the same without starting the server
+        match wait_for_database(&db_client).await {
+            Ok(applied) if applied.is_empty() => info!("Database schema is up to date"),
+            Ok(applied) => info!("Applied {} database migrations", applied.len()),
+            Err(e) => {
+                error!("Failed to migrate database schema: {}", e);
+                return Err(std::io::Error::other(format!(
+                    "Database migration error: {}",
+                    e
+                )));
+            }
+        }
+
+        // --flows only seeds the flow list; flows are managed at runtime through /api/flows
+        for seed_flow in &args.flows {
+            if !is_valid_flow_name(seed_flow) {
+                warn!("Ignoring invalid flow name from --flows: '{}'", seed_flow);
+                continue;
+            }
+            if let Err(e) = db_client
+                .create_flow(seed_flow.clone(), None, Actor::system())
+                .await
+            {
+                error!("Failed to register flow '{}': {}", seed_flow, e);
+            }
+        }
+
+        let flow_registry = FlowRegistry::default();
+        match db_client.get_flow_metadata().await {
+            Ok(metadata) => flow_registry.replace(metadata),
+            Err(e) => error!("Failed to get flow metadata from database: {}", e),
+        }
+
+        // A fresh database without --flows still gets a flow to start with
+        if flow_registry.all().is_empty() {
+            info!("No flows registered, creating 'default'");
+            if let Err(e) = db_client
+                .create_flow("default".to_string(), None, Actor::system())
+                .await
+            {
+                error!("Failed to create default flow: {}", e);
+            }
+            match db_client.get_flow_metadata().await {
+                Ok(metadata) => flow_registry.replace(metadata),
+                Err(e) => error!("Failed to get flow metadata from database: {}", e),
+            }
+        }
+
+        let initial_flows = match db_client.get_keys_from_db().await {
+            Ok(flows) => flows,
+            Err(e) => {
+                error!("Failed to get initial flows from database: {}", e);
+                Vec::new()
+            }
+        };
+
+        // Load API credentials; without them every route stays publicly accessible
+        let credentials = match &args.auth_file {
+            Some(path) => match Credentials::load(path) {
+                Ok(credentials) => Some(web::Data::new(credentials)),
+                Err(e) => {
+                    error!("Failed to load credentials file {}: {}", path, e);
+                    return Err(e);
+                }
+            },
+            None => {
+                warn!("No --auth-file given: the API is accessible without authentication");
+                None
+            }
+        };
+
+        // Bootstrap administrators so a fresh database can be managed at all
+        for admin in &args.admin_users {
+            if let Err(e) = db_client
+                .set_grant(
+                    admin.clone(),
+                    auth::ALL_FLOWS.to_string(),
+                    Role::Admin,
+                    Actor::system(),
+                )
+                .await
+            {
+                error!("Failed to grant admin role to '{}': {}", admin, e);
+            }
+        }
+
+        let grants = GrantStore::default();
+        match db_client.get_grants().await {
+            Ok(initial_grants) => grants.replace(initial_grants),
+            Err(e) => error!("Failed to get initial grants from database: {}", e),
+        }
+
+        let tokens = TokenStore::default();
+        match db_client.get_api_tokens().await {
+            Ok(initial_tokens) => tokens.replace(initial_tokens),
+            Err(e) => error!("Failed to get API tokens from database: {}", e),
+        }
+        let token_count = tokens.all().len();
+        if credentials.is_none() && token_count > 0 {
+            error!(
+                "{} API token(s) exist but no --auth-file is given: they are not checked and grant nothing over anonymous access",
+                token_count
+            );
+        }
+
+        Ok(ServerState {
+            db_client,
+            flows: web::Data::new(Flows::new(initial_flows)),
+            events: FlowEvents::new(),
+            flow_registry: web::Data::new(flow_registry),
+            grants: web::Data::new(grants),
+            tokens: web::Data::new(tokens),
+            credentials,
+        })
+    }
+}
+
+// The application run_server serves, without a listener. Tests hand it to
+// actix_web::test::init_service, or to an HttpServer bound to a free port.
+pub fn build_app(
+    state: &ServerState,
+) -> App<
+    impl ServiceFactory<
+        ServiceRequest,
+        Config = (),
+        Response = ServiceResponse<impl MessageBody>,
+        Error = actix_web::Error,
+        InitError = (),
+    >,
+> {
+    let mut app = App::new()
+        .wrap(from_fn(report_unavailable_database))
+        .wrap(from_fn(auth::authenticate))
+        .app_data(state.flows.clone())
+        .app_data(web::Data::new(state.events.clone()))
+        .app_data(web::Data::new(state.db_client.clone()))
+        .app_data(state.flow_registry.clone())
+        .app_data(state.grants.clone())
+        .app_data(state.tokens.clone())
+        .route("/healthz", web::get().to(healthz))
+        .route("/readyz", web::get().to(readyz))
+        // Original API routes
+        .route("/{flow_id}/keys", web::get().to(get_keys))
+        .route("/{flow_id}/keys", web::post().to(add_keys))
+        .route("/{flow_id}/keys/changes", web::get().to(get_key_changes))
+        .route("/{flow_id}/events", web::get().to(get_flow_events))
+        .route("/{flow_id}/revoked-keys", web::get().to(get_revoked_keys))
+        .route(
+            "/{flow_id}/cert-authorities",
+            web::get().to(get_cert_authorities),
+        )
+        .route(
+            "/{flow_id}/cert-authorities",
+            web::post().to(add_cert_authority),
+        )
+        .route(
+            "/{flow_id}/cert-authorities/{ca_id}",
+            web::delete().to(remove_cert_authority),
+        )
+        // Flow management routes
+        .route("/api/flows", web::post().to(create_flow))
+        .route("/api/flows/{flow_name}", web::patch().to(update_flow))
+        .route("/api/flows/{flow_name}", web::delete().to(archive_flow))
+        // Access control routes
+        .route("/api/grants", web::get().to(get_grants))
+        .route("/api/grants", web::put().to(set_grant))
+        .route(
+            "/api/grants/{username}/{flow_id}",
+            web::delete().to(revoke_grant),
+        )
+        .route("/api/tokens", web::get().to(get_tokens))
+        .route("/api/tokens", web::post().to(create_token))
+        .route("/api/tokens/{token_id}", web::delete().to(revoke_token))
+        // Change history
+        .route("/api/audit", web::get().to(get_audit));
+
+    if let Some(credentials) = &state.credentials {
+        app = app.app_data(credentials.clone());
+    }
+
+    #[cfg(feature = "web")]
+    {
+        app = app.configure(configure_web_routes);
+    }
+
+    app
+}
+
+pub async fn run_server(args: crate::Args) -> std::io::Result<()> {
+    let state = ServerState::load(&args).await?;
+
+    let closing = state.events.clone();
+    let shutdown = shutdown_signal();
+    tokio::spawn(async move {
+        shutdown.await;
+        closing.close();
+    });
+
+    actix_web::rt::spawn(monitor_database(state.clone()));
+
+    // Optional native TLS termination
+    let tls_config = match (&args.tls_cert, &args.tls_key) {
+        (Some(cert_path), Some(key_path)) => {
+            let cert = Arc::new(ReloadableCert::load(cert_path, key_path)?);
+            tls::reload_on_sighup(cert.clone())?;
+            Some(tls::build_server_config(
+                cert,
+                args.tls_client_ca.as_deref(),
+            )?)
+        }
+        _ => None,
+    };
+
+    let scheme = if tls_config.is_some() {
+        "HTTPS"
+    } else {
+        "HTTP"
+    };
+    info!("Starting {} server on {}:{}", scheme, args.ip, args.port);
+    let server =
+        HttpServer::new(move || build_app(&state)).on_connect(tls::record_client_certificate);
+
+    let server = match tls_config {
+        Some(config) => server.bind_rustls_0_23((args.ip.as_str(), args.port), config)?,
+        None => server.bind((args.ip.as_str(), args.port))?,
+    };
+
+    server.run().await
+}
+
+// Apply pending migrations once the database is reachable. It may come up after
+// the server, e.g. when both start with the host; any other error ends the startup.
+async fn wait_for_database(
+    db_client: &Arc<dyn Storage>,
+) -> Result<Vec<&'static Migration>, DbError> {
+    let mut delay = Duration::from_secs(1);
+    loop {
+        match db_client.migrate_up().await {
+            Err(e) if e.is_unavailable() => {
+                warn!(
+                    "Waiting for the database, retrying in {} seconds: {}",
+                    delay.as_secs(),
+                    e
+                );
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(MAX_STARTUP_RETRY_DELAY);
+            }
+            result => return result,
+        }
+    }
+}
+
+// Keep the readiness state current while no requests come in. Once the database
+// is back after an outage everything is read again, since changes made through
+// other servers in the meantime were missed.
+async fn monitor_database(state: ServerState) {
+    let ServerState {
+        db_client,
+        flows,
+        events,
+        flow_registry,
+        grants,
+        tokens,
+        ..
+    } = state;
+    let mut reconnects = db_client.reconnects();
+    loop {
+        tokio::time::sleep(DATABASE_CHECK_INTERVAL).await;
+        // Failures are logged and tracked by the client itself
+        let _ = db_client.ping().await;
+
+        let current = db_client.reconnects();
+        if current == reconnects {
+            continue;
+        }
+        info!("Database is reachable again, reloading flows, grants and API tokens");
+        let reloaded = async {
+            flow_registry.replace(db_client.get_flow_metadata().await?);
+            flows.reload(&db_client, &events).await?;
+            grants.replace(db_client.get_grants().await?);
+            tokens.replace(db_client.get_api_tokens().await?);
+            Ok::<(), DbError>(())
+        }
+        .await;
+        match reloaded {
+            Ok(()) => reconnects = current,
+            Err(e) => error!("Failed to reload state from database: {}", e),
+        }
+    }
+}
+
+// A request that failed while the database is unreachable gets a 503, so that
+// clients and load balancers try again instead of reporting a server error
+async fn report_unavailable_database(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let db_client = req.app_data::<web::Data<Arc<dyn Storage>>>().cloned();
+    let response = next.call(req).await?;
+    match db_client {
+        Some(db_client)
+            if response.status() == StatusCode::INTERNAL_SERVER_ERROR
+                && !db_client.is_available() =>
+        {
+            let (req, _) = response.into_parts();
+            let unavailable = HttpResponse::ServiceUnavailable()
+                .insert_header((RETRY_AFTER, DATABASE_CHECK_INTERVAL.as_secs().to_string()))
+                .body("Database is unavailable, try again later");
+            Ok(ServiceResponse::new(req, unavailable).map_into_right_body())
+        }
+        _ => Ok(response.map_into_left_body()),
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct HealthStatus {
+    pub status: String,
+    pub database: String,
+}
+
+fn health_status(db_client: &Arc<dyn Storage>, status: &str) -> HealthStatus {
+    HealthStatus {
+        status: status.to_string(),
+        database: if db_client.is_available() {
+            "up".to_string()
+        } else {
+            "down".to_string()
+        },
+    }
+}
+
+// Liveness: the process answers, whatever the state of the database. Keys are
+// still served from memory while the database is down.
+pub async fn healthz(db_client: web::Data<Arc<dyn Storage>>) -> impl Responder {
+    HttpResponse::Ok().json(health_status(&db_client, "ok"))
+}
+
+// Readiness: changes can only be stored while the database is reachable
+pub async fn readyz(db_client: web::Data<Arc<dyn Storage>>) -> impl Responder {
+    if db_client.is_available() {
+        HttpResponse::Ok().json(health_status(&db_client, "ready"))
+    } else {
+        HttpResponse::ServiceUnavailable().json(health_status(&db_client, "unavailable"))
+    }
+}
+
+// Actix stops on these signals too, but waits for open responses such as event
+// streams. The handlers are installed right away, so a signal that arrives
+// before the future is first polled is not lost.
+#[cfg(unix)]
+pub(crate) fn shutdown_signal() -> impl std::future::Future<Output = ()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let terminate = signal(SignalKind::terminate());
+    let interrupt = signal(SignalKind::interrupt());
+    async move {
+        match (terminate, interrupt) {
+            (Ok(mut terminate), Ok(mut interrupt)) => {
+                tokio::select! {
+                    _ = terminate.recv() => {}
+                    _ = interrupt.recv() => {}
+                }
+            }
+            (Err(e), _) | (_, Err(e)) => {
+                error!("Failed to listen for shutdown signals: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn shutdown_signal() -> impl std::future::Future<Output = ()> {
+    async {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+#[cfg(feature = "web")]
+fn configure_web_routes(cfg: &mut web::ServiceConfig) {
+    cfg
+        // API routes
+        .route("/api/version", web::get().to(crate::web::get_version_api))
+        .route("/api/flows", web::get().to(crate::web::get_flows_api))
+        .route(
+            "/{flow_id}/scan-dns",
+            web::post().to(crate::web::scan_dns_resolution),
+        )
+        .route(
+            "/{flow_id}/bulk-deprecate",
+            web::post().to(crate::web::bulk_deprecate_servers),
+        )
+        .route(
+            "/{flow_id}/bulk-restore",
+            web::post().to(crate::web::bulk_restore_servers),
+        )
+        .route(
+            "/{flow_id}/keys/{server}",
+            web::delete().to(crate::web::delete_key_by_server),
+        )
+        .route(
+            "/{flow_id}/keys/{server}/restore",
+            web::post().to(crate::web::restore_key_by_server),
+        )
+        .route(
+            "/{flow_id}/keys/{server}/delete",
+            web::delete().to(crate::web::permanently_delete_key_by_server),
+        )
+        .route(
+            "/{flow_id}/keys/{server}/approve",
+            web::post().to(crate::web::approve_key_by_server),
+        )
+        .route(
+            "/{flow_id}/keys/{server}/reject",
+            web::post().to(crate::web::reject_key_by_server),
+        )
+        .route(
+            "/{flow_id}/keys/{server}/revoke",
+            web::post().to(crate::web::revoke_key_by_server),
+        )
+        // Web interface routes
         .route("/", web::get().to(crate::web::serve_web_interface))
         .route(
             "/static/{filename:.*}",
             web::get().to(crate::web::serve_static_file),
         );
-    
+
     // Web GUI routes
     cfg.route("/gui", web::get().to(crate::web_gui::serve_egui_interface))
         .route("/gui/", web::get().to(crate::web_gui::serve_egui_interface))
@@ -0,0 +1,1574 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use log::{error, info};
+use rusqlite::types::Type;
+use rusqlite::{
+    params, params_from_iter, Connection, OptionalExtension, Row, ToSql, Transaction,
+    TransactionBehavior,
+};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::audit::{Actor, AuditEntry, AuditQuery, FLOW_CONTENT_ACTIONS, KEY_ACTIONS};
+use crate::auth::{ApiToken, Grant, Role, TokenScope};
+use crate::client::SyncDirection;
+use crate::db::{group_flows, DbError, FlowChanges, FlowKeyStats, FlowUpdate, KeyInsertStats};
+use crate::known_hosts::Marker;
+use crate::migrations::{self, AppliedMigration, Downgrade, Migration};
+use crate::server::{CertAuthority, Flow, FlowInfo, KeyPolicy, SshKey};
+use crate::storage::Storage;
+
+// How long a statement waits for another process holding the write lock, such as
+// `khm migrate`, before failing
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+const AUDIT_COLUMNS: &str =
+    "id, at, action, flow, server, public_key, actor, client_hostname, details";
+
+// A single SQLite database file. Statements run one at a time on tokio's blocking
+// threads, and every change is one IMMEDIATE transaction together with its audit rows.
+pub struct SqliteStorage {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStorage {
+    pub fn open(path: &str) -> Result<Self, DbError> {
+        let connection = Connection::open(path)?;
+        connection.busy_timeout(BUSY_TIMEOUT)?;
+        // Deleting a key drops its flow associations through the foreign key
+        connection.execute_batch("PRAGMA journal_mode = WAL; PRAGMA foreign_keys = ON;")?;
+        Ok(SqliteStorage {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    async fn run<T: Send + 'static>(
+        &self,
+        operation: impl FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static,
+    ) -> Result<T, DbError> {
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || {
+            // A panic mid-statement rolls its transaction back, so the connection stays usable
+            let mut connection = connection.lock().unwrap_or_else(|e| e.into_inner());
+            operation(&mut connection)
+        })
+        .await
+        .map_err(|e| DbError::Unavailable(e.to_string()))?
+        .map_err(DbError::from)
+    }
+
+    // Run `operation` in a write transaction, committed when it succeeds
+    async fn write<T: Send + 'static>(
+        &self,
+        operation: impl FnOnce(&Transaction) -> rusqlite::Result<T> + Send + 'static,
+    ) -> Result<T, DbError> {
+        self.run(move |connection| {
+            let transaction =
+                connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let result = operation(&transaction)?;
+            transaction.commit()?;
+            Ok(result)
+        })
+        .await
+    }
+}
+
+fn placeholders(count: usize) -> String {
+    vec!["?"; count].join(", ")
+}
+
+// Space-separated part of a public key line: 0 is the type, 1 the key itself
+fn key_part(key: &str, index: usize) -> &str {
+    key.split(' ').nth(index).unwrap_or("")
+}
+
+fn to_json(values: &[String]) -> String {
+    serde_json::Value::from(values.to_vec()).to_string()
+}
+
+fn json_column(row: &Row, index: usize) -> rusqlite::Result<Vec<String>> {
+    let text: String = row.get(index)?;
+    serde_json::from_str(&text)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(index, Type::Text, Box::new(e)))
+}
+
+// Quoted list of the actions that make up a flow revision
+fn revision_actions() -> String {
+    KEY_ACTIONS
+        .iter()
+        .chain(FLOW_CONTENT_ACTIONS)
+        .map(|action| format!("'{}'", action))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn log_action(
+    connection: &Connection,
+    action: &str,
+    flow: &str,
+    server: Option<&str>,
+    public_key: Option<&str>,
+    actor: &Actor,
+    details: Option<&str>,
+) -> rusqlite::Result<()> {
+    connection.execute(
+        "INSERT INTO audit_log (action, flow, server, public_key, actor, client_hostname, details)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            action,
+            flow,
+            server,
+            public_key,
+            actor.user,
+            actor.client_hostname,
+            details
+        ],
+    )?;
+    Ok(())
+}
+
+// Log a change made in `flow` to a shared key for every other flow linking it, whose
+// revision it moves as well
+fn log_other_flows(
+    connection: &Connection,
+    action: &str,
+    flow: &str,
+    key_id: i32,
+    actor: &Actor,
+    details: &str,
+) -> rusqlite::Result<()> {
+    connection.execute(
+        "INSERT INTO audit_log (action, flow, server, public_key, actor, client_hostname, details)
+         SELECT ?1, f.name, k.host, k.key, ?3, ?4, ?5
+         FROM flows f INNER JOIN keys k ON k.key_id = f.key_id
+         WHERE f.key_id = ?6 AND f.name <> ?2",
+        params![
+            action,
+            flow,
+            actor.user,
+            actor.client_hostname,
+            details,
+            key_id
+        ],
+    )?;
+    Ok(())
+}
+
+// Run an UPDATE of keys returning (key_id, host, key) and log every returned key as
+// `action` in every flow linking it
+fn update_keys(
+    connection: &Connection,
+    sql: &str,
+    values: &[&dyn ToSql],
+    action: &str,
+    flow: &str,
+    actor: &Actor,
+) -> rusqlite::Result<u64> {
+    let changed: Vec<(i32, String, String)> = connection
+        .prepare(sql)?
+        .query_map(values, |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    let details = format!("changed in flow {}", flow);
+    for (key_id, host, key) in &changed {
+        log_action(connection, action, flow, Some(host), Some(key), actor, None)?;
+        log_other_flows(connection, action, flow, *key_id, actor, &details)?;
+    }
+    Ok(changed.len() as u64)
+}
+
+fn deprecate_keys(
+    connection: &Connection,
+    hosts: &[String],
+    flow: &str,
+    actor: &Actor,
+) -> rusqlite::Result<u64> {
+    let now = Utc::now();
+    let sql = format!(
+        "UPDATE keys SET deprecated = TRUE, updated = ?
+         WHERE host IN ({})
+         AND key_id IN (SELECT key_id FROM flows WHERE name = ?)
+         RETURNING key_id, host, key",
+        placeholders(hosts.len())
+    );
+    let mut values: Vec<&dyn ToSql> = vec![&now];
+    values.extend(hosts.iter().map(|host| host as &dyn ToSql));
+    values.push(&flow);
+    update_keys(connection, &sql, &values, "key_deprecated", flow, actor)
+}
+
+fn restore_keys(
+    connection: &Connection,
+    hosts: &[String],
+    flow: &str,
+    actor: &Actor,
+) -> rusqlite::Result<u64> {
+    let now = Utc::now();
+    let sql = format!(
+        "UPDATE keys SET deprecated = FALSE, revoked = FALSE, updated = ?
+         WHERE host IN ({})
+         AND deprecated = TRUE
+         AND key_id IN (SELECT key_id FROM flows WHERE name = ?)
+         RETURNING key_id, host, key",
+        placeholders(hosts.len())
+    );
+    let mut values: Vec<&dyn ToSql> = vec![&now];
+    values.extend(hosts.iter().map(|host| host as &dyn ToSql));
+    values.push(&flow);
+    update_keys(connection, &sql, &values, "key_restored", flow, actor)
+}
+
+// Under 'tofu' a key is quarantined when the host already has a different active key
+// of the same type in the flow
+fn host_key_changed(
+    connection: &Connection,
+    flow: &str,
+    host: &str,
+    key: &str,
+) -> rusqlite::Result<bool> {
+    let mut statement = connection.prepare(
+        "SELECT k.key FROM flows f INNER JOIN keys k ON k.key_id = f.key_id
+         WHERE f.name = ?1 AND NOT f.pending AND NOT k.deprecated AND k.host = ?2",
+    )?;
+    let known_keys = statement.query_map(params![flow, host], |row| row.get::<_, String>(0))?;
+    for known_key in known_keys {
+        let known_key = known_key?;
+        if key_part(&known_key, 0) == key_part(key, 0) && known_key != key {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+// Pending keys of a host in a flow as (key_id, host, key), all of them without `public_key`
+fn pending_keys(
+    connection: &Connection,
+    server: &str,
+    flow: &str,
+    public_key: Option<&str>,
+) -> rusqlite::Result<Vec<(i32, String, String)>> {
+    connection
+        .prepare(
+            "SELECT k.key_id, k.host, k.key FROM flows f INNER JOIN keys k ON k.key_id = f.key_id
+             WHERE f.name = ?1 AND f.pending AND k.host = ?2 AND (?3 IS NULL OR k.key = ?3)",
+        )?
+        .query_map(params![flow, server, public_key], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })?
+        .collect()
+}
+
+// Revision of each flow (all of them without a filter): the id of the last audit
+// row that changed what the flow serves
+fn flow_revisions(
+    connection: &Connection,
+    flow_names: Option<&[String]>,
+) -> rusqlite::Result<HashMap<String, i64>> {
+    let sql = format!(
+        "SELECT m.name, (
+             SELECT MAX(a.id) FROM audit_log a WHERE a.flow = m.name AND a.action IN ({})
+         )
+         FROM flow_metadata m",
+        revision_actions()
+    );
+    let mut statement = connection.prepare(&sql)?;
+    let rows = statement.query_map([], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, Option<i64>>(1)?))
+    })?;
+
+    let mut revisions = HashMap::new();
+    for row in rows {
+        let (name, revision) = row?;
+        if flow_names.is_none_or(|names| names.contains(&name)) {
+            revisions.insert(name, revision.unwrap_or(0));
+        }
+    }
+    Ok(revisions)
+}
+
+// Add the flows linking a key of one of `hosts` to `flow_names`
+fn add_flows_of_hosts(
+    connection: &Connection,
+    flow_names: &mut Vec<String>,
+    hosts: &[String],
+) -> rusqlite::Result<()> {
+    if hosts.is_empty() {
+        return Ok(());
+    }
+    let sql = format!(
+        "SELECT DISTINCT f.name FROM flows f INNER JOIN keys k ON k.key_id = f.key_id
+         WHERE k.host IN ({})",
+        placeholders(hosts.len())
+    );
+    let mut statement = connection.prepare(&sql)?;
+    let names = statement.query_map(params_from_iter(hosts), |row| row.get::<_, String>(0))?;
+    for name in names {
+        let name = name?;
+        if !flow_names.contains(&name) {
+            flow_names.push(name);
+        }
+    }
+    Ok(())
+}
+
+fn row_to_flow_key(row: &Row) -> rusqlite::Result<(String, SshKey)> {
+    let revoked: bool = row.get(5)?;
+    Ok((
+        row.get(3)?,
+        SshKey {
+            server: row.get(0)?,
+            public_key: row.get(1)?,
+            deprecated: row.get(2)?,
+            pending: row.get(4)?,
+            marker: revoked.then_some(Marker::Revoked),
+            comment: row.get(6)?,
+        },
+    ))
+}
+
+// Keys with their flows, of the given hosts or of every host
+fn flow_keys(
+    connection: &Connection,
+    hosts: Option<&[String]>,
+) -> rusqlite::Result<Vec<(String, SshKey)>> {
+    let mut sql = String::from(
+        "SELECT k.host, k.key, k.deprecated, f.name, f.pending, k.revoked, k.comment
+         FROM keys k INNER JOIN flows f ON k.key_id = f.key_id",
+    );
+    if let Some(hosts) = hosts {
+        sql.push_str(&format!(" WHERE k.host IN ({})", placeholders(hosts.len())));
+    }
+    connection
+        .prepare(&sql)?
+        .query_map(params_from_iter(hosts.unwrap_or_default()), row_to_flow_key)?
+        .collect()
+}
+
+// Keys, grants, CAs and token scopes move together with the flow
+fn rename_flow(
+    connection: &Connection,
+    flow_name: &str,
+    new_name: &str,
+    actor: &Actor,
+) -> rusqlite::Result<u64> {
+    let renamed = connection.execute(
+        "UPDATE flow_metadata SET name = ?2 WHERE name = ?1",
+        params![flow_name, new_name],
+    )?;
+    for sql in [
+        "UPDATE flows SET name = ?2 WHERE name = ?1",
+        "UPDATE grants SET flow = ?2 WHERE flow = ?1",
+        "UPDATE cert_authorities SET flow = ?2 WHERE flow = ?1",
+    ] {
+        connection.execute(sql, params![flow_name, new_name])?;
+    }
+
+    let scopes: Vec<(i32, Vec<String>)> = connection
+        .prepare("SELECT token_id, flows FROM api_tokens")?
+        .query_map([], |row| Ok((row.get(0)?, json_column(row, 1)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    for (token_id, flows) in scopes {
+        if flows.iter().any(|flow| flow == flow_name) {
+            let flows: Vec<String> = flows
+                .into_iter()
+                .map(|flow| {
+                    if flow == flow_name {
+                        new_name.to_string()
+                    } else {
+                        flow
+                    }
+                })
+                .collect();
+            connection.execute(
+                "UPDATE api_tokens SET flows = ?2 WHERE token_id = ?1",
+                params![token_id, to_json(&flows)],
+            )?;
+        }
+    }
+
+    if renamed > 0 {
+        let details = format!("renamed from {}", flow_name);
+        log_action(
+            connection,
+            "flow_renamed",
+            new_name,
+            None,
+            None,
+            actor,
+            Some(&details),
+        )?;
+    }
+
+    info!("Renamed flow '{}' to '{}'", flow_name, new_name);
+    Ok(renamed as u64)
+}
+
+fn describe_flow(
+    connection: &Connection,
+    flow_name: &str,
+    description: Option<&str>,
+    actor: &Actor,
+) -> rusqlite::Result<u64> {
+    let affected = connection.execute(
+        "UPDATE flow_metadata SET description = ?2 WHERE name = ?1",
+        params![flow_name, description],
+    )?;
+    if affected > 0 {
+        log_action(
+            connection,
+            "flow_described",
+            flow_name,
+            None,
+            None,
+            actor,
+            description,
+        )?;
+    }
+    Ok(affected as u64)
+}
+
+fn archive_flow(
+    connection: &Connection,
+    flow_name: &str,
+    archived: bool,
+    actor: &Actor,
+) -> rusqlite::Result<u64> {
+    let affected = connection.execute(
+        "UPDATE flow_metadata SET archived = ?2 WHERE name = ?1",
+        params![flow_name, archived],
+    )?;
+    if affected > 0 {
+        let action = if archived {
+            "flow_archived"
+        } else {
+            "flow_unarchived"
+        };
+        log_action(connection, action, flow_name, None, None, actor, None)?;
+    }
+
+    info!(
+        "{} flow '{}'",
+        if archived { "Archived" } else { "Unarchived" },
+        flow_name
+    );
+    Ok(affected as u64)
+}
+
+fn set_key_policy(
+    connection: &Connection,
+    flow_name: &str,
+    key_policy: KeyPolicy,
+    actor: &Actor,
+) -> rusqlite::Result<u64> {
+    let affected = connection.execute(
+        "UPDATE flow_metadata SET key_policy = ?2 WHERE name = ?1 AND key_policy <> ?2",
+        params![flow_name, key_policy.as_str()],
+    )?;
+    if affected > 0 {
+        log_action(
+            connection,
+            "flow_policy",
+            flow_name,
+            None,
+            None,
+            actor,
+            Some(key_policy.as_str()),
+        )?;
+    }
+
+    info!("Set key policy of flow '{}' to '{}'", flow_name, key_policy);
+    Ok(affected as u64)
+}
+
+fn row_to_cert_authority(row: &Row) -> rusqlite::Result<CertAuthority> {
+    Ok(CertAuthority {
+        id: row.get(0)?,
+        flow: row.get(1)?,
+        hosts: json_column(row, 2)?,
+        public_key: row.get(3)?,
+        comment: row.get(4)?,
+        created: row.get(5)?,
+    })
+}
+
+// Certificate authorities of the given flows or of every flow
+fn cert_authorities(
+    connection: &Connection,
+    flow_names: Option<&[String]>,
+) -> rusqlite::Result<Vec<CertAuthority>> {
+    let mut sql = String::from(
+        "SELECT ca_id, flow, hosts, public_key, comment, created FROM cert_authorities",
+    );
+    if let Some(flow_names) = flow_names {
+        sql.push_str(&format!(
+            " WHERE flow IN ({})",
+            placeholders(flow_names.len())
+        ));
+    }
+    sql.push_str(" ORDER BY ca_id");
+    connection
+        .prepare(&sql)?
+        .query_map(
+            params_from_iter(flow_names.unwrap_or_default()),
+            row_to_cert_authority,
+        )?
+        .collect()
+}
+
+fn row_to_audit_entry(row: &Row) -> rusqlite::Result<AuditEntry> {
+    Ok(AuditEntry {
+        id: row.get(0)?,
+        at: row.get(1)?,
+        action: row.get(2)?,
+        flow: row.get(3)?,
+        server: row.get(4)?,
+        public_key: row.get(5)?,
+        actor: row.get(6)?,
+        client_hostname: row.get(7)?,
+        details: row.get(8)?,
+    })
+}
+
+fn create_migrations_table(connection: &Connection) -> rusqlite::Result<()> {
+    connection.execute_batch(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+             version INTEGER PRIMARY KEY,
+             name TEXT NOT NULL,
+             applied TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f+00:00', 'now'))
+         )",
+    )
+}
+
+fn applied_migrations(connection: &Connection) -> rusqlite::Result<Vec<AppliedMigration>> {
+    connection
+        .prepare("SELECT version, name, applied FROM schema_migrations ORDER BY version")?
+        .query_map([], |row| {
+            Ok(AppliedMigration {
+                version: row.get(0)?,
+                name: row.get(1)?,
+                applied: row.get(2)?,
+            })
+        })?
+        .collect()
+}
+
+#[async_trait(?Send)]
+impl Storage for SqliteStorage {
+    // A local file is there whenever the server is
+    fn is_available(&self) -> bool {
+        true
+    }
+
+    fn reconnects(&self) -> u64 {
+        0
+    }
+
+    async fn ping(&self) -> Result<(), DbError> {
+        self.run(|connection| connection.query_row("SELECT 1", [], |_| Ok(())))
+            .await
+    }
+
+    fn migrations(&self) -> &'static [Migration] {
+        migrations::SQLITE
+    }
+
+    async fn migrate_up(&self) -> Result<Vec<&'static Migration>, DbError> {
+        self.write(|transaction| {
+            create_migrations_table(transaction)?;
+            let applied = applied_migrations(transaction)?;
+            let pending = migrations::pending(migrations::SQLITE, &applied);
+            for migration in &pending {
+                info!("Applying migration {}", migration.name);
+                transaction.execute_batch(migration.up)?;
+                transaction.execute(
+                    "INSERT INTO schema_migrations (version, name) VALUES (?1, ?2)",
+                    params![migration.version, migration.name],
+                )?;
+            }
+            Ok(pending)
+        })
+        .await
+    }
+
+    async fn migrate_down(&self) -> Result<Downgrade, DbError> {
+        self.write(|transaction| {
+            create_migrations_table(transaction)?;
+            let Some(newest) = applied_migrations(transaction)?.pop() else {
+                return Ok(Downgrade::Empty);
+            };
+            let Some(migration) = migrations::find(migrations::SQLITE, newest.version) else {
+                return Ok(Downgrade::Unknown(newest));
+            };
+            info!("Reverting migration {}", migration.name);
+            transaction.execute_batch(migration.down)?;
+            transaction.execute(
+                "DELETE FROM schema_migrations WHERE version = ?1",
+                [migration.version],
+            )?;
+            Ok(Downgrade::Reverted(migration))
+        })
+        .await
+    }
+
+    async fn get_applied_migrations(&self) -> Result<Vec<AppliedMigration>, DbError> {
+        self.run(|connection| {
+            let exists: bool = connection.query_row(
+                "SELECT EXISTS (
+                     SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'schema_migrations'
+                 )",
+                [],
+                |row| row.get(0),
+            )?;
+            if !exists {
+                return Ok(Vec::new());
+            }
+            applied_migrations(connection)
+        })
+        .await
+    }
+
+    async fn batch_insert_keys(&self, keys: Vec<SshKey>) -> Result<KeyInsertStats, DbError> {
+        self.write(move |transaction| {
+            let total = keys.len();
+            let mut ignored_deprecated = 0;
+            let mut unchanged_keys = Vec::new();
+            let mut inserted_keys = Vec::new();
+
+            for key in keys {
+                let existing = transaction
+                    .query_row(
+                        "SELECT key_id, deprecated FROM keys WHERE host = ?1 AND key = ?2",
+                        params![key.server, key.public_key],
+                        |row| Ok((row.get::<_, i32>(0)?, row.get::<_, bool>(1)?)),
+                    )
+                    .optional()?;
+                match existing {
+                    // Deprecated keys are never brought back by a client sending them again
+                    Some((_, true)) => ignored_deprecated += 1,
+                    Some((key_id, false)) => unchanged_keys.push((key, key_id)),
+                    None => {
+                        transaction.execute(
+                            "INSERT INTO keys (host, key, comment, updated) VALUES (?1, ?2, ?3, ?4)",
+                            params![key.server, key.public_key, key.comment, Utc::now()],
+                        )?;
+                        let key_id = transaction.last_insert_rowid() as i32;
+                        inserted_keys.push((key, key_id));
+                    }
+                }
+            }
+
+            let stats = KeyInsertStats {
+                total,
+                inserted: inserted_keys.len(),
+                unchanged: unchanged_keys.len(),
+                key_id_map: unchanged_keys.into_iter().chain(inserted_keys).collect(),
+            };
+
+            info!(
+                "Keys stats: received={}, new={}, unchanged={}, ignored_deprecated={}",
+                stats.total, stats.inserted, stats.unchanged, ignored_deprecated
+            );
+
+            Ok(stats)
+        })
+        .await
+    }
+
+    async fn batch_insert_flow_keys(
+        &self,
+        flow_name: String,
+        key_ids: Vec<i32>,
+        actor: Actor,
+    ) -> Result<FlowKeyStats, DbError> {
+        if key_ids.is_empty() {
+            info!("No keys to associate with flow '{}'", flow_name);
+            return Ok(FlowKeyStats::default());
+        }
+
+        self.write(move |transaction| {
+            let key_policy = transaction
+                .query_row(
+                    "SELECT key_policy FROM flow_metadata WHERE name = ?1",
+                    [&flow_name],
+                    |row| row.get::<_, String>(0),
+                )
+                .optional()?
+                .and_then(|policy| policy.parse().ok())
+                .unwrap_or_default();
+
+            // Every key is judged against the flow as it was before this batch
+            let mut seen = HashSet::new();
+            let mut existing = 0;
+            let mut additions = Vec::new();
+            for key_id in key_ids {
+                if !seen.insert(key_id) {
+                    continue;
+                }
+                let associated: bool = transaction.query_row(
+                    "SELECT EXISTS (SELECT 1 FROM flows WHERE name = ?1 AND key_id = ?2)",
+                    params![flow_name, key_id],
+                    |row| row.get(0),
+                )?;
+                if associated {
+                    existing += 1;
+                    continue;
+                }
+                let Some((host, key)) = transaction
+                    .query_row(
+                        "SELECT host, key FROM keys WHERE key_id = ?1",
+                        [key_id],
+                        |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)),
+                    )
+                    .optional()?
+                else {
+                    continue;
+                };
+                let pending = match key_policy {
+                    KeyPolicy::Accept => false,
+                    KeyPolicy::Strict => true,
+                    KeyPolicy::Tofu => host_key_changed(transaction, &flow_name, &host, &key)?,
+                };
+                additions.push((key_id, host, key, pending));
+            }
+
+            if additions.is_empty() {
+                info!(
+                    "All {} keys are already associated with flow '{}'",
+                    existing, flow_name
+                );
+                return Ok(FlowKeyStats::default());
+            }
+
+            let mut stats = FlowKeyStats::default();
+            for (key_id, host, key, pending) in additions {
+                transaction.execute(
+                    "INSERT INTO flows (name, key_id, pending) VALUES (?1, ?2, ?3)",
+                    params![flow_name, key_id, pending],
+                )?;
+                let action = if pending { "key_quarantined" } else { "key_added" };
+                log_action(transaction, action, &flow_name, Some(&host), Some(&key), &actor, None)?;
+                stats.added += 1;
+                if pending {
+                    stats.pending += 1;
+                }
+            }
+
+            info!(
+                "Added {} new key-flow associations for flow '{}' ({} pending approval, skipped {} existing)",
+                stats.added, flow_name, stats.pending, existing
+            );
+
+            Ok(stats)
+        })
+        .await
+    }
+
+    async fn get_keys_from_db(&self) -> Result<Vec<Flow>, DbError> {
+        self.run(|connection| {
+            // Read before the keys, so a change racing with this refresh shows up again
+            // in the next revision instead of being lost
+            let revisions = flow_revisions(connection, None)?;
+            let keys = flow_keys(connection, None)?;
+            let flows = group_flows(revisions, keys, cert_authorities(connection, None)?);
+            info!("Retrieved {} flows from database", flows.len());
+            Ok(flows)
+        })
+        .await
+    }
+
+    async fn get_flow_update(
+        &self,
+        mut flow_names: Vec<String>,
+        hosts: Vec<String>,
+    ) -> Result<FlowUpdate, DbError> {
+        self.run(move |connection| {
+            add_flows_of_hosts(connection, &mut flow_names, &hosts)?;
+            let revisions = flow_revisions(connection, Some(&flow_names))?;
+            let keys = if hosts.is_empty() {
+                Vec::new()
+            } else {
+                flow_keys(connection, Some(&hosts))?
+            };
+            let cert_authorities = if flow_names.is_empty() {
+                Vec::new()
+            } else {
+                cert_authorities(connection, Some(&flow_names))?
+            };
+            Ok(FlowUpdate {
+                hosts,
+                keys,
+                flows: flow_names,
+                cert_authorities,
+                revisions,
+            })
+        })
+        .await
+    }
+
+    async fn deprecate_key_by_server(
+        &self,
+        server_name: String,
+        flow_name: String,
+        actor: Actor,
+    ) -> Result<u64, DbError> {
+        self.write(move |transaction| {
+            let affected = deprecate_keys(
+                transaction,
+                std::slice::from_ref(&server_name),
+                &flow_name,
+                &actor,
+            )?;
+            info!(
+                "Deprecated {} key(s) for server '{}' in flow '{}'",
+                affected, server_name, flow_name
+            );
+            Ok(affected)
+        })
+        .await
+    }
+
+    async fn bulk_deprecate_keys_by_servers(
+        &self,
+        server_names: Vec<String>,
+        flow_name: String,
+        actor: Actor,
+    ) -> Result<u64, DbError> {
+        if server_names.is_empty() {
+            return Ok(0);
+        }
+
+        self.write(move |transaction| {
+            let affected = deprecate_keys(transaction, &server_names, &flow_name, &actor)?;
+            info!(
+                "Bulk deprecated {} key(s) for {} servers in flow '{}'",
+                affected,
+                server_names.len(),
+                flow_name
+            );
+            Ok(affected)
+        })
+        .await
+    }
+
+    async fn bulk_restore_keys_by_servers(
+        &self,
+        server_names: Vec<String>,
+        flow_name: String,
+        actor: Actor,
+    ) -> Result<u64, DbError> {
+        if server_names.is_empty() {
+            return Ok(0);
+        }
+
+        self.write(move |transaction| {
+            let affected = restore_keys(transaction, &server_names, &flow_name, &actor)?;
+            info!(
+                "Bulk restored {} key(s) for {} servers in flow '{}'",
+                affected,
+                server_names.len(),
+                flow_name
+            );
+            Ok(affected)
+        })
+        .await
+    }
+
+    async fn restore_key_by_server(
+        &self,
+        server_name: String,
+        flow_name: String,
+        actor: Actor,
+    ) -> Result<u64, DbError> {
+        self.write(move |transaction| {
+            let affected = restore_keys(
+                transaction,
+                std::slice::from_ref(&server_name),
+                &flow_name,
+                &actor,
+            )?;
+            info!(
+                "Restored {} key(s) for server '{}' in flow '{}'",
+                affected, server_name, flow_name
+            );
+            Ok(affected)
+        })
+        .await
+    }
+
+    async fn permanently_delete_key_by_server(
+        &self,
+        server_name: String,
+        flow_name: String,
+        actor: Actor,
+    ) -> Result<u64, DbError> {
+        self.write(move |transaction| {
+            let removed: Vec<(i32, String, String)> = transaction
+                .prepare(
+                    "SELECT f.key_id, k.host, k.key FROM flows f
+                     INNER JOIN keys k ON k.key_id = f.key_id
+                     WHERE k.host = ?1 AND f.name = ?2",
+                )?
+                .query_map(params![server_name, flow_name], |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+                })?
+                .collect::<rusqlite::Result<_>>()?;
+
+            transaction.execute(
+                "DELETE FROM flows
+                 WHERE name = ?2 AND key_id IN (SELECT key_id FROM keys WHERE host = ?1)",
+                params![server_name, flow_name],
+            )?;
+
+            // Keys that no other flow references are deleted with the association; other
+            // flows keep the key unchanged, so only this flow is logged
+            let mut orphaned = 0;
+            for (key_id, host, key) in &removed {
+                orphaned += transaction.execute(
+                    "DELETE FROM keys
+                     WHERE key_id = ?1 AND NOT EXISTS (SELECT 1 FROM flows WHERE key_id = ?1)",
+                    [key_id],
+                )? as u64;
+                log_action(transaction, "key_deleted", &flow_name, Some(host), Some(key), &actor, None)?;
+            }
+
+            let flow_delete_count = removed.len() as u64;
+            info!(
+                "Permanently deleted {} flow associations and {} orphaned keys for server '{}' in flow '{}'",
+                flow_delete_count, orphaned, server_name, flow_name
+            );
+
+            Ok(std::cmp::max(flow_delete_count, orphaned))
+        })
+        .await
+    }
+
+    async fn get_grants(&self) -> Result<Vec<Grant>, DbError> {
+        self.run(|connection| {
+            let rows: Vec<(String, String, String)> = connection
+                .prepare(
+                    "SELECT u.username, g.flow, g.role FROM grants g
+                     INNER JOIN users u ON g.user_id = u.user_id
+                     ORDER BY u.username, g.flow",
+                )?
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+                .collect::<rusqlite::Result<_>>()?;
+
+            let mut grants = Vec::with_capacity(rows.len());
+            for (username, flow, role) in rows {
+                match role.parse::<Role>() {
+                    Ok(role) => grants.push(Grant {
+                        username,
+                        flow,
+                        role,
+                    }),
+                    Err(e) => error!("Ignoring grant for '{}' on '{}': {}", username, flow, e),
+                }
+            }
+
+            info!("Retrieved {} grants from database", grants.len());
+            Ok(grants)
+        })
+        .await
+    }
+
+    async fn set_grant(
+        &self,
+        username: String,
+        flow_name: String,
+        role: Role,
+        actor: Actor,
+    ) -> Result<(), DbError> {
+        self.write(move |transaction| {
+            transaction.execute(
+                "INSERT INTO users (username) VALUES (?1) ON CONFLICT (username) DO NOTHING",
+                [&username],
+            )?;
+
+            // Re-granting the same role (e.g. --admin-users on every start) is not logged again
+            let granted = transaction.execute(
+                "INSERT INTO grants (user_id, flow, role)
+                 SELECT user_id, ?2, ?3 FROM users WHERE username = ?1
+                 ON CONFLICT (user_id, flow) DO UPDATE SET role = excluded.role
+                 WHERE grants.role <> excluded.role",
+                params![username, flow_name, role.as_str()],
+            )?;
+            if granted > 0 {
+                let details = format!("{}={}", username, role.as_str());
+                log_action(
+                    transaction,
+                    "grant_set",
+                    &flow_name,
+                    None,
+                    None,
+                    &actor,
+                    Some(&details),
+                )?;
+            }
+
+            info!(
+                "Granted '{}' role on flow '{}' to user '{}'",
+                role, flow_name, username
+            );
+            Ok(())
+        })
+        .await
+    }
+
+    async fn revoke_grant(
+        &self,
+        username: String,
+        flow_name: String,
+        actor: Actor,
+    ) -> Result<u64, DbError> {
+        self.write(move |transaction| {
+            let revoked: Vec<String> = transaction
+                .prepare(
+                    "DELETE FROM grants
+                     WHERE flow = ?2
+                     AND user_id IN (SELECT user_id FROM users WHERE username = ?1)
+                     RETURNING role",
+                )?
+                .query_map(params![username, flow_name], |row| row.get(0))?
+                .collect::<rusqlite::Result<_>>()?;
+            for role in &revoked {
+                let details = format!("{}={}", username, role);
+                log_action(
+                    transaction,
+                    "grant_revoked",
+                    &flow_name,
+                    None,
+                    None,
+                    &actor,
+                    Some(&details),
+                )?;
+            }
+
+            let affected = revoked.len() as u64;
+            info!(
+                "Revoked {} grant(s) on flow '{}' from user '{}'",
+                affected, flow_name, username
+            );
+            Ok(affected)
+        })
+        .await
+    }
+
+    async fn get_api_tokens(&self) -> Result<Vec<ApiToken>, DbError> {
+        self.run(|connection| {
+            let mut statement = connection.prepare(
+                "SELECT token_id, name, token_hash, flows, role, created_by, created, expires, last_used, last_hostname, direction
+                 FROM api_tokens ORDER BY token_id",
+            )?;
+            let mut rows = statement.query([])?;
+
+            let mut tokens = Vec::new();
+            while let Some(row) = rows.next()? {
+                let name: String = row.get(1)?;
+                let role: String = row.get(4)?;
+                let direction: String = row.get(10)?;
+
+                match (role.parse::<Role>(), direction.parse::<SyncDirection>()) {
+                    (Ok(role), Ok(direction)) => tokens.push(ApiToken {
+                        id: row.get(0)?,
+                        name,
+                        token_hash: row.get(2)?,
+                        flows: json_column(row, 3)?,
+                        role,
+                        direction,
+                        created_by: row.get(5)?,
+                        created: row.get(6)?,
+                        expires: row.get(7)?,
+                        last_used: row.get(8)?,
+                        last_hostname: row.get(9)?,
+                    }),
+                    (Err(e), _) | (_, Err(e)) => error!("Ignoring API token '{}': {}", name, e),
+                }
+            }
+
+            info!("Retrieved {} API tokens from database", tokens.len());
+            Ok(tokens)
+        })
+        .await
+    }
+
+    async fn create_api_token(
+        &self,
+        name: String,
+        token_hash: String,
+        scope: TokenScope,
+        expires: Option<DateTime<Utc>>,
+        actor: Actor,
+    ) -> Result<i32, DbError> {
+        self.write(move |transaction| {
+            transaction.execute(
+                "INSERT INTO api_tokens (name, token_hash, flows, role, created_by, expires, direction)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    name,
+                    token_hash,
+                    to_json(&scope.flows),
+                    scope.role.as_str(),
+                    actor.user,
+                    expires,
+                    scope.direction.as_str()
+                ],
+            )?;
+            let token_id = transaction.last_insert_rowid() as i32;
+
+            let direction = match scope.direction {
+                SyncDirection::Both => String::new(),
+                direction => format!(", {}-only", direction.as_str()),
+            };
+            let details = format!(
+                "token {} ({}, {}{})",
+                token_id,
+                name,
+                scope.role.as_str(),
+                direction
+            );
+            for flow in &scope.flows {
+                log_action(transaction, "token_created", flow, None, None, &actor, Some(&details))?;
+            }
+
+            info!(
+                "Created API token '{}' (id {}) with '{}' role ({}) on flows {:?}",
+                name, token_id, scope.role, scope.direction, scope.flows
+            );
+            Ok(token_id)
+        })
+        .await
+    }
+
+    async fn revoke_api_token(&self, token_id: i32, actor: Actor) -> Result<u64, DbError> {
+        self.write(move |transaction| {
+            let revoked = transaction
+                .query_row(
+                    "DELETE FROM api_tokens WHERE token_id = ?1 RETURNING name, flows, role",
+                    [token_id],
+                    |row| {
+                        Ok((
+                            row.get::<_, String>(0)?,
+                            json_column(row, 1)?,
+                            row.get::<_, String>(2)?,
+                        ))
+                    },
+                )
+                .optional()?;
+
+            let affected = match revoked {
+                Some((name, flows, role)) => {
+                    let details = format!("token {} ({}, {})", token_id, name, role);
+                    for flow in &flows {
+                        log_action(
+                            transaction,
+                            "token_revoked",
+                            flow,
+                            None,
+                            None,
+                            &actor,
+                            Some(&details),
+                        )?;
+                    }
+                    1
+                }
+                None => 0,
+            };
+
+            info!("Revoked {} API token(s) with id {}", affected, token_id);
+            Ok(affected)
+        })
+        .await
+    }
+
+    async fn touch_api_token(&self, token_id: i32, hostname: String) -> Result<(), DbError> {
+        self.write(move |transaction| {
+            transaction.execute(
+                "UPDATE api_tokens SET last_used = ?2, last_hostname = ?3 WHERE token_id = ?1",
+                params![token_id, Utc::now(), hostname],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_flow_metadata(&self) -> Result<Vec<FlowInfo>, DbError> {
+        self.run(|connection| {
+            let flows: Vec<FlowInfo> = connection
+                .prepare(
+                    "SELECT name, description, archived, key_policy, created
+                     FROM flow_metadata ORDER BY name",
+                )?
+                .query_map([], |row| {
+                    let name: String = row.get(0)?;
+                    let key_policy: String = row.get(3)?;
+                    Ok(FlowInfo {
+                        key_policy: key_policy.parse().unwrap_or_else(|e| {
+                            error!("Flow '{}': {}, using the default policy", name, e);
+                            KeyPolicy::default()
+                        }),
+                        name,
+                        description: row.get(1)?,
+                        archived: row.get(2)?,
+                        created: row.get(4)?,
+                    })
+                })?
+                .collect::<rusqlite::Result<_>>()?;
+
+            info!("Retrieved metadata for {} flows from database", flows.len());
+            Ok(flows)
+        })
+        .await
+    }
+
+    async fn create_flow(
+        &self,
+        flow_name: String,
+        description: Option<String>,
+        actor: Actor,
+    ) -> Result<bool, DbError> {
+        self.write(move |transaction| {
+            let created = transaction.execute(
+                "INSERT INTO flow_metadata (name, description) VALUES (?1, ?2)
+                 ON CONFLICT (name) DO NOTHING",
+                params![flow_name, description],
+            )? > 0;
+            if created {
+                log_action(
+                    transaction,
+                    "flow_created",
+                    &flow_name,
+                    None,
+                    None,
+                    &actor,
+                    description.as_deref(),
+                )?;
+                info!("Created flow '{}'", flow_name);
+            }
+            Ok(created)
+        })
+        .await
+    }
+
+    async fn update_flow(
+        &self,
+        flow_name: String,
+        changes: FlowChanges,
+        actor: Actor,
+    ) -> Result<bool, DbError> {
+        // One transaction, so a failing change leaves the flow as it was
+        self.write(move |transaction| {
+            if let Some(new_name) = &changes.name {
+                let taken: bool = transaction.query_row(
+                    "SELECT EXISTS (SELECT 1 FROM flow_metadata WHERE name = ?1)",
+                    [new_name],
+                    |row| row.get(0),
+                )?;
+                if taken {
+                    return Ok(false);
+                }
+            }
+            if let Some(description) = &changes.description {
+                describe_flow(transaction, &flow_name, description.as_deref(), &actor)?;
+            }
+            if let Some(archived) = changes.archived {
+                archive_flow(transaction, &flow_name, archived, &actor)?;
+            }
+            if let Some(key_policy) = changes.key_policy {
+                set_key_policy(transaction, &flow_name, key_policy, &actor)?;
+            }
+            if let Some(new_name) = &changes.name {
+                rename_flow(transaction, &flow_name, new_name, &actor)?;
+            }
+            Ok(true)
+        })
+        .await
+    }
+
+    async fn set_flow_archived(
+        &self,
+        flow_name: String,
+        archived: bool,
+        actor: Actor,
+    ) -> Result<u64, DbError> {
+        self.write(move |transaction| archive_flow(transaction, &flow_name, archived, &actor))
+            .await
+    }
+
+    async fn get_flow_changes(
+        &self,
+        flow_name: String,
+        since: i64,
+        until: i64,
+        limit: i64,
+    ) -> Result<Vec<AuditEntry>, DbError> {
+        self.run(move |connection| {
+            let sql = format!(
+                "SELECT {} FROM audit_log
+                 WHERE flow = ?1 AND id > ?2 AND id <= ?3 AND action IN ({})
+                 ORDER BY id
+                 LIMIT ?4",
+                AUDIT_COLUMNS,
+                revision_actions()
+            );
+            connection
+                .prepare(&sql)?
+                .query_map(params![flow_name, since, until, limit], row_to_audit_entry)?
+                .collect()
+        })
+        .await
+    }
+
+    async fn get_audit_log(
+        &self,
+        query: AuditQuery,
+        limit: i64,
+    ) -> Result<Vec<AuditEntry>, DbError> {
+        // Timestamps are stored in one UTC format, so comparing them as text orders them
+        let sql = format!(
+            "SELECT {} FROM audit_log
+             WHERE (?1 IS NULL OR flow = ?1)
+             AND (?2 IS NULL OR server = ?2)
+             AND (?3 IS NULL OR at >= ?3)
+             ORDER BY id DESC
+             LIMIT ?4",
+            AUDIT_COLUMNS
+        );
+        self.run(move |connection| {
+            connection
+                .prepare(&sql)?
+                .query_map(
+                    params![query.flow, query.server, query.since, limit],
+                    row_to_audit_entry,
+                )?
+                .collect()
+        })
+        .await
+    }
+
+    async fn approve_pending_keys(
+        &self,
+        server_name: String,
+        flow_name: String,
+        public_key: Option<String>,
+        actor: Actor,
+    ) -> Result<u64, DbError> {
+        self.write(move |transaction| {
+            let approved = pending_keys(transaction, &server_name, &flow_name, public_key.as_deref())?;
+
+            // Approving a changed key replaces the host's previous active key of the same type
+            let approved_ids: HashSet<i32> = approved.iter().map(|(key_id, _, _)| *key_id).collect();
+            let approved_types: HashSet<&str> =
+                approved.iter().map(|(_, _, key)| key_part(key, 0)).collect();
+            let mut replaced: Vec<(i32, String, String)> = transaction
+                .prepare(
+                    "SELECT k.key_id, k.host, k.key FROM flows f INNER JOIN keys k ON k.key_id = f.key_id
+                     WHERE f.name = ?1 AND NOT f.pending AND NOT k.deprecated AND k.host = ?2",
+                )?
+                .query_map(params![flow_name, server_name], |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+                })?
+                .collect::<rusqlite::Result<_>>()?;
+            replaced.retain(|(key_id, _, key)| {
+                !approved_ids.contains(key_id) && approved_types.contains(key_part(key, 0))
+            });
+
+            for (key_id, host, key) in &approved {
+                transaction.execute(
+                    "UPDATE flows SET pending = FALSE WHERE name = ?1 AND key_id = ?2",
+                    params![flow_name, key_id],
+                )?;
+                log_action(transaction, "key_approved", &flow_name, Some(host), Some(key), &actor, None)?;
+            }
+            let details = format!("replaced by an approved key in flow {}", flow_name);
+            for (key_id, host, key) in &replaced {
+                transaction.execute(
+                    "UPDATE keys SET deprecated = TRUE, updated = ?2 WHERE key_id = ?1",
+                    params![key_id, Utc::now()],
+                )?;
+                log_action(
+                    transaction,
+                    "key_deprecated",
+                    &flow_name,
+                    Some(host),
+                    Some(key),
+                    &actor,
+                    Some("replaced by an approved key"),
+                )?;
+                log_other_flows(transaction, "key_deprecated", &flow_name, *key_id, &actor, &details)?;
+            }
+
+            info!(
+                "Approved {} pending key(s) for server '{}' in flow '{}'",
+                approved.len(),
+                server_name,
+                flow_name
+            );
+            Ok(approved.len() as u64)
+        })
+        .await
+    }
+
+    async fn reject_pending_keys(
+        &self,
+        server_name: String,
+        flow_name: String,
+        public_key: Option<String>,
+        actor: Actor,
+    ) -> Result<u64, DbError> {
+        // Rejected keys stay in the flow as deprecated so clients re-sending them are ignored
+        self.write(move |transaction| {
+            let rejected =
+                pending_keys(transaction, &server_name, &flow_name, public_key.as_deref())?;
+            let details = format!("rejected in flow {}", flow_name);
+            for (key_id, host, key) in &rejected {
+                transaction.execute(
+                    "UPDATE flows SET pending = FALSE WHERE name = ?1 AND key_id = ?2",
+                    params![flow_name, key_id],
+                )?;
+                transaction.execute(
+                    "UPDATE keys SET deprecated = TRUE, updated = ?2 WHERE key_id = ?1",
+                    params![key_id, Utc::now()],
+                )?;
+                log_action(
+                    transaction,
+                    "key_rejected",
+                    &flow_name,
+                    Some(host),
+                    Some(key),
+                    &actor,
+                    None,
+                )?;
+                log_other_flows(
+                    transaction,
+                    "key_deprecated",
+                    &flow_name,
+                    *key_id,
+                    &actor,
+                    &details,
+                )?;
+            }
+
+            info!(
+                "Rejected {} pending key(s) for server '{}' in flow '{}'",
+                rejected.len(),
+                server_name,
+                flow_name
+            );
+            Ok(rejected.len() as u64)
+        })
+        .await
+    }
+
+    async fn add_cert_authority(
+        &self,
+        flow_name: String,
+        hosts: Vec<String>,
+        public_key: String,
+        comment: Option<String>,
+        actor: Actor,
+    ) -> Result<Option<CertAuthority>, DbError> {
+        self.write(move |transaction| {
+            let added = transaction
+                .query_row(
+                    "INSERT INTO cert_authorities (flow, hosts, public_key, comment)
+                     VALUES (?1, ?2, ?3, ?4)
+                     ON CONFLICT (flow, public_key) DO NOTHING
+                     RETURNING ca_id, flow, hosts, public_key, comment, created",
+                    params![flow_name, to_json(&hosts), public_key, comment],
+                    row_to_cert_authority,
+                )
+                .optional()?;
+
+            if let Some(cert_authority) = &added {
+                log_action(
+                    transaction,
+                    "ca_added",
+                    &flow_name,
+                    Some(&hosts.join(",")),
+                    Some(&cert_authority.public_key),
+                    &actor,
+                    cert_authority.comment.as_deref(),
+                )?;
+                info!(
+                    "Added certificate authority for '{}' to flow '{}'",
+                    hosts.join(","),
+                    flow_name
+                );
+            }
+            Ok(added)
+        })
+        .await
+    }
+
+    async fn remove_cert_authority(
+        &self,
+        flow_name: String,
+        ca_id: i32,
+        actor: Actor,
+    ) -> Result<u64, DbError> {
+        self.write(move |transaction| {
+            let removed: Vec<(Vec<String>, String, Option<String>)> = transaction
+                .prepare(
+                    "DELETE FROM cert_authorities WHERE flow = ?1 AND ca_id = ?2
+                     RETURNING hosts, public_key, comment",
+                )?
+                .query_map(params![flow_name, ca_id], |row| {
+                    Ok((json_column(row, 0)?, row.get(1)?, row.get(2)?))
+                })?
+                .collect::<rusqlite::Result<_>>()?;
+            for (hosts, public_key, comment) in &removed {
+                log_action(
+                    transaction,
+                    "ca_removed",
+                    &flow_name,
+                    Some(&hosts.join(",")),
+                    Some(public_key),
+                    &actor,
+                    comment.as_deref(),
+                )?;
+            }
+
+            info!(
+                "Removed {} certificate authority record(s) with id {} from flow '{}'",
+                removed.len(),
+                ca_id,
+                flow_name
+            );
+            Ok(removed.len() as u64)
+        })
+        .await
+    }
+
+    async fn revoke_keys_by_server(
+        &self,
+        server_name: String,
+        flow_name: String,
+        public_key: Option<String>,
+        actor: Actor,
+    ) -> Result<u64, DbError> {
+        // Revoked keys are deprecated as well, so every path that skips inactive keys skips them
+        self.write(move |transaction| {
+            let now = Utc::now();
+            let affected = update_keys(
+                transaction,
+                "UPDATE keys SET deprecated = TRUE, revoked = TRUE, updated = ?1
+                 WHERE host = ?2
+                 AND revoked = FALSE
+                 AND (?3 IS NULL OR key = ?3)
+                 AND key_id IN (SELECT key_id FROM flows WHERE name = ?4)
+                 RETURNING key_id, host, key",
+                &[&now, &server_name, &public_key, &flow_name],
+                "key_revoked",
+                &flow_name,
+                &actor,
+            )?;
+
+            info!(
+                "Revoked {} key(s) for server '{}' in flow '{}'",
+                affected, server_name, flow_name
+            );
+            Ok(affected)
+        })
+        .await
+    }
+}
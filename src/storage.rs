@@ -0,0 +1,266 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use std::io;
+use std::sync::Arc;
+
+use crate::audit::{Actor, AuditEntry, AuditQuery};
+use crate::auth::{ApiToken, Grant, Role, TokenScope};
+use crate::db::{
+    DbError, FlowChanges, FlowKeyStats, FlowUpdate, KeyInsertStats, ReconnectingDbClient,
+};
+use crate::memory::MemoryStorage;
+use crate::migrations::{AppliedMigration, Downgrade, Migration};
+use crate::server::{CertAuthority, Flow, FlowInfo, SshKey};
+#[cfg(feature = "sqlite")]
+use crate::sqlite::SqliteStorage;
+
+// Everything the server keeps in its database. Every change records its audit
+// rows atomically with it, since flow revisions are read from the audit log.
+// Handlers run on actix's single-threaded workers, so the futures need not be Send.
+#[async_trait(?Send)]
+pub trait Storage: Send + Sync {
+    // Whether the last operation reached the database
+    fn is_available(&self) -> bool;
+
+    // Times the database became reachable. A change means that the server may
+    // have missed changes made by others while it was unreachable.
+    fn reconnects(&self) -> u64;
+
+    // Round trip to the database, for readiness checks
+    async fn ping(&self) -> Result<(), DbError>;
+
+    // Schema migrations of this backend, in order
+    fn migrations(&self) -> &'static [Migration];
+
+    async fn migrate_up(&self) -> Result<Vec<&'static Migration>, DbError>;
+
+    async fn migrate_down(&self) -> Result<Downgrade, DbError>;
+
+    async fn get_applied_migrations(&self) -> Result<Vec<AppliedMigration>, DbError>;
+
+    // Keys are matched on host and `public_key`, which holds no comment (see
+    // SshKey::split_comment). A key that is already stored keeps its comment.
+    async fn batch_insert_keys(&self, keys: Vec<SshKey>) -> Result<KeyInsertStats, DbError>;
+
+    async fn batch_insert_flow_keys(
+        &self,
+        flow_name: String,
+        key_ids: Vec<i32>,
+        actor: Actor,
+    ) -> Result<FlowKeyStats, DbError>;
+
+    async fn get_keys_from_db(&self) -> Result<Vec<Flow>, DbError>;
+
+    // The keys of `hosts` in every flow, and the CAs and revisions of `flow_names`
+    // together with every flow that links one of the hosts
+    async fn get_flow_update(
+        &self,
+        flow_names: Vec<String>,
+        hosts: Vec<String>,
+    ) -> Result<FlowUpdate, DbError>;
+
+    async fn deprecate_key_by_server(
+        &self,
+        server_name: String,
+        flow_name: String,
+        actor: Actor,
+    ) -> Result<u64, DbError>;
+
+    async fn bulk_deprecate_keys_by_servers(
+        &self,
+        server_names: Vec<String>,
+        flow_name: String,
+        actor: Actor,
+    ) -> Result<u64, DbError>;
+
+    async fn bulk_restore_keys_by_servers(
+        &self,
+        server_names: Vec<String>,
+        flow_name: String,
+        actor: Actor,
+    ) -> Result<u64, DbError>;
+
+    async fn restore_key_by_server(
+        &self,
+        server_name: String,
+        flow_name: String,
+        actor: Actor,
+    ) -> Result<u64, DbError>;
+
+    async fn permanently_delete_key_by_server(
+        &self,
+        server_name: String,
+        flow_name: String,
+        actor: Actor,
+    ) -> Result<u64, DbError>;
+
+    async fn get_grants(&self) -> Result<Vec<Grant>, DbError>;
+
+    async fn set_grant(
+        &self,
+        username: String,
+        flow_name: String,
+        role: Role,
+        actor: Actor,
+    ) -> Result<(), DbError>;
+
+    async fn revoke_grant(
+        &self,
+        username: String,
+        flow_name: String,
+        actor: Actor,
+    ) -> Result<u64, DbError>;
+
+    async fn get_api_tokens(&self) -> Result<Vec<ApiToken>, DbError>;
+
+    async fn create_api_token(
+        &self,
+        name: String,
+        token_hash: String,
+        scope: TokenScope,
+        expires: Option<DateTime<Utc>>,
+        actor: Actor,
+    ) -> Result<i32, DbError>;
+
+    async fn revoke_api_token(&self, token_id: i32, actor: Actor) -> Result<u64, DbError>;
+
+    async fn touch_api_token(&self, token_id: i32, hostname: String) -> Result<(), DbError>;
+
+    async fn get_flow_metadata(&self) -> Result<Vec<FlowInfo>, DbError>;
+
+    async fn create_flow(
+        &self,
+        flow_name: String,
+        description: Option<String>,
+        actor: Actor,
+    ) -> Result<bool, DbError>;
+
+    // Apply all changes or none; false, with nothing changed, when the new name is
+    // already taken. Keys, grants, CAs and token scopes move with a renamed flow.
+    async fn update_flow(
+        &self,
+        flow_name: String,
+        changes: FlowChanges,
+        actor: Actor,
+    ) -> Result<bool, DbError>;
+
+    async fn set_flow_archived(
+        &self,
+        flow_name: String,
+        archived: bool,
+        actor: Actor,
+    ) -> Result<u64, DbError>;
+
+    // Audit rows that changed what a flow serves, after revision `since` and up to `until`
+    async fn get_flow_changes(
+        &self,
+        flow_name: String,
+        since: i64,
+        until: i64,
+        limit: i64,
+    ) -> Result<Vec<AuditEntry>, DbError>;
+
+    async fn get_audit_log(
+        &self,
+        query: AuditQuery,
+        limit: i64,
+    ) -> Result<Vec<AuditEntry>, DbError>;
+
+    async fn approve_pending_keys(
+        &self,
+        server_name: String,
+        flow_name: String,
+        public_key: Option<String>,
+        actor: Actor,
+    ) -> Result<u64, DbError>;
+
+    async fn reject_pending_keys(
+        &self,
+        server_name: String,
+        flow_name: String,
+        public_key: Option<String>,
+        actor: Actor,
+    ) -> Result<u64, DbError>;
+
+    // Returns None when the flow already trusts this CA key
+    async fn add_cert_authority(
+        &self,
+        flow_name: String,
+        hosts: Vec<String>,
+        public_key: String,
+        comment: Option<String>,
+        actor: Actor,
+    ) -> Result<Option<CertAuthority>, DbError>;
+
+    async fn remove_cert_authority(
+        &self,
+        flow_name: String,
+        ca_id: i32,
+        actor: Actor,
+    ) -> Result<u64, DbError>;
+
+    async fn revoke_keys_by_server(
+        &self,
+        server_name: String,
+        flow_name: String,
+        public_key: Option<String>,
+        actor: Actor,
+    ) -> Result<u64, DbError>;
+}
+
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+// The storage the --db-* arguments point to; nothing is sent to a server database yet.
+// --db-url takes sqlite:///path/to/khm.db, a postgres:// URL or memory://, otherwise
+// PostgreSQL is reached with --db-host, --db-name, --db-user and --db-password.
+pub fn open(args: &crate::Args) -> io::Result<Arc<dyn Storage>> {
+    let connection_string = match args.db_url.as_deref() {
+        #[cfg(feature = "sqlite")]
+        Some(url) if url.starts_with("sqlite://") => {
+            let path = &url["sqlite://".len()..];
+            info!("Opening SQLite database {}", path);
+            let storage = SqliteStorage::open(path)
+                .map_err(|e| invalid_input(format!("Failed to open {}: {}", path, e)))?;
+            return Ok(Arc::new(storage));
+        }
+        #[cfg(not(feature = "sqlite"))]
+        Some(url) if url.starts_with("sqlite://") => {
+            return Err(invalid_input(format!(
+                "{} needs a build with the sqlite feature",
+                url
+            )));
+        }
+        Some(url) if url.starts_with("memory://") => {
+            warn!("Using in-memory storage, everything is lost when the server stops");
+            return Ok(Arc::new(MemoryStorage::default()));
+        }
+        Some(url) if url.starts_with("postgres://") || url.starts_with("postgresql://") => {
+            url.to_string()
+        }
+        Some(url) => {
+            return Err(invalid_input(format!(
+                "Unsupported --db-url '{}', expected sqlite://, postgres:// or memory://",
+                url
+            )));
+        }
+        None => {
+            let (Some(db_user), Some(db_password)) = (&args.db_user, &args.db_password) else {
+                return Err(invalid_input(
+                    "--db-user and --db-password are required unless --db-url is given".to_string(),
+                ));
+            };
+            format!(
+                "host={} user={} password={} dbname={}",
+                args.db_host, db_user, db_password, args.db_name
+            )
+        }
+    };
+
+    info!("Creating PostgreSQL connection pool");
+    let db_client = ReconnectingDbClient::new(&connection_string, args.db_pool_size)
+        .map_err(|e| invalid_input(format!("Database connection error: {}", e)))?;
+    Ok(Arc::new(db_client))
+}
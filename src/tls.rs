@@ -0,0 +1,395 @@
+use actix_tls::accept::rustls_0_23::TlsStream;
+use actix_web::dev::Extensions;
+use actix_web::rt::net::TcpStream;
+use log::{error, info, warn};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::ring::{default_provider, sign::any_supported_type};
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier};
+use rustls::sign::CertifiedKey;
+use rustls::{
+    CertificateError, ClientConfig, DigitallySignedStruct, RootCertStore, ServerConfig,
+    SignatureScheme,
+};
+use std::any::Any;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::sync::{Arc, RwLock};
+use x509_parser::extensions::GeneralName;
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn open(path: &str) -> io::Result<BufReader<File>> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| io::Error::new(e.kind(), format!("Failed to open {}: {}", path, e)))
+}
+
+fn load_certs(path: &str) -> io::Result<Vec<CertificateDer<'static>>> {
+    let mut reader = open(path)?;
+    let certs = rustls_pemfile::certs(&mut reader).collect::<io::Result<Vec<_>>>()?;
+
+    if certs.is_empty() {
+        return Err(invalid_data(format!("No certificates found in {}", path)));
+    }
+    Ok(certs)
+}
+
+fn load_private_key(path: &str) -> io::Result<PrivateKeyDer<'static>> {
+    let mut reader = open(path)?;
+    rustls_pemfile::private_key(&mut reader)?
+        .ok_or_else(|| invalid_data(format!("No private key found in {}", path)))
+}
+
+fn load_certified_key(cert_path: &str, key_path: &str) -> io::Result<Arc<CertifiedKey>> {
+    let certs = load_certs(cert_path)?;
+    let key = load_private_key(key_path)?;
+    let signing_key = any_supported_type(&key)
+        .map_err(|e| invalid_data(format!("Unsupported private key in {}: {}", key_path, e)))?;
+
+    Ok(Arc::new(CertifiedKey::new(certs, signing_key)))
+}
+
+// Server certificate that can be swapped at runtime without restarting the listener
+#[derive(Debug)]
+pub struct ReloadableCert {
+    cert_path: String,
+    key_path: String,
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ReloadableCert {
+    pub fn load(cert_path: &str, key_path: &str) -> io::Result<Self> {
+        let current = load_certified_key(cert_path, key_path)?;
+        info!("Loaded TLS certificate from {}", cert_path);
+
+        Ok(Self {
+            cert_path: cert_path.to_string(),
+            key_path: key_path.to_string(),
+            current: RwLock::new(current),
+        })
+    }
+
+    /// Re-read certificate and key from disk; the previous pair stays active on failure.
+    pub fn reload(&self) -> io::Result<()> {
+        let reloaded = load_certified_key(&self.cert_path, &self.key_path)?;
+        *self.current.write().unwrap() = reloaded;
+        info!("Reloaded TLS certificate from {}", self.cert_path);
+        Ok(())
+    }
+}
+
+impl ResolvesServerCert for ReloadableCert {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+/// Build the rustls configuration for `--tls-cert`/`--tls-key`.
+/// When a client CA bundle is given, every client must present a certificate signed by it,
+/// which `record_client_certificate` turns into a user.
+pub fn build_server_config(
+    cert: Arc<ReloadableCert>,
+    client_ca_path: Option<&str>,
+) -> io::Result<ServerConfig> {
+    let provider = Arc::new(default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| invalid_data(format!("Invalid TLS protocol configuration: {}", e)))?;
+
+    let builder = match client_ca_path {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for ca_cert in load_certs(path)? {
+                roots.add(ca_cert).map_err(|e| {
+                    invalid_data(format!("Invalid CA certificate in {}: {}", path, e))
+                })?;
+            }
+            info!(
+                "Requiring client certificates signed by {} CA(s) from {}",
+                roots.len(),
+                path
+            );
+
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .map_err(|e| invalid_data(format!("Invalid client CA bundle {}: {}", path, e)))?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    Ok(builder.with_cert_resolver(cert))
+}
+
+// User a client certificate verified against --tls-client-ca stands for, kept with
+// the connection for auth::authenticate
+#[derive(Clone, Debug)]
+pub struct ClientCertificateUser(pub String);
+
+/// Connection hook for `HttpServer::on_connect` that records the user of the client
+/// certificate: its subject common name, or its first DNS name when it has none.
+pub fn record_client_certificate(connection: &dyn Any, data: &mut Extensions) {
+    let Some(stream) = connection.downcast_ref::<TlsStream<TcpStream>>() else {
+        return;
+    };
+    let (_, session) = stream.get_ref();
+    let Some(certificate) = session.peer_certificates().and_then(|certs| certs.first()) else {
+        return;
+    };
+    match certificate_user(certificate) {
+        Some(user) => {
+            data.insert(ClientCertificateUser(user));
+        }
+        None => warn!("Client certificate has neither a common name nor a DNS name"),
+    }
+}
+
+fn certificate_user(certificate: &CertificateDer<'_>) -> Option<String> {
+    let (_, certificate) = x509_parser::parse_x509_certificate(certificate.as_ref()).ok()?;
+    let common_name = certificate
+        .subject()
+        .iter_common_name()
+        .find_map(|name| name.as_str().ok());
+    let dns_name = || {
+        certificate
+            .subject_alternative_name()
+            .ok()??
+            .value
+            .general_names
+            .iter()
+            .find_map(|name| match name {
+                GeneralName::DNSName(dns_name) => Some(*dns_name),
+                _ => None,
+            })
+    };
+    common_name.or_else(dns_name).map(str::to_string)
+}
+
+/// Reload the certificate whenever the process receives SIGHUP.
+#[cfg(unix)]
+pub fn reload_on_sighup(cert: Arc<ReloadableCert>) -> io::Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            info!("Received SIGHUP, reloading TLS certificate");
+            if let Err(e) = cert.reload() {
+                error!(
+                    "Failed to reload TLS certificate, keeping the previous one: {}",
+                    e
+                );
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn reload_on_sighup(_cert: Arc<ReloadableCert>) -> io::Result<()> {
+    Ok(())
+}
+
+// Client-side TLS settings for talking to a KHM server behind a private PKI
+#[derive(Clone, Debug, Default)]
+pub struct ClientTlsOptions {
+    pub ca_cert: Option<String>,
+    pub client_cert: Option<String>,
+    pub client_key: Option<String>,
+    pub pin_sha256: Vec<String>,
+}
+
+impl ClientTlsOptions {
+    pub fn is_default(&self) -> bool {
+        self.ca_cert.is_none()
+            && self.client_cert.is_none()
+            && self.client_key.is_none()
+            && self.pin_sha256.is_empty()
+    }
+}
+
+// Normalise a SHA-256 fingerprint: accepts `AB:CD:...` (openssl) or plain hex
+fn normalize_fingerprint(pin: &str) -> io::Result<String> {
+    let hex: String = pin
+        .chars()
+        .filter(|c| *c != ':')
+        .collect::<String>()
+        .to_ascii_lowercase();
+
+    if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(invalid_data(format!(
+            "Invalid SHA-256 pin '{}': expected 64 hex characters",
+            pin
+        )));
+    }
+    Ok(hex)
+}
+
+fn certificate_fingerprint(cert: &CertificateDer<'_>) -> String {
+    use sha2::{Digest, Sha256};
+
+    Sha256::digest(cert.as_ref())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+// Accepts the server only if its leaf certificate matches one of the pins.
+// With a CA bundle configured the chain must verify as well.
+#[derive(Debug)]
+struct PinnedCertVerifier {
+    pins: Vec<String>,
+    chain_verifier: Option<Arc<WebPkiServerVerifier>>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let fingerprint = certificate_fingerprint(end_entity);
+        if !self.pins.contains(&fingerprint) {
+            error!(
+                "Server certificate SHA-256 {} does not match any pinned fingerprint",
+                fingerprint
+            );
+            return Err(rustls::Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            ));
+        }
+
+        if let Some(chain_verifier) = &self.chain_verifier {
+            chain_verifier.verify_server_cert(
+                end_entity,
+                intermediates,
+                server_name,
+                ocsp_response,
+                now,
+            )?;
+        }
+
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// Build the rustls client configuration for `--ca-cert`, `--client-cert`/`--client-key`
+/// and `--pin-sha256`. Without `--ca-cert` the bundled Mozilla roots are trusted.
+pub fn build_client_config(options: &ClientTlsOptions) -> io::Result<ClientConfig> {
+    let provider = Arc::new(default_provider());
+
+    let mut roots = RootCertStore::empty();
+    match &options.ca_cert {
+        Some(path) => {
+            for ca_cert in load_certs(path)? {
+                roots.add(ca_cert).map_err(|e| {
+                    invalid_data(format!("Invalid CA certificate in {}: {}", path, e))
+                })?;
+            }
+            info!("Trusting {} CA(s) from {}", roots.len(), path);
+        }
+        None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+    }
+    let roots = Arc::new(roots);
+
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| invalid_data(format!("Invalid TLS protocol configuration: {}", e)))?;
+
+    let builder = if options.pin_sha256.is_empty() {
+        builder.with_root_certificates(roots)
+    } else {
+        let pins = options
+            .pin_sha256
+            .iter()
+            .map(|pin| normalize_fingerprint(pin))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        // A pinned self-signed certificate is enough on its own; a CA bundle adds chain checks
+        let chain_verifier = match &options.ca_cert {
+            Some(path) => Some(
+                WebPkiServerVerifier::builder_with_provider(roots, provider.clone())
+                    .build()
+                    .map_err(|e| invalid_data(format!("Invalid CA bundle {}: {}", path, e)))?,
+            ),
+            None => None,
+        };
+
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(PinnedCertVerifier {
+                pins,
+                chain_verifier,
+                provider,
+            }))
+    };
+
+    match (&options.client_cert, &options.client_key) {
+        (Some(cert_path), Some(key_path)) => {
+            let certs = load_certs(cert_path)?;
+            let key = load_private_key(key_path)?;
+            info!("Presenting client certificate from {}", cert_path);
+            builder
+                .with_client_auth_cert(certs, key)
+                .map_err(|e| invalid_data(format!("Invalid client certificate: {}", e)))
+        }
+        (None, None) => Ok(builder.with_no_client_auth()),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Both a client certificate and a client key are required",
+        )),
+    }
+}
+
+/// reqwest builder honouring the client TLS options; plain defaults when none are set.
+pub fn http_client_builder(options: &ClientTlsOptions) -> io::Result<reqwest::ClientBuilder> {
+    let builder = reqwest::Client::builder();
+    if options.is_default() {
+        return Ok(builder);
+    }
+
+    Ok(builder.use_preconfigured_tls(build_client_config(options)?))
+}
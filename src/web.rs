@@ -1,4 +1,4 @@
-use actix_web::{web, HttpResponse, Result};
+use actix_web::{web, HttpRequest, HttpResponse, Result};
 use futures::future;
 use log::info;
 use rust_embed::RustEmbed;
@@ -10,8 +10,12 @@ use tokio::time::{timeout, Duration};
 use trust_dns_resolver::config::*;
 use trust_dns_resolver::TokioAsyncResolver;
 
-use crate::db::ReconnectingDbClient;
-use crate::server::Flows;
+use crate::audit::Actor;
+use crate::auth::{self, Role};
+use crate::flows::Flows;
+use crate::known_hosts::key_material;
+use crate::server::{FlowEvents, FlowRegistry};
+use crate::storage::Storage;
 
 #[derive(RustEmbed)]
 #[folder = "static/"]
@@ -71,17 +75,47 @@ pub async fn get_version_api() -> Result<HttpResponse> {
     })))
 }
 
-// API endpoint to get list of available flows
-pub async fn get_flows_api(allowed_flows: web::Data<Vec<String>>) -> Result<HttpResponse> {
+// API endpoint to get list of available flows.
+// `?details=true` returns full metadata, archived flows included.
+pub async fn get_flows_api(
+    flow_registry: web::Data<FlowRegistry>,
+    query: web::Query<std::collections::HashMap<String, String>>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
     info!("API request for available flows");
-    Ok(HttpResponse::Ok().json(&**allowed_flows))
+
+    let details = query.get("details").is_some_and(|v| v == "true");
+    if details {
+        let readable_flows: Vec<_> = flow_registry
+            .all()
+            .into_iter()
+            .filter(|flow| auth::has_role(&req, &flow.name, Role::Reader))
+            .collect();
+        return Ok(HttpResponse::Ok().json(readable_flows));
+    }
+
+    // Only list flows the caller is allowed to read
+    let readable_flows: Vec<String> = flow_registry
+        .active_names()
+        .into_iter()
+        .filter(|flow| auth::has_role(&req, flow, Role::Reader))
+        .collect();
+
+    Ok(HttpResponse::Ok().json(readable_flows))
+}
+
+fn archived_flow_response(flow_id: &str) -> HttpResponse {
+    HttpResponse::Conflict().json(json!({
+        "error": format!("Flow '{}' is archived", flow_id)
+    }))
 }
 
 // API endpoint to scan DNS resolution for all hosts in a flow
 pub async fn scan_dns_resolution(
     flows: web::Data<Flows>,
     path: web::Path<String>,
-    allowed_flows: web::Data<Vec<String>>,
+    flow_registry: web::Data<FlowRegistry>,
+    req: HttpRequest,
 ) -> Result<HttpResponse> {
     let flow_id_str = path.into_inner();
 
@@ -90,14 +124,17 @@ pub async fn scan_dns_resolution(
         flow_id_str
     );
 
-    if !allowed_flows.contains(&flow_id_str) {
+    if !flow_registry.contains(&flow_id_str) {
         return Ok(HttpResponse::Forbidden().json(json!({
             "error": "Flow ID not allowed"
         })));
     }
 
-    let flows_guard = flows.lock().unwrap();
-    let flow = match flows_guard.iter().find(|flow| flow.name == flow_id_str) {
+    if let Err(response) = auth::authorize(&req, &flow_id_str, Role::Reader) {
+        return Ok(response);
+    }
+
+    let flow = match flows.get(&flow_id_str) {
         Some(flow) => flow,
         None => {
             return Ok(HttpResponse::NotFound().json(json!({
@@ -108,12 +145,10 @@ pub async fn scan_dns_resolution(
 
     // Get unique hostnames
     let mut hostnames: std::collections::HashSet<String> = std::collections::HashSet::new();
-    for key in &flow.servers {
+    for key in flow.keys() {
         hostnames.insert(key.server.clone());
     }
 
-    drop(flows_guard);
-
     info!(
         "Scanning DNS resolution for {} unique hosts",
         hostnames.len()
@@ -147,10 +182,12 @@ pub async fn scan_dns_resolution(
 // API endpoint to bulk deprecate multiple servers
 pub async fn bulk_deprecate_servers(
     flows: web::Data<Flows>,
+    events: web::Data<FlowEvents>,
     path: web::Path<String>,
     request: web::Json<BulkDeprecateRequest>,
-    db_client: web::Data<Arc<ReconnectingDbClient>>,
-    allowed_flows: web::Data<Vec<String>>,
+    db_client: web::Data<Arc<dyn Storage>>,
+    flow_registry: web::Data<FlowRegistry>,
+    req: HttpRequest,
 ) -> Result<HttpResponse> {
     let flow_id_str = path.into_inner();
 
@@ -160,15 +197,27 @@ pub async fn bulk_deprecate_servers(
         flow_id_str
     );
 
-    if !allowed_flows.contains(&flow_id_str) {
+    if !flow_registry.contains(&flow_id_str) {
         return Ok(HttpResponse::Forbidden().json(json!({
             "error": "Flow ID not allowed"
         })));
     }
 
+    if let Err(response) = auth::authorize(&req, &flow_id_str, Role::Admin) {
+        return Ok(response);
+    }
+
+    if flow_registry.is_archived(&flow_id_str) {
+        return Ok(archived_flow_response(&flow_id_str));
+    }
+
     // Use single bulk operation instead of loop
     let total_deprecated = match db_client
-        .bulk_deprecate_keys_by_servers_reconnecting(request.servers.clone(), flow_id_str.clone())
+        .bulk_deprecate_keys_by_servers(
+            request.servers.clone(),
+            flow_id_str.clone(),
+            Actor::from_request(&req),
+        )
         .await
     {
         Ok(count) => {
@@ -187,17 +236,19 @@ pub async fn bulk_deprecate_servers(
     };
 
     // Refresh the in-memory flows
-    let updated_flows = match db_client.get_keys_from_db_reconnecting().await {
-        Ok(flows) => flows,
-        Err(e) => {
-            return Ok(HttpResponse::InternalServerError().json(json!({
-                "error": format!("Failed to refresh flows: {}", e)
-            })));
-        }
-    };
-
-    let mut flows_guard = flows.lock().unwrap();
-    *flows_guard = updated_flows;
+    if let Err(e) = flows
+        .refresh(
+            &db_client,
+            &events,
+            vec![flow_id_str.clone()],
+            request.servers.clone(),
+        )
+        .await
+    {
+        return Ok(HttpResponse::InternalServerError().json(json!({
+            "error": format!("Failed to refresh flows: {}", e)
+        })));
+    }
 
     let response = json!({
         "message": format!("Successfully deprecated {} key(s) for {} server(s)", total_deprecated, request.servers.len()),
@@ -211,10 +262,12 @@ pub async fn bulk_deprecate_servers(
 // API endpoint to bulk restore multiple servers
 pub async fn bulk_restore_servers(
     flows: web::Data<Flows>,
+    events: web::Data<FlowEvents>,
     path: web::Path<String>,
     request: web::Json<BulkDeprecateRequest>,
-    db_client: web::Data<Arc<ReconnectingDbClient>>,
-    allowed_flows: web::Data<Vec<String>>,
+    db_client: web::Data<Arc<dyn Storage>>,
+    flow_registry: web::Data<FlowRegistry>,
+    req: HttpRequest,
 ) -> Result<HttpResponse> {
     let flow_id_str = path.into_inner();
 
@@ -224,15 +277,27 @@ pub async fn bulk_restore_servers(
         flow_id_str
     );
 
-    if !allowed_flows.contains(&flow_id_str) {
+    if !flow_registry.contains(&flow_id_str) {
         return Ok(HttpResponse::Forbidden().json(json!({
             "error": "Flow ID not allowed"
         })));
     }
 
+    if let Err(response) = auth::authorize(&req, &flow_id_str, Role::Admin) {
+        return Ok(response);
+    }
+
+    if flow_registry.is_archived(&flow_id_str) {
+        return Ok(archived_flow_response(&flow_id_str));
+    }
+
     // Use single bulk operation
     let total_restored = match db_client
-        .bulk_restore_keys_by_servers_reconnecting(request.servers.clone(), flow_id_str.clone())
+        .bulk_restore_keys_by_servers(
+            request.servers.clone(),
+            flow_id_str.clone(),
+            Actor::from_request(&req),
+        )
         .await
     {
         Ok(count) => {
@@ -251,17 +316,19 @@ pub async fn bulk_restore_servers(
     };
 
     // Refresh the in-memory flows
-    let updated_flows = match db_client.get_keys_from_db_reconnecting().await {
-        Ok(flows) => flows,
-        Err(e) => {
-            return Ok(HttpResponse::InternalServerError().json(json!({
-                "error": format!("Failed to refresh flows: {}", e)
-            })));
-        }
-    };
-
-    let mut flows_guard = flows.lock().unwrap();
-    *flows_guard = updated_flows;
+    if let Err(e) = flows
+        .refresh(
+            &db_client,
+            &events,
+            vec![flow_id_str.clone()],
+            request.servers.clone(),
+        )
+        .await
+    {
+        return Ok(HttpResponse::InternalServerError().json(json!({
+            "error": format!("Failed to refresh flows: {}", e)
+        })));
+    }
 
     let response = json!({
         "message": format!("Successfully restored {} key(s) for {} server(s)", total_restored, request.servers.len()),
@@ -275,9 +342,11 @@ pub async fn bulk_restore_servers(
 // API endpoint to deprecate a specific key by server name
 pub async fn delete_key_by_server(
     flows: web::Data<Flows>,
+    events: web::Data<FlowEvents>,
     path: web::Path<(String, String)>,
-    db_client: web::Data<Arc<ReconnectingDbClient>>,
-    allowed_flows: web::Data<Vec<String>>,
+    db_client: web::Data<Arc<dyn Storage>>,
+    flow_registry: web::Data<FlowRegistry>,
+    req: HttpRequest,
 ) -> Result<HttpResponse> {
     let (flow_id_str, server_name) = path.into_inner();
 
@@ -286,15 +355,27 @@ pub async fn delete_key_by_server(
         server_name, flow_id_str
     );
 
-    if !allowed_flows.contains(&flow_id_str) {
+    if !flow_registry.contains(&flow_id_str) {
         return Ok(HttpResponse::Forbidden().json(json!({
             "error": "Flow ID not allowed"
         })));
     }
 
+    if let Err(response) = auth::authorize(&req, &flow_id_str, Role::Admin) {
+        return Ok(response);
+    }
+
+    if flow_registry.is_archived(&flow_id_str) {
+        return Ok(archived_flow_response(&flow_id_str));
+    }
+
     // Deprecate in database
     match db_client
-        .deprecate_key_by_server_reconnecting(server_name.clone(), flow_id_str.clone())
+        .deprecate_key_by_server(
+            server_name.clone(),
+            flow_id_str.clone(),
+            Actor::from_request(&req),
+        )
         .await
     {
         Ok(deprecated_count) => {
@@ -305,17 +386,19 @@ pub async fn delete_key_by_server(
                 );
 
                 // Refresh the in-memory flows
-                let updated_flows = match db_client.get_keys_from_db_reconnecting().await {
-                    Ok(flows) => flows,
-                    Err(e) => {
-                        return Ok(HttpResponse::InternalServerError().json(json!({
-                            "error": format!("Failed to refresh flows: {}", e)
-                        })));
-                    }
-                };
-
-                let mut flows_guard = flows.lock().unwrap();
-                *flows_guard = updated_flows;
+                if let Err(e) = flows
+                    .refresh(
+                        &db_client,
+                        &events,
+                        vec![flow_id_str.clone()],
+                        vec![server_name.clone()],
+                    )
+                    .await
+                {
+                    return Ok(HttpResponse::InternalServerError().json(json!({
+                        "error": format!("Failed to refresh flows: {}", e)
+                    })));
+                }
 
                 Ok(HttpResponse::Ok().json(json!({
                     "message": format!("Successfully deprecated {} key(s) for server '{}'", deprecated_count, server_name),
@@ -336,9 +419,11 @@ pub async fn delete_key_by_server(
 // API endpoint to restore a deprecated key
 pub async fn restore_key_by_server(
     flows: web::Data<Flows>,
+    events: web::Data<FlowEvents>,
     path: web::Path<(String, String)>,
-    db_client: web::Data<Arc<ReconnectingDbClient>>,
-    allowed_flows: web::Data<Vec<String>>,
+    db_client: web::Data<Arc<dyn Storage>>,
+    flow_registry: web::Data<FlowRegistry>,
+    req: HttpRequest,
 ) -> Result<HttpResponse> {
     let (flow_id_str, server_name) = path.into_inner();
 
@@ -347,15 +432,27 @@ pub async fn restore_key_by_server(
         server_name, flow_id_str
     );
 
-    if !allowed_flows.contains(&flow_id_str) {
+    if !flow_registry.contains(&flow_id_str) {
         return Ok(HttpResponse::Forbidden().json(json!({
             "error": "Flow ID not allowed"
         })));
     }
 
+    if let Err(response) = auth::authorize(&req, &flow_id_str, Role::Admin) {
+        return Ok(response);
+    }
+
+    if flow_registry.is_archived(&flow_id_str) {
+        return Ok(archived_flow_response(&flow_id_str));
+    }
+
     // Restore in database
     match db_client
-        .restore_key_by_server_reconnecting(server_name.clone(), flow_id_str.clone())
+        .restore_key_by_server(
+            server_name.clone(),
+            flow_id_str.clone(),
+            Actor::from_request(&req),
+        )
         .await
     {
         Ok(restored_count) => {
@@ -366,17 +463,19 @@ pub async fn restore_key_by_server(
                 );
 
                 // Refresh the in-memory flows
-                let updated_flows = match db_client.get_keys_from_db_reconnecting().await {
-                    Ok(flows) => flows,
-                    Err(e) => {
-                        return Ok(HttpResponse::InternalServerError().json(json!({
-                            "error": format!("Failed to refresh flows: {}", e)
-                        })));
-                    }
-                };
-
-                let mut flows_guard = flows.lock().unwrap();
-                *flows_guard = updated_flows;
+                if let Err(e) = flows
+                    .refresh(
+                        &db_client,
+                        &events,
+                        vec![flow_id_str.clone()],
+                        vec![server_name.clone()],
+                    )
+                    .await
+                {
+                    return Ok(HttpResponse::InternalServerError().json(json!({
+                        "error": format!("Failed to refresh flows: {}", e)
+                    })));
+                }
 
                 Ok(HttpResponse::Ok().json(json!({
                     "message": format!("Successfully restored {} key(s) for server '{}'", restored_count, server_name),
@@ -394,12 +493,251 @@ pub async fn restore_key_by_server(
     }
 }
 
+// Optionally narrows approve/reject/revoke down to one key; without it every matching key of the server is affected
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct KeySelectionRequest {
+    #[serde(default)]
+    pub public_key: Option<String>,
+}
+
+impl KeySelectionRequest {
+    // Keys are stored without their comment, so a comment pasted with the key is dropped
+    fn into_public_key(self) -> Option<String> {
+        self.public_key
+            .map(|public_key| key_material(public_key.trim()).to_string())
+    }
+}
+
+// API endpoint to approve quarantined keys for a server
+pub async fn approve_key_by_server(
+    flows: web::Data<Flows>,
+    events: web::Data<FlowEvents>,
+    path: web::Path<(String, String)>,
+    request: Option<web::Json<KeySelectionRequest>>,
+    db_client: web::Data<Arc<dyn Storage>>,
+    flow_registry: web::Data<FlowRegistry>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    let (flow_id_str, server_name) = path.into_inner();
+    let public_key = request.and_then(|request| request.into_inner().into_public_key());
+
+    info!(
+        "API request to approve pending key(s) for server '{}' in flow '{}'",
+        server_name, flow_id_str
+    );
+
+    if !flow_registry.contains(&flow_id_str) {
+        return Ok(HttpResponse::Forbidden().json(json!({
+            "error": "Flow ID not allowed"
+        })));
+    }
+
+    if let Err(response) = auth::authorize(&req, &flow_id_str, Role::Admin) {
+        return Ok(response);
+    }
+
+    if flow_registry.is_archived(&flow_id_str) {
+        return Ok(archived_flow_response(&flow_id_str));
+    }
+
+    match db_client
+        .approve_pending_keys(
+            server_name.clone(),
+            flow_id_str.clone(),
+            public_key,
+            Actor::from_request(&req),
+        )
+        .await
+    {
+        Ok(approved_count) => {
+            if approved_count > 0 {
+                // Refresh the in-memory flows
+                if let Err(e) = flows
+                    .refresh(
+                        &db_client,
+                        &events,
+                        vec![flow_id_str.clone()],
+                        vec![server_name.clone()],
+                    )
+                    .await
+                {
+                    return Ok(HttpResponse::InternalServerError().json(json!({
+                        "error": format!("Failed to refresh flows: {}", e)
+                    })));
+                }
+
+                Ok(HttpResponse::Ok().json(json!({
+                    "message": format!("Successfully approved {} key(s) for server '{}'", approved_count, server_name),
+                    "approved_count": approved_count
+                })))
+            } else {
+                Ok(HttpResponse::NotFound().json(json!({
+                    "error": format!("No pending keys found for server '{}'", server_name)
+                })))
+            }
+        }
+        Err(e) => Ok(HttpResponse::InternalServerError().json(json!({
+            "error": format!("Failed to approve key: {}", e)
+        }))),
+    }
+}
+
+// API endpoint to reject quarantined keys for a server
+pub async fn reject_key_by_server(
+    flows: web::Data<Flows>,
+    events: web::Data<FlowEvents>,
+    path: web::Path<(String, String)>,
+    request: Option<web::Json<KeySelectionRequest>>,
+    db_client: web::Data<Arc<dyn Storage>>,
+    flow_registry: web::Data<FlowRegistry>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    let (flow_id_str, server_name) = path.into_inner();
+    let public_key = request.and_then(|request| request.into_inner().into_public_key());
+
+    info!(
+        "API request to reject pending key(s) for server '{}' in flow '{}'",
+        server_name, flow_id_str
+    );
+
+    if !flow_registry.contains(&flow_id_str) {
+        return Ok(HttpResponse::Forbidden().json(json!({
+            "error": "Flow ID not allowed"
+        })));
+    }
+
+    if let Err(response) = auth::authorize(&req, &flow_id_str, Role::Admin) {
+        return Ok(response);
+    }
+
+    if flow_registry.is_archived(&flow_id_str) {
+        return Ok(archived_flow_response(&flow_id_str));
+    }
+
+    match db_client
+        .reject_pending_keys(
+            server_name.clone(),
+            flow_id_str.clone(),
+            public_key,
+            Actor::from_request(&req),
+        )
+        .await
+    {
+        Ok(rejected_count) => {
+            if rejected_count > 0 {
+                // Refresh the in-memory flows
+                if let Err(e) = flows
+                    .refresh(
+                        &db_client,
+                        &events,
+                        vec![flow_id_str.clone()],
+                        vec![server_name.clone()],
+                    )
+                    .await
+                {
+                    return Ok(HttpResponse::InternalServerError().json(json!({
+                        "error": format!("Failed to refresh flows: {}", e)
+                    })));
+                }
+
+                Ok(HttpResponse::Ok().json(json!({
+                    "message": format!("Rejected {} key(s) for server '{}'", rejected_count, server_name),
+                    "rejected_count": rejected_count
+                })))
+            } else {
+                Ok(HttpResponse::NotFound().json(json!({
+                    "error": format!("No pending keys found for server '{}'", server_name)
+                })))
+            }
+        }
+        Err(e) => Ok(HttpResponse::InternalServerError().json(json!({
+            "error": format!("Failed to reject key: {}", e)
+        }))),
+    }
+}
+
+// API endpoint to revoke keys for a server; clients write them as @revoked lines
+pub async fn revoke_key_by_server(
+    flows: web::Data<Flows>,
+    events: web::Data<FlowEvents>,
+    path: web::Path<(String, String)>,
+    request: Option<web::Json<KeySelectionRequest>>,
+    db_client: web::Data<Arc<dyn Storage>>,
+    flow_registry: web::Data<FlowRegistry>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    let (flow_id_str, server_name) = path.into_inner();
+    let public_key = request.and_then(|request| request.into_inner().into_public_key());
+
+    info!(
+        "API request to revoke key(s) for server '{}' in flow '{}'",
+        server_name, flow_id_str
+    );
+
+    if !flow_registry.contains(&flow_id_str) {
+        return Ok(HttpResponse::Forbidden().json(json!({
+            "error": "Flow ID not allowed"
+        })));
+    }
+
+    if let Err(response) = auth::authorize(&req, &flow_id_str, Role::Admin) {
+        return Ok(response);
+    }
+
+    if flow_registry.is_archived(&flow_id_str) {
+        return Ok(archived_flow_response(&flow_id_str));
+    }
+
+    match db_client
+        .revoke_keys_by_server(
+            server_name.clone(),
+            flow_id_str.clone(),
+            public_key,
+            Actor::from_request(&req),
+        )
+        .await
+    {
+        Ok(revoked_count) => {
+            if revoked_count > 0 {
+                // Refresh the in-memory flows
+                if let Err(e) = flows
+                    .refresh(
+                        &db_client,
+                        &events,
+                        vec![flow_id_str.clone()],
+                        vec![server_name.clone()],
+                    )
+                    .await
+                {
+                    return Ok(HttpResponse::InternalServerError().json(json!({
+                        "error": format!("Failed to refresh flows: {}", e)
+                    })));
+                }
+
+                Ok(HttpResponse::Ok().json(json!({
+                    "message": format!("Successfully revoked {} key(s) for server '{}'", revoked_count, server_name),
+                    "revoked_count": revoked_count
+                })))
+            } else {
+                Ok(HttpResponse::NotFound().json(json!({
+                    "error": format!("No keys to revoke found for server '{}'", server_name)
+                })))
+            }
+        }
+        Err(e) => Ok(HttpResponse::InternalServerError().json(json!({
+            "error": format!("Failed to revoke key: {}", e)
+        }))),
+    }
+}
+
 // API endpoint to permanently delete a key
 pub async fn permanently_delete_key_by_server(
     flows: web::Data<Flows>,
+    events: web::Data<FlowEvents>,
     path: web::Path<(String, String)>,
-    db_client: web::Data<Arc<ReconnectingDbClient>>,
-    allowed_flows: web::Data<Vec<String>>,
+    db_client: web::Data<Arc<dyn Storage>>,
+    flow_registry: web::Data<FlowRegistry>,
+    req: HttpRequest,
 ) -> Result<HttpResponse> {
     let (flow_id_str, server_name) = path.into_inner();
 
@@ -408,15 +746,27 @@ pub async fn permanently_delete_key_by_server(
         server_name, flow_id_str
     );
 
-    if !allowed_flows.contains(&flow_id_str) {
+    if !flow_registry.contains(&flow_id_str) {
         return Ok(HttpResponse::Forbidden().json(json!({
             "error": "Flow ID not allowed"
         })));
     }
 
+    if let Err(response) = auth::authorize(&req, &flow_id_str, Role::Admin) {
+        return Ok(response);
+    }
+
+    if flow_registry.is_archived(&flow_id_str) {
+        return Ok(archived_flow_response(&flow_id_str));
+    }
+
     // Permanently delete from database
     match db_client
-        .permanently_delete_key_by_server_reconnecting(server_name.clone(), flow_id_str.clone())
+        .permanently_delete_key_by_server(
+            server_name.clone(),
+            flow_id_str.clone(),
+            Actor::from_request(&req),
+        )
         .await
     {
         Ok(deleted_count) => {
@@ -427,17 +777,19 @@ pub async fn permanently_delete_key_by_server(
                 );
 
                 // Refresh the in-memory flows
-                let updated_flows = match db_client.get_keys_from_db_reconnecting().await {
-                    Ok(flows) => flows,
-                    Err(e) => {
-                        return Ok(HttpResponse::InternalServerError().json(json!({
-                            "error": format!("Failed to refresh flows: {}", e)
-                        })));
-                    }
-                };
-
-                let mut flows_guard = flows.lock().unwrap();
-                *flows_guard = updated_flows;
+                if let Err(e) = flows
+                    .refresh(
+                        &db_client,
+                        &events,
+                        vec![flow_id_str.clone()],
+                        vec![server_name.clone()],
+                    )
+                    .await
+                {
+                    return Ok(HttpResponse::InternalServerError().json(json!({
+                        "error": format!("Failed to refresh flows: {}", e)
+                    })));
+                }
 
                 Ok(HttpResponse::Ok().json(json!({
                     "message": format!("Successfully deleted {} key(s) for server '{}'", deleted_count, server_name),
@@ -1,4 +1,5 @@
-use actix_web::{HttpResponse, Result, web};
+use actix_web::{HttpRequest, HttpResponse, Result, web};
+use crate::auth::{self, Role};
 use serde_json::json;
 use log::info;
 
@@ -167,20 +168,30 @@ pub async fn serve_egui_interface() -> Result<HttpResponse> {
 
 /// API endpoint to get GUI configuration
 pub async fn get_gui_config(
-    flows: web::Data<crate::server::Flows>,
-    allowed_flows: web::Data<Vec<String>>,
+    flows: web::Data<crate::flows::Flows>,
+    flow_registry: web::Data<crate::server::FlowRegistry>,
+    req: HttpRequest,
 ) -> Result<HttpResponse> {
     info!("Web GUI config requested");
     
-    let flows_guard = flows.lock().unwrap();
-    let available_flows: Vec<String> = flows_guard.iter().map(|f| f.name.clone()).collect();
+    let available_flows: Vec<String> = flows
+        .all()
+        .iter()
+        .filter(|f| auth::has_role(&req, &f.name, Role::Reader))
+        .map(|f| f.name.clone())
+        .collect();
+    let allowed_flows: Vec<String> = flow_registry
+        .active_names()
+        .into_iter()
+        .filter(|flow| auth::has_role(&req, flow, Role::Reader))
+        .collect();
     
     Ok(HttpResponse::Ok().json(json!({
         "version": env!("CARGO_PKG_VERSION"),
         "gui_ready": cfg!(feature = "web-gui"),
         "features": ["key_management", "bulk_operations", "real_time_updates"],
         "available_flows": available_flows,
-        "allowed_flows": &**allowed_flows,
+        "allowed_flows": allowed_flows,
         "api_endpoints": {
             "flows": "/api/flows",
             "keys": "/{flow}/keys",
@@ -196,22 +207,27 @@ pub async fn get_gui_config(
 
 /// API endpoint for web GUI state management
 pub async fn get_gui_state(
-    flows: web::Data<crate::server::Flows>,
-    allowed_flows: web::Data<Vec<String>>,
+    flows: web::Data<crate::flows::Flows>,
+    flow_registry: web::Data<crate::server::FlowRegistry>,
+    req: HttpRequest,
 ) -> Result<HttpResponse> {
     info!("Web GUI state requested");
     
-    let flows_guard = flows.lock().unwrap();
-    let flow_data: Vec<_> = flows_guard.iter().map(|f| json!({
+    let allowed_flows: Vec<String> = flow_registry
+        .active_names()
+        .into_iter()
+        .filter(|flow| auth::has_role(&req, flow, Role::Reader))
+        .collect();
+    let flow_data: Vec<_> = flows.all().iter().filter(|f| auth::has_role(&req, &f.name, Role::Reader)).map(|f| json!({
         "name": f.name,
-        "servers_count": f.servers.len(),
-        "active_keys": f.servers.iter().filter(|k| !k.deprecated).count(),
-        "deprecated_keys": f.servers.iter().filter(|k| k.deprecated).count()
+        "servers_count": f.key_count(),
+        "active_keys": f.keys().filter(|k| !k.deprecated).count(),
+        "deprecated_keys": f.keys().filter(|k| k.deprecated).count()
     })).collect();
     
     Ok(HttpResponse::Ok().json(json!({
         "flows": flow_data,
-        "allowed_flows": &**allowed_flows,
+        "allowed_flows": allowed_flows,
         "timestamp": chrono::Utc::now().to_rfc3339()
     })))
 }
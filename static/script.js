@@ -10,6 +10,7 @@ class SSHKeyManager {
         this.serversPerPage = 10;
         this.selectedKeys = new Set();
         this.showDeprecatedOnly = false;
+        this.showPendingOnly = false;
         
         this.initializeEventListeners();
         this.loadVersion();
@@ -35,6 +36,11 @@ class SSHKeyManager {
             }
         });
 
+        // Manage flows button
+        document.getElementById('manageFlowsBtn').addEventListener('click', () => {
+            this.showFlowsModal();
+        });
+
         // Add key button
         document.getElementById('addKeyBtn').addEventListener('click', () => {
             this.showAddKeyModal();
@@ -45,6 +51,11 @@ class SSHKeyManager {
             this.scanDnsResolution();
         });
 
+        // History button
+        document.getElementById('historyBtn').addEventListener('click', () => {
+            this.showHistoryModal();
+        });
+
         // Bulk delete button
         document.getElementById('bulkDeleteBtn').addEventListener('click', () => {
             this.deleteSelectedKeys();
@@ -80,6 +91,13 @@ class SSHKeyManager {
             this.filterKeys(document.getElementById('searchInput').value);
         });
 
+        // Pending filter checkbox
+        document.getElementById('showPendingOnly').addEventListener('change', (e) => {
+            this.showPendingOnly = e.target.checked;
+            e.target.closest('.filter-label').classList.toggle('active', e.target.checked);
+            this.filterKeys(document.getElementById('searchInput').value);
+        });
+
         // Select all checkbox
         document.getElementById('selectAll').addEventListener('change', (e) => {
             this.toggleSelectAll(e.target.checked);
@@ -121,6 +139,26 @@ class SSHKeyManager {
             this.copyKeyToClipboard();
         });
 
+        // Flow management modal
+        document.getElementById('createFlowForm').addEventListener('submit', (e) => {
+            e.preventDefault();
+            this.createFlow();
+        });
+
+        document.getElementById('closeFlows').addEventListener('click', () => {
+            this.hideModal('flowsModal');
+        });
+
+        // Change history modal
+        document.getElementById('historyFilterForm').addEventListener('submit', (e) => {
+            e.preventDefault();
+            this.loadHistory();
+        });
+
+        document.getElementById('closeHistory').addEventListener('click', () => {
+            this.hideModal('historyModal');
+        });
+
         // DNS scan modal
         document.getElementById('closeDnsScan').addEventListener('click', () => {
             this.hideModal('dnsScanModal');
@@ -190,6 +228,12 @@ class SSHKeyManager {
             select.appendChild(option);
         });
         
+        // Keep the current flow selected if it still exists
+        if (this.currentFlow && flows.includes(this.currentFlow)) {
+            select.value = this.currentFlow;
+            return;
+        }
+
         // Auto-select the first flow if available
         if (flows.length > 0) {
             select.value = flows[0];
@@ -240,6 +284,9 @@ class SSHKeyManager {
         if (this.showDeprecatedOnly) {
             keys = keys.filter(key => key.deprecated);
         }
+        if (this.showPendingOnly) {
+            keys = keys.filter(key => key.pending && !key.deprecated);
+        }
         
         // Then apply search filter
         if (!searchTerm || !searchTerm.trim()) {
@@ -259,12 +306,14 @@ class SSHKeyManager {
     updateStats() {
         const totalKeys = this.keys.length;
         const deprecatedKeys = this.keys.filter(key => key.deprecated).length;
-        const activeKeys = totalKeys - deprecatedKeys;
+        const pendingKeys = this.keys.filter(key => key.pending && !key.deprecated).length;
+        const activeKeys = totalKeys - deprecatedKeys - pendingKeys;
         const uniqueServers = new Set(this.keys.map(key => key.server));
         
         document.getElementById('totalKeys').textContent = totalKeys;
         document.getElementById('activeKeys').textContent = activeKeys;
         document.getElementById('deprecatedKeys').textContent = deprecatedKeys;
+        document.getElementById('pendingKeys').textContent = pendingKeys;
         document.getElementById('uniqueServers').textContent = uniqueServers.size;
     }
 
@@ -309,6 +358,7 @@ class SSHKeyManager {
             const serverKeys = groupedFilteredKeys[server];
             const activeCount = serverKeys.filter(k => !k.deprecated).length;
             const deprecatedCount = serverKeys.filter(k => k.deprecated).length;
+            const pendingCount = serverKeys.filter(k => k.pending && !k.deprecated).length;
             const isExpanded = this.expandedGroups.has(server);
             
             // Server group header
@@ -326,6 +376,7 @@ class SSHKeyManager {
                         <span class="host-summary">
                             <span class="key-count">${serverKeys.length} keys</span>
                             ${deprecatedCount > 0 ? `<span class="deprecated-count">${deprecatedCount} deprecated</span>` : ''}
+                            ${pendingCount > 0 ? `<span class="pending-count">${pendingCount} pending</span>` : ''}
                         </span>
                     </td>
                 </tr>
@@ -345,16 +396,25 @@ class SSHKeyManager {
                             </td>
                             <td style="padding-left: 2rem;">
                                 <span class="key-type ${keyType.toLowerCase()}">${keyType}</span>
-                                ${key.deprecated ? '<span class="deprecated-badge">DEPRECATED</span>' : ''}
+                                ${key.marker === 'revoked' ? '<span class="revoked-badge">REVOKED</span>' : key.deprecated ? '<span class="deprecated-badge">DEPRECATED</span>' : ''}
+                                ${key.pending && !key.deprecated ? '<span class="pending-badge">PENDING</span>' : ''}
+                                ${key.marker === 'cert-authority' ? '<span class="ca-badge">CERT AUTHORITY</span>' : ''}
                             </td>
                             <td><span class="key-preview">${keyPreview}</span></td>
                             <td></td>
                             <td class="table-actions">
                                 <button class="btn btn-sm btn-secondary" onclick="sshKeyManager.viewKey('${keyId}')">View</button>
-                                ${key.deprecated ? 
+                                <button class="btn btn-sm btn-secondary" onclick="sshKeyManager.showHistoryModal('${this.escapeHtml(key.server)}')">History</button>
+                                ${key.marker === 'cert-authority' ?
+                                    `<button class="btn btn-sm btn-danger" onclick="sshKeyManager.removeCertAuthority('${keyId}')">Remove</button>` :
+                                  key.deprecated ? 
                                     `<button class="btn btn-sm btn-success" onclick="sshKeyManager.restoreKey('${keyId}')">Restore</button>
                                      <button class="btn btn-sm btn-danger" onclick="sshKeyManager.permanentlyDeleteKey('${keyId}')">Delete</button>` : 
-                                    `<button class="btn btn-sm btn-danger" onclick="sshKeyManager.deleteKey('${keyId}')">Deprecate</button>`
+                                  key.pending ?
+                                    `<button class="btn btn-sm btn-success" onclick="sshKeyManager.decidePendingKey('${keyId}', 'approve')">Approve</button>
+                                     <button class="btn btn-sm btn-danger" onclick="sshKeyManager.decidePendingKey('${keyId}', 'reject')">Reject</button>` :
+                                    `<button class="btn btn-sm btn-danger" onclick="sshKeyManager.deleteKey('${keyId}')">Deprecate</button>
+                                     <button class="btn btn-sm btn-danger" onclick="sshKeyManager.revokeKey('${keyId}')">Revoke</button>`
                                 }
                             </td>
                         </tr>
@@ -566,12 +626,14 @@ class SSHKeyManager {
         
         document.getElementById('serverInput').value = '';
         document.getElementById('keyInput').value = '';
+        document.getElementById('certAuthorityInput').checked = false;
         this.showModal('addKeyModal');
     }
 
     async addKey() {
         const server = document.getElementById('serverInput').value.trim();
         const publicKey = document.getElementById('keyInput').value.trim();
+        const certAuthority = document.getElementById('certAuthorityInput').checked;
         
         if (!server || !publicKey) {
             this.showToast('Please fill in all fields', 'warning');
@@ -585,16 +647,27 @@ class SSHKeyManager {
 
         try {
             this.showLoading();
-            const response = await fetch(`/${this.currentFlow}/keys`, {
-                method: 'POST',
-                headers: {
-                    'Content-Type': 'application/json',
-                },
-                body: JSON.stringify([{
-                    server: server,
-                    public_key: publicKey
-                }])
-            });
+            const response = certAuthority
+                ? await fetch(`/${this.currentFlow}/cert-authorities`, {
+                    method: 'POST',
+                    headers: {
+                        'Content-Type': 'application/json',
+                    },
+                    body: JSON.stringify({
+                        hosts: server.split(',').map(host => host.trim()).filter(host => host),
+                        public_key: publicKey
+                    })
+                })
+                : await fetch(`/${this.currentFlow}/keys`, {
+                    method: 'POST',
+                    headers: {
+                        'Content-Type': 'application/json',
+                    },
+                    body: JSON.stringify([{
+                        server: server,
+                        public_key: publicKey
+                    }])
+                });
 
             if (!response.ok) {
                 const errorText = await response.text();
@@ -854,6 +927,112 @@ class SSHKeyManager {
         }
     }
 
+    async revokeKey(keyId) {
+        const key = this.findKeyById(keyId);
+        if (!key) return;
+
+        if (!confirm(`Revoke this key for '${key.server}'? Clients will write it as an @revoked line and SSH will refuse it.`)) {
+            return;
+        }
+
+        try {
+            this.showLoading();
+            const response = await fetch(`/${this.currentFlow}/keys/${encodeURIComponent(key.server)}/revoke`, {
+                method: 'POST',
+                headers: {
+                    'Content-Type': 'application/json',
+                },
+                body: JSON.stringify({ public_key: key.public_key })
+            });
+
+            if (!response.ok) {
+                const errorText = await response.text();
+                throw new Error(errorText || 'Failed to revoke key');
+            }
+
+            this.showToast('SSH key revoked', 'success');
+            await this.loadKeys();
+        } catch (error) {
+            this.showToast('Failed to revoke key: ' + error.message, 'error');
+        } finally {
+            this.hideLoading();
+        }
+    }
+
+    async removeCertAuthority(keyId) {
+        const key = this.findKeyById(keyId);
+        if (!key || key.marker !== 'cert-authority') return;
+
+        if (!confirm(`Stop trusting this certificate authority for '${key.server}'?`)) {
+            return;
+        }
+
+        try {
+            this.showLoading();
+            const listResponse = await fetch(`/${this.currentFlow}/cert-authorities`);
+            if (!listResponse.ok) {
+                throw new Error('Failed to load certificate authorities');
+            }
+            const authorities = await listResponse.json();
+            const authority = authorities.find(ca =>
+                (ca.comment ? `${ca.public_key} ${ca.comment}` : ca.public_key) === key.public_key
+            );
+            if (!authority) {
+                throw new Error('Certificate authority not found');
+            }
+
+            const response = await fetch(`/${this.currentFlow}/cert-authorities/${authority.id}`, {
+                method: 'DELETE'
+            });
+            if (!response.ok) {
+                const errorText = await response.text();
+                throw new Error(errorText || 'Failed to remove certificate authority');
+            }
+
+            this.showToast('Certificate authority removed', 'success');
+            await this.loadKeys();
+        } catch (error) {
+            this.showToast('Failed to remove certificate authority: ' + error.message, 'error');
+        } finally {
+            this.hideLoading();
+        }
+    }
+
+    async decidePendingKey(keyId, decision) {
+        const key = this.findKeyById(keyId);
+        if (!key || !key.pending) return;
+
+        const question = decision === 'approve'
+            ? `Approve this key for '${key.server}'? Older keys of the same type for this host will be deprecated.`
+            : `Reject this key for '${key.server}'? It will be kept as deprecated so clients cannot submit it again.`;
+        if (!confirm(question)) {
+            return;
+        }
+
+        try {
+            this.showLoading();
+            const response = await fetch(`/${this.currentFlow}/keys/${encodeURIComponent(key.server)}/${decision}`, {
+                method: 'POST',
+                headers: {
+                    'Content-Type': 'application/json',
+                },
+                body: JSON.stringify({ public_key: key.public_key })
+            });
+
+            if (!response.ok) {
+                const errorText = await response.text();
+                throw new Error(errorText || `Failed to ${decision} key`);
+            }
+
+            this.showToast(decision === 'approve' ? 'SSH key approved' : 'SSH key rejected', 'success');
+            await this.loadKeys();
+        } catch (error) {
+            this.showToast(`Failed to ${decision} key: ` + error.message, 'error');
+        } finally {
+            this.hideLoading();
+        }
+    }
+
     findKeyById(keyId) {
         return this.keys.find(key => `${key.server}-${key.public_key}` === keyId);
     }
@@ -913,6 +1092,7 @@ class SSHKeyManager {
         document.getElementById('totalKeys').textContent = '0';
         document.getElementById('activeKeys').textContent = '0';
         document.getElementById('deprecatedKeys').textContent = '0';
+        document.getElementById('pendingKeys').textContent = '0';
         document.getElementById('uniqueServers').textContent = '0';
         this.selectedKeys.clear();
         this.updateBulkDeleteButton();
@@ -958,6 +1138,288 @@ class SSHKeyManager {
         }, 4000);
     }
 
+    // Flow Management
+    async showFlowsModal() {
+        document.getElementById('flowNameInput').value = '';
+        document.getElementById('flowDescriptionInput').value = '';
+        this.showModal('flowsModal');
+        await this.loadFlowDetails();
+    }
+
+    async loadFlowDetails() {
+        try {
+            this.showLoading();
+            const response = await fetch('/api/flows?details=true');
+            if (!response.ok) throw new Error('Failed to load flows');
+
+            const flows = await response.json();
+            this.renderFlowList(flows);
+        } catch (error) {
+            this.showToast('Failed to load flows: ' + error.message, 'error');
+        } finally {
+            this.hideLoading();
+        }
+    }
+
+    renderFlowList(flows) {
+        const flowList = document.getElementById('flowList');
+
+        if (flows.length === 0) {
+            flowList.innerHTML = '<div class="empty-state">No flows yet</div>';
+            return;
+        }
+
+        flowList.innerHTML = flows.map(flow => `
+            <div class="host-item flow-item ${flow.archived ? 'archived' : ''}">
+                <span class="host-name">${this.escapeHtml(flow.name)}</span>
+                ${flow.archived ? '<span class="deprecated-badge">ARCHIVED</span>' : ''}
+                <span class="flow-description">${this.escapeHtml(flow.description || '')}</span>
+                <select class="flow-policy" data-flow="${this.escapeHtml(flow.name)}" title="How new keys for known hosts are handled">
+                    ${['tofu', 'strict', 'accept'].map(policy => `
+                        <option value="${policy}" ${flow.key_policy === policy ? 'selected' : ''}>${this.getKeyPolicyLabel(policy)}</option>
+                    `).join('')}
+                </select>
+                <div class="table-actions">
+                    <button class="btn btn-sm btn-secondary" data-action="rename" data-flow="${this.escapeHtml(flow.name)}">Rename</button>
+                    <button class="btn btn-sm btn-secondary" data-action="describe" data-flow="${this.escapeHtml(flow.name)}">Describe</button>
+                    ${flow.archived
+                        ? `<button class="btn btn-sm btn-success" data-action="unarchive" data-flow="${this.escapeHtml(flow.name)}">Unarchive</button>`
+                        : `<button class="btn btn-sm btn-danger" data-action="archive" data-flow="${this.escapeHtml(flow.name)}">Archive</button>`}
+                </div>
+            </div>
+        `).join('');
+
+        flowList.querySelectorAll('select.flow-policy').forEach(select => {
+            select.addEventListener('change', () => {
+                this.updateFlow(select.dataset.flow, { key_policy: select.value }, 'Key policy updated');
+            });
+        });
+
+        flowList.querySelectorAll('button[data-action]').forEach(button => {
+            button.addEventListener('click', () => {
+                const flow = flows.find(f => f.name === button.dataset.flow);
+                if (!flow) return;
+
+                switch (button.dataset.action) {
+                    case 'rename': this.renameFlow(flow); break;
+                    case 'describe': this.describeFlow(flow); break;
+                    case 'archive': this.archiveFlow(flow); break;
+                    case 'unarchive': this.updateFlow(flow.name, { archived: false }, 'Flow unarchived'); break;
+                }
+            });
+        });
+    }
+
+    getKeyPolicyLabel(policy) {
+        const labels = {
+            tofu: 'Trust first key',
+            strict: 'Approve every key',
+            accept: 'Accept all keys'
+        };
+        return labels[policy] || policy;
+    }
+
+    async createFlow() {
+        const name = document.getElementById('flowNameInput').value.trim();
+        const description = document.getElementById('flowDescriptionInput').value.trim();
+
+        if (!name) {
+            this.showToast('Please enter a flow name', 'warning');
+            return;
+        }
+
+        try {
+            this.showLoading();
+            const response = await fetch('/api/flows', {
+                method: 'POST',
+                headers: {
+                    'Content-Type': 'application/json',
+                },
+                body: JSON.stringify({ name: name, description: description || null })
+            });
+
+            if (!response.ok) {
+                const errorText = await response.text();
+                throw new Error(errorText || 'Failed to create flow');
+            }
+
+            document.getElementById('flowNameInput').value = '';
+            document.getElementById('flowDescriptionInput').value = '';
+            this.showToast(`Flow '${name}' created`, 'success');
+            await this.loadFlowDetails();
+            await this.loadFlows();
+        } catch (error) {
+            this.showToast('Failed to create flow: ' + error.message, 'error');
+        } finally {
+            this.hideLoading();
+        }
+    }
+
+    async renameFlow(flow) {
+        const newName = (prompt(`Rename flow '${flow.name}' to:`, flow.name) || '').trim();
+        if (!newName || newName === flow.name) return;
+
+        // Follow the renamed flow if it was the one being viewed
+        const wasCurrent = this.currentFlow === flow.name;
+        if (wasCurrent) {
+            this.currentFlow = newName;
+        }
+        if (!await this.updateFlow(flow.name, { name: newName }, 'Flow renamed') && wasCurrent) {
+            this.currentFlow = flow.name;
+        }
+    }
+
+    async describeFlow(flow) {
+        const description = prompt(`Description for flow '${flow.name}':`, flow.description || '');
+        if (description === null) return;
+
+        await this.updateFlow(flow.name, { description: description }, 'Flow description updated');
+    }
+
+    async archiveFlow(flow) {
+        if (!confirm(`Archive flow '${flow.name}'? Its keys stay readable but clients can no longer add keys.`)) {
+            return;
+        }
+
+        try {
+            this.showLoading();
+            const response = await fetch(`/api/flows/${encodeURIComponent(flow.name)}`, {
+                method: 'DELETE'
+            });
+
+            if (!response.ok) {
+                const errorText = await response.text();
+                throw new Error(errorText || 'Failed to archive flow');
+            }
+
+            this.showToast(`Flow '${flow.name}' archived`, 'success');
+            await this.loadFlowDetails();
+            await this.loadFlows();
+        } catch (error) {
+            this.showToast('Failed to archive flow: ' + error.message, 'error');
+        } finally {
+            this.hideLoading();
+        }
+    }
+
+    async updateFlow(name, changes, successMessage) {
+        try {
+            this.showLoading();
+            const response = await fetch(`/api/flows/${encodeURIComponent(name)}`, {
+                method: 'PATCH',
+                headers: {
+                    'Content-Type': 'application/json',
+                },
+                body: JSON.stringify(changes)
+            });
+
+            if (!response.ok) {
+                const errorText = await response.text();
+                throw new Error(errorText || 'Failed to update flow');
+            }
+
+            this.showToast(successMessage, 'success');
+            await this.loadFlowDetails();
+            await this.loadFlows();
+            return true;
+        } catch (error) {
+            this.showToast('Failed to update flow: ' + error.message, 'error');
+            return false;
+        } finally {
+            this.hideLoading();
+        }
+    }
+
+    // Change History
+    async showHistoryModal(server = '') {
+        if (!this.currentFlow) {
+            this.showToast('Please select a flow first', 'warning');
+            return;
+        }
+
+        document.getElementById('historyServerInput').value = server;
+        document.getElementById('historySinceInput').value = '';
+        this.showModal('historyModal');
+        await this.loadHistory();
+    }
+
+    async loadHistory() {
+        const params = new URLSearchParams({ flow: this.currentFlow });
+        const server = document.getElementById('historyServerInput').value.trim();
+        const since = document.getElementById('historySinceInput').value;
+        if (server) params.set('server', server);
+        if (since) params.set('since', new Date(since).toISOString());
+
+        try {
+            this.showLoading();
+            const response = await fetch(`/api/audit?${params}`);
+            if (!response.ok) {
+                const errorText = await response.text();
+                throw new Error(errorText || 'Failed to load history');
+            }
+
+            const entries = await response.json();
+            this.renderHistory(entries);
+        } catch (error) {
+            this.showToast('Failed to load history: ' + error.message, 'error');
+        } finally {
+            this.hideLoading();
+        }
+    }
+
+    renderHistory(entries) {
+        const tbody = document.getElementById('historyTableBody');
+        const noHistoryMessage = document.getElementById('noHistoryMessage');
+
+        noHistoryMessage.style.display = entries.length === 0 ? 'block' : 'none';
+        tbody.innerHTML = entries.map(entry => {
+            const details = entry.public_key
+                ? `<span class="key-type ${this.getKeyType(entry.public_key).toLowerCase()}">${this.getKeyType(entry.public_key)}</span>
+                   <span class="key-preview">${this.escapeHtml(this.getKeyPreview(entry.public_key))}</span>`
+                : this.escapeHtml(entry.details || '');
+            const removed = ['key_deprecated', 'key_deleted', 'key_quarantined', 'key_rejected', 'key_revoked', 'ca_removed', 'grant_revoked', 'token_revoked', 'flow_archived'].includes(entry.action);
+
+            return `
+                <tr>
+                    <td class="history-time">${new Date(entry.at).toLocaleString()}</td>
+                    <td class="history-action ${removed ? 'removed' : ''}">${this.escapeHtml(this.getAuditActionLabel(entry.action))}</td>
+                    <td>${this.escapeHtml(entry.server || '')}</td>
+                    <td>
+                        ${this.escapeHtml(entry.actor || 'anonymous')}
+                        ${entry.client_hostname ? `<span class="history-hostname">${this.escapeHtml(entry.client_hostname)}</span>` : ''}
+                    </td>
+                    <td>${details}</td>
+                </tr>
+            `;
+        }).join('');
+    }
+
+    getAuditActionLabel(action) {
+        const labels = {
+            key_added: 'Key added',
+            key_deprecated: 'Key deprecated',
+            key_restored: 'Key restored',
+            key_deleted: 'Key deleted',
+            key_quarantined: 'Key quarantined',
+            key_approved: 'Key approved',
+            key_rejected: 'Key rejected',
+            key_revoked: 'Key revoked',
+            ca_added: 'CA trusted',
+            ca_removed: 'CA removed',
+            flow_created: 'Flow created',
+            flow_renamed: 'Flow renamed',
+            flow_described: 'Description changed',
+            flow_archived: 'Flow archived',
+            flow_unarchived: 'Flow unarchived',
+            flow_policy: 'Key policy changed',
+            grant_set: 'Role granted',
+            grant_revoked: 'Role revoked',
+            token_created: 'Token created',
+            token_revoked: 'Token revoked'
+        };
+        return labels[action] || action;
+    }
+
     // DNS Resolution Scanning
     async scanDnsResolution() {
         if (!this.currentFlow) {
@@ -0,0 +1,246 @@
+// The client in client.rs syncing a known_hosts file with a server on a local port
+mod common;
+
+use clap::Parser;
+use khm::client::run_client;
+use khm::server::SshKey;
+use khm::Args;
+use std::fs;
+
+use common::{ed25519_key, isolate_home, key_json, memory_server, spawn_server, temp_dir};
+
+fn client_args(host: &str, known_hosts: &str, extra: &[&str]) -> Args {
+    let mut args = vec![
+        "khm",
+        "--host",
+        host,
+        "--flow",
+        "work",
+        "--known-hosts",
+        known_hosts,
+    ];
+    args.extend_from_slice(extra);
+    Args::parse_from(args)
+}
+
+#[actix_web::test]
+async fn clients_share_keys_through_the_server() {
+    isolate_home();
+    let (host, server) = spawn_server(memory_server(&["--flows", "work"]).await);
+    let dir = temp_dir();
+
+    let first = dir.join("first_known_hosts");
+    let first_line = format!("alpha.example.com {}\n", ed25519_key(1));
+    fs::write(&first, &first_line).unwrap();
+    let changed = run_client(client_args(&host, first.to_str().unwrap(), &[]))
+        .await
+        .unwrap();
+    assert!(!changed);
+
+    let second = dir.join("second_known_hosts");
+    let second_line = format!("beta.example.com {}\n", ed25519_key(2));
+    fs::write(&second, &second_line).unwrap();
+    let changed = run_client(client_args(
+        &host,
+        second.to_str().unwrap(),
+        &["--in-place", "--backups", "0"],
+    ))
+    .await
+    .unwrap();
+    assert!(changed);
+    let contents = fs::read_to_string(&second).unwrap();
+    assert!(contents.contains(&first_line));
+    assert!(contents.contains(&second_line));
+
+    // A dry run reports the change but leaves the file alone
+    let changed = run_client(client_args(&host, first.to_str().unwrap(), &["--dry-run"]))
+        .await
+        .unwrap();
+    assert!(changed);
+    assert_eq!(fs::read_to_string(&first).unwrap(), first_line);
+
+    let changed = run_client(client_args(
+        &host,
+        first.to_str().unwrap(),
+        &["--pull-only", "--backups", "0"],
+    ))
+    .await
+    .unwrap();
+    assert!(changed);
+    assert!(fs::read_to_string(&first).unwrap().contains(&second_line));
+
+    server.stop(true).await;
+}
+
+#[actix_web::test]
+async fn pull_only_clients_upload_nothing() {
+    isolate_home();
+    let (host, server) = spawn_server(memory_server(&["--flows", "work"]).await);
+    let dir = temp_dir();
+
+    let known_hosts = dir.join("known_hosts");
+    fs::write(
+        &known_hosts,
+        format!("alpha.example.com {}\n", ed25519_key(1)),
+    )
+    .unwrap();
+    let changed = run_client(client_args(
+        &host,
+        known_hosts.to_str().unwrap(),
+        &["--pull-only", "--merge-mode", "union", "--backups", "0"],
+    ))
+    .await
+    .unwrap();
+    assert!(!changed);
+
+    let keys: Vec<serde_json::Value> = reqwest::get(format!("{}/work/keys", host))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!(keys.is_empty());
+
+    server.stop(true).await;
+}
+
+// The keys the client cached for the work flow of a server
+fn cached_keys(host: &str) -> Vec<SshKey> {
+    let port = host.rsplit(':').next().unwrap();
+    let cache_dir = std::path::Path::new(&std::env::var("HOME").unwrap()).join(".khm/cache");
+    let path = fs::read_dir(cache_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| {
+            path.to_string_lossy()
+                .ends_with(&format!("_{}_work.json", port))
+        })
+        .expect("cache of the work flow");
+    let cache: serde_json::Value = serde_json::from_slice(&fs::read(path).unwrap()).unwrap();
+    serde_json::from_value(cache["keys"].clone()).unwrap()
+}
+
+#[actix_web::test]
+async fn delta_syncs_keep_the_order_of_a_full_fetch() {
+    isolate_home();
+    let (host, server) = spawn_server(memory_server(&["--flows", "work"]).await);
+    let client = reqwest::Client::new();
+    let upload = |keys: serde_json::Value| {
+        client
+            .post(format!("{}/work/keys", host))
+            .json(&keys)
+            .send()
+    };
+
+    upload(serde_json::json!([
+        key_json("beta.example.com", &ed25519_key(2)),
+        key_json("delta.example.com", &ed25519_key(4)),
+    ]))
+    .await
+    .unwrap();
+    client
+        .post(format!("{}/work/cert-authorities", host))
+        .json(&serde_json::json!({ "hosts": ["*.example.com"], "public_key": ed25519_key(9) }))
+        .send()
+        .await
+        .unwrap();
+
+    let known_hosts = temp_dir().join("known_hosts");
+    let sync = || {
+        run_client(client_args(
+            &host,
+            known_hosts.to_str().unwrap(),
+            &["--pull-only", "--backups", "0"],
+        ))
+    };
+    sync().await.unwrap();
+
+    // New hosts that sort before and between the cached ones arrive as a delta
+    upload(serde_json::json!([
+        key_json("alpha.example.com", &ed25519_key(1)),
+        key_json("gamma.example.com", &ed25519_key(3)),
+    ]))
+    .await
+    .unwrap();
+    sync().await.unwrap();
+
+    let full: Vec<SshKey> = reqwest::get(format!("{}/work/keys", host))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let order = |keys: &[SshKey]| -> Vec<(String, String)> {
+        keys.iter()
+            .map(|key| (key.server.clone(), key.public_key.clone()))
+            .collect()
+    };
+    assert_eq!(full.len(), 5);
+    assert_eq!(order(&cached_keys(&host)), order(&full));
+
+    server.stop(true).await;
+}
+
+#[actix_web::test]
+async fn only_union_mode_keeps_lines_that_were_not_uploaded() {
+    isolate_home();
+    let (host, server) = spawn_server(memory_server(&["--flows", "work"]).await);
+
+    let plain = format!("alpha.example.com {}", ed25519_key(1));
+    let hashed = format!(
+        "|1|lcVkwjHfN9b/qDuW3bSSybuRZ/c=|hJiHZmUFx/6hZ0R5vofeAU0Xcxg= {}",
+        ed25519_key(2)
+    );
+    let revoked = format!("@revoked * {}", ed25519_key(3));
+    let content = format!("{}\n{}\n{}\n", plain, hashed, revoked);
+
+    for (mode, keeps_local) in [("replace", false), ("union", true)] {
+        let known_hosts = temp_dir().join("known_hosts");
+        fs::write(&known_hosts, &content).unwrap();
+        run_client(client_args(
+            &host,
+            known_hosts.to_str().unwrap(),
+            &["--in-place", "--merge-mode", mode, "--backups", "0"],
+        ))
+        .await
+        .unwrap();
+
+        let written = fs::read_to_string(&known_hosts).unwrap();
+        assert!(written.contains(&plain), "{}", mode);
+        assert_eq!(written.contains(&hashed), keeps_local, "{}", mode);
+        assert_eq!(written.contains(&revoked), keeps_local, "{}", mode);
+    }
+
+    server.stop(true).await;
+}
+
+#[actix_web::test]
+async fn dry_runs_count_the_keys_a_sync_would_upload() {
+    isolate_home();
+    let (host, server) = spawn_server(memory_server(&["--flows", "work"]).await);
+
+    let known_hosts = temp_dir().join("known_hosts");
+    let line = format!("alpha.example.com {}\n", ed25519_key(1));
+    fs::write(&known_hosts, &line).unwrap();
+
+    // The server has no keys yet, but a real sync would upload the local one first
+    let changed = run_client(client_args(
+        &host,
+        known_hosts.to_str().unwrap(),
+        &["--dry-run"],
+    ))
+    .await
+    .unwrap();
+    assert!(!changed);
+    assert_eq!(fs::read_to_string(&known_hosts).unwrap(), line);
+
+    let keys: Vec<SshKey> = reqwest::get(format!("{}/work/keys", host))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!(keys.is_empty());
+
+    server.stop(true).await;
+}
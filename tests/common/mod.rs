@@ -0,0 +1,120 @@
+// Helpers shared by the end-to-end tests. Every test gets its own server state on
+// in-memory storage, so tests run in parallel without a database.
+#![allow(dead_code)]
+
+use actix_web::http::header::AUTHORIZATION;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use clap::Parser;
+use khm::server::{build_app, ServerState};
+use khm::Args;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Once;
+
+pub const ADMIN: (&str, &str) = ("admin", "secret");
+pub const READER: (&str, &str) = ("reader", "rpass");
+
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+static HOME: Once = Once::new();
+
+// A fresh directory for the files of one test
+pub fn temp_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "khm-test-{}-{}",
+        std::process::id(),
+        NEXT_DIR.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::create_dir_all(&dir).expect("create test directory");
+    dir
+}
+
+// The client caches flows under ~/.khm, which must not be the real home directory
+pub fn isolate_home() {
+    HOME.call_once(|| std::env::set_var("HOME", temp_dir()));
+}
+
+// A server on in-memory storage with the given extra arguments
+pub async fn memory_server(extra: &[&str]) -> ServerState {
+    let mut args = vec!["khm", "--server", "--db-url", "memory://"];
+    args.extend_from_slice(extra);
+    ServerState::load(&Args::parse_from(args))
+        .await
+        .expect("load server state")
+}
+
+// A server that requires authentication: ADMIN administers every flow, READER is
+// a known user without any grant yet
+pub async fn authenticated_server(flows: &str) -> ServerState {
+    let auth_file = temp_dir().join("htpasswd");
+    let contents: String = [ADMIN, READER]
+        .iter()
+        .map(|(user, password)| {
+            let hash = bcrypt::hash(password, 4).expect("hash password");
+            format!("{}:{}\n", user, hash)
+        })
+        .collect();
+    std::fs::write(&auth_file, contents).expect("write htpasswd");
+
+    memory_server(&[
+        "--flows",
+        flows,
+        "--auth-file",
+        auth_file.to_str().unwrap(),
+        "--admin-users",
+        ADMIN.0,
+    ])
+    .await
+}
+
+pub fn basic_auth((user, password): (&str, &str)) -> (actix_web::http::header::HeaderName, String) {
+    let credentials = STANDARD.encode(format!("{}:{}", user, password));
+    (AUTHORIZATION, format!("Basic {}", credentials))
+}
+
+// A well-formed ssh-ed25519 public key, different for every seed
+pub fn ed25519_key(seed: u8) -> String {
+    let mut blob = Vec::new();
+    blob.extend_from_slice(&11u32.to_be_bytes());
+    blob.extend_from_slice(b"ssh-ed25519");
+    blob.extend_from_slice(&32u32.to_be_bytes());
+    blob.extend_from_slice(&[seed; 32]);
+    format!("ssh-ed25519 {}", STANDARD.encode(blob))
+}
+
+pub fn key_json(server: &str, public_key: &str) -> serde_json::Value {
+    serde_json::json!({ "server": server, "public_key": public_key })
+}
+
+// Serve the application on a free local port and return its address
+pub fn spawn_server(state: ServerState) -> (String, actix_web::dev::ServerHandle) {
+    let server = actix_web::HttpServer::new(move || build_app(&state))
+        .workers(1)
+        .disable_signals()
+        .bind(("127.0.0.1", 0))
+        .expect("bind test server");
+    let address = server.addrs()[0];
+    let server = server.run();
+    let handle = server.handle();
+    actix_web::rt::spawn(server);
+    (format!("http://{}", address), handle)
+}
+
+// The same over HTTPS, with the connection hook run_server installs for client
+// certificates; the address uses the name localhost
+pub fn spawn_tls_server(
+    state: ServerState,
+    config: rustls::ServerConfig,
+) -> (String, actix_web::dev::ServerHandle) {
+    let server = actix_web::HttpServer::new(move || build_app(&state))
+        .on_connect(khm::tls::record_client_certificate)
+        .workers(1)
+        .disable_signals()
+        .bind_rustls_0_23(("127.0.0.1", 0), config)
+        .expect("bind test server");
+    let port = server.addrs()[0].port();
+    let server = server.run();
+    let handle = server.handle();
+    actix_web::rt::spawn(server);
+    (format!("https://localhost:{}", port), handle)
+}
@@ -0,0 +1,504 @@
+// End-to-end tests of the API handlers in server.rs, on in-memory storage
+mod common;
+
+use actix_web::dev::ServiceResponse;
+use actix_web::http::header::{AsHeaderName, ETAG, IF_NONE_MATCH};
+use actix_web::http::StatusCode;
+use actix_web::test::{call_service, init_service, read_body_json, TestRequest};
+use khm::audit::AuditEntry;
+use khm::events::{FlowEvent, SseParser};
+use khm::server::{build_app, CertAuthority, KeyChanges, SshKey};
+use khm::tls::{self, ReloadableCert};
+use rcgen::{BasicConstraints, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa, KeyPair};
+use serde_json::json;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::{
+    authenticated_server, basic_auth, ed25519_key, key_json, memory_server, spawn_server,
+    spawn_tls_server, temp_dir, ADMIN, READER,
+};
+
+fn header<B>(response: &ServiceResponse<B>, name: impl AsHeaderName) -> &str {
+    response.headers().get(name).unwrap().to_str().unwrap()
+}
+
+#[actix_web::test]
+async fn uploaded_keys_are_served_with_a_revision_etag() {
+    let state = memory_server(&["--flows", "work"]).await;
+    let app = init_service(build_app(&state)).await;
+
+    let upload = json!([
+        key_json("alpha.example.com", &ed25519_key(1)),
+        key_json("beta.example.com", &ed25519_key(2)),
+    ]);
+    let response = call_service(
+        &app,
+        TestRequest::post()
+            .uri("/work/keys")
+            .set_json(&upload)
+            .to_request(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(header(&response, "X-Keys-New"), "2");
+
+    let response = call_service(&app, TestRequest::get().uri("/work/keys").to_request()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let etag = header(&response, ETAG).to_string();
+    let keys: Vec<SshKey> = read_body_json(response).await;
+    assert_eq!(keys.len(), 2);
+    assert_eq!(keys[0].server, "alpha.example.com");
+
+    let response = call_service(
+        &app,
+        TestRequest::get()
+            .uri("/work/keys")
+            .insert_header((IF_NONE_MATCH, etag.clone()))
+            .to_request(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+    // Uploading the same keys again changes nothing, so the revision stays
+    let response = call_service(
+        &app,
+        TestRequest::post()
+            .uri("/work/keys")
+            .set_json(&upload)
+            .to_request(),
+    )
+    .await;
+    assert_eq!(header(&response, "X-Keys-Unchanged"), "2");
+    let response = call_service(&app, TestRequest::get().uri("/work/keys").to_request()).await;
+    assert_eq!(header(&response, ETAG), etag);
+
+    let response = call_service(&app, TestRequest::get().uri("/other/keys").to_request()).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[actix_web::test]
+async fn changed_host_keys_are_quarantined() {
+    let state = memory_server(&["--flows", "work"]).await;
+    let app = init_service(build_app(&state)).await;
+
+    for (seed, pending) in [(1, "0"), (2, "1")] {
+        let response = call_service(
+            &app,
+            TestRequest::post()
+                .uri("/work/keys")
+                .set_json(json!([key_json("alpha.example.com", &ed25519_key(seed))]))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(header(&response, "X-Keys-Pending"), pending);
+    }
+
+    let response = call_service(&app, TestRequest::get().uri("/work/keys").to_request()).await;
+    let keys: Vec<SshKey> = read_body_json(response).await;
+    assert_eq!(keys.len(), 1);
+    assert_eq!(keys[0].public_key, ed25519_key(1));
+}
+
+#[actix_web::test]
+async fn keys_with_and_without_a_comment_are_one_key() {
+    let state = memory_server(&["--flows", "work"]).await;
+    let app = init_service(build_app(&state)).await;
+
+    // Older clients send the comment as part of the key, newer ones apart from it
+    let key = ed25519_key(1);
+    let uploads = [
+        (
+            json!([key_json("alpha.example.com", &format!("{} root@vm", key))]),
+            "1",
+        ),
+        (json!([key_json("alpha.example.com", &key)]), "0"),
+        (
+            json!([{ "server": "alpha.example.com", "public_key": key, "comment": "root@vm" }]),
+            "0",
+        ),
+    ];
+    for (upload, new) in uploads {
+        let response = call_service(
+            &app,
+            TestRequest::post()
+                .uri("/work/keys")
+                .set_json(&upload)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(header(&response, "X-Keys-New"), new);
+    }
+
+    let response = call_service(&app, TestRequest::get().uri("/work/keys").to_request()).await;
+    let keys: Vec<SshKey> = read_body_json(response).await;
+    assert_eq!(keys.len(), 1);
+    assert_eq!(keys[0].public_key, key);
+    assert_eq!(keys[0].comment.as_deref(), Some("root@vm"));
+}
+
+// The next event on an open /{flow}/events stream
+async fn next_event(events: &mut reqwest::Response, parser: &mut SseParser) -> FlowEvent {
+    loop {
+        let chunk = tokio::time::timeout(Duration::from_secs(5), events.chunk())
+            .await
+            .expect("event in time")
+            .unwrap()
+            .expect("open event stream");
+        if let Some(event) = parser.push(&chunk).into_iter().next() {
+            return event;
+        }
+    }
+}
+
+#[actix_web::test]
+async fn watchers_of_a_flow_see_changes_made_through_another_flow() {
+    let (host, server) = spawn_server(memory_server(&["--flows", "work,staging"]).await);
+    let client = reqwest::Client::new();
+
+    let upload = json!([key_json("alpha.example.com", &ed25519_key(1))]);
+    for flow in ["work", "staging"] {
+        let response = client
+            .post(format!("{}/{}/keys", host, flow))
+            .json(&upload)
+            .send()
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+    }
+
+    let mut events = client
+        .get(format!("{}/staging/events", host))
+        .send()
+        .await
+        .unwrap();
+    let mut parser = SseParser::default();
+    let first = next_event(&mut events, &mut parser).await;
+    assert_eq!(first.flow, "staging");
+
+    // Both flows hold the key, so deprecating it in one changes the other
+    let response = client
+        .delete(format!("{}/work/keys/alpha.example.com", host))
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    let event = next_event(&mut events, &mut parser).await;
+    assert_eq!(event.flow, "staging");
+    assert!(event.revision > first.revision);
+
+    drop(events);
+    server.stop(true).await;
+}
+
+#[actix_web::test]
+async fn requests_need_credentials_and_a_grant() {
+    let state = authenticated_server("work").await;
+    let app = init_service(build_app(&state)).await;
+
+    let response = call_service(&app, TestRequest::get().uri("/work/keys").to_request()).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = call_service(&app, TestRequest::get().uri("/healthz").to_request()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let reader_get = || {
+        TestRequest::get()
+            .uri("/work/keys")
+            .insert_header(basic_auth(READER))
+            .to_request()
+    };
+    let response = call_service(&app, reader_get()).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = call_service(
+        &app,
+        TestRequest::put()
+            .uri("/api/grants")
+            .insert_header(basic_auth(ADMIN))
+            .set_json(json!({ "username": READER.0, "flow": "work", "role": "reader" }))
+            .to_request(),
+    )
+    .await;
+    assert!(response.status().is_success());
+
+    let response = call_service(&app, reader_get()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = call_service(
+        &app,
+        TestRequest::post()
+            .uri("/work/keys")
+            .insert_header(basic_auth(READER))
+            .set_json(json!([key_json("alpha.example.com", &ed25519_key(1))]))
+            .to_request(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+// A CA, a server certificate for localhost and a client certificate for `user`, all
+// written to `dir`, and a client presenting the client certificate
+fn client_certificate_setup(dir: &Path, user: &str) -> (rustls::ServerConfig, reqwest::Client) {
+    let ca_key = KeyPair::generate().unwrap();
+    let mut ca_params = CertificateParams::new(Vec::new()).unwrap();
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca = ca_params.self_signed(&ca_key).unwrap();
+
+    let server_key = KeyPair::generate().unwrap();
+    let server_cert = CertificateParams::new(vec!["localhost".to_string()])
+        .unwrap()
+        .signed_by(&server_key, &ca, &ca_key)
+        .unwrap();
+
+    let client_key = KeyPair::generate().unwrap();
+    let mut client_params = CertificateParams::new(Vec::new()).unwrap();
+    client_params
+        .distinguished_name
+        .push(DnType::CommonName, user);
+    client_params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+    let client_cert = client_params.signed_by(&client_key, &ca, &ca_key).unwrap();
+
+    let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+    fs::write(path("ca.pem"), ca.pem()).unwrap();
+    fs::write(path("server.pem"), server_cert.pem()).unwrap();
+    fs::write(path("server.key"), server_key.serialize_pem()).unwrap();
+
+    let server_cert = ReloadableCert::load(&path("server.pem"), &path("server.key")).unwrap();
+    let config = tls::build_server_config(Arc::new(server_cert), Some(&path("ca.pem"))).unwrap();
+    let identity = format!("{}{}", client_cert.pem(), client_key.serialize_pem());
+    let client = reqwest::Client::builder()
+        .use_rustls_tls()
+        .add_root_certificate(reqwest::Certificate::from_pem(ca.pem().as_bytes()).unwrap())
+        .identity(reqwest::Identity::from_pem(identity.as_bytes()).unwrap())
+        .build()
+        .unwrap();
+    (config, client)
+}
+
+#[actix_web::test]
+async fn client_certificates_authenticate_their_common_name() {
+    let (config, client) = client_certificate_setup(&temp_dir(), READER.0);
+    let (host, server) = spawn_tls_server(authenticated_server("work").await, config);
+    let keys = || client.get(format!("{}/work/keys", host)).send();
+
+    // The certificate names the user, whose grants still apply
+    assert_eq!(
+        keys().await.unwrap().status(),
+        reqwest::StatusCode::FORBIDDEN
+    );
+
+    let (name, value) = basic_auth(ADMIN);
+    let response = client
+        .put(format!("{}/api/grants", host))
+        .header(name.as_str(), value)
+        .json(&json!({ "username": READER.0, "flow": "work", "role": "reader" }))
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    assert_eq!(keys().await.unwrap().status(), reqwest::StatusCode::OK);
+
+    // A graceful stop would wait for the client's TLS connections to time out
+    server.stop(false).await;
+}
+
+#[actix_web::test]
+async fn flows_are_created_renamed_and_archived() {
+    let state = authenticated_server("work").await;
+    let app = init_service(build_app(&state)).await;
+    let admin = basic_auth(ADMIN);
+
+    for expected in [StatusCode::CREATED, StatusCode::CONFLICT] {
+        let response = call_service(
+            &app,
+            TestRequest::post()
+                .uri("/api/flows")
+                .insert_header(admin.clone())
+                .set_json(json!({ "name": "staging" }))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), expected);
+    }
+
+    let rename = |name: &str| {
+        TestRequest::patch()
+            .uri("/api/flows/work")
+            .insert_header(admin.clone())
+            .set_json(json!({ "name": name }))
+            .to_request()
+    };
+    let response = call_service(&app, rename("staging")).await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let response = call_service(&app, rename("prod")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let get_keys = |flow: &str| {
+        TestRequest::get()
+            .uri(&format!("/{}/keys", flow))
+            .insert_header(admin.clone())
+            .to_request()
+    };
+    let response = call_service(&app, get_keys("work")).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = call_service(&app, get_keys("prod")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = call_service(
+        &app,
+        TestRequest::delete()
+            .uri("/api/flows/prod")
+            .insert_header(admin.clone())
+            .to_request(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let response = call_service(
+        &app,
+        TestRequest::post()
+            .uri("/prod/keys")
+            .insert_header(admin.clone())
+            .set_json(json!([key_json("alpha.example.com", &ed25519_key(1))]))
+            .to_request(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+}
+
+#[actix_web::test]
+async fn api_tokens_are_limited_to_their_scope_until_revoked() {
+    let state = authenticated_server("work,staging").await;
+    let app = init_service(build_app(&state)).await;
+
+    let response = call_service(
+        &app,
+        TestRequest::post()
+            .uri("/api/tokens")
+            .insert_header(basic_auth(ADMIN))
+            .set_json(json!({ "name": "ci", "flows": ["work"], "role": "writer" }))
+            .to_request(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let created: serde_json::Value = read_body_json(response).await;
+    let bearer = format!("Bearer {}", created["token"].as_str().unwrap());
+
+    let upload = |flow: &str| {
+        TestRequest::post()
+            .uri(&format!("/{}/keys", flow))
+            .insert_header(("Authorization", bearer.clone()))
+            .set_json(json!([key_json("alpha.example.com", &ed25519_key(1))]))
+            .to_request()
+    };
+    let response = call_service(&app, upload("work")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = call_service(&app, upload("staging")).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = call_service(
+        &app,
+        TestRequest::delete()
+            .uri(&format!("/api/tokens/{}", created["id"]))
+            .insert_header(basic_auth(ADMIN))
+            .to_request(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let response = call_service(&app, upload("work")).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[actix_web::test]
+async fn api_tokens_need_an_auth_file() {
+    let state = memory_server(&["--flows", "work"]).await;
+    let app = init_service(build_app(&state)).await;
+
+    let response = call_service(
+        &app,
+        TestRequest::post()
+            .uri("/api/tokens")
+            .set_json(json!({ "name": "ci", "flows": ["work"], "role": "writer" }))
+            .to_request(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    let response = call_service(&app, TestRequest::get().uri("/api/tokens").to_request()).await;
+    let tokens: Vec<serde_json::Value> = read_body_json(response).await;
+    assert!(tokens.is_empty());
+}
+
+#[actix_web::test]
+async fn certificate_authorities_show_up_in_keys_changes_and_audit() {
+    let state = memory_server(&["--flows", "work"]).await;
+    let app = init_service(build_app(&state)).await;
+
+    let response = call_service(
+        &app,
+        TestRequest::post()
+            .uri("/work/keys")
+            .set_json(json!([key_json("alpha.example.com", &ed25519_key(1))]))
+            .to_request(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = call_service(&app, TestRequest::get().uri("/work/keys").to_request()).await;
+    let revision: i64 = header(&response, ETAG).trim_matches('"').parse().unwrap();
+
+    let new_ca = json!({ "hosts": ["*.example.com"], "public_key": ed25519_key(9) });
+    let response = call_service(
+        &app,
+        TestRequest::post()
+            .uri("/work/cert-authorities")
+            .set_json(&new_ca)
+            .to_request(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let cert_authority: CertAuthority = read_body_json(response).await;
+    assert_eq!(cert_authority.hosts, ["*.example.com"]);
+
+    let response = call_service(
+        &app,
+        TestRequest::post()
+            .uri("/work/cert-authorities")
+            .set_json(&new_ca)
+            .to_request(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    let response = call_service(&app, TestRequest::get().uri("/work/keys").to_request()).await;
+    let keys: Vec<SshKey> = read_body_json(response).await;
+    assert_eq!(keys.len(), 2);
+    assert!(keys.iter().any(|key| key.marker.is_some()));
+
+    let response = call_service(
+        &app,
+        TestRequest::get()
+            .uri(&format!("/work/keys/changes?since={}", revision))
+            .to_request(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let changes: KeyChanges = read_body_json(response).await;
+    assert!(changes.revision > revision);
+
+    let response = call_service(
+        &app,
+        TestRequest::get().uri("/api/audit?flow=work").to_request(),
+    )
+    .await;
+    let entries: Vec<AuditEntry> = read_body_json(response).await;
+    let actions: Vec<&str> = entries.iter().map(|entry| entry.action.as_str()).collect();
+    assert_eq!(actions, ["ca_added", "key_added", "flow_created"]);
+}
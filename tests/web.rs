@@ -0,0 +1,250 @@
+// End-to-end tests of the key management handlers in web.rs, on in-memory storage
+mod common;
+
+use actix_web::http::header::{ETAG, IF_NONE_MATCH};
+use actix_web::http::StatusCode;
+use actix_web::test::{call_service, init_service, read_body, read_body_json, TestRequest};
+use khm::server::{build_app, KeyChanges, SshKey};
+use serde_json::json;
+
+use common::{ed25519_key, key_json, memory_server};
+
+// Every key of the flow, deprecated ones included
+fn all_keys() -> TestRequest {
+    TestRequest::get().uri("/work/keys?include_deprecated=true")
+}
+
+#[actix_web::test]
+async fn keys_are_deprecated_restored_and_deleted() {
+    let state = memory_server(&["--flows", "work"]).await;
+    let app = init_service(build_app(&state)).await;
+
+    let hosts = ["alpha.example.com", "beta.example.com", "gamma.example.com"];
+    let upload: Vec<_> = hosts
+        .iter()
+        .zip(1..)
+        .map(|(host, seed)| key_json(host, &ed25519_key(seed)))
+        .collect();
+    let response = call_service(
+        &app,
+        TestRequest::post()
+            .uri("/work/keys")
+            .set_json(&upload)
+            .to_request(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = call_service(
+        &app,
+        TestRequest::delete()
+            .uri("/work/keys/alpha.example.com")
+            .to_request(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = call_service(
+        &app,
+        TestRequest::post()
+            .uri("/work/bulk-deprecate")
+            .set_json(json!({ "servers": ["beta.example.com", "gamma.example.com"] }))
+            .to_request(),
+    )
+    .await;
+    let result: serde_json::Value = read_body_json(response).await;
+    assert_eq!(result["deprecated_count"], 2);
+
+    let keys: Vec<SshKey> = read_body_json(call_service(&app, all_keys().to_request()).await).await;
+    assert!(keys.iter().all(|key| key.deprecated));
+
+    let response = call_service(
+        &app,
+        TestRequest::post()
+            .uri("/work/keys/alpha.example.com/restore")
+            .to_request(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = call_service(
+        &app,
+        TestRequest::post()
+            .uri("/work/bulk-restore")
+            .set_json(json!({ "servers": ["beta.example.com"] }))
+            .to_request(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = call_service(
+        &app,
+        TestRequest::delete()
+            .uri("/work/keys/gamma.example.com/delete")
+            .to_request(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let keys: Vec<SshKey> = read_body_json(call_service(&app, all_keys().to_request()).await).await;
+    let servers: Vec<(&str, bool)> = keys
+        .iter()
+        .map(|key| (key.server.as_str(), key.deprecated))
+        .collect();
+    assert_eq!(
+        servers,
+        [("alpha.example.com", false), ("beta.example.com", false)]
+    );
+}
+
+#[actix_web::test]
+async fn deprecating_a_shared_key_changes_every_flow_holding_it() {
+    let state = memory_server(&["--flows", "work,staging"]).await;
+    let app = init_service(build_app(&state)).await;
+
+    let upload = json!([key_json("alpha.example.com", &ed25519_key(1))]);
+    for flow in ["work", "staging"] {
+        let response = call_service(
+            &app,
+            TestRequest::post()
+                .uri(&format!("/{}/keys", flow))
+                .set_json(&upload)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    let response = call_service(&app, TestRequest::get().uri("/staging/keys").to_request()).await;
+    let etag = response
+        .headers()
+        .get(ETAG)
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string();
+    let revision: i64 = etag.trim_matches('"').parse().unwrap();
+
+    let response = call_service(
+        &app,
+        TestRequest::delete()
+            .uri("/work/keys/alpha.example.com")
+            .to_request(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = call_service(
+        &app,
+        TestRequest::get()
+            .uri("/staging/keys")
+            .insert_header((IF_NONE_MATCH, etag))
+            .to_request(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let keys: Vec<SshKey> = read_body_json(response).await;
+    assert!(keys.is_empty());
+
+    let response = call_service(
+        &app,
+        TestRequest::get()
+            .uri(&format!("/staging/keys/changes?since={}", revision))
+            .to_request(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let changes: KeyChanges = read_body_json(response).await;
+    assert!(changes.revision > revision);
+    assert!(!changes.full);
+    assert_eq!(changes.deprecated.len(), 1);
+    assert_eq!(changes.deprecated[0].public_key, ed25519_key(1));
+}
+
+#[actix_web::test]
+async fn quarantined_keys_are_approved_or_rejected() {
+    let state = memory_server(&["--flows", "work"]).await;
+    let app = init_service(build_app(&state)).await;
+
+    for (host, seed) in [
+        ("alpha.example.com", 1),
+        ("alpha.example.com", 2),
+        ("beta.example.com", 3),
+        ("beta.example.com", 4),
+    ] {
+        let response = call_service(
+            &app,
+            TestRequest::post()
+                .uri("/work/keys")
+                .set_json(json!([key_json(host, &ed25519_key(seed))]))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    for action in ["alpha.example.com/approve", "beta.example.com/reject"] {
+        let response = call_service(
+            &app,
+            TestRequest::post()
+                .uri(&format!("/work/keys/{}", action))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    // The approved key replaces alpha's old one, beta keeps its first key
+    let response = call_service(&app, TestRequest::get().uri("/work/keys").to_request()).await;
+    let keys: Vec<SshKey> = read_body_json(response).await;
+    let served: Vec<&str> = keys.iter().map(|key| key.public_key.as_str()).collect();
+    assert_eq!(served, [ed25519_key(2), ed25519_key(3)]);
+}
+
+#[actix_web::test]
+async fn revoked_keys_are_listed_for_clients() {
+    let state = memory_server(&["--flows", "work"]).await;
+    let app = init_service(build_app(&state)).await;
+
+    let response = call_service(
+        &app,
+        TestRequest::post()
+            .uri("/work/keys")
+            .set_json(json!([key_json("alpha.example.com", &ed25519_key(1))]))
+            .to_request(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = call_service(
+        &app,
+        TestRequest::post()
+            .uri("/work/keys/alpha.example.com/revoke")
+            .to_request(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = call_service(
+        &app,
+        TestRequest::get().uri("/work/revoked-keys").to_request(),
+    )
+    .await;
+    let body = read_body(response).await;
+    assert_eq!(body, format!("{}\n", ed25519_key(1)));
+}
+
+#[actix_web::test]
+async fn flows_api_lists_active_flows() {
+    let state = memory_server(&["--flows", "work,staging"]).await;
+    let app = init_service(build_app(&state)).await;
+
+    let response = call_service(
+        &app,
+        TestRequest::delete().uri("/api/flows/staging").to_request(),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let response = call_service(&app, TestRequest::get().uri("/api/flows").to_request()).await;
+    let flows: Vec<String> = read_body_json(response).await;
+    assert_eq!(flows, ["work"]);
+}